@@ -0,0 +1,34 @@
+//! YUYV to RGB conversion of a 720p frame, scalar against the SIMD path
+//! this CPU takes. Run with `cargo bench --bench yuv`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+#[allow(dead_code)]
+#[path = "../src/yuv.rs"]
+mod yuv;
+
+const WIDTH: usize = 1280;
+const HEIGHT: usize = 720;
+const YUYV: [usize; 4] = [0, 1, 2, 3];
+
+fn convert(c: &mut Criterion) {
+    let frame: Vec<u8> = (0..WIDTH * HEIGHT * 2)
+        .map(|i| (i * 7 % 251) as u8)
+        .collect();
+    let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+
+    let mut group = c.benchmark_group("yuyv_to_rgb_720p");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    group.bench_function("scalar", |b| {
+        b.iter(|| yuv::packed_to_rgb_scalar(black_box(&frame), YUYV, &mut rgb))
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| yuv::packed_to_rgb(black_box(&frame), YUYV, &mut rgb))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, convert);
+criterion_main!(benches);
@@ -0,0 +1,170 @@
+//! Microphone capture over ALSA, served as an endless WAV stream for
+//! listening in, e.g. with a webcam's built-in mic as a baby monitor.
+//!
+//! Capturing needs the `audio` feature, which links libasound; the device is
+//! only held open while someone is listening.
+
+use std::{thread, time::Duration};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::sync::broadcast;
+
+use crate::config::AudioConfig;
+
+/// Chunks a slow listener may fall behind before it starts skipping.
+const CHUNK_CHANNEL_CAPACITY: usize = 16;
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// The capture thread's output, shared with every listener.
+#[derive(Clone)]
+pub struct AudioHandle {
+    chunks: broadcast::Sender<Bytes>,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl AudioHandle {
+    pub fn start(config: &AudioConfig) -> Self {
+        let (chunks, _) = broadcast::channel(CHUNK_CHANNEL_CAPACITY);
+        let handle = Self {
+            chunks,
+            sample_rate: config.sample_rate,
+            channels: config.channels,
+        };
+
+        let device = config.device.clone();
+        let capture = handle.clone();
+        thread::Builder::new()
+            .name("audio-capture".to_string())
+            .spawn(move || capture.run(&device))
+            .expect("failed to spawn audio capture thread");
+        handle
+    }
+
+    /// Interleaved little-endian 16-bit samples.
+    pub fn subscribe(&self) -> broadcast::Receiver<Bytes> {
+        self.chunks.subscribe()
+    }
+
+    /// WAV header for a stream of unknown length; players read the data
+    /// chunk until the connection ends.
+    pub fn wav_header(&self) -> Bytes {
+        let block_align = self.channels * BITS_PER_SAMPLE / 8;
+        let mut header = BytesMut::with_capacity(44);
+        header.put_slice(b"RIFF");
+        header.put_u32_le(u32::MAX);
+        header.put_slice(b"WAVEfmt ");
+        header.put_u32_le(16);
+        header.put_u16_le(1); // PCM
+        header.put_u16_le(self.channels);
+        header.put_u32_le(self.sample_rate);
+        header.put_u32_le(self.sample_rate * u32::from(block_align));
+        header.put_u16_le(block_align);
+        header.put_u16_le(BITS_PER_SAMPLE);
+        header.put_slice(b"data");
+        header.put_u32_le(u32::MAX);
+        header.freeze()
+    }
+
+    fn has_listeners(&self) -> bool {
+        self.chunks.receiver_count() > 0
+    }
+
+    /// Opens the device while anyone listens and closes it once nobody
+    /// does, reopening after errors such as the USB device being unplugged.
+    fn run(&self, device: &str) {
+        loop {
+            if !self.has_listeners() {
+                thread::sleep(IDLE_POLL_INTERVAL);
+                continue;
+            }
+
+            tracing::info!(device, "Opening audio device");
+            match backend::capture(device, self) {
+                Ok(()) => tracing::info!(device, "No listeners; audio device closed"),
+                Err(err) => {
+                    tracing::error!(device, retry_in = ?RETRY_DELAY, error = format!("{err:#}"), "Audio capture failed");
+                    thread::sleep(RETRY_DELAY);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "audio", target_os = "linux"))]
+mod backend {
+    use alsa::{
+        pcm::{Access, Format, HwParams, PCM},
+        Direction, ValueOr,
+    };
+    use anyhow::{anyhow, Context, Result};
+    use bytes::{BufMut, BytesMut};
+
+    use super::AudioHandle;
+
+    /// Samples are handed to listeners in chunks of this many milliseconds.
+    const CHUNK_MILLIS: usize = 100;
+
+    /// Reads from `device` until nobody is listening any more.
+    pub fn capture(device: &str, handle: &AudioHandle) -> Result<()> {
+        let pcm = PCM::new(device, Direction::Capture, false)
+            .with_context(|| format!("Failed to open audio device {device}"))?;
+        {
+            let params = HwParams::any(&pcm)?;
+            params.set_access(Access::RWInterleaved)?;
+            params.set_format(Format::s16())?;
+            params
+                .set_channels(u32::from(handle.channels))
+                .with_context(|| format!("{device} cannot record {} channels", handle.channels))?;
+            params.set_rate(handle.sample_rate, ValueOr::Nearest)?;
+            let rate = params.get_rate()?;
+            if rate != handle.sample_rate {
+                return Err(anyhow!(
+                    "{device} records at {rate} Hz, not {} Hz; use a plughw: device to resample",
+                    handle.sample_rate
+                ));
+            }
+            pcm.hw_params(&params)
+                .with_context(|| format!("Failed to configure audio device {device}"))?;
+        }
+
+        let io = pcm.io_i16()?;
+        let chunk_frames = handle.sample_rate as usize * CHUNK_MILLIS / 1000;
+        let mut samples = vec![0i16; chunk_frames * usize::from(handle.channels)];
+        while handle.has_listeners() {
+            let frames = match io.readi(&mut samples) {
+                Ok(frames) => frames,
+                Err(err) => {
+                    // Overruns happen when the thread is starved for a moment.
+                    tracing::debug!(error = %err, "Audio read failed; recovering");
+                    pcm.try_recover(err, true)
+                        .context("Audio device did not recover")?;
+                    continue;
+                }
+            };
+
+            let samples = &samples[..frames * usize::from(handle.channels)];
+            let mut chunk = BytesMut::with_capacity(samples.len() * 2);
+            for sample in samples {
+                chunk.put_i16_le(*sample);
+            }
+            // Sending only fails while nobody is subscribed; the loop
+            // condition notices that.
+            let _ = handle.chunks.send(chunk.freeze());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(all(feature = "audio", target_os = "linux")))]
+mod backend {
+    use anyhow::{bail, Result};
+
+    use super::AudioHandle;
+
+    pub fn capture(_device: &str, _handle: &AudioHandle) -> Result<()> {
+        bail!("Built without audio support; enable the `audio` feature")
+    }
+}
@@ -0,0 +1,586 @@
+//! With `AUDIT_LOG`, an append-only record of who watched, downloaded or
+//! reconfigured what: one JSON line per stream, snapshot, recording,
+//! configuration, camera control, shared link or audit log access, whether
+//! authentication let it through or not.
+//!
+//! A line is written once the access ends, so a stream's line tells how
+//! long the viewer stayed.
+
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, OnceLock,
+    },
+    task::{Context as TaskContext, Poll},
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, FixedOffset, Local};
+use http_body::{Frame, SizeHint};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::mpsc::{self, error::TrySendError},
+};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{auth::Identity, logging::ClientAddr, shutdown::DrainGuard};
+
+/// Route of the admin endpoint querying the log.
+pub const PATH: &str = "/audit";
+pub const DEFAULT_QUERY_LIMIT: usize = 100;
+pub const MAX_QUERY_LIMIT: usize = 1000;
+/// Lines a query reads back from the end of the log at most, so one that
+/// matches little does not read a log grown over years.
+pub const MAX_QUERY_SCAN: usize = 100_000;
+/// Bytes read at a time while reading the log backwards.
+const READ_CHUNK: usize = 64 * 1024;
+/// Entries waiting to be written; more are dropped with a warning rather
+/// than holding up requests.
+const QUEUE_LENGTH: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessKind {
+    /// MJPEG and WebSocket streams and audio.
+    Stream,
+    /// Snapshots, thumbnails and previews.
+    Snapshot,
+    /// Clip downloads.
+    Recording,
+    /// Reading, changing or reloading the configuration.
+    Config,
+    /// Pausing or resuming a camera, or changing its controls, pan, tilt,
+    /// zoom or focus.
+    Control,
+    /// Creating shared links.
+    Link,
+    /// Queries of the audit log itself.
+    Audit,
+}
+
+impl AccessKind {
+    /// The kind of access a `method` request for `path`, as routes see it,
+    /// is.
+    fn of(method: &Method, path: &str) -> Option<Self> {
+        if path.starts_with("/recordings/") {
+            return Some(Self::Recording);
+        }
+        let view = match path.strip_prefix("/cameras/") {
+            Some(rest) => rest.split_once('/')?.1,
+            None => path
+                .strip_prefix("/camera/")
+                .or_else(|| path.strip_prefix('/'))?,
+        };
+        let changes = !matches!(*method, Method::GET | Method::HEAD);
+        match view {
+            "stream" | "ws" | "audio.wav" => Some(Self::Stream),
+            "snapshot" | "thumbnail" | "preview.gif" => Some(Self::Snapshot),
+            "config" | "config/reload" => Some(Self::Config),
+            "pause" | "resume" => Some(Self::Control),
+            "controls" | "ptz" | "focus" if changes => Some(Self::Control),
+            "links" => Some(Self::Link),
+            "audit" => Some(Self::Audit),
+            _ => None,
+        }
+    }
+}
+
+/// One line of the log.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    /// RFC 3339 time the request arrived.
+    pub timestamp: String,
+    pub kind: AccessKind,
+    pub method: String,
+    /// Without the query string, which may carry credentials.
+    pub path: String,
+    /// Client address, as in the server log.
+    pub client: String,
+    /// Who the client was let in as, where their credentials tell: `user`
+    /// and the Basic auth user, `sub` and the JWT subject, or `link` and
+    /// the start of the shared link's signature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    pub status: u16,
+    /// Until the response was sent, or for streams until the viewer left.
+    pub duration_ms: u64,
+}
+
+/// Queues entries for the writer; without `AUDIT_LOG`, nothing is logged.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    path: Option<Arc<PathBuf>>,
+    queue: Option<mpsc::Sender<AuditEntry>>,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, creating it if needed, and writes queued
+    /// entries to it. At shutdown, entries already queued are still written.
+    pub async fn open(path: &Path, shutdown: DrainGuard) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        let (queue, entries) = mpsc::channel(QUEUE_LENGTH);
+        tokio::spawn(write_entries(file, entries, shutdown));
+        Ok(Self {
+            path: Some(Arc::new(path.to_path_buf())),
+            queue: Some(queue),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref().map(PathBuf::as_path)
+    }
+}
+
+async fn write_entries(
+    mut file: tokio::fs::File,
+    mut entries: mpsc::Receiver<AuditEntry>,
+    shutdown: DrainGuard,
+) {
+    loop {
+        // Entries already queued win over the shutdown.
+        let entry = tokio::select! {
+            biased;
+            entry = entries.recv() => entry,
+            () = shutdown.requested() => None,
+        };
+        let Some(entry) = entry else {
+            break;
+        };
+        let mut line = match serde_json::to_vec(&entry) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::error!(error = %err, "Serializing audit entry failed");
+                continue;
+            }
+        };
+        line.push(b'\n');
+        let written = async {
+            file.write_all(&line).await?;
+            file.flush().await
+        };
+        if let Err(err) = written.await {
+            tracing::error!(error = %err, "Writing audit log failed");
+        }
+    }
+}
+
+/// An access being served; its entry is queued once every handle is gone.
+/// Streams hold one in their response body, and WebSocket handlers in
+/// their session.
+#[derive(Clone)]
+pub struct Access(Arc<Pending>);
+
+impl Access {
+    /// Records who the client was let in as; called by `require_auth`,
+    /// which runs after the access is opened.
+    pub fn identify(&self, Identity(identity): &Identity) {
+        let _ = self.0.identity.set(identity.clone());
+    }
+}
+
+struct Pending {
+    entry: AuditEntry,
+    identity: OnceLock<String>,
+    started: Instant,
+    status: AtomicU16,
+    queue: mpsc::Sender<AuditEntry>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let entry = &mut self.entry;
+        entry.identity = self.identity.take();
+        entry.status = self.status.load(Ordering::Relaxed);
+        entry.duration_ms = self.started.elapsed().as_millis() as u64;
+        // Closed only once the writer stopped at shutdown.
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(entry.clone()) {
+            tracing::warn!("Audit log queue full; dropping entry");
+        }
+    }
+}
+
+/// Logs accesses to the routes worth auditing, including those turned away
+/// with `401` or `403`. Runs outside `require_auth`, which fills in who the
+/// client was let in as through the [`Access`] in the request's extensions.
+pub async fn record(
+    State(audit): State<AuditLog>,
+    ClientAddr(client): ClientAddr,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let kind = AccessKind::of(request.method(), request.uri().path());
+    let (Some(queue), Some(kind)) = (audit.queue, kind) else {
+        return next.run(request).await;
+    };
+    let access = Access(Arc::new(Pending {
+        entry: AuditEntry {
+            timestamp: Local::now().to_rfc3339(),
+            kind,
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            client,
+            identity: None,
+            status: 0,
+            duration_ms: 0,
+        },
+        identity: OnceLock::new(),
+        started: Instant::now(),
+        status: AtomicU16::new(0),
+        queue,
+    }));
+    request.extensions_mut().insert(access.clone());
+
+    let response = next.run(request).await;
+    access
+        .0
+        .status
+        .store(response.status().as_u16(), Ordering::Relaxed);
+    response.map(|body| {
+        Body::new(Tracked {
+            body,
+            _access: access,
+        })
+    })
+}
+
+/// A response body that keeps its access open until the client has all of
+/// it or went away.
+struct Tracked {
+    body: Body,
+    _access: Access,
+}
+
+impl HttpBody for Tracked {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Narrows an audit log query; every parameter is optional.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    /// RFC 3339 time; only accesses that started at or after it.
+    pub since: Option<String>,
+    /// RFC 3339 time; only accesses that started before it.
+    pub until: Option<String>,
+    pub kind: Option<AccessKind>,
+    /// Client address, with or without the port.
+    pub client: Option<String>,
+    /// As logged, e.g. `user alice`.
+    pub identity: Option<String>,
+    /// Most entries returned, 100 by default and at most 1000.
+    pub limit: Option<usize>,
+}
+
+/// [`AuditQuery`] with its times parsed.
+pub struct Filter {
+    since: Option<DateTime<FixedOffset>>,
+    until: Option<DateTime<FixedOffset>>,
+    kind: Option<AccessKind>,
+    client: Option<String>,
+    identity: Option<String>,
+    limit: usize,
+}
+
+impl TryFrom<AuditQuery> for Filter {
+    type Error = anyhow::Error;
+
+    fn try_from(query: AuditQuery) -> Result<Self> {
+        let time = |raw: Option<String>, name: &str| {
+            raw.map(|raw| {
+                DateTime::parse_from_rfc3339(&raw)
+                    .with_context(|| format!("Invalid {name}: expected an RFC 3339 time"))
+            })
+            .transpose()
+        };
+        Ok(Self {
+            since: time(query.since, "since")?,
+            until: time(query.until, "until")?,
+            kind: query.kind,
+            client: query.client,
+            identity: query.identity,
+            limit: query
+                .limit
+                .unwrap_or(DEFAULT_QUERY_LIMIT)
+                .min(MAX_QUERY_LIMIT),
+        })
+    }
+}
+
+impl Filter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        if self.since.is_some() || self.until.is_some() {
+            let Ok(started) = DateTime::parse_from_rfc3339(&entry.timestamp) else {
+                return false;
+            };
+            if self.since.is_some_and(|since| started < since)
+                || self.until.is_some_and(|until| started >= until)
+            {
+                return false;
+            }
+        }
+        let client_matches = |client: &String| {
+            *client == entry.client
+                || entry
+                    .client
+                    .parse::<SocketAddr>()
+                    .is_ok_and(|addr| addr.ip().to_string() == *client)
+        };
+        self.kind.is_none_or(|kind| kind == entry.kind)
+            && self.client.as_ref().is_none_or(client_matches)
+            && self
+                .identity
+                .as_ref()
+                .is_none_or(|identity| entry.identity.as_ref() == Some(identity))
+    }
+}
+
+/// The latest entries in the log at `path` that match `filter`, newest
+/// first, found by reading the log backwards from its end, through at most
+/// [`MAX_QUERY_SCAN`] lines. Lines that do not parse, such as one cut short
+/// by a crash, are skipped.
+pub fn query(path: &Path, filter: &Filter) -> Result<Vec<AuditEntry>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+    let mut lines = ReverseLines::new(file)?;
+    let mut latest = Vec::new();
+    for _ in 0..MAX_QUERY_SCAN {
+        if latest.len() >= filter.limit {
+            break;
+        }
+        let Some(line) = lines.next_line().context("Failed to read audit log")? else {
+            break;
+        };
+        let Ok(entry) = serde_json::from_slice::<AuditEntry>(&line) else {
+            continue;
+        };
+        if filter.matches(&entry) {
+            latest.push(entry);
+        }
+    }
+    Ok(latest)
+}
+
+/// Lines of a file from the last to the first, read a chunk at a time.
+struct ReverseLines {
+    file: fs::File,
+    /// Where the bytes not yet read end.
+    unread: u64,
+    /// Read bytes not yet returned, starting with a line that may continue
+    /// in the bytes before them.
+    pending: Vec<u8>,
+}
+
+impl ReverseLines {
+    fn new(mut file: fs::File) -> std::io::Result<Self> {
+        let unread = file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            file,
+            unread,
+            pending: Vec::new(),
+        })
+    }
+
+    fn next_line(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(newline) = self.pending.iter().rposition(|&byte| byte == b'\n') {
+                let line = self.pending.split_off(newline + 1);
+                self.pending.truncate(newline);
+                return Ok(Some(line));
+            }
+            if self.unread == 0 {
+                return Ok((!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending)));
+            }
+            let length = self.unread.min(READ_CHUNK as u64);
+            self.unread -= length;
+            let mut chunk = vec![0; length as usize];
+            self.file.seek(SeekFrom::Start(self.unread))?;
+            self.file.read_exact(&mut chunk)?;
+            chunk.append(&mut self.pending);
+            self.pending = chunk;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, io::Write, process};
+
+    use super::*;
+
+    fn entry(timestamp: &str, kind: AccessKind, client: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp: timestamp.to_string(),
+            kind,
+            method: "GET".to_string(),
+            path: "/stream".to_string(),
+            client: client.to_string(),
+            identity: None,
+            status: 200,
+            duration_ms: 0,
+        }
+    }
+
+    fn filter(query: AuditQuery) -> Filter {
+        Filter::try_from(query).unwrap()
+    }
+
+    fn everything(limit: usize) -> AuditQuery {
+        AuditQuery {
+            since: None,
+            until: None,
+            kind: None,
+            client: None,
+            identity: None,
+            limit: Some(limit),
+        }
+    }
+
+    /// A log holding `lines`, each followed by a newline.
+    fn log(name: &str, lines: &[String]) -> PathBuf {
+        let path = env::temp_dir().join(format!("picam-audit-{}-{name}.log", process::id()));
+        let mut file = fs::File::create(&path).unwrap();
+        for line in lines {
+            writeln!(file, "{line}").unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn views_and_changes_are_audited() {
+        for (method, path, kind) in [
+            (Method::GET, "/stream", AccessKind::Stream),
+            (Method::GET, "/cameras/1/stream", AccessKind::Stream),
+            (Method::GET, "/cameras/0/thumbnail", AccessKind::Snapshot),
+            (Method::GET, "/recordings/clip.mp4", AccessKind::Recording),
+            (Method::PUT, "/config", AccessKind::Config),
+            (Method::POST, "/config/reload", AccessKind::Config),
+            (Method::POST, "/camera/pause", AccessKind::Control),
+            (Method::POST, "/cameras/2/resume", AccessKind::Control),
+            (Method::POST, "/controls", AccessKind::Control),
+            (Method::POST, "/cameras/0/ptz", AccessKind::Control),
+            (Method::POST, "/focus", AccessKind::Control),
+            (Method::POST, "/links", AccessKind::Link),
+            (Method::GET, "/audit", AccessKind::Audit),
+        ] {
+            assert_eq!(AccessKind::of(&method, path), Some(kind), "{method} {path}");
+        }
+    }
+
+    #[test]
+    fn reads_without_effect_are_not_audited() {
+        for path in [
+            "/cameras",
+            "/controls",
+            "/cameras/0/ptz",
+            "/focus",
+            "/camera/capabilities",
+            "/stats",
+            "/health",
+        ] {
+            assert_eq!(AccessKind::of(&Method::GET, path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn query_returns_the_latest_matches_newest_first() {
+        let lines: Vec<String> = (0..10)
+            .map(|second| {
+                let kind = if second % 2 == 0 {
+                    AccessKind::Stream
+                } else {
+                    AccessKind::Snapshot
+                };
+                let timestamp = format!("2026-10-16T12:00:0{second}+00:00");
+                serde_json::to_string(&entry(&timestamp, kind, "192.168.1.5:50000")).unwrap()
+            })
+            .collect();
+        let path = log("latest", &lines);
+
+        let found = query(
+            &path,
+            &filter(AuditQuery {
+                kind: Some(AccessKind::Stream),
+                ..everything(3)
+            }),
+        )
+        .unwrap();
+        let timestamps: Vec<&str> = found.iter().map(|entry| entry.timestamp.as_str()).collect();
+        assert_eq!(
+            timestamps,
+            [
+                "2026-10-16T12:00:08+00:00",
+                "2026-10-16T12:00:06+00:00",
+                "2026-10-16T12:00:04+00:00"
+            ]
+        );
+
+        let found = query(
+            &path,
+            &filter(AuditQuery {
+                client: Some("192.168.1.5".to_string()),
+                since: Some("2026-10-16T12:00:07+00:00".to_string()),
+                ..everything(100)
+            }),
+        )
+        .unwrap();
+        assert_eq!(found.len(), 3);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn query_reads_lines_across_chunks_and_skips_broken_ones() {
+        // Long enough that entries straddle the chunks read.
+        let client = "x".repeat(READ_CHUNK / 3);
+        let mut lines: Vec<String> = (0..7)
+            .map(|second| {
+                let timestamp = format!("2026-10-16T12:00:0{second}+00:00");
+                serde_json::to_string(&entry(&timestamp, AccessKind::Stream, &client)).unwrap()
+            })
+            .collect();
+        lines.insert(3, "{\"timestamp\":".to_string());
+        let path = log("chunks", &lines);
+
+        let found = query(&path, &filter(everything(100))).unwrap();
+        assert_eq!(found.len(), 7);
+        assert!(found.iter().all(|entry| entry.client == client));
+        assert_eq!(found[0].timestamp, "2026-10-16T12:00:06+00:00");
+        assert_eq!(found[6].timestamp, "2026-10-16T12:00:00+00:00");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use subtle::ConstantTimeEq;
+use tokio::time::Instant;
+
+use crate::{
+    audit,
+    config::AuthConfig,
+    jwt::{self, JwtVerifier},
+    links,
+};
+
+/// The configured credentials, replaced when the configuration is reloaded
+/// so that rotating a leaked one takes effect at once.
+#[derive(Clone)]
+pub struct Auth(Arc<RwLock<Arc<Credentials>>>);
+
+/// The credentials in effect, plus the JWT verifier and its key cache.
+struct Credentials {
+    config: AuthConfig,
+    jwt: Option<Arc<JwtVerifier>>,
+}
+
+impl Auth {
+    pub fn new(config: AuthConfig) -> Result<Self> {
+        let jwt = verifier(&config)?;
+        Ok(Self(Arc::new(RwLock::new(Arc::new(Credentials {
+            config,
+            jwt,
+        })))))
+    }
+
+    /// Lets requests in with `config` from now on. The JWT verifier, and
+    /// with it the cached keys, is kept while the JWT settings stay the same.
+    pub fn replace(&self, config: AuthConfig) -> Result<()> {
+        let current = self.current();
+        let jwt = if config.jwt == current.config.jwt {
+            current.jwt.clone()
+        } else {
+            verifier(&config)?
+        };
+        *self.0.write().expect("credentials poisoned") = Arc::new(Credentials { config, jwt });
+        Ok(())
+    }
+
+    fn current(&self) -> Arc<Credentials> {
+        self.0.read().expect("credentials poisoned").clone()
+    }
+}
+
+fn verifier(config: &AuthConfig) -> Result<Option<Arc<JwtVerifier>>> {
+    Ok(config
+        .jwt
+        .as_ref()
+        .map(JwtVerifier::new)
+        .transpose()?
+        .map(Arc::new))
+}
+
+/// Who a request was let in as, where its credentials tell: the Basic auth
+/// user, a JWT's subject or the signature of a shared link. Added to the
+/// request's extensions for `VIEWER_WATERMARK`, and to its audit log
+/// entry.
+#[derive(Clone, Debug)]
+pub struct Identity(pub String);
+
+/// When the credentials a request was let in with run out: a JWT's `exp`
+/// or a shared link's expiry. Added to the request's extensions, so streams end then instead of
+/// outliving them.
+#[derive(Clone, Copy, Debug)]
+pub struct Expiry(pub Instant);
+
+impl Expiry {
+    /// At `expires`, in Unix seconds; `None` if too far off to count.
+    fn at(expires: u64) -> Option<Self> {
+        let now = u64::try_from(Utc::now().timestamp()).unwrap_or(0);
+        Instant::now()
+            .checked_add(Duration::from_secs(expires.saturating_sub(now)))
+            .map(Self)
+    }
+}
+
+/// Link signatures are long; this much of one is enough to tell them apart.
+const LINK_ID_LENGTH: usize = 8;
+
+enum Access {
+    Granted {
+        identity: Option<Identity>,
+        expiry: Option<Expiry>,
+    },
+    /// A valid JWT without the scope this request needs.
+    MissingScope,
+    Denied,
+}
+
+/// Rejects requests that carry neither the configured bearer token, the
+/// configured Basic credentials, a valid JWT nor the signature of an
+/// unexpired link to the requested view. Tokens may also be passed as an
+/// `access_token` query parameter, since `<img>` tags cannot set headers.
+/// A JWT only allows changes and reading the audit log with the `admin`
+/// scope, so viewer tokens cannot reconfigure the camera or see who watched.
+pub async fn require_auth(State(auth): State<Auth>, mut request: Request, next: Next) -> Response {
+    // One snapshot, so a reload halfway through cannot mix old and new.
+    let auth = auth.current();
+    if !auth.config.is_enabled() {
+        return next.run(request).await;
+    }
+    if let Some((identity, expiry)) = signed_link(&auth, &request) {
+        admit(&mut request, Some(identity), expiry);
+        return next.run(request).await;
+    }
+    let presented =
+        bearer_token(request.headers()).or_else(|| query_param(&request, "access_token"));
+    let needs_admin = !matches!(*request.method(), Method::GET | Method::HEAD)
+        || request.uri().path() == audit::PATH;
+    match access(&auth, needs_admin, request.headers(), presented).await {
+        Access::Granted { identity, expiry } => {
+            admit(&mut request, identity, expiry);
+            return next.run(request).await;
+        }
+        Access::MissingScope => {
+            return (
+                StatusCode::FORBIDDEN,
+                format!("token lacks the {} scope", jwt::ADMIN_SCOPE),
+            )
+                .into_response()
+        }
+        Access::Denied => {}
+    }
+
+    let challenge = if auth.config.user.is_some() {
+        r#"Basic realm="PiCam", charset="UTF-8""#
+    } else {
+        r#"Bearer realm="PiCam""#
+    };
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, challenge)],
+        "unauthorized",
+    )
+        .into_response()
+}
+
+/// Tells the handlers, and the audit log when the request is audited, what
+/// the request was let in with.
+fn admit(request: &mut Request, identity: Option<Identity>, expiry: Option<Expiry>) {
+    if let Some(identity) = identity {
+        if let Some(access) = request.extensions().get::<audit::Access>() {
+            access.identify(&identity);
+        }
+        request.extensions_mut().insert(identity);
+    }
+    if let Some(expiry) = expiry {
+        request.extensions_mut().insert(expiry);
+    }
+}
+
+async fn access(
+    auth: &Credentials,
+    needs_admin: bool,
+    headers: &HeaderMap,
+    presented: Option<String>,
+) -> Access {
+    if let (Some(expected), Some(token)) = (auth.config.token.as_deref(), presented.as_deref()) {
+        // Everyone shares the token, so it tells nobody apart.
+        if secure_eq(token, expected) {
+            return Access::Granted {
+                identity: None,
+                expiry: None,
+            };
+        }
+    }
+
+    if let (Some(user), Some(password)) =
+        (auth.config.user.as_deref(), auth.config.password.as_deref())
+    {
+        if let Some((presented_user, presented_password)) = basic_credentials(headers) {
+            // Evaluate both comparisons so timing does not reveal which one failed.
+            let user_ok = secure_eq(&presented_user, user);
+            let password_ok = secure_eq(&presented_password, password);
+            if user_ok & password_ok {
+                return Access::Granted {
+                    identity: Some(Identity(format!("user {presented_user}"))),
+                    expiry: None,
+                };
+            }
+            return Access::Denied;
+        }
+    }
+
+    if let (Some(verifier), Some(token)) = (&auth.jwt, presented.as_deref()) {
+        match verifier.verify(token).await {
+            Ok(claims) => {
+                return if !needs_admin || claims.has_scope(jwt::ADMIN_SCOPE) {
+                    Access::Granted {
+                        identity: claims
+                            .subject()
+                            .map(|subject| Identity(format!("sub {subject}"))),
+                        expiry: Expiry::at(claims.expires()),
+                    }
+                } else {
+                    Access::MissingScope
+                };
+            }
+            Err(err) => tracing::debug!(error = format!("{err:#}"), "Rejected JWT"),
+        }
+    }
+
+    Access::Denied
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?;
+    Some(token.trim().to_string())
+}
+
+fn query_param(request: &Request, name: &str) -> Option<String> {
+    request.uri().query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then(|| value.to_string())
+    })
+}
+
+/// Links only grant reading the view they were made for. Identified by the
+/// start of their signature, which differs for every link.
+fn signed_link(auth: &Credentials, request: &Request) -> Option<(Identity, Option<Expiry>)> {
+    let secret = auth.config.link_secret.as_deref()?;
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return None;
+    }
+    let expires = query_param(request, "exp")?;
+    let signature = query_param(request, "sig")?;
+    if !links::verify(secret, request.uri().path(), &expires, &signature) {
+        return None;
+    }
+    let id: String = signature.chars().take(LINK_ID_LENGTH).collect();
+    let expiry = expires.parse().ok().and_then(Expiry::at);
+    Some((Identity(format!("link {id}")), expiry))
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+fn secure_eq(presented: &str, expected: &str) -> bool {
+    presented.as_bytes().ct_eq(expected.as_bytes()).into()
+}
@@ -0,0 +1,152 @@
+//! Keeps the combined stream output under `MAX_BANDWIDTH_KBPS`, stepping
+//! viewers down to a lower JPEG quality and then to fewer frames while it is
+//! exceeded, and back up once there is headroom again.
+//!
+//! Degraded frames are rendered by the [`Scaler`](crate::scaler::Scaler),
+//! once per camera for all viewers. Recordings, snapshots and motion
+//! detection keep the original frames.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::{
+    sync::RwLock,
+    time::{interval, MissedTickBehavior},
+};
+use utoipa::ToSchema;
+
+use crate::{config::Config, stats::StreamStats};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Steps taken one per check while over budget; quality goes first, as
+/// dropping frames is more noticeable. Viewers never get more than
+/// `JPEG_QUALITY`.
+const LEVELS: [Level; 7] = [
+    Level::new(None, 1),
+    Level::new(Some(70), 1),
+    Level::new(Some(55), 1),
+    Level::new(Some(40), 1),
+    Level::new(Some(40), 2),
+    Level::new(Some(40), 3),
+    Level::new(Some(40), 4),
+];
+/// Output has to stay below this share of the budget before stepping back
+/// up, so the next level has room to send more.
+const RECOVERY_SHARE: f64 = 0.6;
+const CALM_CHECKS_BEFORE_RECOVERY: u32 = 10;
+
+/// How much streams are currently degraded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Level {
+    /// JPEG quality frames are re-encoded at; `None` sends them as captured.
+    pub quality: Option<u8>,
+    /// Viewers get every this many frames.
+    pub frame_divisor: u32,
+}
+
+impl Level {
+    const fn new(quality: Option<u8>, frame_divisor: u32) -> Self {
+        Self {
+            quality,
+            frame_divisor,
+        }
+    }
+}
+
+/// The current level, shared by the governor and every stream.
+#[derive(Default)]
+pub struct Bandwidth {
+    level: AtomicUsize,
+    /// Output over the last check.
+    kbps: AtomicU64,
+}
+
+impl Bandwidth {
+    pub fn level(&self) -> Level {
+        LEVELS[self.level.load(Ordering::Relaxed)]
+    }
+
+    pub fn kbps(&self) -> u64 {
+        self.kbps.load(Ordering::Relaxed)
+    }
+
+    /// Whether a viewer sends the frame it numbered `sequence`, counting
+    /// from zero.
+    pub fn sends(&self, sequence: u64) -> bool {
+        sequence.is_multiple_of(u64::from(self.level().frame_divisor))
+    }
+}
+
+/// Measures stream output every second and moves `bandwidth` between
+/// levels. Reads the limit each time, so a reload can change or lift it.
+pub fn spawn(
+    bandwidth: Arc<Bandwidth>,
+    streams: Arc<StreamStats>,
+    cameras: usize,
+    config: Arc<RwLock<Config>>,
+) {
+    tokio::spawn(async move {
+        let total_sent = || (0..cameras).map(|id| streams.bytes_sent(id)).sum::<u64>();
+        let mut ticker = interval(CHECK_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_sent = total_sent();
+        let mut last_check = Instant::now();
+        let mut calm_checks = 0;
+
+        loop {
+            ticker.tick().await;
+            let sent = total_sent();
+            let elapsed = last_check.elapsed().as_secs_f64().max(f64::EPSILON);
+            let kbps = ((sent - last_sent) as f64 * 8.0 / 1000.0 / elapsed) as u64;
+            last_sent = sent;
+            last_check = Instant::now();
+            bandwidth.kbps.store(kbps, Ordering::Relaxed);
+
+            let level = bandwidth.level.load(Ordering::Relaxed);
+            let Some(limit) = config.read().await.max_bandwidth_kbps else {
+                if level > 0 {
+                    bandwidth.level.store(0, Ordering::Relaxed);
+                    tracing::info!("Bandwidth limit lifted; streams restored");
+                }
+                continue;
+            };
+
+            if kbps > u64::from(limit) {
+                calm_checks = 0;
+                if level + 1 < LEVELS.len() {
+                    bandwidth.level.store(level + 1, Ordering::Relaxed);
+                    let next = LEVELS[level + 1];
+                    tracing::warn!(
+                        kbps,
+                        limit,
+                        quality = next.quality,
+                        frame_divisor = next.frame_divisor,
+                        "Stream bandwidth over budget; degrading streams"
+                    );
+                }
+            } else if level > 0 && (kbps as f64) < f64::from(limit) * RECOVERY_SHARE {
+                calm_checks += 1;
+                if calm_checks >= CALM_CHECKS_BEFORE_RECOVERY {
+                    calm_checks = 0;
+                    bandwidth.level.store(level - 1, Ordering::Relaxed);
+                    let next = LEVELS[level - 1];
+                    tracing::info!(
+                        kbps,
+                        limit,
+                        quality = next.quality,
+                        frame_divisor = next.frame_divisor,
+                        "Stream bandwidth back under budget; restoring streams"
+                    );
+                }
+            } else {
+                calm_checks = 0;
+            }
+        }
+    });
+}
@@ -0,0 +1,147 @@
+//! Demosaicing of RAW Bayer frames, for cameras such as some global-shutter
+//! Pi modules that only deliver RAW at their full frame rate.
+//!
+//! Needs the `bayer` feature. Every pixel is interpolated on the CPU, which
+//! costs far more than converting YUV.
+
+pub use backend::demosaic;
+
+/// Bits per Bayer sample as the driver delivers them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Depth {
+    /// One byte per sample.
+    Eight,
+    /// Ten bits in the low end of a little-endian 16-bit word.
+    Ten,
+}
+
+#[cfg(feature = "bayer")]
+mod backend {
+    use anyhow::{bail, Result};
+
+    use super::Depth;
+    use crate::config::Demosaic;
+
+    /// Interpolates a BGGR frame into packed RGB in `rgb`, which holds three
+    /// bytes per pixel.
+    pub fn demosaic(
+        frame: &[u8],
+        depth: Depth,
+        width: usize,
+        height: usize,
+        method: Demosaic,
+        rgb: &mut [u8],
+    ) -> Result<()> {
+        if width < 2 || height < 2 || !width.is_multiple_of(2) || !height.is_multiple_of(2) {
+            bail!("Bayer frames need an even width and height, got {width}x{height}");
+        }
+        match depth {
+            Depth::Eight => interpolate(|index| frame[index], width, height, method, rgb),
+            // JPEG keeps eight bits, so the two lowest are dropped.
+            Depth::Ten => interpolate(
+                |index| {
+                    let sample = u16::from_le_bytes([frame[2 * index], frame[2 * index + 1]]);
+                    (sample >> 2).min(255) as u8
+                },
+                width,
+                height,
+                method,
+                rgb,
+            ),
+        }
+        Ok(())
+    }
+
+    fn interpolate(
+        sample: impl Fn(usize) -> u8,
+        width: usize,
+        height: usize,
+        method: Demosaic,
+        rgb: &mut [u8],
+    ) {
+        match method {
+            Demosaic::Nearest => nearest(sample, width, height, rgb),
+            Demosaic::Bilinear => bilinear(sample, width, height, rgb),
+        }
+    }
+
+    /// Gives all four pixels of each 2x2 cell its red, averaged green and
+    /// blue sample.
+    fn nearest(sample: impl Fn(usize) -> u8, width: usize, height: usize, rgb: &mut [u8]) {
+        for y in (0..height).step_by(2) {
+            for x in (0..width).step_by(2) {
+                let top = y * width + x;
+                let bottom = top + width;
+                let green = (u16::from(sample(top + 1)) + u16::from(sample(bottom))) / 2;
+                let pixel = [sample(bottom + 1), green as u8, sample(top)];
+                for index in [top, top + 1, bottom, bottom + 1] {
+                    rgb[3 * index..3 * index + 3].copy_from_slice(&pixel);
+                }
+            }
+        }
+    }
+
+    /// Averages the nearest samples of each colour a pixel lacks: the four
+    /// diagonal or crosswise neighbours, or the two on its row or column.
+    fn bilinear(sample: impl Fn(usize) -> u8, width: usize, height: usize, rgb: &mut [u8]) {
+        // Mirroring at the edges lands on a sample of the same colour as
+        // the missing neighbour, which clamping would not.
+        let before = |position: usize| if position == 0 { 1 } else { position - 1 };
+        let after = |position: usize, len: usize| {
+            if position + 1 == len {
+                len - 2
+            } else {
+                position + 1
+            }
+        };
+
+        for y in 0..height {
+            let row = y * width;
+            let up = before(y) * width;
+            let down = after(y, height) * width;
+            for x in 0..width {
+                let left = before(x);
+                let right = after(x, width);
+                let at = |row: usize, column: usize| u16::from(sample(row + column));
+                let here = at(row, x);
+                let cross = || (at(up, x) + at(down, x) + at(row, left) + at(row, right) + 2) / 4;
+                let diagonal =
+                    || (at(up, left) + at(up, right) + at(down, left) + at(down, right) + 2) / 4;
+                let horizontal = || (at(row, left) + at(row, right)).div_ceil(2);
+                let vertical = || (at(up, x) + at(down, x)).div_ceil(2);
+
+                // BGGR: even rows alternate blue and green, odd rows green
+                // and red.
+                let (red, green, blue) = match (y % 2, x % 2) {
+                    (0, 0) => (diagonal(), cross(), here),
+                    (0, _) => (vertical(), here, horizontal()),
+                    (_, 0) => (horizontal(), here, vertical()),
+                    _ => (here, cross(), diagonal()),
+                };
+                let index = 3 * (row + x);
+                rgb[index] = red as u8;
+                rgb[index + 1] = green as u8;
+                rgb[index + 2] = blue as u8;
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "bayer"))]
+mod backend {
+    use anyhow::{bail, Result};
+
+    use super::Depth;
+    use crate::config::Demosaic;
+
+    pub fn demosaic(
+        _frame: &[u8],
+        _depth: Depth,
+        _width: usize,
+        _height: usize,
+        _method: Demosaic,
+        _rgb: &mut [u8],
+    ) -> Result<()> {
+        bail!("Built without RAW Bayer capture; enable the `bayer` feature")
+    }
+}
@@ -0,0 +1,73 @@
+//! Recycled byte buffers for per-frame work, so capture, raw format
+//! conversion and stream output do not allocate a fresh frame-sized buffer
+//! for every frame.
+
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+
+#[derive(Clone)]
+pub struct BufferPool {
+    free: Arc<Mutex<Vec<Vec<u8>>>>,
+    /// Spare buffers kept; more are allocated while older ones are still
+    /// held by clients, recordings or the pre-event buffer.
+    max_free: usize,
+}
+
+impl BufferPool {
+    pub fn new(max_free: usize) -> Self {
+        Self {
+            free: Arc::default(),
+            max_free,
+        }
+    }
+
+    /// An empty buffer, reusing the allocation of a returned one if any.
+    pub fn take(&self) -> Vec<u8> {
+        let mut buffer = self
+            .free
+            .lock()
+            .expect("buffer pool poisoned")
+            .pop()
+            .unwrap_or_default();
+        buffer.clear();
+        buffer
+    }
+
+    /// Returns a buffer that was not handed out as `Bytes`.
+    pub fn give_back(&self, buffer: Vec<u8>) {
+        let mut free = self.free.lock().expect("buffer pool poisoned");
+        if free.len() < self.max_free {
+            free.push(buffer);
+        }
+    }
+
+    /// Shares `buffer`, which returns to the pool once the last handle is
+    /// dropped.
+    pub fn freeze(&self, buffer: Vec<u8>) -> Bytes {
+        Bytes::from_owner(PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        })
+    }
+}
+
+struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.give_back(mem::take(&mut self.buffer));
+    }
+}
@@ -0,0 +1,613 @@
+//! Webcams on macOS, read through an AVFoundation capture session.
+//!
+//! Cameras are named by their index in `/devices`, their unique ID or their
+//! name. The session delivers UYVY frames to a delegate on a dispatch queue
+//! of its own, which encodes them like V4L2 UYVY frames; while the capture
+//! loop has not taken the last frame, AVFoundation drops new ones.
+
+use std::{
+    slice,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        mpsc as std_mpsc, Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use block2::RcBlock;
+use dispatch2::{DispatchQueue, DispatchQueueAttr};
+use objc2::{
+    define_class, msg_send,
+    rc::Retained,
+    runtime::{AnyObject, Bool, ProtocolObject},
+    AnyThread, DefinedClass,
+};
+use objc2_av_foundation::{
+    AVAuthorizationStatus, AVCaptureConnection, AVCaptureDevice, AVCaptureDeviceDiscoverySession,
+    AVCaptureDeviceFormat, AVCaptureDeviceInput, AVCaptureDevicePosition,
+    AVCaptureDeviceTypeBuiltInWideAngleCamera, AVCaptureOutput, AVCaptureSession,
+    AVCaptureVideoDataOutput, AVCaptureVideoDataOutputSampleBufferDelegate, AVError,
+    AVMediaTypeVideo,
+};
+use objc2_core_foundation::CFString;
+use objc2_core_media::{CMSampleBuffer, CMTime, CMVideoFormatDescriptionGetDimensions};
+use objc2_core_video::{
+    kCVPixelBufferHeightKey, kCVPixelBufferPixelFormatTypeKey, kCVPixelBufferWidthKey,
+    kCVPixelFormatType_422YpCbCr8, kCVReturnSuccess, CVPixelBufferGetBaseAddress,
+    CVPixelBufferGetBytesPerRow, CVPixelBufferGetHeight, CVPixelBufferGetWidth,
+    CVPixelBufferLockBaseAddress, CVPixelBufferLockFlags, CVPixelBufferUnlockBaseAddress,
+};
+use objc2_foundation::{
+    NSArray, NSDictionary, NSError, NSNumber, NSObject, NSObjectProtocol, NSString,
+};
+use tokio::{sync::mpsc, time};
+
+use super::{
+    Camera, CameraError, Capabilities, CaptureFormat, CaptureThread, ControlChange, ControlInfo,
+    DeviceInfo, FormatInfo, Frame, FrameRates, Resolution,
+};
+use crate::{
+    buffer_pool::BufferPool,
+    jpeg::{self, RawFormat},
+};
+
+/// AVFoundation stops calling the delegate when a camera is unplugged, so
+/// waiting longer than this for a frame counts as a failed capture.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct AvFoundationCamera {
+    width: u32,
+    height: u32,
+    jpeg_quality: Arc<AtomicU8>,
+    /// Holds the session; frames come from its delegate.
+    thread: CaptureThread,
+    /// Formats the camera offers, listed when it was opened.
+    formats: Vec<FormatInfo>,
+}
+
+/// What the session thread was asked to open.
+struct Request {
+    device: String,
+    width: u32,
+    height: u32,
+    frame_rate: f32,
+}
+
+impl AvFoundationCamera {
+    /// Opens `device`, an index, unique ID or name, at the requested size
+    /// and rate. Frames are encoded at `jpeg_quality`.
+    pub fn new(
+        device: &str,
+        width: u32,
+        height: u32,
+        frame_rate: f32,
+        jpeg_quality: u8,
+    ) -> Result<Self, CameraError> {
+        let request = Request {
+            device: device.to_string(),
+            width,
+            height,
+            frame_rate,
+        };
+        let jpeg_quality = Arc::new(AtomicU8::new(jpeg_quality));
+        let quality = jpeg_quality.clone();
+        // Session objects stay on the thread; it only waits to be stopped.
+        let (thread, (format, formats)) = CaptureThread::spawn(device, move |context| {
+            match Session::start(&request, quality, context.frames()) {
+                Ok(session) => {
+                    context.opened(Ok((session.format, session.formats.clone())));
+                    context.wait_for_stop();
+                }
+                Err(err) => context.opened(Err(err)),
+            }
+        })?;
+        Ok(Self {
+            width: format.width,
+            height: format.height,
+            jpeg_quality,
+            thread,
+            formats,
+        })
+    }
+}
+
+/// A running capture session, stopped when dropped.
+struct Session {
+    session: Retained<AVCaptureSession>,
+    output: Retained<AVCaptureVideoDataOutput>,
+    /// The output does not keep its delegate alive.
+    _delegate: Retained<FrameDelegate>,
+    format: CaptureFormat,
+    formats: Vec<FormatInfo>,
+}
+
+impl Session {
+    fn start(
+        request: &Request,
+        jpeg_quality: Arc<AtomicU8>,
+        frames: mpsc::Sender<Result<Frame, CameraError>>,
+    ) -> Result<Self, CameraError> {
+        authorize()?;
+        let device = find_device(&request.device)?;
+        unsafe {
+            let input =
+                AVCaptureDeviceInput::deviceInputWithDevice_error(&device).map_err(|err| {
+                    camera_error(&format!("Failed to open camera {}", request.device), &err)
+                })?;
+            let session = AVCaptureSession::new();
+            if !session.canAddInput(&input) {
+                return Err(CameraError::Busy(format!(
+                    "Camera {} cannot be added to a capture session",
+                    request.device
+                )));
+            }
+            session.addInput(&input);
+
+            // The output scales to the requested size if the camera has no
+            // format of that size.
+            let output = AVCaptureVideoDataOutput::new();
+            let pixel_format = NSNumber::new_u32(kCVPixelFormatType_422YpCbCr8);
+            let width = NSNumber::new_u32(request.width);
+            let height = NSNumber::new_u32(request.height);
+            let settings = NSDictionary::<NSString, AnyObject>::from_slices(
+                &[
+                    cf_key(kCVPixelBufferPixelFormatTypeKey),
+                    cf_key(kCVPixelBufferWidthKey),
+                    cf_key(kCVPixelBufferHeightKey),
+                ],
+                &[&pixel_format, &width, &height],
+            );
+            output.setVideoSettings(Some(&settings));
+            output.setAlwaysDiscardsLateVideoFrames(true);
+            let delegate = FrameDelegate::new(jpeg_quality, frames);
+            let queue = DispatchQueue::new("picam.capture", DispatchQueueAttr::SERIAL);
+            output.setSampleBufferDelegate_queue(
+                Some(ProtocolObject::from_ref(&*delegate)),
+                Some(&queue),
+            );
+            if !session.canAddOutput(&output) {
+                return Err(CameraError::FormatUnsupported(format!(
+                    "Camera {} cannot deliver UYVY frames",
+                    request.device
+                )));
+            }
+            session.addOutput(&output);
+
+            // Formats set while the device is locked survive starting the
+            // session, which otherwise picks its own.
+            let locked = device.lockForConfiguration().is_ok();
+            if locked {
+                select_format(&device, request);
+            }
+            session.startRunning();
+            if locked {
+                device.unlockForConfiguration();
+            }
+
+            Ok(Self {
+                session,
+                output,
+                _delegate: delegate,
+                format: CaptureFormat {
+                    fourcc: "UYVY",
+                    width: request.width,
+                    height: request.height,
+                },
+                formats: describe_formats(&device.formats().to_vec()),
+            })
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe {
+            self.session.stopRunning();
+            self.output.setSampleBufferDelegate_queue(None, None);
+        }
+    }
+}
+
+/// Asks for camera access the first time, which shows a dialog for the
+/// terminal or app the server runs in.
+fn authorize() -> Result<(), CameraError> {
+    let video = unsafe { AVMediaTypeVideo }.expect("AVMediaTypeVideo is always defined");
+    let granted = match unsafe { AVCaptureDevice::authorizationStatusForMediaType(video) } {
+        AVAuthorizationStatus::Authorized => true,
+        AVAuthorizationStatus::NotDetermined => {
+            tracing::info!("Asking for camera access");
+            let (granted_tx, granted_rx) = std_mpsc::channel();
+            let handler = RcBlock::new(move |granted: Bool| {
+                let _ = granted_tx.send(granted.as_bool());
+            });
+            unsafe {
+                AVCaptureDevice::requestAccessForMediaType_completionHandler(video, &handler)
+            };
+            granted_rx.recv().unwrap_or(false)
+        }
+        _ => false,
+    };
+    if granted {
+        Ok(())
+    } else {
+        Err(CameraError::Device(
+            "Camera access denied; allow it under Privacy & Security > Camera in System Settings"
+                .to_string(),
+        ))
+    }
+}
+
+/// Cameras in the order `/devices` lists them.
+fn video_devices() -> Vec<Retained<AVCaptureDevice>> {
+    // AVCaptureDeviceTypeExternal only exists from macOS 14 on; linking it
+    // would keep the server from starting on older versions.
+    #[allow(deprecated)]
+    let types = unsafe {
+        NSArray::from_slice(&[
+            AVCaptureDeviceTypeBuiltInWideAngleCamera,
+            objc2_av_foundation::AVCaptureDeviceTypeExternalUnknown,
+        ])
+    };
+    unsafe {
+        AVCaptureDeviceDiscoverySession::discoverySessionWithDeviceTypes_mediaType_position(
+            &types,
+            AVMediaTypeVideo,
+            AVCaptureDevicePosition::Unspecified,
+        )
+        .devices()
+        .to_vec()
+    }
+}
+
+fn find_device(device: &str) -> Result<Retained<AVCaptureDevice>, CameraError> {
+    let devices = video_devices();
+    let found = match device.parse::<usize>() {
+        Ok(index) => devices.into_iter().nth(index),
+        Err(_) => devices.into_iter().find(|candidate| unsafe {
+            candidate.uniqueID().to_string() == device
+                || candidate
+                    .localizedName()
+                    .to_string()
+                    .eq_ignore_ascii_case(device)
+        }),
+    };
+    found.ok_or_else(|| CameraError::DeviceNotFound(format!("No camera {device}")))
+}
+
+/// Switches the locked `device` to a format of the requested size that runs
+/// at the requested rate, if it has one.
+unsafe fn select_format(device: &AVCaptureDevice, request: &Request) {
+    let fps = f64::from(request.frame_rate);
+    for format in unsafe { device.formats() }.iter() {
+        let dimensions =
+            unsafe { CMVideoFormatDescriptionGetDimensions(&format.formatDescription()) };
+        if dimensions.width as u32 != request.width || dimensions.height as u32 != request.height {
+            continue;
+        }
+        let ranges = unsafe { format.videoSupportedFrameRateRanges() };
+        let Some(range) = ranges
+            .iter()
+            .find(|range| unsafe { range.minFrameRate() <= fps && fps <= range.maxFrameRate() })
+        else {
+            continue;
+        };
+        unsafe {
+            device.setActiveFormat(&format);
+            // Durations outside the range raise an exception, so the
+            // range's own limit is used where the rate is at it.
+            let duration = if (range.maxFrameRate() - fps).abs() < 0.01 {
+                range.minFrameDuration()
+            } else {
+                CMTime::new(1000, (fps * 1000.0).round() as i32)
+            };
+            device.setActiveVideoMinFrameDuration(duration);
+        }
+        return;
+    }
+    tracing::debug!(
+        width = request.width,
+        height = request.height,
+        fps,
+        "No camera format of that size and rate; scaling frames instead"
+    );
+}
+
+/// Core Video dictionary keys are toll-free bridged to `NSString`.
+fn cf_key(key: &'static CFString) -> &'static NSString {
+    unsafe { &*(key as *const CFString).cast::<NSString>() }
+}
+
+fn camera_error(action: &str, err: &NSError) -> CameraError {
+    let message = format!("{action}: {}", err.localizedDescription());
+    match AVError(err.code()) {
+        AVError::ApplicationIsNotAuthorizedToUseDevice => CameraError::Device(format!(
+            "{message}; allow camera access under Privacy & Security > Camera in System Settings"
+        )),
+        AVError::DeviceInUseByAnotherApplication | AVError::DeviceAlreadyUsedByAnotherSession => {
+            CameraError::Busy(message)
+        }
+        AVError::DeviceWasDisconnected | AVError::DeviceNotConnected => {
+            CameraError::Disconnected(message)
+        }
+        _ => CameraError::Device(message),
+    }
+}
+
+struct DelegateIvars {
+    frames: mpsc::Sender<Result<Frame, CameraError>>,
+    encoder: Mutex<Encoder>,
+}
+
+define_class!(
+    // SAFETY: NSObject has no subclassing requirements, and FrameDelegate
+    // does not implement Drop.
+    #[unsafe(super(NSObject))]
+    #[name = "PiCamFrameDelegate"]
+    #[ivars = DelegateIvars]
+    struct FrameDelegate;
+
+    unsafe impl NSObjectProtocol for FrameDelegate {}
+
+    unsafe impl AVCaptureVideoDataOutputSampleBufferDelegate for FrameDelegate {
+        #[unsafe(method(captureOutput:didOutputSampleBuffer:fromConnection:))]
+        fn did_output(
+            &self,
+            _output: &AVCaptureOutput,
+            sample: &CMSampleBuffer,
+            _connection: &AVCaptureConnection,
+        ) {
+            let timestamp = SystemTime::now();
+            let frame = self
+                .ivars()
+                .encoder
+                .lock()
+                .expect("frame encoder poisoned")
+                .encode(sample, timestamp);
+            // Fails once the camera is dropped.
+            let _ = self.ivars().frames.blocking_send(frame);
+        }
+    }
+);
+
+impl FrameDelegate {
+    fn new(
+        jpeg_quality: Arc<AtomicU8>,
+        frames: mpsc::Sender<Result<Frame, CameraError>>,
+    ) -> Retained<Self> {
+        let this = Self::alloc().set_ivars(DelegateIvars {
+            frames,
+            encoder: Mutex::new(Encoder {
+                jpeg_quality,
+                buffers: BufferPool::new(CaptureThread::MAX_POOLED_BUFFERS),
+                scratch: Vec::new(),
+                packed: Vec::new(),
+                sequence: 0,
+            }),
+        });
+        unsafe { msg_send![super(this), init] }
+    }
+}
+
+struct Encoder {
+    jpeg_quality: Arc<AtomicU8>,
+    buffers: BufferPool,
+    /// Converted pixels of the last frame, reused for the next one.
+    scratch: Vec<u8>,
+    /// UYVY rows without their padding, when they have any.
+    packed: Vec<u8>,
+    /// Frames delivered so far, broken ones included.
+    sequence: u64,
+}
+
+impl Encoder {
+    fn encode(
+        &mut self,
+        sample: &CMSampleBuffer,
+        timestamp: SystemTime,
+    ) -> Result<Frame, CameraError> {
+        self.sequence += 1;
+        let pixels = unsafe { sample.image_buffer() }.ok_or_else(|| {
+            CameraError::Encode(anyhow!("Camera delivered a frame without pixels"))
+        })?;
+        let width = CVPixelBufferGetWidth(&pixels);
+        let height = CVPixelBufferGetHeight(&pixels);
+        let stride = CVPixelBufferGetBytesPerRow(&pixels);
+        if unsafe { CVPixelBufferLockBaseAddress(&pixels, CVPixelBufferLockFlags::ReadOnly) }
+            != kCVReturnSuccess
+        {
+            return Err(CameraError::Encode(anyhow!(
+                "Failed to read frame from camera"
+            )));
+        }
+        let base = CVPixelBufferGetBaseAddress(&pixels);
+        let mut output = self.buffers.take();
+        let result = if base.is_null() {
+            Err(anyhow!("Camera delivered an empty frame"))
+        } else {
+            let data = unsafe { slice::from_raw_parts(base.cast::<u8>(), stride * height) };
+            self.encode_uyvy(data, width, height, stride, &mut output)
+        };
+        unsafe { CVPixelBufferUnlockBaseAddress(&pixels, CVPixelBufferLockFlags::ReadOnly) };
+
+        match result {
+            Ok(()) => Ok(Frame {
+                data: self.buffers.freeze(output),
+                timestamp,
+                sequence: self.sequence,
+                pixel_format: "UYVY",
+                width: width as u32,
+                height: height as u32,
+            }),
+            Err(err) => {
+                self.buffers.give_back(output);
+                Err(CameraError::Encode(err))
+            }
+        }
+    }
+
+    fn encode_uyvy(
+        &mut self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        output: &mut Vec<u8>,
+    ) -> Result<()> {
+        let row_len = width * 2;
+        let frame = if stride == row_len {
+            data
+        } else {
+            self.packed.clear();
+            for row in data.chunks(stride).take(height) {
+                self.packed.extend_from_slice(
+                    row.get(..row_len)
+                        .ok_or_else(|| anyhow!("UYVY frame is truncated"))?,
+                );
+            }
+            &self.packed
+        };
+        jpeg::encode_raw(
+            RawFormat::Uyvy,
+            frame,
+            width as u32,
+            height as u32,
+            self.jpeg_quality.load(Ordering::Relaxed),
+            &mut self.scratch,
+            output,
+        )
+    }
+}
+
+/// Cameras AVFoundation knows of, by index, with the formats they offer.
+pub fn list_devices() -> Vec<DeviceInfo> {
+    video_devices()
+        .iter()
+        .enumerate()
+        .map(|(index, device)| DeviceInfo {
+            path: index.to_string(),
+            name: Some(unsafe { device.localizedName() }.to_string()),
+            driver: None,
+            formats: describe_formats(&unsafe { device.formats() }.to_vec()),
+        })
+        .collect()
+}
+
+fn describe_formats(device_formats: &[Retained<AVCaptureDeviceFormat>]) -> Vec<FormatInfo> {
+    let mut formats: Vec<FormatInfo> = Vec::new();
+    for device_format in device_formats {
+        let description = unsafe { device_format.formatDescription() };
+        let fourcc = fourcc(unsafe { description.media_sub_type() });
+        let dimensions = unsafe { CMVideoFormatDescriptionGetDimensions(&description) };
+        let (width, height) = (dimensions.width as u32, dimensions.height as u32);
+        let ranges: Vec<(f32, f32)> = unsafe { device_format.videoSupportedFrameRateRanges() }
+            .iter()
+            .map(|range| unsafe { (range.minFrameRate() as f32, range.maxFrameRate() as f32) })
+            .collect();
+
+        let index = match formats.iter().position(|format| format.fourcc == fourcc) {
+            Some(index) => index,
+            None => {
+                formats.push(FormatInfo {
+                    compressed: matches!(fourcc.as_str(), "MJPG" | "H264" | "HEVC"),
+                    description: fourcc.clone(),
+                    fourcc: fourcc.clone(),
+                    resolutions: Vec::new(),
+                    size_range: None,
+                });
+                formats.len() - 1
+            }
+        };
+        let resolutions = &mut formats[index].resolutions;
+        let position = resolutions
+            .iter()
+            .position(|resolution| resolution.width == width && resolution.height == height);
+        let resolution = match position {
+            Some(position) => &mut resolutions[position],
+            None => {
+                resolutions.push(Resolution {
+                    width,
+                    height,
+                    frame_rates: FrameRates::Discrete { values: Vec::new() },
+                });
+                resolutions.last_mut().expect("just pushed")
+            }
+        };
+        for (min, max) in ranges {
+            resolution.frame_rates = match &mut resolution.frame_rates {
+                FrameRates::Discrete { values } if min == max => {
+                    if !values.contains(&max) {
+                        values.push(max);
+                    }
+                    continue;
+                }
+                FrameRates::Discrete { values } => FrameRates::Range {
+                    min: values.iter().copied().fold(min, f32::min),
+                    max: values.iter().copied().fold(max, f32::max),
+                },
+                FrameRates::Range {
+                    min: range_min,
+                    max: range_max,
+                } => FrameRates::Range {
+                    min: range_min.min(min),
+                    max: range_max.max(max),
+                },
+                FrameRates::Unknown => FrameRates::Unknown,
+            };
+        }
+    }
+    formats
+}
+
+/// Names Core Video pixel formats the way V4L2 does where they match.
+fn fourcc(code: u32) -> String {
+    match &code.to_be_bytes() {
+        b"2vuy" => "UYVY".to_string(),
+        b"yuvs" => "YUYV".to_string(),
+        b"dmb1" => "MJPG".to_string(),
+        b"420v" | b"420f" => "NV12".to_string(),
+        bytes => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+#[async_trait]
+impl Camera for AvFoundationCamera {
+    async fn capture_frame(&self) -> Result<Frame, CameraError> {
+        time::timeout(FRAME_TIMEOUT, self.thread.next_frame())
+            .await
+            .unwrap_or(Err(CameraError::Timeout))
+    }
+
+    async fn list_controls(&self) -> Result<Vec<ControlInfo>> {
+        Ok(Vec::new())
+    }
+
+    async fn set_control(&self, change: ControlChange) -> Result<()> {
+        Err(anyhow!("Unknown control id {}", change.id))
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "avfoundation"
+    }
+
+    fn format(&self) -> Option<CaptureFormat> {
+        Some(CaptureFormat {
+            fourcc: "UYVY",
+            width: self.width,
+            height: self.height,
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            jpeg_encoding: true,
+            formats: self.formats.clone(),
+            ..Capabilities::default()
+        }
+    }
+
+    fn set_jpeg_quality(&self, quality: u8) {
+        self.jpeg_quality.store(quality, Ordering::Relaxed);
+    }
+}
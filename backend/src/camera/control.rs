@@ -0,0 +1,133 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A device control such as brightness or exposure, as reported by a camera.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ControlInfo {
+    pub id: u32,
+    pub name: String,
+    pub read_only: bool,
+    /// Emulated by the backend because the device lacks it.
+    pub software: bool,
+    #[serde(flatten)]
+    pub value: ControlValue,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+// Only V4L2 cameras have menu controls so far.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub enum ControlValue {
+    Integer {
+        value: i64,
+        default: i64,
+        minimum: i64,
+        maximum: i64,
+        step: i64,
+    },
+    Boolean {
+        value: bool,
+        default: bool,
+    },
+    Menu {
+        value: u32,
+        default: u32,
+        items: Vec<MenuItem>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct MenuItem {
+    pub index: u32,
+    pub name: String,
+}
+
+/// Request to change a single control. Booleans take `0`/`1`, menus the item index.
+#[derive(Clone, Copy, Debug, Deserialize, ToSchema)]
+pub struct ControlChange {
+    pub id: u32,
+    pub value: i64,
+}
+
+impl ControlInfo {
+    pub fn integer(id: u32, name: &str, default: i64, minimum: i64, maximum: i64) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            read_only: false,
+            software: false,
+            value: ControlValue::Integer {
+                value: default,
+                default,
+                minimum,
+                maximum,
+                step: 1,
+            },
+        }
+    }
+
+    pub fn boolean(id: u32, name: &str, default: bool) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            read_only: false,
+            software: false,
+            value: ControlValue::Boolean {
+                value: default,
+                default,
+            },
+        }
+    }
+
+    /// Validates `value` against the control's type and range, then stores it.
+    pub fn set(&mut self, value: i64) -> Result<()> {
+        if self.read_only {
+            bail!("Control {} is read-only", self.name);
+        }
+
+        match &mut self.value {
+            ControlValue::Integer {
+                value: current,
+                minimum,
+                maximum,
+                step,
+                ..
+            } => {
+                if !(*minimum..=*maximum).contains(&value) {
+                    bail!(
+                        "Value {value} for {} outside range {minimum}..={maximum}",
+                        self.name
+                    );
+                }
+                if *step > 1 && (value - *minimum) % *step != 0 {
+                    bail!(
+                        "Value {value} for {} is not a multiple of step {step}",
+                        self.name
+                    );
+                }
+                *current = value;
+            }
+            ControlValue::Boolean { value: current, .. } => {
+                *current = match value {
+                    0 => false,
+                    1 => true,
+                    _ => bail!("Value {value} for {} must be 0 or 1", self.name),
+                };
+            }
+            ControlValue::Menu {
+                value: current,
+                items,
+                ..
+            } => {
+                let index = u32::try_from(value)
+                    .ok()
+                    .filter(|index| items.iter().any(|item| item.index == *index))
+                    .ok_or_else(|| anyhow!("Value {value} is not a menu item of {}", self.name))?;
+                *current = index;
+            }
+        }
+
+        Ok(())
+    }
+}
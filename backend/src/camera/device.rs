@@ -0,0 +1,91 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A capture device node and the formats it can stream, as listed by `/devices`.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct DeviceInfo {
+    pub path: String,
+    /// Card name reported by the driver, e.g. `HD Pro Webcam C920`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Kernel driver bound to the device, e.g. `uvcvideo`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    pub formats: Vec<FormatInfo>,
+}
+
+/// The format an open camera delivers frames in, as negotiated with the
+/// device.
+#[derive(Clone, Copy, Debug, Serialize, ToSchema)]
+pub struct CaptureFormat {
+    /// FourCC such as `MJPG` or `YUYV`.
+    pub fourcc: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What an open camera's backend and device support beyond delivering
+/// frames.
+#[derive(Clone, Debug, Default, Serialize, ToSchema)]
+pub struct Capabilities {
+    /// Controls can be listed and changed at `/controls`.
+    pub controls: bool,
+    pub ptz: bool,
+    pub focus: bool,
+    /// The backend compresses frames itself, so `JPEG_QUALITY` applies.
+    pub jpeg_encoding: bool,
+    /// The device delivers frames already compressed to JPEG.
+    pub hardware_encoding: bool,
+    /// Formats, sizes and frame rates the device offers; empty where the
+    /// backend cannot list them.
+    pub formats: Vec<FormatInfo>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct FormatInfo {
+    /// FourCC such as `MJPG` or `YUYV`.
+    pub fourcc: String,
+    pub description: String,
+    pub compressed: bool,
+    /// Fixed sizes the driver offers; empty when it reports `size_range`.
+    pub resolutions: Vec<Resolution>,
+    /// Drivers such as the Pi camera accept any size within a range instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_range: Option<SizeRange>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rates: FrameRates,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SizeRange {
+    pub min_width: u32,
+    pub min_height: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub step_width: u32,
+    pub step_height: u32,
+    /// Frame rates at the largest size.
+    pub frame_rates: FrameRates,
+}
+
+/// Frames per second supported at one size.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+// Only V4L2 drivers report ranges of frame rates.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub enum FrameRates {
+    Discrete {
+        values: Vec<f32>,
+    },
+    Range {
+        min: f32,
+        max: f32,
+    },
+    /// The driver does not report frame intervals.
+    Unknown,
+}
@@ -0,0 +1,88 @@
+use std::{fmt, io};
+
+/// Why a camera could not be opened or deliver a frame.
+#[derive(Debug)]
+pub enum CameraError {
+    /// The device node does not exist, e.g. while a USB camera is unplugged.
+    DeviceNotFound(String),
+    /// Another process has the device open.
+    Busy(String),
+    /// The device accepts none of the configured formats at the configured
+    /// size and frame rate.
+    FormatUnsupported(String),
+    /// The device went away or the backend stopped while it was open.
+    Disconnected(String),
+    /// The device did not deliver a frame in time.
+    Timeout,
+    /// A frame could not be checked, converted or encoded to JPEG.
+    Encode(anyhow::Error),
+    /// The device or driver failed otherwise, e.g. for lack of permission.
+    Device(String),
+    /// The device is kept closed outside `STREAM_SCHEDULE`.
+    OffDuty,
+    /// The device is kept closed until `POST /camera/resume`.
+    Paused,
+}
+
+/// `EBUSY` and `ENODEV`, which `io::ErrorKind` has no stable kinds for.
+const EBUSY: i32 = 16;
+const ENODEV: i32 = 19;
+
+impl CameraError {
+    /// Classifies an error the device node answered with. `action` says what
+    /// was attempted, as in "Failed to open /dev/video0".
+    pub fn from_io(action: &str, err: io::Error) -> Self {
+        let message = format!("{action}: {err}");
+        match (err.kind(), err.raw_os_error()) {
+            (io::ErrorKind::NotFound, _) => Self::DeviceNotFound(message),
+            (_, Some(EBUSY)) => Self::Busy(message),
+            (_, Some(ENODEV)) => Self::Disconnected(message),
+            (io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock, _) => Self::Timeout,
+            _ => Self::Device(message),
+        }
+    }
+
+    /// Whether reopening the device with the same settings cannot help, so
+    /// only a configuration change can.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::FormatUnsupported(_))
+    }
+
+    /// Whether the camera was closed on purpose rather than failing.
+    pub fn is_hold(&self) -> bool {
+        matches!(self, Self::OffDuty | Self::Paused)
+    }
+
+    /// Short kebab-case name, as sent in HTTP error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::DeviceNotFound(_) => "camera-not-found",
+            Self::Busy(_) => "camera-busy",
+            Self::FormatUnsupported(_) => "camera-format-unsupported",
+            Self::Disconnected(_) => "camera-disconnected",
+            Self::Timeout => "camera-timeout",
+            Self::Encode(_) => "encode-failed",
+            Self::Device(_) => "camera-error",
+            Self::OffDuty => "camera-off-duty",
+            Self::Paused => "camera-paused",
+        }
+    }
+}
+
+impl fmt::Display for CameraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeviceNotFound(message)
+            | Self::Busy(message)
+            | Self::FormatUnsupported(message)
+            | Self::Disconnected(message)
+            | Self::Device(message) => f.write_str(message),
+            Self::Timeout => f.write_str("Camera did not deliver a frame in time"),
+            Self::Encode(err) => write!(f, "{err:#}"),
+            Self::OffDuty => f.write_str("Camera is off duty outside its streaming schedule"),
+            Self::Paused => f.write_str("Camera is paused"),
+        }
+    }
+}
+
+impl std::error::Error for CameraError {}
@@ -0,0 +1,255 @@
+//! Replays footage from disk in a loop, for developing and demoing without
+//! a camera: a directory of JPEG images, shown in name order, or a
+//! Motion-JPEG video such as the AVI and MP4 clips the recorder writes.
+//! Videos in other codecs, such as H.264, are not decoded; they have to be
+//! converted to Motion-JPEG first.
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::Mutex,
+};
+
+use super::{Camera, CameraError, CaptureFormat, ControlChange, ControlInfo, Frame};
+use crate::mjpeg::{self, StreamSplitter};
+
+/// Bytes of a video read at a time.
+const READ_CHUNK: usize = 64 * 1024;
+
+enum Source {
+    /// JPEG files, read from disk as they come up.
+    Images(Vec<PathBuf>),
+    /// A Motion-JPEG video, read from disk a chunk at a time as its frames
+    /// come up.
+    Mjpeg {
+        reader: Mutex<MjpegReader>,
+        frames: usize,
+    },
+}
+
+struct MjpegReader {
+    file: tokio::fs::File,
+    splitter: StreamSplitter,
+    chunk: Vec<u8>,
+}
+
+impl MjpegReader {
+    /// The next frame of the video, starting over once the end is reached;
+    /// `None` if a whole pass finds no frame, as when the file was cut
+    /// short since it was opened.
+    async fn next_frame(&mut self) -> io::Result<Option<Bytes>> {
+        let mut rewound = false;
+        loop {
+            if let Some(frame) = self.splitter.next_image() {
+                return Ok(Some(frame));
+            }
+            let read = self.file.read(&mut self.chunk).await?;
+            if read > 0 {
+                self.splitter.push(&self.chunk[..read]);
+                continue;
+            }
+            if rewound {
+                return Ok(None);
+            }
+            // A frame cut short at the end is dropped.
+            self.file.seek(SeekFrom::Start(0)).await?;
+            self.splitter = StreamSplitter::default();
+            rewound = true;
+        }
+    }
+}
+
+pub struct FileCamera {
+    path: PathBuf,
+    source: Source,
+    sequence: AtomicU64,
+    format: Option<CaptureFormat>,
+}
+
+impl FileCamera {
+    /// Whether `device` names a directory or file rather than a device node,
+    /// so [`FileCamera::open`] should handle it.
+    pub fn handles(device: &str) -> bool {
+        fs::metadata(device).is_ok_and(|metadata| metadata.is_dir() || metadata.is_file())
+    }
+
+    pub fn open(path: &str) -> Result<Self, CameraError> {
+        let path = PathBuf::from(path);
+        let metadata = fs::metadata(&path).map_err(|err| {
+            CameraError::from_io(&format!("Failed to open {}", path.display()), err)
+        })?;
+        let (source, first) = if metadata.is_dir() {
+            let paths = list_images(&path)?;
+            let first = fs::read(&paths[0]).ok().map(Bytes::from);
+            (Source::Images(paths), first)
+        } else {
+            let (reader, frames, first) = open_mjpeg(&path)?;
+            let source = Source::Mjpeg {
+                reader: Mutex::new(reader),
+                frames,
+            };
+            (source, Some(first))
+        };
+
+        let format = first
+            .as_deref()
+            .and_then(mjpeg::dimensions)
+            .map(|(width, height)| CaptureFormat {
+                fourcc: "MJPG",
+                width,
+                height,
+            });
+
+        Ok(Self {
+            path,
+            source,
+            sequence: AtomicU64::new(0),
+            format,
+        })
+    }
+
+    /// Frames in one pass over the footage.
+    pub fn frame_count(&self) -> usize {
+        match &self.source {
+            Source::Images(paths) => paths.len(),
+            Source::Mjpeg { frames, .. } => *frames,
+        }
+    }
+}
+
+fn list_images(dir: &Path) -> Result<Vec<PathBuf>, CameraError> {
+    let read_error = |err| CameraError::from_io(&format!("Failed to read {}", dir.display()), err);
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        let is_jpeg = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("jpg") || extension.eq_ignore_ascii_case("jpeg")
+            });
+        if is_jpeg && path.is_file() {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        return Err(CameraError::Device(format!(
+            "{} holds no .jpg or .jpeg images",
+            dir.display()
+        )));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Opens the Motion-JPEG video at `path`, counting its frames in one pass
+/// that keeps only the first of them.
+fn open_mjpeg(path: &Path) -> Result<(MjpegReader, usize, Bytes), CameraError> {
+    let read_error = |err| CameraError::from_io(&format!("Failed to read {}", path.display()), err);
+    let mut file = fs::File::open(path).map_err(read_error)?;
+    let mut splitter = StreamSplitter::default();
+    let mut chunk = vec![0; READ_CHUNK];
+    let mut frames = 0;
+    let mut first = None;
+    loop {
+        let read = file.read(&mut chunk).map_err(read_error)?;
+        if read == 0 {
+            break;
+        }
+        splitter.push(&chunk[..read]);
+        while let Some(frame) = splitter.next_image() {
+            frames += 1;
+            first.get_or_insert(frame);
+        }
+    }
+    let Some(first) = first else {
+        return Err(CameraError::FormatUnsupported(format!(
+            "{} holds no Motion-JPEG frames; only Motion-JPEG videos can be \
+             replayed, so convert it with e.g. `ffmpeg -i {0} -c:v mjpeg -q:v 3 out.avi`",
+            path.display()
+        )));
+    };
+    file.seek(SeekFrom::Start(0)).map_err(read_error)?;
+
+    let reader = MjpegReader {
+        file: tokio::fs::File::from_std(file),
+        splitter: StreamSplitter::default(),
+        chunk,
+    };
+    Ok((reader, frames, first))
+}
+
+#[async_trait]
+impl Camera for FileCamera {
+    async fn capture_frame(&self) -> Result<Frame, CameraError> {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        let index = ((sequence - 1) % self.frame_count() as u64) as usize;
+        let data = match &self.source {
+            Source::Images(paths) => {
+                let path = &paths[index];
+                tokio::fs::read(path)
+                    .await
+                    .map(Bytes::from)
+                    .map_err(|err| {
+                        CameraError::from_io(&format!("Failed to read {}", path.display()), err)
+                    })?
+            }
+            Source::Mjpeg { reader, .. } => reader
+                .lock()
+                .await
+                .next_frame()
+                .await
+                .map_err(|err| {
+                    CameraError::from_io(&format!("Failed to read {}", self.path.display()), err)
+                })?
+                .ok_or_else(|| {
+                    CameraError::Device(format!(
+                        "{} no longer holds Motion-JPEG frames",
+                        self.path.display()
+                    ))
+                })?,
+        };
+        let (width, height) = mjpeg::dimensions(&data).ok_or_else(|| {
+            CameraError::Encode(anyhow!(
+                "Frame {} of {} is not a JPEG image",
+                index + 1,
+                self.path.display()
+            ))
+        })?;
+
+        Ok(Frame {
+            data,
+            timestamp: SystemTime::now(),
+            sequence,
+            pixel_format: "MJPG",
+            width,
+            height,
+        })
+    }
+
+    async fn list_controls(&self) -> Result<Vec<ControlInfo>> {
+        Ok(Vec::new())
+    }
+
+    async fn set_control(&self, change: ControlChange) -> Result<()> {
+        Err(anyhow!("Unknown control id {}", change.id))
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "file"
+    }
+
+    fn format(&self) -> Option<CaptureFormat> {
+        self.format
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::time;
+use utoipa::ToSchema;
+
+use super::{ptz, Camera, ControlChange, ControlInfo, ControlValue, PtzAxis};
+
+pub const CID_FOCUS_ABSOLUTE: u32 = 0x009a_090a;
+pub const CID_FOCUS_AUTO: u32 = 0x009a_090c;
+/// A button, so it never shows up in `/controls`.
+pub const CID_AUTO_FOCUS_START: u32 = 0x009a_091c;
+
+/// Devices without a one-shot trigger get continuous autofocus for this
+/// long instead, then keep the focus it found.
+const ONE_SHOT_SETTLE: Duration = Duration::from_secs(2);
+
+/// Autofocus and manual focus, offered by cameras with a motorised lens.
+#[async_trait]
+pub trait Focus: Send + Sync {
+    async fn state(&self) -> Result<FocusState>;
+
+    /// Applies `request` and returns the focus state it leaves behind.
+    async fn refocus(&self, request: FocusRequest) -> Result<FocusState>;
+}
+
+/// Request to refocus. A one-shot trigger or manual position turns
+/// continuous autofocus off, as it would move the lens straight back.
+#[derive(Clone, Copy, Debug, Deserialize, ToSchema)]
+pub struct FocusRequest {
+    /// Focus once on the current scene and hold there.
+    #[serde(default)]
+    pub trigger: bool,
+    pub continuous: Option<bool>,
+    /// Lens position within the range reported by `GET /focus`.
+    pub position: Option<i64>,
+}
+
+/// What the camera's focus can do and where it stands; features it lacks
+/// are `null`.
+#[derive(Clone, Copy, Debug, Serialize, ToSchema)]
+pub struct FocusState {
+    /// Whether continuous autofocus is on.
+    pub continuous: Option<bool>,
+    pub position: Option<PtzAxis>,
+    /// Whether the device has a one-shot trigger of its own, rather than
+    /// one emulated with continuous autofocus.
+    pub trigger: bool,
+}
+
+impl FocusState {
+    pub fn from_controls(controls: &[ControlInfo], has_trigger: bool) -> Self {
+        let continuous = controls
+            .iter()
+            .filter(|control| control.id == CID_FOCUS_AUTO && !control.read_only)
+            .find_map(|control| match control.value {
+                ControlValue::Boolean { value, .. } => Some(value),
+                _ => None,
+            });
+        Self {
+            continuous,
+            position: ptz::axis(controls, CID_FOCUS_ABSOLUTE),
+            trigger: has_trigger,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn is_empty(&self) -> bool {
+        self.continuous.is_none() && self.position.is_none() && !self.trigger
+    }
+}
+
+/// Focuses a camera whose focus is driven by plain V4L2 controls;
+/// `has_trigger` tells whether it has `V4L2_CID_AUTO_FOCUS_START`.
+pub async fn focus_with_controls(
+    camera: &dyn Camera,
+    has_trigger: bool,
+    request: FocusRequest,
+) -> Result<FocusState> {
+    let manual = request.trigger || request.position.is_some();
+    if !manual && request.continuous.is_none() {
+        bail!("Request names no action; give trigger, continuous or position");
+    }
+    if manual && request.continuous == Some(true) {
+        bail!("trigger and position cannot be combined with continuous autofocus");
+    }
+    if request.trigger && request.position.is_some() {
+        bail!("Give either trigger or position, not both");
+    }
+
+    // Checked up front, so an invalid request does not leave focus changed.
+    let state = FocusState::from_controls(&camera.list_controls().await?, has_trigger);
+    if request.continuous.is_some() && state.continuous.is_none() {
+        bail!("Camera has no continuous autofocus");
+    }
+    if request.trigger && !has_trigger && state.continuous.is_none() {
+        bail!("Camera has no autofocus");
+    }
+    if let Some(position) = request.position {
+        let axis = state
+            .position
+            .ok_or_else(|| anyhow!("Camera has no manual focus"))?;
+        if !(axis.minimum..=axis.maximum).contains(&position) {
+            bail!(
+                "Focus position {position} outside range {}..={}",
+                axis.minimum,
+                axis.maximum
+            );
+        }
+    }
+
+    let continuous = if manual {
+        state.continuous.map(|_| false)
+    } else {
+        request.continuous
+    };
+    if let Some(enabled) = continuous {
+        set(camera, CID_FOCUS_AUTO, enabled.into()).await?;
+    }
+    if let Some(position) = request.position {
+        set(camera, CID_FOCUS_ABSOLUTE, position).await?;
+    }
+    if request.trigger {
+        if has_trigger {
+            set(camera, CID_AUTO_FOCUS_START, 1).await?;
+        } else {
+            set(camera, CID_FOCUS_AUTO, 1).await?;
+            time::sleep(ONE_SHOT_SETTLE).await;
+            set(camera, CID_FOCUS_AUTO, 0).await?;
+        }
+    }
+    Ok(FocusState::from_controls(
+        &camera.list_controls().await?,
+        has_trigger,
+    ))
+}
+
+async fn set(camera: &dyn Camera, id: u32, value: i64) -> Result<()> {
+    camera.set_control(ControlChange { id, value }).await
+}
@@ -0,0 +1,26 @@
+use std::time::SystemTime;
+
+use bytes::Bytes;
+
+/// A JPEG frame as a camera backend delivers it.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub data: Bytes,
+    /// When the backend took the frame from the device.
+    pub timestamp: SystemTime,
+    /// Counts the frames the device delivered since it was opened, from 1.
+    /// Frames the backend discarded, such as broken MJPEG, leave a gap.
+    pub sequence: u64,
+    /// FourCC the device delivered the frame in, before any encoding.
+    pub pixel_format: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Frame {
+    /// Keeps the metadata of a frame whose pixels were reworked, such as by
+    /// software white balance.
+    pub fn with_data(self, data: Bytes) -> Self {
+        Self { data, ..self }
+    }
+}
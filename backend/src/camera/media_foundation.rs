@@ -0,0 +1,637 @@
+//! Webcams on Windows, read through a Media Foundation source reader on a
+//! capture thread of its own, like V4L2 devices on Linux.
+//!
+//! Cameras are named by their index in `/devices` or by their friendly
+//! name. MJPG is passed through when the camera offers it at the
+//! configured size; otherwise the source reader converts to YUY2, which is
+//! encoded like a V4L2 YUYV frame.
+
+use std::{
+    ffi::c_void,
+    ptr, slice,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use windows::{
+    core::{Error as WindowsError, GUID, PWSTR},
+    Win32::{
+        Foundation::E_ACCESSDENIED,
+        Media::MediaFoundation::{
+            IMFActivate, IMFAttributes, IMFMediaSource, IMFMediaType, IMFSample, IMFSourceReader,
+            MFCreateAttributes, MFCreateMediaType, MFCreateSourceReaderFromMediaSource,
+            MFEnumDeviceSources, MFMediaType_Video, MFShutdown, MFStartup, MFVideoFormat_YUY2,
+            MFSTARTUP_NOSOCKET, MF_DEVSOURCE_ATTRIBUTE_FRIENDLY_NAME,
+            MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE, MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID,
+            MF_E_HW_MFT_FAILED_START_STREAMING, MF_E_INVALIDMEDIATYPE,
+            MF_E_VIDEO_RECORDING_DEVICE_INVALIDATED, MF_E_VIDEO_RECORDING_DEVICE_PREEMPTED,
+            MF_MT_DEFAULT_STRIDE, MF_MT_FRAME_RATE, MF_MT_FRAME_SIZE, MF_MT_MAJOR_TYPE,
+            MF_MT_SUBTYPE, MF_SOURCE_READERF_ENDOFSTREAM, MF_SOURCE_READERF_ERROR,
+            MF_SOURCE_READER_ENABLE_ADVANCED_VIDEO_PROCESSING, MF_SOURCE_READER_FIRST_VIDEO_STREAM,
+            MF_VERSION,
+        },
+        System::Com::{CoInitializeEx, CoTaskMemFree, CoUninitialize, COINIT_MULTITHREADED},
+    },
+};
+
+use super::{
+    Camera, CameraError, Capabilities, CaptureContext, CaptureFormat, CaptureThread, ControlChange,
+    ControlInfo, DeviceInfo, FormatInfo, Frame, FrameRates, Resolution,
+};
+use crate::{
+    buffer_pool::BufferPool,
+    jpeg::{self, RawFormat},
+    mjpeg,
+};
+
+const FIRST_VIDEO_STREAM: u32 = MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32;
+
+pub struct MediaFoundationCamera {
+    width: u32,
+    height: u32,
+    pixel_format: &'static str,
+    jpeg_quality: Arc<AtomicU8>,
+    thread: CaptureThread,
+    /// Formats the camera offers, listed when it was opened.
+    formats: Vec<FormatInfo>,
+}
+
+/// What the capture thread was asked to open.
+struct Request {
+    device: String,
+    width: u32,
+    height: u32,
+    frame_rate: f32,
+}
+
+impl MediaFoundationCamera {
+    /// Opens `device`, an index or friendly name, at the requested size and
+    /// rate. YUY2 frames are encoded at `jpeg_quality`.
+    pub fn new(
+        device: &str,
+        width: u32,
+        height: u32,
+        frame_rate: f32,
+        jpeg_quality: u8,
+    ) -> Result<Self, CameraError> {
+        let request = Request {
+            device: device.to_string(),
+            width,
+            height,
+            frame_rate,
+        };
+        let jpeg_quality = Arc::new(AtomicU8::new(jpeg_quality));
+        let quality = jpeg_quality.clone();
+        let (thread, (format, formats)) = CaptureThread::spawn(device, move |context| {
+            run_capture_thread(&request, quality, context);
+        })?;
+        Ok(Self {
+            width: format.width,
+            height: format.height,
+            pixel_format: format.fourcc,
+            jpeg_quality,
+            thread,
+            formats,
+        })
+    }
+}
+
+/// COM and Media Foundation, started for the current thread until dropped.
+/// COM objects are created and used on that thread only.
+struct MediaFoundation;
+
+impl MediaFoundation {
+    fn start() -> windows::core::Result<Self> {
+        unsafe {
+            CoInitializeEx(None, COINIT_MULTITHREADED).ok()?;
+            if let Err(err) = MFStartup(MF_VERSION, MFSTARTUP_NOSOCKET) {
+                CoUninitialize();
+                return Err(err);
+            }
+        }
+        Ok(Self)
+    }
+}
+
+impl Drop for MediaFoundation {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = MFShutdown();
+            CoUninitialize();
+        }
+    }
+}
+
+fn run_capture_thread(
+    request: &Request,
+    jpeg_quality: Arc<AtomicU8>,
+    context: CaptureContext<(CaptureFormat, Vec<FormatInfo>)>,
+) {
+    let _media_foundation = match MediaFoundation::start() {
+        Ok(media_foundation) => media_foundation,
+        Err(err) => {
+            context.opened(Err(camera_error("Failed to start Media Foundation", err)));
+            return;
+        }
+    };
+    match Capture::open(request, jpeg_quality) {
+        Ok((mut capture, formats)) => {
+            context.opened(Ok((capture.format(), formats)));
+            context.run(|| capture.next_frame());
+        }
+        Err(err) => context.opened(Err(err)),
+    }
+}
+
+/// The capture thread's state.
+struct Capture {
+    reader: IMFSourceReader,
+    width: u32,
+    height: u32,
+    pixel_format: &'static str,
+    /// Bytes per row of YUY2 frames, which may be padded.
+    stride: usize,
+    jpeg_quality: Arc<AtomicU8>,
+    buffers: BufferPool,
+    /// Converted pixels of the last frame, reused for the next one.
+    scratch: Vec<u8>,
+    /// YUY2 rows without their padding, when they have any.
+    packed: Vec<u8>,
+    /// Frames read so far, broken ones included.
+    sequence: u64,
+}
+
+impl Capture {
+    /// Opens the camera and lists the formats it offers.
+    fn open(
+        request: &Request,
+        jpeg_quality: Arc<AtomicU8>,
+    ) -> Result<(Self, Vec<FormatInfo>), CameraError> {
+        let device = &request.device;
+        let activate = find_device(device)?;
+        let reader = unsafe { open_reader(&activate) }
+            .map_err(|err| camera_error(&format!("Failed to open camera {device}"), err))?;
+
+        let native = unsafe { native_types(&reader) }
+            .map_err(|err| camera_error(&format!("Failed to list formats of {device}"), err))?;
+        let mjpeg = native
+            .iter()
+            .filter(|native| {
+                native.fourcc == "MJPG"
+                    && native.width == request.width
+                    && native.height == request.height
+            })
+            .min_by(|a, b| {
+                let from_requested = |native: &NativeType| (native.fps - request.frame_rate).abs();
+                from_requested(a).total_cmp(&from_requested(b))
+            });
+        let pixel_format = match mjpeg {
+            Some(native) => {
+                unsafe { reader.SetCurrentMediaType(FIRST_VIDEO_STREAM, None, &native.media_type) }
+                    .map_err(|err| camera_error(&format!("Failed to configure {device}"), err))?;
+                "MJPG"
+            }
+            None => {
+                unsafe { set_yuy2(&reader, request) }.map_err(|err| {
+                    camera_error(
+                        &format!(
+                            "Failed to configure {device} for YUY2 at {}x{}",
+                            request.width, request.height
+                        ),
+                        err,
+                    )
+                })?;
+                "YUYV"
+            }
+        };
+
+        // The reader may have settled on another size than requested.
+        let (width, height, stride) = unsafe {
+            let current = reader
+                .GetCurrentMediaType(FIRST_VIDEO_STREAM)
+                .map_err(|err| camera_error(&format!("Failed to configure {device}"), err))?;
+            let (width, height) = current
+                .GetUINT64(&MF_MT_FRAME_SIZE)
+                .map(split_u64)
+                .unwrap_or((request.width, request.height));
+            let stride = current
+                .GetUINT32(&MF_MT_DEFAULT_STRIDE)
+                .map(|stride| (stride as i32).unsigned_abs() as usize)
+                .unwrap_or(width as usize * 2);
+            (width, height, stride)
+        };
+        tracing::info!(
+            device,
+            width,
+            height,
+            format = pixel_format,
+            "Opened Media Foundation camera"
+        );
+
+        let capture = Self {
+            reader,
+            width,
+            height,
+            pixel_format,
+            stride,
+            jpeg_quality,
+            buffers: BufferPool::new(CaptureThread::MAX_POOLED_BUFFERS),
+            scratch: Vec::new(),
+            packed: Vec::new(),
+            sequence: 0,
+        };
+        Ok((capture, group_formats(&native)))
+    }
+
+    fn format(&self) -> CaptureFormat {
+        CaptureFormat {
+            fourcc: self.pixel_format,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<Frame, CameraError> {
+        let mut broken = 0;
+        loop {
+            let (sample, timestamp) = self.read_sample()?;
+            let mut output = self.buffers.take();
+            let result =
+                unsafe { with_sample_data(&sample, |data| self.convert(data, &mut output)) }
+                    .map_err(|err| camera_error("Failed to read frame from camera", err))?;
+            match result {
+                Ok(()) => {
+                    return Ok(Frame {
+                        data: self.buffers.freeze(output),
+                        timestamp,
+                        sequence: self.sequence,
+                        pixel_format: self.pixel_format,
+                        width: self.width,
+                        height: self.height,
+                    })
+                }
+                Err(err) => {
+                    self.buffers.give_back(output);
+                    broken += 1;
+                    if self.pixel_format != "MJPG" {
+                        return Err(CameraError::Encode(err));
+                    }
+                    if broken == CaptureThread::MAX_BROKEN_FRAMES {
+                        return Err(CameraError::Encode(
+                            err.context("Camera keeps delivering broken MJPEG frames"),
+                        ));
+                    }
+                    tracing::debug!(error = %err, "Dropping broken MJPEG frame");
+                }
+            }
+        }
+    }
+
+    /// Waits for the next frame and notes when it arrived.
+    fn read_sample(&mut self) -> Result<(IMFSample, SystemTime), CameraError> {
+        loop {
+            let mut flags = 0u32;
+            let mut sample = None;
+            unsafe {
+                self.reader.ReadSample(
+                    FIRST_VIDEO_STREAM,
+                    0,
+                    None,
+                    Some(&mut flags),
+                    None,
+                    Some(&mut sample),
+                )
+            }
+            .map_err(|err| camera_error("Failed to capture frame from camera", err))?;
+            let stopped = (MF_SOURCE_READERF_ERROR.0 | MF_SOURCE_READERF_ENDOFSTREAM.0) as u32;
+            if flags & stopped != 0 {
+                return Err(CameraError::Disconnected(
+                    "Camera stopped delivering frames".to_string(),
+                ));
+            }
+            // Stream ticks, sent for gaps in the stream, carry no sample.
+            if let Some(sample) = sample {
+                self.sequence += 1;
+                return Ok((sample, SystemTime::now()));
+            }
+        }
+    }
+
+    fn convert(&mut self, data: &[u8], output: &mut Vec<u8>) -> Result<()> {
+        if self.pixel_format == "MJPG" {
+            return mjpeg::repair(data, output);
+        }
+        let row_len = self.width as usize * 2;
+        let frame = if self.stride == row_len {
+            data
+        } else {
+            self.packed.clear();
+            for row in data.chunks(self.stride).take(self.height as usize) {
+                self.packed.extend_from_slice(
+                    row.get(..row_len)
+                        .ok_or_else(|| anyhow!("YUY2 frame is truncated"))?,
+                );
+            }
+            &self.packed
+        };
+        jpeg::encode_raw(
+            RawFormat::Yuyv,
+            frame,
+            self.width,
+            self.height,
+            self.jpeg_quality.load(Ordering::Relaxed),
+            &mut self.scratch,
+            output,
+        )
+    }
+}
+
+/// A format the camera offers by itself.
+struct NativeType {
+    media_type: IMFMediaType,
+    fourcc: String,
+    width: u32,
+    height: u32,
+    fps: f32,
+}
+
+fn find_device(device: &str) -> Result<IMFActivate, CameraError> {
+    let devices =
+        unsafe { video_devices() }.map_err(|err| camera_error("Failed to list cameras", err))?;
+    let found = match device.parse::<usize>() {
+        Ok(index) => devices.into_iter().nth(index),
+        Err(_) => devices.into_iter().find(|activate| {
+            unsafe { friendly_name(activate) }.is_some_and(|name| name.eq_ignore_ascii_case(device))
+        }),
+    };
+    found.ok_or_else(|| CameraError::DeviceNotFound(format!("No camera {device}")))
+}
+
+unsafe fn video_devices() -> windows::core::Result<Vec<IMFActivate>> {
+    unsafe {
+        let attributes = create_attributes()?;
+        attributes.SetGUID(
+            &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE,
+            &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID,
+        )?;
+        let mut activates: *mut Option<IMFActivate> = ptr::null_mut();
+        let mut count = 0;
+        MFEnumDeviceSources(&attributes, &mut activates, &mut count)?;
+        let devices = (0..count as usize)
+            .filter_map(|index| (*activates.add(index)).take())
+            .collect();
+        CoTaskMemFree(Some(activates as *const c_void));
+        Ok(devices)
+    }
+}
+
+unsafe fn friendly_name(activate: &IMFActivate) -> Option<String> {
+    unsafe {
+        let mut name = PWSTR::null();
+        let mut len = 0;
+        activate
+            .GetAllocatedString(&MF_DEVSOURCE_ATTRIBUTE_FRIENDLY_NAME, &mut name, &mut len)
+            .ok()?;
+        let friendly = name.to_string().ok();
+        CoTaskMemFree(Some(name.0 as *const c_void));
+        friendly
+    }
+}
+
+unsafe fn create_attributes() -> windows::core::Result<IMFAttributes> {
+    unsafe {
+        let mut attributes = None;
+        MFCreateAttributes(&mut attributes, 1)?;
+        Ok(attributes.expect("MFCreateAttributes returns attributes on success"))
+    }
+}
+
+unsafe fn open_reader(activate: &IMFActivate) -> windows::core::Result<IMFSourceReader> {
+    unsafe {
+        let source: IMFMediaSource = activate.ActivateObject()?;
+        // Lets the reader convert and scale to the requested YUY2 size.
+        let attributes = create_attributes()?;
+        attributes.SetUINT32(&MF_SOURCE_READER_ENABLE_ADVANCED_VIDEO_PROCESSING, 1)?;
+        MFCreateSourceReaderFromMediaSource(&source, &attributes)
+    }
+}
+
+unsafe fn native_types(reader: &IMFSourceReader) -> windows::core::Result<Vec<NativeType>> {
+    unsafe {
+        let mut types = Vec::new();
+        // Listing ends with MF_E_NO_MORE_TYPES.
+        while let Ok(media_type) = reader.GetNativeMediaType(FIRST_VIDEO_STREAM, types.len() as u32)
+        {
+            let subtype = media_type.GetGUID(&MF_MT_SUBTYPE)?;
+            let (width, height) = split_u64(media_type.GetUINT64(&MF_MT_FRAME_SIZE)?);
+            let fps = media_type
+                .GetUINT64(&MF_MT_FRAME_RATE)
+                .map(split_u64)
+                .map_or(0.0, |(numerator, denominator)| {
+                    numerator as f32 / denominator.max(1) as f32
+                });
+            types.push(NativeType {
+                media_type,
+                fourcc: fourcc(&subtype),
+                width,
+                height,
+                fps,
+            });
+        }
+        Ok(types)
+    }
+}
+
+unsafe fn set_yuy2(reader: &IMFSourceReader, request: &Request) -> windows::core::Result<()> {
+    unsafe {
+        let media_type = MFCreateMediaType()?;
+        media_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)?;
+        media_type.SetGUID(&MF_MT_SUBTYPE, &MFVideoFormat_YUY2)?;
+        media_type.SetUINT64(
+            &MF_MT_FRAME_SIZE,
+            (u64::from(request.width) << 32) | u64::from(request.height),
+        )?;
+        let fps = request.frame_rate.max(1.0).round() as u64;
+        media_type.SetUINT64(&MF_MT_FRAME_RATE, (fps << 32) | 1)?;
+        reader.SetCurrentMediaType(FIRST_VIDEO_STREAM, None, &media_type)
+    }
+}
+
+/// Calls `f` with the bytes of `sample`, copied into one buffer if they
+/// are spread over several.
+unsafe fn with_sample_data<R>(
+    sample: &IMFSample,
+    f: impl FnOnce(&[u8]) -> R,
+) -> windows::core::Result<R> {
+    unsafe {
+        let buffer = sample.ConvertToContiguousBuffer()?;
+        let mut data = ptr::null_mut();
+        let mut len = 0;
+        buffer.Lock(&mut data, None, Some(&mut len))?;
+        let result = f(slice::from_raw_parts(data, len as usize));
+        buffer.Unlock()?;
+        Ok(result)
+    }
+}
+
+/// Media Foundation packs sizes and frame rates into the two halves of a
+/// `u64`, e.g. width above height.
+fn split_u64(value: u64) -> (u32, u32) {
+    ((value >> 32) as u32, value as u32)
+}
+
+/// Video subtypes are FourCCs in the first field of a fixed GUID.
+fn fourcc(subtype: &GUID) -> String {
+    let name = String::from_utf8_lossy(&subtype.data1.to_le_bytes()).into_owned();
+    // Same as V4L2, for CAMERA_FORMATS and clients alike.
+    if name == "YUY2" {
+        "YUYV".to_string()
+    } else {
+        name
+    }
+}
+
+fn camera_error(action: &str, err: WindowsError) -> CameraError {
+    let message = format!("{action}: {err}");
+    match err.code() {
+        E_ACCESSDENIED => CameraError::Device(format!(
+            "{message}; check that apps may use the camera in the Windows privacy settings"
+        )),
+        MF_E_VIDEO_RECORDING_DEVICE_PREEMPTED | MF_E_HW_MFT_FAILED_START_STREAMING => {
+            CameraError::Busy(message)
+        }
+        MF_E_VIDEO_RECORDING_DEVICE_INVALIDATED => CameraError::Disconnected(message),
+        MF_E_INVALIDMEDIATYPE => CameraError::FormatUnsupported(message),
+        _ => CameraError::Device(message),
+    }
+}
+
+/// Cameras Media Foundation knows of, by index, with the formats they offer.
+pub fn list_devices() -> Vec<DeviceInfo> {
+    let _media_foundation = match MediaFoundation::start() {
+        Ok(media_foundation) => media_foundation,
+        Err(err) => {
+            tracing::warn!(error = %err, "Failed to start Media Foundation");
+            return Vec::new();
+        }
+    };
+    let devices = match unsafe { video_devices() } {
+        Ok(devices) => devices,
+        Err(err) => {
+            tracing::warn!(error = %err, "Failed to list cameras");
+            return Vec::new();
+        }
+    };
+    devices
+        .iter()
+        .enumerate()
+        .map(|(index, activate)| DeviceInfo {
+            path: index.to_string(),
+            name: unsafe { friendly_name(activate) },
+            driver: None,
+            formats: describe_formats(activate),
+        })
+        .collect()
+}
+
+fn describe_formats(activate: &IMFActivate) -> Vec<FormatInfo> {
+    let native = match unsafe { open_reader(activate).and_then(|reader| native_types(&reader)) } {
+        Ok(native) => native,
+        Err(err) => {
+            tracing::debug!(error = %err, "Skipping formats of camera that cannot be opened");
+            return Vec::new();
+        }
+    };
+    group_formats(&native)
+}
+
+/// Gathers native media types into formats, each with its sizes and rates.
+fn group_formats(native: &[NativeType]) -> Vec<FormatInfo> {
+    let mut formats: Vec<FormatInfo> = Vec::new();
+    for native in native {
+        let index = match formats
+            .iter()
+            .position(|format| format.fourcc == native.fourcc)
+        {
+            Some(index) => index,
+            None => {
+                formats.push(FormatInfo {
+                    compressed: matches!(native.fourcc.as_str(), "MJPG" | "H264" | "HEVC"),
+                    description: native.fourcc.clone(),
+                    fourcc: native.fourcc.clone(),
+                    resolutions: Vec::new(),
+                    size_range: None,
+                });
+                formats.len() - 1
+            }
+        };
+        let resolutions = &mut formats[index].resolutions;
+        let existing = resolutions.iter_mut().find(|resolution| {
+            resolution.width == native.width && resolution.height == native.height
+        });
+        match existing {
+            Some(Resolution {
+                frame_rates: FrameRates::Discrete { values },
+                ..
+            }) => {
+                if !values.contains(&native.fps) {
+                    values.push(native.fps);
+                }
+            }
+            Some(_) => {}
+            None => resolutions.push(Resolution {
+                width: native.width,
+                height: native.height,
+                frame_rates: FrameRates::Discrete {
+                    values: vec![native.fps],
+                },
+            }),
+        }
+    }
+    formats
+}
+
+#[async_trait]
+impl Camera for MediaFoundationCamera {
+    async fn capture_frame(&self) -> Result<Frame, CameraError> {
+        self.thread.next_frame().await
+    }
+
+    async fn list_controls(&self) -> Result<Vec<ControlInfo>> {
+        Ok(Vec::new())
+    }
+
+    async fn set_control(&self, change: ControlChange) -> Result<()> {
+        Err(anyhow!("Unknown control id {}", change.id))
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "mediafoundation"
+    }
+
+    fn format(&self) -> Option<CaptureFormat> {
+        Some(CaptureFormat {
+            fourcc: self.pixel_format,
+            width: self.width,
+            height: self.height,
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            jpeg_encoding: self.pixel_format != "MJPG",
+            hardware_encoding: self.pixel_format == "MJPG",
+            formats: self.formats.clone(),
+            ..Capabilities::default()
+        }
+    }
+
+    fn set_jpeg_quality(&self, quality: u8) {
+        self.jpeg_quality.store(quality, Ordering::Relaxed);
+    }
+}
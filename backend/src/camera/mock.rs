@@ -1,35 +1,84 @@
 use std::{
     io::Cursor,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use image::{codecs::jpeg::JpegEncoder, ColorType, ImageBuffer};
+use bytes::Bytes;
+use image::{codecs::jpeg::JpegEncoder, ColorType, RgbImage};
 use tokio::task;
 
-use super::Camera;
+use super::{
+    focus::{self, CID_FOCUS_ABSOLUTE, CID_FOCUS_AUTO},
+    pattern, Camera, CameraError, Capabilities, CaptureFormat, ControlChange, ControlInfo, Focus,
+    FocusRequest, FocusState, FormatInfo, Frame, FrameRates, Resolution,
+};
+use crate::config::MockPattern;
+
+// Standard V4L2 control ids, so clients can treat the mock like a real device.
+const CID_BRIGHTNESS: u32 = 0x0098_0900;
+const CID_CONTRAST: u32 = 0x0098_0901;
+const CID_AUTO_WHITE_BALANCE: u32 = 0x0098_090c;
+const CID_GAIN: u32 = 0x0098_0913;
+const CID_WHITE_BALANCE_TEMPERATURE: u32 = 0x0098_091a;
+const CID_EXPOSURE_ABSOLUTE: u32 = 0x009a_0902;
 
 #[derive(Debug)]
 pub struct MockCamera {
     counter: Arc<Mutex<u64>>,
+    controls: Mutex<Vec<ControlInfo>>,
     width: u32,
     height: u32,
+    jpeg_quality: AtomicU8,
+    pattern: MockPattern,
 }
 
 impl MockCamera {
-    pub fn new(width: u32, height: u32) -> Self {
+    pub fn new(width: u32, height: u32, jpeg_quality: u8, pattern: MockPattern) -> Self {
         Self {
             counter: Arc::new(Mutex::new(0)),
+            controls: Mutex::new(default_controls()),
             width,
             height,
+            jpeg_quality: AtomicU8::new(jpeg_quality),
+            pattern,
         }
     }
 }
 
+fn default_controls() -> Vec<ControlInfo> {
+    vec![
+        ControlInfo::integer(CID_BRIGHTNESS, "Brightness", 128, 0, 255),
+        ControlInfo::integer(CID_CONTRAST, "Contrast", 128, 0, 255),
+        ControlInfo::integer(CID_GAIN, "Gain", 0, 0, 100),
+        ControlInfo::boolean(CID_AUTO_WHITE_BALANCE, "White Balance, Automatic", true),
+        ControlInfo::integer(
+            CID_WHITE_BALANCE_TEMPERATURE,
+            "White Balance Temperature",
+            4600,
+            2800,
+            6500,
+        ),
+        ControlInfo::integer(
+            CID_EXPOSURE_ABSOLUTE,
+            "Exposure Time, Absolute",
+            156,
+            1,
+            5000,
+        ),
+        ControlInfo::boolean(CID_FOCUS_AUTO, "Focus, Automatic Continuous", true),
+        ControlInfo::integer(CID_FOCUS_ABSOLUTE, "Focus, Absolute", 0, 0, 250),
+    ]
+}
+
 #[async_trait]
 impl Camera for MockCamera {
-    async fn capture_frame(&self) -> Result<Vec<u8>> {
+    async fn capture_frame(&self) -> Result<Frame, CameraError> {
         let counter = {
             let mut guard = self.counter.lock().expect("mock camera counter poisoned");
             *guard += 1;
@@ -37,34 +86,106 @@ impl Camera for MockCamera {
         };
         let width = self.width;
         let height = self.height;
+        let quality = self.jpeg_quality.load(Ordering::Relaxed);
+        let pattern = self.pattern;
+        // Taken before rendering, so the QR pattern shows the frame's own
+        // timestamp.
+        let timestamp = SystemTime::now();
 
-        let jpeg = task::spawn_blocking(move || generate_frame(width, height, counter))
-            .await
-            .expect("spawn blocking failed")?;
-        Ok(jpeg)
+        let jpeg = task::spawn_blocking(move || {
+            let buffer = pattern::render(pattern, width, height, counter, timestamp);
+            encode_frame(&buffer, quality)
+        })
+        .await
+        .expect("spawn blocking failed")
+        .map_err(CameraError::Encode)?;
+        Ok(Frame {
+            data: Bytes::from(jpeg),
+            timestamp,
+            sequence: counter,
+            pixel_format: "MJPG",
+            width,
+            height,
+        })
+    }
+
+    async fn list_controls(&self) -> Result<Vec<ControlInfo>> {
+        let controls = self.controls.lock().expect("mock camera controls poisoned");
+        Ok(controls.clone())
+    }
+
+    async fn set_control(&self, change: ControlChange) -> Result<()> {
+        let mut controls = self.controls.lock().expect("mock camera controls poisoned");
+        let control = controls
+            .iter_mut()
+            .find(|control| control.id == change.id)
+            .ok_or_else(|| anyhow!("Unknown control id {}", change.id))?;
+        control.set(change.value)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "mock"
     }
-}
 
-fn generate_frame(width: u32, height: u32, counter: u64) -> Result<Vec<u8>> {
-    let mut buffer = ImageBuffer::from_fn(width, height, |x, y| {
-        let t = counter as f32;
-        let xf = x as f32 / width.max(1) as f32;
-        let yf = y as f32 / height.max(1) as f32;
-        let r = ((xf * 255.0 + t) % 255.0) as u8;
-        let g = ((yf * 255.0 + t * 0.5) % 255.0) as u8;
-        let b = (((xf + yf) * 127.0 + t * 0.25) % 255.0) as u8;
-        image::Rgb([r, g, b])
-    });
-
-    for x in (0..width).step_by((width / 10).max(1) as usize) {
-        for y in 0..height {
-            buffer.put_pixel(x, y, image::Rgb([255, 255, 255]));
+    fn format(&self) -> Option<CaptureFormat> {
+        Some(CaptureFormat {
+            fourcc: "MJPG",
+            width: self.width,
+            height: self.height,
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            controls: true,
+            ptz: false,
+            focus: true,
+            jpeg_encoding: true,
+            hardware_encoding: false,
+            // Frames are rendered at whatever size is configured, as fast as
+            // the capture loop asks for them.
+            formats: vec![FormatInfo {
+                fourcc: "MJPG".to_string(),
+                description: "Test pattern".to_string(),
+                compressed: true,
+                resolutions: vec![Resolution {
+                    width: self.width,
+                    height: self.height,
+                    frame_rates: FrameRates::Unknown,
+                }],
+                size_range: None,
+            }],
         }
     }
 
+    fn set_jpeg_quality(&self, quality: u8) {
+        self.jpeg_quality.store(quality, Ordering::Relaxed);
+    }
+
+    fn focus(&self) -> Option<&dyn Focus> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl Focus for MockCamera {
+    async fn state(&self) -> Result<FocusState> {
+        Ok(FocusState::from_controls(
+            &self.list_controls().await?,
+            false,
+        ))
+    }
+
+    async fn refocus(&self, request: FocusRequest) -> Result<FocusState> {
+        focus::focus_with_controls(self, false, request).await
+    }
+}
+
+fn encode_frame(buffer: &RgbImage, quality: u8) -> Result<Vec<u8>> {
+    let (width, height) = buffer.dimensions();
     let mut cursor = Cursor::new(Vec::new());
-    let mut encoder = JpegEncoder::new_with_quality(&mut cursor, 80);
-    encoder.encode(&buffer, width, height, ColorType::Rgb8)?;
+    let mut encoder = JpegEncoder::new_with_quality(&mut cursor, quality);
+    encoder.encode(buffer, width, height, ColorType::Rgb8)?;
 
     Ok(cursor.into_inner())
 }
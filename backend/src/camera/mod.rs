@@ -1,16 +1,278 @@
+#[cfg(all(target_os = "macos", feature = "av-foundation"))]
+mod av_foundation;
+mod control;
+mod device;
+mod error;
+mod file;
+mod focus;
+mod frame;
 mod mock;
+mod pattern;
+mod ptz;
+mod registry;
+mod upstream;
+mod white_balance;
 
+#[cfg(all(windows, feature = "media-foundation"))]
+mod media_foundation;
 #[cfg(target_os = "linux")]
 mod v4l2;
 
+pub use control::{ControlChange, ControlInfo, ControlValue};
+pub use device::{Capabilities, CaptureFormat, DeviceInfo, FormatInfo, FrameRates, Resolution};
+pub use error::CameraError;
+pub use file::FileCamera;
+pub use focus::{Focus, FocusRequest, FocusState};
+pub use frame::Frame;
 pub use mock::MockCamera;
+pub use ptz::{Ptz, PtzAxis, PtzMove, PtzPosition};
+pub use registry::{backends, mock_camera, open_camera};
+pub use upstream::UpstreamCamera;
+pub use white_balance::{ColorBalance, SoftwareWhiteBalance};
 
+#[cfg(all(target_os = "macos", feature = "av-foundation"))]
+pub use av_foundation::AvFoundationCamera;
+#[cfg(all(windows, feature = "media-foundation"))]
+pub use media_foundation::MediaFoundationCamera;
 #[cfg(target_os = "linux")]
 pub use v4l2::V4l2Camera;
 
+#[cfg(any(
+    target_os = "linux",
+    feature = "media-foundation",
+    feature = "av-foundation"
+))]
+use std::{
+    sync::{
+        mpsc::{self as std_mpsc, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
 use async_trait::async_trait;
+#[cfg(any(
+    target_os = "linux",
+    feature = "media-foundation",
+    feature = "av-foundation"
+))]
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
 
 #[async_trait]
 pub trait Camera: Send + Sync {
-    async fn capture_frame(&self) -> anyhow::Result<Vec<u8>>;
+    async fn capture_frame(&self) -> Result<Frame, CameraError>;
+
+    async fn list_controls(&self) -> anyhow::Result<Vec<ControlInfo>>;
+
+    async fn set_control(&self, change: ControlChange) -> anyhow::Result<()>;
+
+    /// Short identifier of the backend driving this camera, e.g. `"v4l2"`.
+    fn backend_name(&self) -> &'static str;
+
+    /// Pixel format and size the device was opened with, where known.
+    fn format(&self) -> Option<CaptureFormat> {
+        None
+    }
+
+    /// What this camera supports; backends with controls or their own JPEG
+    /// encoding say so.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ptz: self.ptz().is_some(),
+            focus: self.focus().is_some(),
+            ..Capabilities::default()
+        }
+    }
+
+    /// Changes the quality of frames the backend compresses itself; devices
+    /// that deliver JPEG keep theirs.
+    fn set_jpeg_quality(&self, _quality: u8) {}
+
+    /// Pan/tilt/zoom, for backends and devices that support it.
+    fn ptz(&self) -> Option<&dyn Ptz> {
+        None
+    }
+
+    /// Autofocus and manual focus, for backends and devices that support it.
+    fn focus(&self) -> Option<&dyn Focus> {
+        None
+    }
+}
+
+/// Capture devices present on this machine: V4L2 devices on Linux, Media
+/// Foundation cameras on Windows, AVFoundation cameras on macOS, and none
+/// elsewhere.
+pub fn list_devices() -> Vec<DeviceInfo> {
+    #[cfg(target_os = "linux")]
+    return v4l2::list_devices();
+
+    #[cfg(all(windows, feature = "media-foundation"))]
+    return media_foundation::list_devices();
+
+    #[cfg(all(target_os = "macos", feature = "av-foundation"))]
+    return av_foundation::list_devices();
+
+    #[cfg(not(any(
+        target_os = "linux",
+        all(windows, feature = "media-foundation"),
+        all(target_os = "macos", feature = "av-foundation")
+    )))]
+    Vec::new()
+}
+
+/// A native camera's thread of its own, which opens the device and then
+/// captures, checks or encodes its frames and queues them for
+/// `capture_frame`. Native backends run on one so a slow device never
+/// holds up the runtime.
+#[cfg(any(
+    target_os = "linux",
+    feature = "media-foundation",
+    feature = "av-foundation"
+))]
+struct CaptureThread {
+    frames: AsyncMutex<mpsc::Receiver<Result<Frame, CameraError>>>,
+    /// Dropped to tell the thread to stop.
+    stop: Option<std_mpsc::Sender<()>>,
+    /// Disconnects once the thread has exited.
+    stopped: Mutex<std_mpsc::Receiver<()>>,
+}
+
+#[cfg(any(
+    target_os = "linux",
+    feature = "media-foundation",
+    feature = "av-foundation"
+))]
+impl CaptureThread {
+    /// Spare frame buffers kept for reuse.
+    const MAX_POOLED_BUFFERS: usize = 8;
+    /// Broken MJPEG frames in a row that make a capture fail; fewer are
+    /// dropped and the next frame is taken instead.
+    #[cfg_attr(
+        not(any(target_os = "linux", feature = "media-foundation")),
+        allow(dead_code)
+    )]
+    const MAX_BROKEN_FRAMES: u32 = 3;
+    /// Frames the thread may have ready before the capture loop asks for
+    /// them; more would only add latency.
+    const FRAME_QUEUE: usize = 1;
+    /// How long dropping a camera waits for its thread to release the
+    /// device, which it does after the frame it is waiting for.
+    const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Runs `run` on a thread named after `device`, and waits for it to
+    /// report the camera opened, along with what it learnt about it, or why
+    /// the camera could not be opened.
+    fn spawn<T, F>(device: &str, run: F) -> Result<(Self, T), CameraError>
+    where
+        T: Send + 'static,
+        F: FnOnce(CaptureContext<T>) + Send + 'static,
+    {
+        let (opened_tx, opened_rx) = std_mpsc::sync_channel(1);
+        let (frame_tx, frame_rx) = mpsc::channel(Self::FRAME_QUEUE);
+        let (stop_tx, stop_rx) = std_mpsc::channel();
+        let (stopped_tx, stopped_rx) = std_mpsc::channel::<()>();
+        let context = CaptureContext {
+            opened: opened_tx,
+            frames: frame_tx,
+            stop: stop_rx,
+        };
+        thread::Builder::new()
+            .name(format!("capture {device}"))
+            .spawn(move || {
+                run(context);
+                drop(stopped_tx);
+            })
+            .map_err(|err| CameraError::from_io("Failed to start capture thread", err))?;
+
+        let opened = opened_rx.recv().map_err(|_| {
+            CameraError::Device("Capture thread exited while opening the camera".to_string())
+        })??;
+        let thread = Self {
+            frames: AsyncMutex::new(frame_rx),
+            stop: Some(stop_tx),
+            stopped: Mutex::new(stopped_rx),
+        };
+        Ok((thread, opened))
+    }
+
+    /// The next frame the thread queued, or why it failed to capture one.
+    async fn next_frame(&self) -> Result<Frame, CameraError> {
+        self.frames.lock().await.recv().await.unwrap_or_else(|| {
+            Err(CameraError::Disconnected(
+                "Capture thread stopped".to_string(),
+            ))
+        })
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    feature = "media-foundation",
+    feature = "av-foundation"
+))]
+impl Drop for CaptureThread {
+    /// Stops the thread and waits for it to release the device, so the
+    /// device can be opened again right away. A thread waiting on a camera
+    /// that stopped delivering frames is left behind.
+    fn drop(&mut self) {
+        // Unblocks a thread queueing a frame first, or it would never stop.
+        self.frames.get_mut().close();
+        drop(self.stop.take());
+        let stopped = self
+            .stopped
+            .get_mut()
+            .expect("capture thread signal poisoned");
+        if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(Self::STOP_TIMEOUT) {
+            tracing::warn!("Capture thread still waiting for the camera; leaving it behind");
+        }
+    }
+}
+
+/// The capture thread's end of a [`CaptureThread`].
+#[cfg(any(
+    target_os = "linux",
+    feature = "media-foundation",
+    feature = "av-foundation"
+))]
+struct CaptureContext<T> {
+    opened: std_mpsc::SyncSender<Result<T, CameraError>>,
+    frames: mpsc::Sender<Result<Frame, CameraError>>,
+    stop: std_mpsc::Receiver<()>,
+}
+
+#[cfg(any(
+    target_os = "linux",
+    feature = "media-foundation",
+    feature = "av-foundation"
+))]
+impl<T> CaptureContext<T> {
+    /// Hands `CaptureThread::spawn` what it returns.
+    fn opened(&self, opened: Result<T, CameraError>) {
+        let _ = self.opened.send(opened);
+    }
+
+    /// Queues what `next_frame` returns until the camera is dropped. Failed
+    /// captures are queued like frames, so the capture loop counts them as
+    /// usual.
+    #[cfg_attr(
+        not(any(target_os = "linux", feature = "media-foundation")),
+        allow(dead_code)
+    )]
+    fn run(&self, mut next_frame: impl FnMut() -> Result<Frame, CameraError>) {
+        while self.frames.blocking_send(next_frame()).is_ok() {}
+    }
+
+    /// Where frames go when the platform calls back with them rather than
+    /// being asked in a loop.
+    #[cfg_attr(not(feature = "av-foundation"), allow(dead_code))]
+    fn frames(&self) -> mpsc::Sender<Result<Frame, CameraError>> {
+        self.frames.clone()
+    }
+
+    /// Blocks until the camera is dropped.
+    #[cfg_attr(not(feature = "av-foundation"), allow(dead_code))]
+    fn wait_for_stop(&self) {
+        let _ = self.stop.recv();
+    }
 }
@@ -0,0 +1,170 @@
+//! Scenes the mock camera renders, selected by `MOCK_PATTERN`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::{Rgb, RgbImage};
+use qrcodegen::{QrCode, QrCodeEcc};
+
+use crate::config::MockPattern;
+
+/// SMPTE bars at 75% intensity: grey, yellow, cyan, green, magenta, red and
+/// blue, and below them the reverse-order castellations.
+const BARS: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+const CASTELLATIONS: [[u8; 3]; 7] = [
+    [0, 0, 191],
+    [19, 19, 19],
+    [191, 0, 191],
+    [19, 19, 19],
+    [0, 191, 191],
+    [19, 19, 19],
+    [191, 191, 191],
+];
+/// The bottom row, as (colour, width in bars): -I, white, +Q, black, the
+/// PLUGE strips just below, at and just above black, and black again.
+const BOTTOM: [([u8; 3], f32); 8] = [
+    ([0, 33, 76], 1.25),
+    ([255, 255, 255], 1.25),
+    ([50, 0, 106], 1.25),
+    ([19, 19, 19], 1.25),
+    ([9, 9, 9], 1.0 / 3.0),
+    ([19, 19, 19], 1.0 / 3.0),
+    ([29, 29, 29], 1.0 / 3.0),
+    ([19, 19, 19], 1.0),
+];
+/// Pixels the bouncing box moves per frame, across and down.
+const BOX_SPEED: (u64, u64) = (7, 4);
+/// Modules of white kept around the QR code, as the standard asks for.
+const QR_QUIET_ZONE: u32 = 4;
+
+pub fn render(
+    pattern: MockPattern,
+    width: u32,
+    height: u32,
+    sequence: u64,
+    timestamp: SystemTime,
+) -> RgbImage {
+    match pattern {
+        MockPattern::Gradient => gradient(width, height, sequence),
+        MockPattern::Bars => bars(width, height),
+        MockPattern::Noise => noise(width, height, sequence),
+        MockPattern::Box => bouncing_box(width, height, sequence),
+        MockPattern::Qr => qr(width, height, sequence, timestamp),
+    }
+}
+
+fn gradient(width: u32, height: u32, sequence: u64) -> RgbImage {
+    let mut buffer = RgbImage::from_fn(width, height, |x, y| {
+        let t = sequence as f32;
+        let xf = x as f32 / width.max(1) as f32;
+        let yf = y as f32 / height.max(1) as f32;
+        let r = ((xf * 255.0 + t) % 255.0) as u8;
+        let g = ((yf * 255.0 + t * 0.5) % 255.0) as u8;
+        let b = (((xf + yf) * 127.0 + t * 0.25) % 255.0) as u8;
+        Rgb([r, g, b])
+    });
+
+    for x in (0..width).step_by((width / 10).max(1) as usize) {
+        for y in 0..height {
+            buffer.put_pixel(x, y, Rgb([255, 255, 255]));
+        }
+    }
+    buffer
+}
+
+fn bars(width: u32, height: u32) -> RgbImage {
+    let width = width.max(1);
+    let height = height.max(1);
+    RgbImage::from_fn(width, height, |x, y| {
+        // Position in bar widths, 0 to 7.
+        let across = x as f32 * 7.0 / width as f32;
+        let bar = (across as usize).min(6);
+        let down = y as f32 / height as f32;
+        if down < 2.0 / 3.0 {
+            Rgb(BARS[bar])
+        } else if down < 0.75 {
+            Rgb(CASTELLATIONS[bar])
+        } else {
+            let mut start = 0.0;
+            for (colour, bars) in BOTTOM {
+                start += bars;
+                if across < start {
+                    return Rgb(colour);
+                }
+            }
+            Rgb(BOTTOM[BOTTOM.len() - 1].0)
+        }
+    })
+}
+
+fn noise(width: u32, height: u32, sequence: u64) -> RgbImage {
+    // xorshift64, seeded from the frame so every frame differs.
+    let mut state = sequence.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    RgbImage::from_fn(width, height, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        Rgb([(state >> 56) as u8; 3])
+    })
+}
+
+fn bouncing_box(width: u32, height: u32, sequence: u64) -> RgbImage {
+    let size = (width.min(height) / 5).max(1);
+    let left = bounce(sequence * BOX_SPEED.0, width.saturating_sub(size));
+    let top = bounce(sequence * BOX_SPEED.1, height.saturating_sub(size));
+    let mut image = RgbImage::from_pixel(width, height, Rgb([32, 32, 48]));
+    for y in top..(top + size).min(height) {
+        for x in left..(left + size).min(width) {
+            image.put_pixel(x, y, Rgb([240, 200, 40]));
+        }
+    }
+    image
+}
+
+/// Where something moving `distance` pixels along a track `span` pixels
+/// long is, turning back at either end.
+fn bounce(distance: u64, span: u32) -> u32 {
+    let span = u64::from(span);
+    if span == 0 {
+        return 0;
+    }
+    let position = distance % (2 * span);
+    (if position <= span {
+        position
+    } else {
+        2 * span - position
+    }) as u32
+}
+
+/// A QR code of `"{sequence} {milliseconds since the Unix epoch}"`, as large
+/// as fits, on white.
+fn qr(width: u32, height: u32, sequence: u64, timestamp: SystemTime) -> RgbImage {
+    let millis = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let code = QrCode::encode_text(&format!("{sequence} {millis}"), QrCodeEcc::Medium)
+        .expect("two numbers fit a QR code");
+    let modules = code.size() as u32;
+    let scale = (width.min(height) / (modules + 2 * QR_QUIET_ZONE)).max(1);
+    let left = width.saturating_sub(modules * scale) / 2;
+    let top = height.saturating_sub(modules * scale) / 2;
+
+    let mut image = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (Some(column), Some(row)) = (x.checked_sub(left), y.checked_sub(top)) else {
+            continue;
+        };
+        if code.get_module((column / scale) as i32, (row / scale) as i32) {
+            *pixel = Rgb([0, 0, 0]);
+        }
+    }
+    image
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+#[cfg(target_os = "linux")]
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[cfg(target_os = "linux")]
+use super::{Camera, ControlChange};
+use super::{ControlInfo, ControlValue};
+
+// UVC pan/tilt are in arc seconds, zoom in device-specific steps.
+#[cfg(target_os = "linux")]
+pub const CID_PAN_ABSOLUTE: u32 = 0x009a_0908;
+#[cfg(target_os = "linux")]
+pub const CID_TILT_ABSOLUTE: u32 = 0x009a_0909;
+#[cfg(target_os = "linux")]
+pub const CID_ZOOM_ABSOLUTE: u32 = 0x009a_090d;
+
+/// Pan/tilt/zoom, offered by cameras that can point or zoom.
+#[async_trait]
+pub trait Ptz: Send + Sync {
+    async fn position(&self) -> Result<PtzPosition>;
+
+    /// Moves every axis named in `request` and returns where the camera ends up.
+    async fn move_to(&self, request: PtzMove) -> Result<PtzPosition>;
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PtzMode {
+    /// Axis values are positions within the ranges reported by `GET /ptz`.
+    #[default]
+    Absolute,
+    /// Axis values are offsets from the current position, clamped to the range.
+    Relative,
+}
+
+/// Request to move the camera; axes left out stay where they are.
+#[derive(Clone, Copy, Debug, Deserialize, ToSchema)]
+// Only V4L2 cameras can be moved so far.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub struct PtzMove {
+    #[serde(default)]
+    pub mode: PtzMode,
+    pub pan: Option<i64>,
+    pub tilt: Option<i64>,
+    pub zoom: Option<i64>,
+}
+
+/// Current position; axes the camera lacks are `null`.
+#[derive(Clone, Copy, Debug, Serialize, ToSchema)]
+pub struct PtzPosition {
+    pub pan: Option<PtzAxis>,
+    pub tilt: Option<PtzAxis>,
+    pub zoom: Option<PtzAxis>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, ToSchema)]
+pub struct PtzAxis {
+    pub value: i64,
+    pub minimum: i64,
+    pub maximum: i64,
+    pub step: i64,
+}
+
+#[cfg(target_os = "linux")]
+impl PtzPosition {
+    pub fn from_controls(controls: &[ControlInfo]) -> Self {
+        Self {
+            pan: axis(controls, CID_PAN_ABSOLUTE),
+            tilt: axis(controls, CID_TILT_ABSOLUTE),
+            zoom: axis(controls, CID_ZOOM_ABSOLUTE),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pan.is_none() && self.tilt.is_none() && self.zoom.is_none()
+    }
+}
+
+pub(super) fn axis(controls: &[ControlInfo], id: u32) -> Option<PtzAxis> {
+    controls
+        .iter()
+        .filter(|control| control.id == id && !control.read_only)
+        .find_map(|control| match control.value {
+            ControlValue::Integer {
+                value,
+                minimum,
+                maximum,
+                step,
+                ..
+            } => Some(PtzAxis {
+                value,
+                minimum,
+                maximum,
+                step: step.max(1),
+            }),
+            _ => None,
+        })
+}
+
+/// Moves a camera whose PTZ axes are plain V4L2 integer controls.
+///
+/// Relative moves are applied to the absolute controls rather than the UVC
+/// relative ones, which many cameras implement as speeds instead of offsets.
+#[cfg(target_os = "linux")]
+pub async fn move_with_controls(camera: &dyn Camera, request: PtzMove) -> Result<PtzPosition> {
+    if request.pan.is_none() && request.tilt.is_none() && request.zoom.is_none() {
+        bail!("Move names no axis; give pan, tilt or zoom");
+    }
+
+    let position = PtzPosition::from_controls(&camera.list_controls().await?);
+    let moves = [
+        ("pan", CID_PAN_ABSOLUTE, request.pan, position.pan),
+        ("tilt", CID_TILT_ABSOLUTE, request.tilt, position.tilt),
+        ("zoom", CID_ZOOM_ABSOLUTE, request.zoom, position.zoom),
+    ];
+
+    // Checked up front, so an invalid axis does not leave the others moved.
+    let mut changes = Vec::new();
+    for (name, id, requested, axis) in moves {
+        let Some(requested) = requested else {
+            continue;
+        };
+        let axis = axis.ok_or_else(|| anyhow!("Camera cannot {name}"))?;
+        let value = match request.mode {
+            PtzMode::Absolute => {
+                if !(axis.minimum..=axis.maximum).contains(&requested) {
+                    bail!(
+                        "Value {requested} for {name} outside range {}..={}",
+                        axis.minimum,
+                        axis.maximum
+                    );
+                }
+                requested
+            }
+            PtzMode::Relative => {
+                let target = axis
+                    .value
+                    .saturating_add(requested)
+                    .clamp(axis.minimum, axis.maximum);
+                // Round down onto the step grid the device accepts.
+                axis.minimum + (target - axis.minimum) / axis.step * axis.step
+            }
+        };
+        changes.push(ControlChange { id, value });
+    }
+
+    for change in changes {
+        camera.set_control(change).await?;
+    }
+    Ok(PtzPosition::from_controls(&camera.list_controls().await?))
+}
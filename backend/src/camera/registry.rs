@@ -0,0 +1,156 @@
+//! The camera backends this build has, and which one opens a device.
+
+use std::sync::Arc;
+
+#[cfg(all(target_os = "macos", feature = "av-foundation"))]
+use super::AvFoundationCamera;
+#[cfg(all(windows, feature = "media-foundation"))]
+use super::MediaFoundationCamera;
+#[cfg(target_os = "linux")]
+use super::V4l2Camera;
+use super::{Camera, CameraError, FileCamera, MockCamera, UpstreamCamera};
+use crate::config::{CameraBackend, Config};
+
+/// Opens a device with one backend.
+type Open = fn(&Config, &str) -> Result<Arc<dyn Camera>, CameraError>;
+
+struct Backend {
+    kind: CameraBackend,
+    /// Whether `CAMERA_BACKEND=auto` hands `device` to this backend.
+    handles: fn(&str) -> bool,
+    open: Open,
+}
+
+/// Backends built for this platform, in the order `auto` asks them.
+const BACKENDS: &[Backend] = &[
+    Backend {
+        kind: CameraBackend::Upstream,
+        handles: UpstreamCamera::handles,
+        open: open_upstream,
+    },
+    Backend {
+        kind: CameraBackend::File,
+        handles: FileCamera::handles,
+        open: open_file,
+    },
+    #[cfg(target_os = "linux")]
+    Backend {
+        kind: CameraBackend::V4l2,
+        handles: |_| true,
+        open: open_v4l2,
+    },
+    #[cfg(all(windows, feature = "media-foundation"))]
+    Backend {
+        kind: CameraBackend::MediaFoundation,
+        handles: |_| true,
+        open: open_media_foundation,
+    },
+    #[cfg(all(target_os = "macos", feature = "av-foundation"))]
+    Backend {
+        kind: CameraBackend::AvFoundation,
+        handles: |_| true,
+        open: open_av_foundation,
+    },
+    Backend {
+        kind: CameraBackend::Mock,
+        handles: |_| false,
+        open: |config, _| Ok(Arc::new(mock_camera(config))),
+    },
+];
+
+/// Backends `CAMERA_BACKEND` may name on this platform, besides `auto`.
+pub fn backends() -> impl Iterator<Item = CameraBackend> {
+    BACKENDS.iter().map(|backend| backend.kind)
+}
+
+/// Opens `device` with the backend `CAMERA_BACKEND` names, or the first
+/// that handles it. Without a device, or a backend for it, frames come from
+/// the mock camera.
+pub fn open_camera(config: &Config, device: Option<&str>) -> Result<Arc<dyn Camera>, CameraError> {
+    let Some(device) = device else {
+        if config.camera_backend != CameraBackend::Mock {
+            tracing::warn!("No camera device configured; using mock camera");
+        }
+        return Ok(Arc::new(mock_camera(config)));
+    };
+    let backend = BACKENDS.iter().find(|backend| match config.camera_backend {
+        CameraBackend::Auto => (backend.handles)(device),
+        kind => backend.kind == kind,
+    });
+    match backend {
+        Some(backend) => (backend.open)(config, device),
+        None => {
+            tracing::warn!(
+                device,
+                "No camera backend on this platform; using mock camera"
+            );
+            Ok(Arc::new(mock_camera(config)))
+        }
+    }
+}
+
+pub fn mock_camera(config: &Config) -> MockCamera {
+    MockCamera::new(
+        config.resolution_width,
+        config.resolution_height,
+        config.jpeg_quality,
+        config.mock_pattern,
+    )
+}
+
+fn open_upstream(config: &Config, url: &str) -> Result<Arc<dyn Camera>, CameraError> {
+    let camera = UpstreamCamera::open(url, config.frame_rate, config.upstream_auth.as_ref())?;
+    tracing::info!(url, "Re-serving upstream camera");
+    Ok(Arc::new(camera))
+}
+
+fn open_file(_config: &Config, path: &str) -> Result<Arc<dyn Camera>, CameraError> {
+    let camera = FileCamera::open(path)?;
+    tracing::info!(
+        path,
+        frames = camera.frame_count(),
+        "Replaying footage from disk"
+    );
+    Ok(Arc::new(camera))
+}
+
+#[cfg(target_os = "linux")]
+fn open_v4l2(config: &Config, device: &str) -> Result<Arc<dyn Camera>, CameraError> {
+    let camera = V4l2Camera::new(
+        device,
+        config.resolution_width,
+        config.resolution_height,
+        config.frame_rate,
+        &config.camera_formats,
+        config.bayer_demosaic,
+        config.jpeg_quality,
+    )?;
+    tracing::info!(device, "Using V4L2 camera device");
+    Ok(Arc::new(camera))
+}
+
+#[cfg(all(windows, feature = "media-foundation"))]
+fn open_media_foundation(config: &Config, device: &str) -> Result<Arc<dyn Camera>, CameraError> {
+    let camera = MediaFoundationCamera::new(
+        device,
+        config.resolution_width,
+        config.resolution_height,
+        config.frame_rate,
+        config.jpeg_quality,
+    )?;
+    tracing::info!(device, "Using Media Foundation camera");
+    Ok(Arc::new(camera))
+}
+
+#[cfg(all(target_os = "macos", feature = "av-foundation"))]
+fn open_av_foundation(config: &Config, device: &str) -> Result<Arc<dyn Camera>, CameraError> {
+    let camera = AvFoundationCamera::new(
+        device,
+        config.resolution_width,
+        config.resolution_height,
+        config.frame_rate,
+        config.jpeg_quality,
+    )?;
+    tracing::info!(device, "Using AVFoundation camera");
+    Ok(Arc::new(camera))
+}
@@ -0,0 +1,276 @@
+//! Re-serves another camera's network stream, so this server can add what
+//! its firmware lacks. HTTP(S) MJPEG streams are read directly; RTSP
+//! streams, usually H.264, are transcoded to MJPEG by an `ffmpeg` process.
+
+use std::{
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::{Client, RequestBuilder, Url};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    process::Command,
+    sync::{watch, Mutex as AsyncMutex},
+    task::JoinHandle,
+    time,
+};
+
+use super::{Camera, CameraError, CaptureFormat, ControlChange, ControlInfo, Frame};
+use crate::{
+    config::UpstreamAuth,
+    mjpeg::{self, StreamSplitter},
+};
+
+const SCHEMES: [&str; 4] = ["http://", "https://", "rtsp://", "rtsps://"];
+/// How long to wait for the next frame before reporting a timeout.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+/// The stream itself has no timeout; [`FRAME_TIMEOUT`] covers stalls.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bytes read from `ffmpeg` at a time.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// The newest image the reader received, or why it stopped.
+type Latest = Option<Result<Bytes, String>>;
+
+pub struct UpstreamCamera {
+    frames: AsyncMutex<watch::Receiver<Latest>>,
+    reader: JoinHandle<()>,
+    sequence: AtomicU64,
+    format: Mutex<Option<CaptureFormat>>,
+}
+
+impl UpstreamCamera {
+    /// Whether `device` is a URL this backend reads.
+    pub fn handles(device: &str) -> bool {
+        SCHEMES.iter().any(|scheme| device.starts_with(scheme))
+    }
+
+    /// Starts reading `url` in the background; failing to connect shows up
+    /// in [`Camera::capture_frame`]. RTSP streams are sampled at
+    /// `frame_rate` so `ffmpeg` does not encode frames that would be
+    /// dropped.
+    pub fn open(
+        url: &str,
+        frame_rate: f32,
+        auth: Option<&UpstreamAuth>,
+    ) -> Result<Self, CameraError> {
+        let mut url = Url::parse(url)
+            .map_err(|err| CameraError::Device(format!("Invalid camera URL {url}: {err}")))?;
+        let (sender, frames) = watch::channel(None);
+
+        let reader = if url.scheme().starts_with("http") {
+            let client = Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .build()
+                .map_err(|err| {
+                    CameraError::Device(format!("Failed to build HTTP client: {err}"))
+                })?;
+            let mut request = client.get(url);
+            if let Some(auth) = auth {
+                request = request.basic_auth(&auth.user, auth.password.as_ref());
+            }
+            tokio::spawn(async move {
+                let result = read_http(request, &sender).await;
+                stopped(&sender, result);
+            })
+        } else {
+            if let Some(auth) = auth {
+                // ffmpeg only takes RTSP credentials in the URL, so they end
+                // up on its command line, where local users can see them.
+                let _ = url.set_username(&auth.user);
+                let _ = url.set_password(auth.password.as_deref());
+            }
+            tokio::spawn(async move {
+                let result = read_ffmpeg(&url, frame_rate, &sender).await;
+                stopped(&sender, result);
+            })
+        };
+
+        Ok(Self {
+            frames: AsyncMutex::new(frames),
+            reader,
+            sequence: AtomicU64::new(0),
+            format: Mutex::new(None),
+        })
+    }
+}
+
+impl Drop for UpstreamCamera {
+    fn drop(&mut self) {
+        // Closes the connection, or kills ffmpeg along with its handle.
+        self.reader.abort();
+    }
+}
+
+async fn read_http(request: RequestBuilder, frames: &watch::Sender<Latest>) -> Result<()> {
+    let mut response = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context("Failed to connect to upstream camera")?;
+    let mut splitter = StreamSplitter::default();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .context("Failed to read from upstream camera")?
+    {
+        publish(&mut splitter, &chunk, frames);
+    }
+    Ok(())
+}
+
+async fn read_ffmpeg(url: &Url, frame_rate: f32, frames: &watch::Sender<Latest>) -> Result<()> {
+    let mut child = Command::new("ffmpeg")
+        .args([
+            "-nostdin",
+            "-loglevel",
+            "error",
+            "-rtsp_transport",
+            "tcp",
+            "-i",
+        ])
+        .arg(url.as_str())
+        .args(["-an", "-r"])
+        .arg(frame_rate.to_string())
+        .args(["-c:v", "mjpeg", "-q:v", "3", "-f", "mjpeg", "pipe:1"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start ffmpeg, which RTSP cameras need")?;
+    let mut stdout = child.stdout.take().context("ffmpeg has no stdout")?;
+    let stderr = child.stderr.take().context("ffmpeg has no stderr")?;
+
+    let read_frames = async {
+        let mut splitter = StreamSplitter::default();
+        let mut chunk = vec![0; READ_CHUNK];
+        loop {
+            let len = stdout
+                .read(&mut chunk)
+                .await
+                .context("Failed to read from ffmpeg")?;
+            if len == 0 {
+                return Ok::<_, anyhow::Error>(());
+            }
+            publish(&mut splitter, &chunk[..len], frames);
+        }
+    };
+    // Drained alongside stdout: a full pipe would block ffmpeg mid-stream.
+    let read_errors = async {
+        let mut lines = BufReader::new(stderr).lines();
+        let mut last = None;
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .context("Failed to read ffmpeg errors")?
+        {
+            let line = without_credentials(&line, url);
+            tracing::debug!(line, "ffmpeg");
+            last = Some(line);
+        }
+        Ok::<_, anyhow::Error>(last)
+    };
+    let ((), last_error) = tokio::try_join!(read_frames, read_errors)?;
+
+    let status = child.wait().await.context("Failed to wait for ffmpeg")?;
+    Err(anyhow!(
+        "ffmpeg stopped ({status}): {}",
+        last_error.as_deref().unwrap_or("no error output")
+    ))
+}
+
+/// ffmpeg quotes the input URL in its errors; hides the password in it.
+fn without_credentials(line: &str, url: &Url) -> String {
+    if url.password().is_none() {
+        return line.to_string();
+    }
+    let mut shown = url.clone();
+    let _ = shown.set_password(None);
+    line.replace(url.as_str(), shown.as_str())
+}
+
+fn publish(splitter: &mut StreamSplitter, data: &[u8], frames: &watch::Sender<Latest>) {
+    splitter.push(data);
+    while let Some(image) = splitter.next_image() {
+        // Like USB cameras, cheap IP cameras may leave out the Huffman tables.
+        let mut repaired = Vec::with_capacity(image.len());
+        match mjpeg::repair(&image, &mut repaired) {
+            Ok(()) => {
+                frames.send_replace(Some(Ok(Bytes::from(repaired))));
+            }
+            Err(err) => tracing::debug!(error = %err, "Dropping broken upstream frame"),
+        }
+    }
+}
+
+fn stopped(frames: &watch::Sender<Latest>, result: Result<()>) {
+    let message = match result {
+        Ok(()) => "Upstream camera ended the stream".to_string(),
+        Err(err) => format!("{err:#}"),
+    };
+    frames.send_replace(Some(Err(message)));
+}
+
+#[async_trait]
+impl Camera for UpstreamCamera {
+    async fn capture_frame(&self) -> Result<Frame, CameraError> {
+        let mut frames = self.frames.lock().await;
+        let changed = time::timeout(FRAME_TIMEOUT, frames.changed())
+            .await
+            .map_err(|_| CameraError::Timeout)?;
+        let latest = frames.borrow_and_update().clone();
+        let data = match (changed, latest) {
+            (Ok(()), Some(Ok(data))) => data,
+            (_, Some(Err(message))) => return Err(CameraError::Disconnected(message)),
+            _ => {
+                return Err(CameraError::Disconnected(
+                    "Upstream camera reader stopped".to_string(),
+                ))
+            }
+        };
+        let (width, height) = mjpeg::dimensions(&data).ok_or_else(|| {
+            CameraError::Encode(anyhow!(
+                "Upstream camera sent a JPEG without a frame header"
+            ))
+        })?;
+        *self.format.lock().expect("upstream format poisoned") = Some(CaptureFormat {
+            fourcc: "MJPG",
+            width,
+            height,
+        });
+
+        Ok(Frame {
+            data,
+            timestamp: SystemTime::now(),
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed) + 1,
+            pixel_format: "MJPG",
+            width,
+            height,
+        })
+    }
+
+    async fn list_controls(&self) -> Result<Vec<ControlInfo>> {
+        Ok(Vec::new())
+    }
+
+    async fn set_control(&self, change: ControlChange) -> Result<()> {
+        Err(anyhow!("Unknown control id {}", change.id))
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "upstream"
+    }
+
+    fn format(&self) -> Option<CaptureFormat> {
+        *self.format.lock().expect("upstream format poisoned")
+    }
+}
@@ -1,155 +1,584 @@
 use std::{
-    io::Cursor,
-    sync::{Arc, Mutex},
+    fs::{self, File},
+    io,
+    os::fd::AsRawFd,
+    path::Path,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
 };
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use image::{codecs::jpeg::JpegEncoder, ImageBuffer, Rgb};
-use rscam::{self, Config as V4l2Config};
+use bytes::Bytes;
+use rscam::{self, Config as V4l2Config, IntervalInfo, ResolutionInfo};
 use tokio::task;
 
-use super::Camera;
+use super::{
+    control::MenuItem, device::SizeRange, focus, ptz, Camera, CameraError, Capabilities,
+    CaptureFormat, CaptureThread, ControlChange, ControlInfo, ControlValue, DeviceInfo, Focus,
+    FocusRequest, FocusState, FormatInfo, Frame, FrameRates, Ptz, PtzMove, PtzPosition, Resolution,
+};
+use crate::{
+    buffer_pool::BufferPool,
+    config::{Demosaic, PixelFormat},
+    jpeg::{self, RawFormat},
+    mjpeg,
+};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum PixelFormat {
-    Mjpeg,
-    Yuyv,
-}
+/// How long the capture thread waits for the driver to fill a buffer.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// A V4L2 device read by a capture thread of its own, which dequeues,
+/// checks or encodes each frame and queues it for `capture_frame`.
 pub struct V4l2Camera {
-    camera: Arc<Mutex<rscam::Camera>>,
+    /// Shared with the capture thread; controls are set alongside capture.
+    camera: Arc<rscam::Camera>,
     width: u32,
     height: u32,
     pixel_format: PixelFormat,
+    jpeg_quality: Arc<AtomicU8>,
+    thread: CaptureThread,
+    /// The device has pan, tilt or zoom controls.
+    has_ptz: bool,
+    /// The device has focus controls.
+    has_focus: bool,
+    /// The device has a one-shot autofocus button.
+    has_focus_trigger: bool,
+    /// Formats the device offers, listed when it was opened.
+    formats: Vec<FormatInfo>,
 }
 
 impl V4l2Camera {
-    pub fn new(device: &str, width: u32, height: u32, frame_rate: f32) -> Result<Self> {
-        let mut camera = rscam::Camera::new(device)
-            .with_context(|| format!("Failed to open camera device {device}"))?;
+    /// Opens `device` in the first of `formats` the driver accepts at the
+    /// requested size and rate. RAW Bayer frames are interpolated with
+    /// `demosaic`, and uncompressed frames encoded at `jpeg_quality`.
+    pub fn new(
+        device: &str,
+        width: u32,
+        height: u32,
+        frame_rate: f32,
+        formats: &[PixelFormat],
+        demosaic: Demosaic,
+        jpeg_quality: u8,
+    ) -> Result<Self, CameraError> {
+        let mut camera = rscam::Camera::new(device).map_err(|err| {
+            CameraError::from_io(&format!("Failed to open camera device {device}"), err)
+        })?;
 
         let fps = frame_rate.max(1.0).round() as u32;
         let resolution = (width, height);
 
-        let mut pixel_format = PixelFormat::Mjpeg;
-
-        match camera.start(&V4l2Config {
-            interval: (1, fps.max(1)),
-            resolution,
-            format: *b"MJPG",
-            ..Default::default()
-        }) {
-            Ok(()) => {}
-            Err(err) => {
-                tracing::warn!(?resolution, fps, device, error = %err, "MJPG format unsupported, falling back to YUYV");
-                let second_attempt = camera.start(&V4l2Config {
-                    interval: (1, fps.max(1)),
-                    resolution,
-                    format: *b"YUYV",
-                    ..Default::default()
-                });
-
-                match second_attempt {
-                    Ok(()) => {
-                        pixel_format = PixelFormat::Yuyv;
-                    }
-                    Err(second_err) => {
-                        return Err(anyhow::anyhow!(
-                            "Failed to configure camera for MJPG ({err}) and YUYV ({second_err})"
-                        ));
-                    }
+        let mut failures = Vec::new();
+        let mut pixel_format = None;
+        for &format in formats {
+            match camera.start(&V4l2Config {
+                interval: (1, fps.max(1)),
+                resolution,
+                format: format.fourcc().as_bytes(),
+                ..Default::default()
+            }) {
+                Ok(()) => {
+                    pixel_format = Some(format);
+                    break;
+                }
+                // Not about the format, so the next one would fail alike.
+                Err(rscam::Error::Io(err)) => {
+                    return Err(CameraError::from_io(
+                        &format!("Failed to start camera device {device}"),
+                        err,
+                    ))
+                }
+                Err(err) => {
+                    tracing::debug!(?resolution, fps, device, format = format.fourcc(), error = %err, "Camera format unsupported");
+                    failures.push(format!("{} ({err})", format.fourcc()));
                 }
             }
         }
+        let Some(pixel_format) = pixel_format else {
+            return Err(CameraError::FormatUnsupported(format!(
+                "Failed to configure camera for {}",
+                failures.join(", ")
+            )));
+        };
+        if !failures.is_empty() {
+            tracing::warn!(
+                device,
+                format = pixel_format.fourcc(),
+                "Preferred camera formats unsupported, falling back"
+            );
+        }
+
+        // Buttons are left out of the control list, so the autofocus
+        // trigger is looked for among the raw controls.
+        let mut has_focus_trigger = false;
+        let mut controls = Vec::new();
+        for control in camera.controls().flatten() {
+            if control.id == focus::CID_AUTO_FOCUS_START
+                && control.flags & rscam::FLAG_DISABLED == 0
+            {
+                has_focus_trigger = true;
+            }
+            controls.extend(control_info(control));
+        }
+        let has_ptz = !PtzPosition::from_controls(&controls).is_empty();
+        if has_ptz {
+            tracing::info!(device, "Camera supports pan/tilt/zoom");
+        }
+        let has_focus = !FocusState::from_controls(&controls, has_focus_trigger).is_empty();
+        if has_focus {
+            tracing::info!(device, "Camera supports focus control");
+        }
+
+        let formats = describe_formats(&camera);
+        // rscam keeps its descriptor to itself, so readiness is polled on a
+        // second one; drivers signal filled buffers to every open file.
+        let poller = File::open(device).map_err(|err| {
+            CameraError::from_io(&format!("Failed to open camera device {device}"), err)
+        })?;
+
+        let camera = Arc::new(camera);
+        let jpeg_quality = Arc::new(AtomicU8::new(jpeg_quality));
+        let mut capture = Capture {
+            camera: camera.clone(),
+            poller,
+            width,
+            height,
+            pixel_format: pixel_format.fourcc(),
+            raw_format: raw_format(pixel_format, demosaic),
+            jpeg_quality: jpeg_quality.clone(),
+            buffers: BufferPool::new(CaptureThread::MAX_POOLED_BUFFERS),
+            scratch: Vec::new(),
+            sequence: 0,
+        };
+        // The device is already open, so the thread only captures.
+        let (thread, ()) = CaptureThread::spawn(device, move |context| {
+            context.opened(Ok(()));
+            context.run(|| capture.next_frame());
+        })?;
 
         Ok(Self {
-            camera: Arc::new(Mutex::new(camera)),
+            camera,
             width,
             height,
             pixel_format,
+            jpeg_quality,
+            thread,
+            has_ptz,
+            has_focus,
+            has_focus_trigger,
+            formats,
+        })
+    }
+}
+
+/// Whether `file` became readable, or reported an error that the next
+/// dequeue will tell, within `timeout`.
+fn wait_readable(file: &File, timeout: Duration) -> io::Result<bool> {
+    let mut poll = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+    loop {
+        // SAFETY: `poll` is a single valid pollfd for the duration of the call.
+        match unsafe { libc::poll(&mut poll, 1, timeout) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            ready => return Ok(ready > 0),
+        }
+    }
+}
+
+/// `None` for MJPG, which is passed through rather than encoded.
+fn raw_format(format: PixelFormat, demosaic: Demosaic) -> Option<RawFormat> {
+    match format {
+        PixelFormat::Mjpg => None,
+        PixelFormat::Yuyv => Some(RawFormat::Yuyv),
+        PixelFormat::Uyvy => Some(RawFormat::Uyvy),
+        PixelFormat::Nv12 => Some(RawFormat::Nv12),
+        PixelFormat::Rgbp => Some(RawFormat::Rgb565),
+        PixelFormat::Grey => Some(RawFormat::Grey),
+        PixelFormat::Sbggr8 => Some(RawFormat::Sbggr8(demosaic)),
+        PixelFormat::Sbggr10 => Some(RawFormat::Sbggr10(demosaic)),
+    }
+}
+
+/// The capture thread's state.
+struct Capture {
+    camera: Arc<rscam::Camera>,
+    /// The same device, waited on for the next frame.
+    poller: File,
+    width: u32,
+    height: u32,
+    pixel_format: &'static str,
+    raw_format: Option<RawFormat>,
+    jpeg_quality: Arc<AtomicU8>,
+    buffers: BufferPool,
+    /// Converted pixels of the last raw frame, reused for the next one.
+    scratch: Vec<u8>,
+    /// Frames dequeued so far, broken ones included.
+    sequence: u64,
+}
+
+impl Capture {
+    fn next_frame(&mut self) -> Result<Frame, CameraError> {
+        // The driver only has a couple of mapped buffers and reuses each
+        // one once a frame is dropped, so frames are copied out of them.
+        let (data, timestamp) = match self.raw_format {
+            Some(raw_format) => self.capture_raw(raw_format)?,
+            None => self.capture_mjpeg()?,
+        };
+        Ok(Frame {
+            data,
+            timestamp,
+            sequence: self.sequence,
+            pixel_format: self.pixel_format,
+            width: self.width,
+            height: self.height,
         })
     }
+
+    /// Waits for the next frame and notes when it arrived; gives up after
+    /// [`FRAME_TIMEOUT`], so a wedged device does not block the thread.
+    fn dequeue(&mut self) -> Result<(rscam::Frame, SystemTime), CameraError> {
+        if !wait_readable(&self.poller, FRAME_TIMEOUT)
+            .map_err(|err| CameraError::from_io("Failed to wait for v4l2 camera", err))?
+        {
+            return Err(CameraError::Timeout);
+        }
+        let frame = self
+            .camera
+            .capture()
+            .map_err(|err| CameraError::from_io("Failed to capture frame from v4l2 camera", err))?;
+        self.sequence += 1;
+        Ok((frame, SystemTime::now()))
+    }
+
+    fn capture_raw(&mut self, raw_format: RawFormat) -> Result<(Bytes, SystemTime), CameraError> {
+        let (frame, timestamp) = self.dequeue()?;
+        let mut output = self.buffers.take();
+        match jpeg::encode_raw(
+            raw_format,
+            &frame,
+            self.width,
+            self.height,
+            self.jpeg_quality.load(Ordering::Relaxed),
+            &mut self.scratch,
+            &mut output,
+        ) {
+            Ok(()) => Ok((self.buffers.freeze(output), timestamp)),
+            Err(err) => {
+                self.buffers.give_back(output);
+                Err(CameraError::Encode(err))
+            }
+        }
+    }
+
+    /// Captures the next intact MJPEG frame, skipping up to
+    /// `MAX_BROKEN_FRAMES - 1` broken ones in a row.
+    fn capture_mjpeg(&mut self) -> Result<(Bytes, SystemTime), CameraError> {
+        let mut broken = 0;
+        loop {
+            let (frame, timestamp) = self.dequeue()?;
+            let mut output = self.buffers.take();
+            match mjpeg::repair(&frame, &mut output) {
+                Ok(()) => return Ok((self.buffers.freeze(output), timestamp)),
+                Err(err) => {
+                    self.buffers.give_back(output);
+                    broken += 1;
+                    if broken == CaptureThread::MAX_BROKEN_FRAMES {
+                        return Err(CameraError::Encode(
+                            err.context("Camera keeps delivering broken MJPEG frames"),
+                        ));
+                    }
+                    tracing::debug!(error = %err, "Dropping broken MJPEG frame");
+                }
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl Camera for V4l2Camera {
-    async fn capture_frame(&self) -> Result<Vec<u8>> {
+    async fn capture_frame(&self) -> Result<Frame, CameraError> {
+        self.thread.next_frame().await
+    }
+
+    async fn list_controls(&self) -> Result<Vec<ControlInfo>> {
         let camera = self.camera.clone();
-        let width = self.width;
-        let height = self.height;
-        let format = self.pixel_format;
 
         task::spawn_blocking(move || {
-            let mut camera = camera.lock().expect("v4l2 camera lock poisoned");
-            let frame = camera
-                .capture()
-                .context("Failed to capture frame from v4l2 camera")?;
-
-            match format {
-                PixelFormat::Mjpeg => Ok(frame.to_vec()),
-                PixelFormat::Yuyv => yuyv_to_jpeg(&frame, width, height),
+            let mut controls = Vec::new();
+            for control in camera.controls() {
+                let control = control.context("Failed to query v4l2 control")?;
+                if let Some(info) = control_info(control) {
+                    controls.push(info);
+                }
             }
+            Ok(controls)
         })
         .await
         .expect("spawn_blocking failed")
     }
+
+    async fn set_control(&self, change: ControlChange) -> Result<()> {
+        let camera = self.camera.clone();
+
+        task::spawn_blocking(move || {
+            camera
+                .set_control(change.id, &change.value)
+                .with_context(|| format!("Failed to set v4l2 control {}", change.id))
+        })
+        .await
+        .expect("spawn_blocking failed")
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "v4l2"
+    }
+
+    fn format(&self) -> Option<CaptureFormat> {
+        Some(CaptureFormat {
+            fourcc: self.pixel_format.fourcc(),
+            width: self.width,
+            height: self.height,
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            controls: true,
+            ptz: self.has_ptz,
+            focus: self.has_focus,
+            jpeg_encoding: self.pixel_format != PixelFormat::Mjpg,
+            hardware_encoding: self.pixel_format == PixelFormat::Mjpg,
+            formats: self.formats.clone(),
+        }
+    }
+
+    fn set_jpeg_quality(&self, quality: u8) {
+        self.jpeg_quality.store(quality, Ordering::Relaxed);
+    }
+
+    fn ptz(&self) -> Option<&dyn Ptz> {
+        self.has_ptz.then_some(self as &dyn Ptz)
+    }
+
+    fn focus(&self) -> Option<&dyn Focus> {
+        self.has_focus.then_some(self as &dyn Focus)
+    }
 }
 
-fn yuyv_to_jpeg(frame: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
-    let expected_len = (width as usize) * (height as usize) * 2;
-    if frame.len() < expected_len {
-        anyhow::bail!(
-            "YUYV frame length {} smaller than expected {} for resolution {}x{}",
-            frame.len(),
-            expected_len,
-            width,
-            height
-        );
+#[async_trait]
+impl Ptz for V4l2Camera {
+    async fn position(&self) -> Result<PtzPosition> {
+        Ok(PtzPosition::from_controls(&self.list_controls().await?))
+    }
+
+    async fn move_to(&self, request: PtzMove) -> Result<PtzPosition> {
+        ptz::move_with_controls(self, request).await
+    }
+}
+
+#[async_trait]
+impl Focus for V4l2Camera {
+    async fn state(&self) -> Result<FocusState> {
+        Ok(FocusState::from_controls(
+            &self.list_controls().await?,
+            self.has_focus_trigger,
+        ))
     }
 
-    let mut rgb = Vec::with_capacity((width as usize) * (height as usize) * 3);
-    for chunk in frame.chunks_exact(4) {
-        let y0 = chunk[0] as f32;
-        let u = chunk[1] as f32 - 128.0;
-        let y1 = chunk[2] as f32;
-        let v = chunk[3] as f32 - 128.0;
+    async fn refocus(&self, request: FocusRequest) -> Result<FocusState> {
+        focus::focus_with_controls(self, self.has_focus_trigger, request).await
+    }
+}
+
+/// Describes every `/dev/video*` node that offers capture formats. Metadata
+/// and output nodes, and nodes that cannot be opened, are left out.
+pub fn list_devices() -> Vec<DeviceInfo> {
+    let entries = match fs::read_dir("/dev") {
+        Ok(entries) => entries,
+        Err(err) => {
+            tracing::warn!(error = %err, "Failed to list /dev");
+            return Vec::new();
+        }
+    };
+
+    let mut nodes: Vec<(u32, String)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let index = name.strip_prefix("video")?.parse().ok()?;
+            Some((index, name))
+        })
+        .collect();
+    nodes.sort();
+
+    nodes
+        .into_iter()
+        .filter_map(|(_, name)| describe_device(&name))
+        .collect()
+}
 
-        let (r0, g0, b0) = yuv_to_rgb(y0, u, v);
-        let (r1, g1, b1) = yuv_to_rgb(y1, u, v);
+fn describe_device(name: &str) -> Option<DeviceInfo> {
+    let path = format!("/dev/{name}");
+    let camera = match rscam::Camera::new(&path) {
+        Ok(camera) => camera,
+        Err(err) => {
+            tracing::debug!(device = path, error = %err, "Skipping device that cannot be opened");
+            return None;
+        }
+    };
 
-        rgb.push(r0);
-        rgb.push(g0);
-        rgb.push(b0);
-        rgb.push(r1);
-        rgb.push(g1);
-        rgb.push(b1);
+    let formats = describe_formats(&camera);
+    if formats.is_empty() {
+        return None;
     }
 
-    let buffer: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_vec(width, height, rgb)
-        .context("Failed to build RGB buffer from YUYV data")?;
+    let sysfs = Path::new("/sys/class/video4linux").join(name);
+    let card = fs::read_to_string(sysfs.join("name"))
+        .ok()
+        .map(|card| card.trim().to_string());
+    let driver = fs::read_link(sysfs.join("device/driver"))
+        .ok()
+        .and_then(|driver| Some(driver.file_name()?.to_string_lossy().into_owned()));
 
-    let mut cursor = Cursor::new(Vec::new());
-    let mut encoder = JpegEncoder::new_with_quality(&mut cursor, 85);
-    encoder
-        .encode_image(&buffer)
-        .context("Failed to encode YUYV frame to JPEG")?;
+    Some(DeviceInfo {
+        path,
+        name: card,
+        driver,
+        formats,
+    })
+}
+
+/// Every format the device offers, with its sizes and frame rates.
+fn describe_formats(camera: &rscam::Camera) -> Vec<FormatInfo> {
+    camera
+        .formats()
+        .map_while(Result::ok)
+        .map(|format| {
+            let (resolutions, size_range) = match camera.resolutions(&format.format) {
+                Ok(ResolutionInfo::Discretes(sizes)) => {
+                    let resolutions = sizes
+                        .into_iter()
+                        .map(|(width, height)| Resolution {
+                            width,
+                            height,
+                            frame_rates: frame_rates(camera, &format.format, (width, height)),
+                        })
+                        .collect();
+                    (resolutions, None)
+                }
+                Ok(ResolutionInfo::Stepwise { min, max, step }) => {
+                    let range = SizeRange {
+                        min_width: min.0,
+                        min_height: min.1,
+                        max_width: max.0,
+                        max_height: max.1,
+                        step_width: step.0,
+                        step_height: step.1,
+                        frame_rates: frame_rates(camera, &format.format, max),
+                    };
+                    (Vec::new(), Some(range))
+                }
+                Err(_) => (Vec::new(), None),
+            };
 
-    Ok(cursor.into_inner())
+            FormatInfo {
+                fourcc: String::from_utf8_lossy(&format.format).into_owned(),
+                description: format.description,
+                compressed: format.compressed,
+                resolutions,
+                size_range,
+            }
+        })
+        .collect()
 }
 
-fn yuv_to_rgb(y: f32, u: f32, v: f32) -> (u8, u8, u8) {
-    let r = y + 1.402 * v;
-    let g = y - 0.344_136 * u - 0.714_136 * v;
-    let b = y + 1.772 * u;
+/// Converts V4L2 frame intervals (seconds per frame) into frame rates.
+fn frame_rates(camera: &rscam::Camera, format: &[u8], resolution: (u32, u32)) -> FrameRates {
+    let fps = |(numerator, denominator): (u32, u32)| denominator as f32 / numerator.max(1) as f32;
 
-    (
-        clamp_u8(r.round()),
-        clamp_u8(g.round()),
-        clamp_u8(b.round()),
-    )
+    match camera.intervals(format, resolution) {
+        Ok(IntervalInfo::Discretes(intervals)) => FrameRates::Discrete {
+            values: intervals.into_iter().map(fps).collect(),
+        },
+        // The longest interval is the lowest rate.
+        Ok(IntervalInfo::Stepwise { min, max, .. }) => FrameRates::Range {
+            min: fps(max),
+            max: fps(min),
+        },
+        Err(_) => FrameRates::Unknown,
+    }
 }
 
-fn clamp_u8(value: f32) -> u8 {
-    value.max(0.0).min(255.0) as u8
+/// Maps an rscam control onto the backend-neutral description, skipping
+/// disabled controls and types the API cannot set (buttons, strings, ...).
+fn control_info(control: rscam::Control) -> Option<ControlInfo> {
+    if control.flags & rscam::FLAG_DISABLED != 0 {
+        return None;
+    }
+
+    let value = match control.data {
+        rscam::CtrlData::Integer {
+            value,
+            default,
+            minimum,
+            maximum,
+            step,
+        } => ControlValue::Integer {
+            value: value.into(),
+            default: default.into(),
+            minimum: minimum.into(),
+            maximum: maximum.into(),
+            step: step.into(),
+        },
+        rscam::CtrlData::Integer64 {
+            value,
+            default,
+            minimum,
+            maximum,
+            step,
+        } => ControlValue::Integer {
+            value,
+            default,
+            minimum,
+            maximum,
+            step,
+        },
+        rscam::CtrlData::Boolean { value, default } => ControlValue::Boolean { value, default },
+        rscam::CtrlData::Menu {
+            value,
+            default,
+            items,
+        } => ControlValue::Menu {
+            value,
+            default,
+            items: items
+                .into_iter()
+                .map(|item| MenuItem {
+                    index: item.index,
+                    name: item.name,
+                })
+                .collect(),
+        },
+        _ => return None,
+    };
+
+    Some(ControlInfo {
+        id: control.id,
+        name: control.name,
+        read_only: control.flags & rscam::FLAG_READ_ONLY != 0,
+        software: false,
+        value,
+    })
 }
@@ -0,0 +1,251 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc, Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use image::RgbImage;
+use tokio::task;
+
+use super::{
+    Camera, CameraError, Capabilities, CaptureFormat, ControlChange, ControlInfo, ControlValue,
+    Focus, Frame, Ptz,
+};
+use crate::jpeg;
+
+const CID_RED_BALANCE: u32 = 0x0098_090e;
+const CID_BLUE_BALANCE: u32 = 0x0098_090f;
+const CID_WHITE_BALANCE_TEMPERATURE: u32 = 0x0098_091a;
+
+/// Emulated red and blue balance are gains in percent.
+const GAIN_RANGE: (i64, i64) = (25, 400);
+/// Frames are taken to be balanced for daylight, so this temperature
+/// leaves them as captured.
+const NEUTRAL_TEMPERATURE: i64 = 6500;
+const TEMPERATURE_RANGE: (i64, i64) = (2800, 10_000);
+
+/// Red/blue balance and white balance temperature emulated for one camera,
+/// by the handle so they survive the device being reopened. Only those the
+/// device lacks are offered.
+#[derive(Debug)]
+pub struct ColorBalance {
+    controls: Mutex<Vec<ControlInfo>>,
+}
+
+impl Default for ColorBalance {
+    fn default() -> Self {
+        let emulated = |control: ControlInfo| ControlInfo {
+            software: true,
+            ..control
+        };
+        Self {
+            controls: Mutex::new(vec![
+                emulated(ControlInfo::integer(
+                    CID_RED_BALANCE,
+                    "Red Balance",
+                    100,
+                    GAIN_RANGE.0,
+                    GAIN_RANGE.1,
+                )),
+                emulated(ControlInfo::integer(
+                    CID_BLUE_BALANCE,
+                    "Blue Balance",
+                    100,
+                    GAIN_RANGE.0,
+                    GAIN_RANGE.1,
+                )),
+                emulated(ControlInfo::integer(
+                    CID_WHITE_BALANCE_TEMPERATURE,
+                    "White Balance Temperature",
+                    NEUTRAL_TEMPERATURE,
+                    TEMPERATURE_RANGE.0,
+                    TEMPERATURE_RANGE.1,
+                )),
+            ]),
+        }
+    }
+}
+
+impl ColorBalance {
+    fn emulates(&self, id: u32) -> bool {
+        self.lock().iter().any(|control| control.id == id)
+    }
+
+    /// The emulated controls missing from `native`.
+    fn missing_from(&self, native: &[ControlInfo]) -> Vec<ControlInfo> {
+        self.lock()
+            .iter()
+            .filter(|control| native.iter().all(|native| native.id != control.id))
+            .cloned()
+            .collect()
+    }
+
+    fn set(&self, change: ControlChange) -> Result<()> {
+        let mut controls = self.lock();
+        let control = controls
+            .iter_mut()
+            .find(|control| control.id == change.id)
+            .ok_or_else(|| anyhow!("Unknown control id {}", change.id))?;
+        control.set(change.value)
+    }
+
+    /// Red, green and blue factors of the diagonal correction matrix, or
+    /// `None` while every emulated control is at its default.
+    fn gains(&self) -> Option<[f32; 3]> {
+        let controls = self.lock();
+        let value = |id| {
+            controls
+                .iter()
+                .find(|control| control.id == id)
+                .and_then(|control| match control.value {
+                    ControlValue::Integer { value, .. } => Some(value),
+                    _ => None,
+                })
+        };
+        let red = value(CID_RED_BALANCE)? as f32 / 100.0;
+        let blue = value(CID_BLUE_BALANCE)? as f32 / 100.0;
+        let temperature = value(CID_WHITE_BALANCE_TEMPERATURE)?;
+        if red == 1.0 && blue == 1.0 && temperature == NEUTRAL_TEMPERATURE {
+            return None;
+        }
+
+        // Scale each channel by how much less of it the light holds than
+        // daylight does, relative to green.
+        let light = white_point(temperature as f32);
+        let daylight = white_point(NEUTRAL_TEMPERATURE as f32);
+        let relative =
+            |channel: usize| (daylight[channel] / light[channel]) / (daylight[1] / light[1]);
+        Some([red * relative(0), 1.0, blue * relative(2)])
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<ControlInfo>> {
+        self.controls.lock().expect("color balance poisoned")
+    }
+}
+
+/// Approximate colour of a black body at `kelvin`, after Tanner Helland's
+/// fit of the CIE data.
+fn white_point(kelvin: f32) -> [f32; 3] {
+    let t = kelvin / 100.0;
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let green = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_17 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let blue = if t >= 66.0 {
+        255.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+    [red, green, blue].map(|channel| channel.clamp(1.0, 255.0))
+}
+
+fn correct(image: &mut RgbImage, gains: [f32; 3]) {
+    let tables = gains.map(|gain| -> [u8; 256] {
+        std::array::from_fn(|value| (value as f32 * gain).round().min(255.0) as u8)
+    });
+    for pixel in image.pixels_mut() {
+        for (channel, table) in pixel.0.iter_mut().zip(&tables) {
+            *channel = table[usize::from(*channel)];
+        }
+    }
+}
+
+/// Wraps a camera to add the white balance controls it lacks, correcting its
+/// frames in software while they are off their defaults.
+pub struct SoftwareWhiteBalance {
+    camera: Arc<dyn Camera>,
+    balance: Arc<ColorBalance>,
+    /// Quality corrected frames are encoded at.
+    jpeg_quality: AtomicU8,
+}
+
+impl SoftwareWhiteBalance {
+    pub fn new(camera: Arc<dyn Camera>, balance: Arc<ColorBalance>, jpeg_quality: u8) -> Self {
+        Self {
+            camera,
+            balance,
+            jpeg_quality: AtomicU8::new(jpeg_quality),
+        }
+    }
+}
+
+#[async_trait]
+impl Camera for SoftwareWhiteBalance {
+    async fn capture_frame(&self) -> Result<Frame, CameraError> {
+        let frame = self.camera.capture_frame().await?;
+        let Some(gains) = self.balance.gains() else {
+            return Ok(frame);
+        };
+
+        let source = frame.data.clone();
+        let quality = self.jpeg_quality.load(Ordering::Relaxed);
+        let corrected = task::spawn_blocking(move || {
+            let mut image = jpeg::decode_rgb(&source)?;
+            correct(&mut image, gains);
+            jpeg::encode_rgb(&image, quality)
+        })
+        .await
+        .expect("spawn_blocking failed");
+        match corrected {
+            Ok(corrected) => Ok(frame.with_data(Bytes::from(corrected))),
+            Err(err) => {
+                tracing::warn!(error = %err, "White balance correction failed; sending frame as captured");
+                Ok(frame)
+            }
+        }
+    }
+
+    async fn list_controls(&self) -> Result<Vec<ControlInfo>> {
+        let mut controls = self.camera.list_controls().await?;
+        let emulated = self.balance.missing_from(&controls);
+        controls.extend(emulated);
+        Ok(controls)
+    }
+
+    async fn set_control(&self, change: ControlChange) -> Result<()> {
+        if self.balance.emulates(change.id) {
+            let native = self.camera.list_controls().await?;
+            if native.iter().all(|control| control.id != change.id) {
+                return self.balance.set(change);
+            }
+        }
+        self.camera.set_control(change).await
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.camera.backend_name()
+    }
+
+    fn format(&self) -> Option<CaptureFormat> {
+        self.camera.format()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // The emulated white balance controls are always listed.
+        Capabilities {
+            controls: true,
+            ..self.camera.capabilities()
+        }
+    }
+
+    fn set_jpeg_quality(&self, quality: u8) {
+        self.jpeg_quality.store(quality, Ordering::Relaxed);
+        self.camera.set_jpeg_quality(quality);
+    }
+
+    fn ptz(&self) -> Option<&dyn Ptz> {
+        self.camera.ptz()
+    }
+
+    fn focus(&self) -> Option<&dyn Focus> {
+        self.camera.focus()
+    }
+}
@@ -0,0 +1,1055 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use image::GrayImage;
+use tokio::{
+    sync::{
+        broadcast::{
+            self,
+            error::{RecvError, TryRecvError},
+        },
+        watch, Mutex, Notify,
+    },
+    task::{self, JoinHandle},
+    time::{self, interval, interval_at},
+};
+use tracing::Instrument;
+
+use crate::{
+    camera::{
+        self, Camera, CameraError, Capabilities, CaptureFormat, ColorBalance, ControlChange,
+        ControlInfo, Frame, MockCamera, SoftwareWhiteBalance,
+    },
+    config::{CaptureFallback, Config},
+    load::Load,
+    motion,
+    overlay::OverlayReceiver,
+    timings::{self, Stage},
+};
+
+/// How long to wait for a single frame, covering an on-demand camera being
+/// opened.
+pub const FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of frames a slow client may fall behind before it starts skipping.
+const FRAME_CHANNEL_CAPACITY: usize = 4;
+/// On-demand cameras stay open this long after the last subscriber leaves,
+/// so reloading the page does not reopen the device.
+const IDLE_CLOSE_DELAY: Duration = Duration::from_secs(10);
+const DEMAND_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+/// Held cameras tell subscribers why once a second, which is as often as the
+/// placeholder picture changes.
+const HOLD_INTERVAL: Duration = Duration::from_secs(1);
+/// Achieved frame rates are averaged over this window.
+const FPS_WINDOW: Duration = Duration::from_secs(5);
+/// Failed captures are counted as recent for this long.
+const ERROR_WINDOW: Duration = Duration::from_secs(60);
+/// Consecutive failed captures after which a camera counts as failing,
+/// so a single dropped frame does not fail health checks.
+const FAILURES_BEFORE_UNHEALTHY: u32 = 3;
+
+/// Output of a single capture attempt, shared with every subscriber.
+#[derive(Clone, Debug)]
+pub enum FrameEvent {
+    Frame {
+        data: Bytes,
+        captured_at: SystemTime,
+        /// Counts the camera's frames since startup, from 1.
+        number: u64,
+        /// Time since the frame before; `None` for the first one after the
+        /// camera was opened.
+        duration: Option<Duration>,
+        /// Whether the frame visibly differs from the last one that did;
+        /// always set unless `STREAM_SKIP_UNCHANGED` is on.
+        changed: bool,
+    },
+    /// Captures failed `CAPTURE_FALLBACK_AFTER` times in a row, or more, and
+    /// viewers get placeholders for them; the watchdog reopens the device if
+    /// it keeps failing. Sent once a second, with [`CameraError::OffDuty`]
+    /// or [`CameraError::Paused`], while the camera is held.
+    Error(Arc<CameraError>),
+}
+
+/// Why a camera keeps its device closed and sends no frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Hold {
+    /// Outside `STREAM_SCHEDULE`.
+    OffDuty,
+    /// Paused with `POST /camera/pause`.
+    Paused,
+}
+
+impl Hold {
+    fn error(self) -> CameraError {
+        match self {
+            Self::OffDuty => CameraError::OffDuty,
+            Self::Paused => CameraError::Paused,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BufferedFrame {
+    pub data: Bytes,
+    pub captured_at: SystemTime,
+}
+
+/// Ring buffer of the most recent frames, so motion-triggered recordings can
+/// start with footage from before the trigger. Frames older than `depth` are
+/// evicted as new ones arrive; a zero depth disables buffering.
+pub struct FrameHistory {
+    depth: Duration,
+    frames: std::sync::Mutex<VecDeque<(Instant, BufferedFrame)>>,
+}
+
+impl FrameHistory {
+    fn new(depth: Duration) -> Self {
+        Self {
+            depth,
+            frames: std::sync::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn push(&self, frame: BufferedFrame) {
+        if self.depth.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut frames = self.frames.lock().expect("frame history poisoned");
+        while frames
+            .front()
+            .is_some_and(|(stored_at, _)| now - *stored_at > self.depth)
+        {
+            frames.pop_front();
+        }
+        frames.push_back((now, frame));
+    }
+
+    /// Buffered frames, oldest first.
+    pub fn recent(&self) -> Vec<BufferedFrame> {
+        let frames = self.frames.lock().expect("frame history poisoned");
+        frames.iter().map(|(_, frame)| frame.clone()).collect()
+    }
+}
+
+/// A configured camera together with the broadcast its capture loop feeds.
+///
+/// The broadcast and frame history outlive pipeline restarts, so subscribers
+/// keep receiving frames when the camera is reopened with new settings.
+#[derive(Clone)]
+pub struct CameraHandle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    device: Option<String>,
+    frames: broadcast::Sender<FrameEvent>,
+    history: Arc<FrameHistory>,
+    overlay: OverlayReceiver,
+    /// Settings the camera is opened with next.
+    config: std::sync::Mutex<Config>,
+    /// `None` while an on-demand camera is idle.
+    pipeline: Mutex<Option<Pipeline>>,
+    /// Signalled when a subscriber arrives or an idle camera is opened.
+    demand: Notify,
+    /// Signalled when the camera is restarted with new settings, which the
+    /// watchdog waits for after a failure reopening cannot fix.
+    reconfigured: Notify,
+    health: Arc<Health>,
+    /// White balance emulated for devices without the controls.
+    balance: Arc<ColorBalance>,
+    /// Lowers the capture rate while the machine is overloaded.
+    load: Arc<Load>,
+    /// `true` outside `STREAM_SCHEDULE`, while the device is kept closed.
+    off_duty: watch::Receiver<bool>,
+    /// Set by `POST /camera/pause`; keeps the device closed until resumed.
+    paused: AtomicBool,
+}
+
+/// Capture health shared by the capture loop, the watchdog, `/health` and
+/// `/stats`.
+#[derive(Default)]
+struct Health {
+    consecutive_failures: AtomicU32,
+    /// Watched by webhooks so they can report outages without subscribing
+    /// to frames, which would keep an on-demand camera open.
+    reconnecting: watch::Sender<bool>,
+    reconnect_attempts: AtomicU32,
+    frames_captured: AtomicU64,
+    capture_errors: AtomicU64,
+    /// When the frames of the last `FPS_WINDOW` were captured.
+    recent_frames: std::sync::Mutex<VecDeque<Instant>>,
+    last_frame_at: std::sync::Mutex<Option<SystemTime>>,
+    /// When the device last delivered a frame, or the pipeline was started.
+    last_progress: std::sync::Mutex<Option<Instant>>,
+    /// Counts the stalls the watchdog restarted the camera for; watched by
+    /// `/events`.
+    stalls: watch::Sender<u64>,
+    /// When the captures that failed in the last `ERROR_WINDOW` did.
+    recent_errors: std::sync::Mutex<VecDeque<Instant>>,
+    /// Why the last failed capture failed.
+    last_error: std::sync::Mutex<Option<String>>,
+}
+
+impl Health {
+    /// Counts a frame and returns its number.
+    fn frame_captured(&self, captured_at: SystemTime) -> u64 {
+        let number = self.frames_captured.fetch_add(1, Ordering::Relaxed) + 1;
+        *self.last_frame_at.lock().expect("frame time poisoned") = Some(captured_at);
+        let now = Instant::now();
+        let mut recent = self.recent_frames.lock().expect("frame times poisoned");
+        trim_window(&mut recent, now, FPS_WINDOW);
+        recent.push_back(now);
+        number
+    }
+
+    /// Counts a failed capture and returns how many failed in a row before it.
+    fn capture_failed(&self, err: &CameraError) -> u32 {
+        self.capture_errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().expect("last error poisoned") = Some(err.to_string());
+        let now = Instant::now();
+        let mut recent = self.recent_errors.lock().expect("error times poisoned");
+        trim_window(&mut recent, now, ERROR_WINDOW);
+        recent.push_back(now);
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn made_progress(&self) {
+        *self.last_progress.lock().expect("progress time poisoned") = Some(Instant::now());
+    }
+
+    /// How long the device has gone without delivering a frame.
+    fn stalled_for(&self) -> Duration {
+        self.last_progress
+            .lock()
+            .expect("progress time poisoned")
+            .map_or(Duration::ZERO, |at| at.elapsed())
+    }
+
+    fn fps(&self) -> f32 {
+        let mut recent = self.recent_frames.lock().expect("frame times poisoned");
+        trim_window(&mut recent, Instant::now(), FPS_WINDOW);
+        recent.len() as f32 / FPS_WINDOW.as_secs_f32()
+    }
+
+    fn recent_errors(&self) -> usize {
+        let mut recent = self.recent_errors.lock().expect("error times poisoned");
+        trim_window(&mut recent, Instant::now(), ERROR_WINDOW);
+        recent.len()
+    }
+}
+
+fn trim_window(recent: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+    while recent.front().is_some_and(|at| now - *at > window) {
+        recent.pop_front();
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CameraStatus {
+    /// The device kept failing and is being reopened.
+    pub reconnecting: bool,
+    /// Reopen attempts since startup.
+    pub reconnect_attempts: u32,
+    /// Captures that failed since the last one that worked.
+    pub consecutive_failures: u32,
+    /// Failed captures over the last minute.
+    pub recent_errors: usize,
+    /// When the last frame was delivered; `None` before the first one.
+    pub last_frame_at: Option<SystemTime>,
+}
+
+impl CameraStatus {
+    /// Whether captures keep failing, or the device is being reopened.
+    pub fn is_failing(&self) -> bool {
+        self.reconnecting || self.consecutive_failures >= FAILURES_BEFORE_UNHEALTHY
+    }
+}
+
+/// Capture counters since startup, as reported by `/stats`.
+#[derive(Clone, Copy, Debug)]
+pub struct CaptureStats {
+    /// Frames per second actually delivered, averaged over the last few seconds.
+    pub fps: f32,
+    /// Frames per second captures are paced at: `FRAME_RATE`, lowered by
+    /// `ADAPTIVE_FRAME_RATE` while the machine is overloaded.
+    pub target_fps: f32,
+    pub frames_captured: u64,
+    pub capture_errors: u64,
+}
+
+struct Pipeline {
+    camera: Arc<dyn Camera>,
+    task: JoinHandle<()>,
+    /// Why the pipeline stands in for a closed device, if it does.
+    hold: Option<Hold>,
+}
+
+impl CameraHandle {
+    pub fn start(
+        device: Option<String>,
+        config: &Config,
+        overlay: OverlayReceiver,
+        load: Arc<Load>,
+        off_duty: watch::Receiver<bool>,
+    ) -> Self {
+        let (frames, _) = broadcast::channel(FRAME_CHANNEL_CAPACITY);
+        let mut shared = Shared {
+            device,
+            frames,
+            history: Arc::new(FrameHistory::new(config.pre_event_buffer())),
+            overlay,
+            config: std::sync::Mutex::new(config.clone()),
+            pipeline: Mutex::new(None),
+            demand: Notify::new(),
+            reconfigured: Notify::new(),
+            health: Arc::new(Health::default()),
+            balance: Arc::new(ColorBalance::default()),
+            load,
+            off_duty,
+            paused: AtomicBool::new(false),
+        };
+        // On-demand cameras stay closed until the first subscriber arrives.
+        if !config.on_demand_capture {
+            let pipeline = if let Some(hold) = shared.hold() {
+                Pipeline::held(&shared, hold)
+            } else {
+                let camera = build_camera(config, shared.device.as_deref());
+                shared.spawn_pipeline(camera, config)
+            };
+            *shared.pipeline.get_mut() = Some(pipeline);
+        }
+        let shared = Arc::new(shared);
+        if config.on_demand_capture {
+            tokio::spawn(follow_demand(shared.clone()));
+        }
+        tokio::spawn(follow_schedule(shared.clone()));
+        if shared.device.is_some() {
+            tokio::spawn(watchdog(shared.clone()));
+        }
+
+        Self { shared }
+    }
+
+    pub fn device(&self) -> Option<&str> {
+        self.shared.device.as_deref()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<FrameEvent> {
+        let frames = self.shared.frames.subscribe();
+        self.shared.demand.notify_one();
+        frames
+    }
+
+    /// The next frame broadcast, or the error the camera reports instead;
+    /// [`CameraError::Timeout`] if none comes within `timeout`.
+    pub async fn next_frame(&self, timeout: Duration) -> Result<BufferedFrame, Arc<CameraError>> {
+        let mut frames = self.subscribe();
+        let frame = async {
+            loop {
+                match frames.recv().await {
+                    Ok(FrameEvent::Frame {
+                        data, captured_at, ..
+                    }) => break Ok(BufferedFrame { data, captured_at }),
+                    Ok(FrameEvent::Error(err)) => break Err(err),
+                    Err(RecvError::Closed) => {
+                        break Err(Arc::new(CameraError::Device(
+                            "Camera capture stopped".to_string(),
+                        )))
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                }
+            }
+        };
+        time::timeout(timeout, frame)
+            .await
+            .unwrap_or_else(|_| Err(Arc::new(CameraError::Timeout)))
+    }
+
+    pub fn history(&self) -> Arc<FrameHistory> {
+        self.shared.history.clone()
+    }
+
+    /// The open camera, opening an idle on-demand camera if needed.
+    pub async fn camera(&self) -> Arc<dyn Camera> {
+        let mut pipeline = self.shared.pipeline.lock().await;
+        if pipeline.is_none() {
+            self.shared.open(&mut pipeline).await;
+            // Lets the idle timer close it again.
+            self.shared.demand.notify_one();
+        }
+        pipeline
+            .as_ref()
+            .expect("capture pipeline missing after open")
+            .camera
+            .clone()
+    }
+
+    /// Name of the open camera's backend; `None` while it is idle.
+    pub async fn backend_name(&self) -> Option<&'static str> {
+        let pipeline = self.shared.pipeline.lock().await;
+        pipeline
+            .as_ref()
+            .map(|pipeline| pipeline.camera.backend_name())
+    }
+
+    /// The format the open camera captures in; `None` while it is closed.
+    pub async fn format(&self) -> Option<CaptureFormat> {
+        let pipeline = self.shared.pipeline.lock().await;
+        pipeline
+            .as_ref()
+            .and_then(|pipeline| pipeline.camera.format())
+    }
+
+    /// What the open camera supports; `None` while it is closed.
+    pub async fn capabilities(&self) -> Option<Capabilities> {
+        let pipeline = self.shared.pipeline.lock().await;
+        pipeline
+            .as_ref()
+            .map(|pipeline| pipeline.camera.capabilities())
+    }
+
+    /// Whether the device is kept closed, outside `STREAM_SCHEDULE` or
+    /// while paused.
+    pub fn is_held(&self) -> bool {
+        self.shared.hold().is_some()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    /// Closes the device and sends subscribers a paused placeholder instead
+    /// of frames until [`resume`](Self::resume); viewers stay connected.
+    pub async fn pause(&self) {
+        self.shared.paused.store(true, Ordering::Relaxed);
+        self.shared.apply_hold().await;
+    }
+
+    /// Reopens the device of a paused camera, unless it is off duty.
+    pub async fn resume(&self) {
+        self.shared.paused.store(false, Ordering::Relaxed);
+        self.shared.apply_hold().await;
+    }
+
+    /// Changes whenever the watchdog starts or stops reopening the device.
+    pub fn watch_reconnecting(&self) -> watch::Receiver<bool> {
+        self.shared.health.reconnecting.subscribe()
+    }
+
+    /// Changes whenever the watchdog restarts a camera that stopped
+    /// delivering frames.
+    pub fn watch_stalls(&self) -> watch::Receiver<u64> {
+        self.shared.health.stalls.subscribe()
+    }
+
+    pub fn status(&self) -> CameraStatus {
+        let health = &self.shared.health;
+        CameraStatus {
+            reconnecting: *health.reconnecting.borrow(),
+            reconnect_attempts: health.reconnect_attempts.load(Ordering::Relaxed),
+            consecutive_failures: health.consecutive_failures.load(Ordering::Relaxed),
+            recent_errors: health.recent_errors(),
+            last_frame_at: *health.last_frame_at.lock().expect("frame time poisoned"),
+        }
+    }
+
+    /// Why the last failed capture failed, e.g. `Camera disconnected`.
+    pub fn last_error(&self) -> Option<String> {
+        self.shared
+            .health
+            .last_error
+            .lock()
+            .expect("last error poisoned")
+            .clone()
+    }
+
+    pub fn capture_stats(&self) -> CaptureStats {
+        let health = &self.shared.health;
+        let frame_rate = self
+            .shared
+            .config
+            .lock()
+            .expect("camera config poisoned")
+            .frame_rate;
+        CaptureStats {
+            fps: health.fps(),
+            target_fps: frame_rate * self.shared.load.frame_rate_share(),
+            frames_captured: health.frames_captured.load(Ordering::Relaxed),
+            capture_errors: health.capture_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Encodes frames at `quality` from now on, without reopening the camera.
+    pub async fn set_jpeg_quality(&self, quality: u8) {
+        self.shared
+            .config
+            .lock()
+            .expect("camera config poisoned")
+            .jpeg_quality = quality;
+        if let Some(pipeline) = self.shared.pipeline.lock().await.as_ref() {
+            pipeline.camera.set_jpeg_quality(quality);
+        }
+    }
+
+    /// Stops the capture loop, reopens the camera with `config` and resumes.
+    /// An idle on-demand camera only picks up `config` once it is reopened.
+    pub async fn restart(&self, config: &Config) {
+        *self.shared.config.lock().expect("camera config poisoned") = config.clone();
+        self.shared.reconfigured.notify_one();
+
+        let mut pipeline = self.shared.pipeline.lock().await;
+        if pipeline.is_none() {
+            return;
+        }
+
+        Shared::close(&mut pipeline).await;
+        self.shared.open(&mut pipeline).await;
+        tracing::info!(device = self.device(), "Capture pipeline restarted");
+    }
+}
+
+impl Shared {
+    /// Paused takes precedence, as it is lifted by hand.
+    fn hold(&self) -> Option<Hold> {
+        if self.paused.load(Ordering::Relaxed) {
+            Some(Hold::Paused)
+        } else if *self.off_duty.borrow() {
+            Some(Hold::OffDuty)
+        } else {
+            None
+        }
+    }
+
+    /// Opens the device, falling back to the mock camera when it is
+    /// unavailable; while the camera is held it stays closed.
+    async fn open(&self, pipeline: &mut Option<Pipeline>) {
+        if let Some(hold) = self.hold() {
+            *pipeline = Some(Pipeline::held(self, hold));
+            return;
+        }
+        let config = self.config.lock().expect("camera config poisoned").clone();
+        let device = self.device.clone();
+        let build_config = config.clone();
+        let camera = task::spawn_blocking(move || build_camera(&build_config, device.as_deref()))
+            .await
+            .expect("spawn_blocking failed");
+
+        *pipeline = Some(self.spawn_pipeline(camera, &config));
+    }
+
+    /// Opens the device without the mock fallback, so a camera that is still
+    /// missing is reported rather than replaced.
+    async fn reopen(&self, pipeline: &mut Option<Pipeline>) -> Result<(), CameraError> {
+        if let Some(hold) = self.hold() {
+            *pipeline = Some(Pipeline::held(self, hold));
+            return Ok(());
+        }
+        let config = self.config.lock().expect("camera config poisoned").clone();
+        let device = self.device.clone();
+        let build_config = config.clone();
+        let camera =
+            task::spawn_blocking(move || camera::open_camera(&build_config, device.as_deref()))
+                .await
+                .expect("spawn_blocking failed")?;
+
+        *pipeline = Some(self.spawn_pipeline(camera, &config));
+        Ok(())
+    }
+
+    fn spawn_pipeline(&self, camera: Arc<dyn Camera>, config: &Config) -> Pipeline {
+        Pipeline::spawn(camera, config, self)
+    }
+
+    /// Swaps the device for a stand-in, or back, after the camera was paused
+    /// or resumed or went off or on duty. An idle on-demand camera is left
+    /// for the next subscriber to open.
+    async fn apply_hold(&self) {
+        let mut pipeline = self.pipeline.lock().await;
+        let hold = self.hold();
+        if pipeline
+            .as_ref()
+            .is_none_or(|pipeline| pipeline.hold == hold)
+        {
+            return;
+        }
+        Self::close(&mut pipeline).await;
+        self.open(&mut pipeline).await;
+        tracing::debug!(
+            device = self.device.as_deref(),
+            ?hold,
+            "Camera hold changed"
+        );
+    }
+
+    async fn close(pipeline: &mut Option<Pipeline>) {
+        if let Some(old) = pipeline.take() {
+            old.task.abort();
+            let _ = old.task.await;
+            // The device has to be released before it can be opened again,
+            // which waits for its capture thread.
+            let camera = old.camera;
+            task::spawn_blocking(move || drop(camera))
+                .await
+                .expect("spawn_blocking failed");
+        }
+    }
+}
+
+/// Opens an on-demand camera while anyone is subscribed to its frames, and
+/// closes it once nobody has been for `IDLE_CLOSE_DELAY`. Motion detection,
+/// recording and MQTT stay subscribed, so they keep the camera open.
+async fn follow_demand(shared: Arc<Shared>) {
+    let mut idle_since: Option<Instant> = None;
+
+    loop {
+        let mut pipeline = shared.pipeline.lock().await;
+        if shared.frames.receiver_count() > 0 {
+            idle_since = None;
+            if pipeline.is_none() {
+                shared.open(&mut pipeline).await;
+                tracing::info!(device = shared.device.as_deref(), "Camera opened on demand");
+            }
+        } else if pipeline.is_some() {
+            let since = *idle_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= IDLE_CLOSE_DELAY {
+                Shared::close(&mut pipeline).await;
+                idle_since = None;
+                tracing::info!(device = shared.device.as_deref(), "Camera idle; closed");
+            }
+        }
+        let idle = pipeline.is_none();
+        drop(pipeline);
+
+        if idle {
+            shared.demand.notified().await;
+        } else {
+            // Subscribers leave without notice, so poll while open.
+            let _ = time::timeout(DEMAND_POLL_INTERVAL, shared.demand.notified()).await;
+        }
+    }
+}
+
+/// Closes the device when the camera goes off duty and opens it again when
+/// it is back on.
+async fn follow_schedule(shared: Arc<Shared>) {
+    let mut off_duty = shared.off_duty.clone();
+    while off_duty.changed().await.is_ok() {
+        off_duty.borrow_and_update();
+        shared.apply_hold().await;
+    }
+}
+
+/// Closes and reopens a device whose captures keep failing, e.g. after the
+/// USB camera was unplugged or its driver wedged, or that silently stopped
+/// delivering frames, as some UVC drivers do until reopened. Backs off
+/// exponentially while the device stays unavailable, as set by the
+/// `CAPTURE_RECONNECT_*` settings, and stops retrying when it rejects the
+/// configured formats until the settings change.
+async fn watchdog(shared: Arc<Shared>) {
+    let health = shared.health.clone();
+    let mut ticker = interval(WATCHDOG_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+        let config = shared
+            .config
+            .lock()
+            .expect("camera config poisoned")
+            .clone();
+        if health.consecutive_failures.load(Ordering::Relaxed) >= config.capture_reconnect_after {
+            tracing::warn!(device = shared.device.as_deref(), "Camera keeps failing");
+        } else if let Some(stalled_for) = stall(&shared).await {
+            tracing::warn!(
+                device = shared.device.as_deref(),
+                ?stalled_for,
+                "Camera stopped delivering frames"
+            );
+            health.stalls.send_modify(|stalls| *stalls += 1);
+        } else {
+            continue;
+        }
+
+        health.reconnecting.send_replace(true);
+        let mut delay = config.capture_reconnect_delay();
+        loop {
+            let mut pipeline = shared.pipeline.lock().await;
+            // Closed by on-demand capture or held meanwhile; the next open
+            // starts afresh.
+            if pipeline
+                .as_ref()
+                .is_none_or(|pipeline| pipeline.hold.is_some())
+            {
+                break;
+            }
+
+            let attempt = health.reconnect_attempts.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::warn!(
+                device = shared.device.as_deref(),
+                attempt,
+                "Reopening camera"
+            );
+            Shared::close(&mut pipeline).await;
+            match shared.reopen(&mut pipeline).await {
+                Ok(()) => {
+                    tracing::info!(
+                        device = shared.device.as_deref(),
+                        attempt,
+                        "Camera reconnected"
+                    );
+                    break;
+                }
+                Err(err) => {
+                    let config = shared
+                        .config
+                        .lock()
+                        .expect("camera config poisoned")
+                        .clone();
+                    *pipeline = Some(shared.spawn_pipeline(Arc::new(Disconnected), &config));
+                    if err.is_fatal() {
+                        tracing::error!(device = shared.device.as_deref(), attempt, error = %err, "Camera cannot be reopened with these settings; waiting for a configuration change");
+                        drop(pipeline);
+                        shared.reconfigured.notified().await;
+                        break;
+                    }
+                    tracing::warn!(device = shared.device.as_deref(), attempt, retry_in = ?delay, error = %err, "Camera reconnect failed");
+                }
+            }
+            drop(pipeline);
+
+            time::sleep(delay).await;
+            delay = (delay * 2).min(config.capture_reconnect_max_delay());
+        }
+        health.consecutive_failures.store(0, Ordering::Relaxed);
+        health.reconnecting.send_replace(false);
+    }
+}
+
+/// How long an open camera has gone without a frame, once that exceeds
+/// `CAPTURE_STALL_SECS`.
+async fn stall(shared: &Shared) -> Option<Duration> {
+    let timeout = shared
+        .config
+        .lock()
+        .expect("camera config poisoned")
+        .capture_stall_timeout()?;
+    let stalled_for = shared.health.stalled_for();
+    if stalled_for < timeout {
+        return None;
+    }
+    // An idle on-demand camera or a held one has nothing to deliver.
+    shared
+        .pipeline
+        .lock()
+        .await
+        .as_ref()
+        .filter(|pipeline| pipeline.hold.is_none())?;
+    Some(stalled_for)
+}
+
+/// Stands in for a device the watchdog could not reopen yet, so subscribers
+/// keep receiving the capture fallback and control requests fail cleanly.
+struct Disconnected;
+
+#[async_trait]
+impl Camera for Disconnected {
+    async fn capture_frame(&self) -> Result<Frame, CameraError> {
+        Err(CameraError::Disconnected("Camera disconnected".to_string()))
+    }
+
+    async fn list_controls(&self) -> Result<Vec<ControlInfo>> {
+        bail!("Camera disconnected")
+    }
+
+    async fn set_control(&self, _change: ControlChange) -> Result<()> {
+        bail!("Camera disconnected")
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "disconnected"
+    }
+}
+
+/// Stands in for the device while the camera is held, so control requests
+/// fail cleanly while it is closed.
+struct Held(Hold);
+
+#[async_trait]
+impl Camera for Held {
+    async fn capture_frame(&self) -> Result<Frame, CameraError> {
+        Err(self.0.error())
+    }
+
+    async fn list_controls(&self) -> Result<Vec<ControlInfo>> {
+        bail!(self.0.error())
+    }
+
+    async fn set_control(&self, _change: ControlChange) -> Result<()> {
+        bail!(self.0.error())
+    }
+
+    fn backend_name(&self) -> &'static str {
+        match self.0 {
+            Hold::OffDuty => "off-duty",
+            Hold::Paused => "paused",
+        }
+    }
+}
+
+impl Pipeline {
+    fn spawn(camera: Arc<dyn Camera>, config: &Config, shared: &Shared) -> Self {
+        let camera: Arc<dyn Camera> = Arc::new(SoftwareWhiteBalance::new(
+            camera,
+            shared.balance.clone(),
+            config.jpeg_quality,
+        ));
+        // A new pipeline gets the full stall timeout for its first frame.
+        shared.health.made_progress();
+        let task = tokio::spawn(capture_loop(
+            camera.clone(),
+            config.clone(),
+            shared.frames.clone(),
+            shared.history.clone(),
+            shared.overlay.clone(),
+            shared.health.clone(),
+            shared.load.clone(),
+        ));
+        Self {
+            camera,
+            task,
+            hold: None,
+        }
+    }
+
+    /// Keeps the device closed and tells subscribers why, so viewers get a
+    /// placeholder and nothing is recorded.
+    fn held(shared: &Shared, hold: Hold) -> Self {
+        // Failures from before are no reason to report the camera failing.
+        shared
+            .health
+            .consecutive_failures
+            .store(0, Ordering::Relaxed);
+        let frames = shared.frames.clone();
+        let task = tokio::spawn(async move {
+            let mut ticker = interval(HOLD_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let _ = frames.send(FrameEvent::Error(Arc::new(hold.error())));
+            }
+        });
+        Self {
+            camera: Arc::new(Held(hold)),
+            task,
+            hold: Some(hold),
+        }
+    }
+}
+
+async fn capture_loop(
+    camera: Arc<dyn Camera>,
+    config: Config,
+    frames: broadcast::Sender<FrameEvent>,
+    history: Arc<FrameHistory>,
+    overlay: OverlayReceiver,
+    health: Arc<Health>,
+    load: Arc<Load>,
+) {
+    let mut share = load.frame_rate_share();
+    let mut ticker = interval(config.frame_interval().div_f32(share));
+    let mut previous_capture: Option<SystemTime> = None;
+    let mut previous_sequence = 0;
+    // Analysis frame of the last changed frame.
+    let mut reference: Option<GrayImage> = None;
+    // Created once captures fail for long enough with `CAPTURE_FALLBACK=mock`.
+    let mut fallback: Option<MockCamera> = None;
+
+    loop {
+        ticker.tick().await;
+        if load.frame_rate_share() != share {
+            share = load.frame_rate_share();
+            let period = config.frame_interval().div_f32(share);
+            ticker = interval_at(time::Instant::now() + period, period);
+        }
+        let started = Instant::now();
+        let span = tracing::info_span!(
+            "capture_frame",
+            pixel_format = tracing::field::Empty,
+            width = tracing::field::Empty,
+            height = tracing::field::Empty,
+            sequence = tracing::field::Empty,
+        );
+        let mut captured = camera.capture_frame().instrument(span.clone()).await;
+        for retry in 1..=config.capture_retries {
+            let Err(err) = &captured else {
+                break;
+            };
+            tracing::debug!(retry, error = %err, "Camera capture failed; retrying");
+            captured = camera.capture_frame().instrument(span.clone()).await;
+        }
+        timings::record(Stage::Capture, started.elapsed());
+        let frame = match captured {
+            Ok(frame) => {
+                span.record("pixel_format", frame.pixel_format);
+                span.record("width", frame.width);
+                span.record("height", frame.height);
+                span.record("sequence", frame.sequence);
+                if frame.sequence > previous_sequence + 1 {
+                    tracing::debug!(
+                        skipped = frame.sequence - previous_sequence - 1,
+                        "Camera backend discarded frames"
+                    );
+                }
+                previous_sequence = frame.sequence;
+                health.consecutive_failures.store(0, Ordering::Relaxed);
+                health.made_progress();
+                frame
+            }
+            Err(err) => {
+                // Only the first failure in a row is worth an error; the
+                // watchdog reports the rest while it reconnects.
+                let failures = health.capture_failed(&err) + 1;
+                if failures == 1 {
+                    tracing::error!(error = %err, "Camera capture failed");
+                } else {
+                    tracing::debug!(error = %err, "Camera capture failed");
+                }
+                if failures < config.capture_fallback_after {
+                    continue;
+                }
+                match config.capture_fallback {
+                    CaptureFallback::Placeholder => {
+                        let _ = frames.send(FrameEvent::Error(Arc::new(err)));
+                        continue;
+                    }
+                    CaptureFallback::Mock => {
+                        let mock = fallback.get_or_insert_with(|| camera::mock_camera(&config));
+                        match mock.capture_frame().await {
+                            Ok(frame) => frame,
+                            Err(err) => {
+                                let _ = frames.send(FrameEvent::Error(Arc::new(err)));
+                                continue;
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        let captured_at = frame.timestamp;
+        let frame = frame.data;
+        let current_overlay = overlay
+            .borrow()
+            .clone()
+            .filter(|overlay| overlay.is_active());
+        let frame = match current_overlay {
+            Some(overlay) => {
+                let span = tracing::info_span!("overlay");
+                task::spawn_blocking(move || {
+                    let _entered = span.enter();
+                    match timings::time(Stage::Overlay, || overlay.apply(&frame, captured_at)) {
+                        Ok(rendered) => Some(Bytes::from(rendered)),
+                        Err(err) if overlay.hides_regions() => {
+                            tracing::warn!(error = %err, "Overlay failed; dropping frame to keep private regions hidden");
+                            None
+                        }
+                        Err(err) => {
+                            tracing::warn!(error = %err, "Overlay failed; sending frame as captured");
+                            Some(frame)
+                        }
+                    }
+                })
+                .await
+                .expect("spawn_blocking failed")
+            }
+            None => Some(frame),
+        };
+        let Some(data) = frame else {
+            continue;
+        };
+        let number = health.frame_captured(captured_at);
+        let duration = previous_capture
+            .replace(captured_at)
+            .and_then(|previous| captured_at.duration_since(previous).ok());
+        history.push(BufferedFrame {
+            data: data.clone(),
+            captured_at,
+        });
+        let changed = !config.stream_skip_unchanged || frame_changed(&mut reference, &data).await;
+        let event = FrameEvent::Frame {
+            data,
+            captured_at,
+            number,
+            duration,
+            changed,
+        };
+        // Sending only fails while nobody is subscribed, which is fine.
+        let _ = frames.send(event);
+    }
+}
+
+/// Whether `data` visibly differs from `reference`, which it replaces if so.
+/// Frames that cannot be analysed count as changed.
+async fn frame_changed(reference: &mut Option<GrayImage>, data: &Bytes) -> bool {
+    let source = data.clone();
+    let current = match task::spawn_blocking(move || motion::analysis_frame(&source))
+        .await
+        .expect("spawn_blocking failed")
+    {
+        Ok(current) => current,
+        Err(err) => {
+            tracing::debug!(error = %err, "Change analysis failed; sending frame");
+            return true;
+        }
+    };
+    let changed = reference
+        .as_ref()
+        .is_none_or(|reference| motion::visibly_differs(reference, &current));
+    if changed {
+        *reference = Some(current);
+    }
+    changed
+}
+
+/// Waits for the next event and returns the newest one queued, dropping any
+/// older frames a slow client has not consumed yet. `None` once capture ends.
+pub async fn recv_latest(frames: &mut broadcast::Receiver<FrameEvent>) -> Option<FrameEvent> {
+    let mut latest = loop {
+        match frames.recv().await {
+            Ok(event) => break event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return None,
+        }
+    };
+
+    let mut skipped = 0;
+    loop {
+        match frames.try_recv() {
+            Ok(event) => {
+                latest = event;
+                skipped += 1;
+            }
+            Err(TryRecvError::Lagged(missed)) => skipped += missed,
+            Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+        }
+    }
+    if skipped > 0 {
+        tracing::debug!(skipped, "Client behind; skipping to latest frame");
+    }
+    Some(latest)
+}
+
+fn build_camera(config: &Config, device: Option<&str>) -> Arc<dyn Camera> {
+    camera::open_camera(config, device).unwrap_or_else(|err| {
+        tracing::error!(device, error = %err, "Falling back to mock camera");
+        Arc::new(camera::mock_camera(config))
+    })
+}
@@ -0,0 +1,168 @@
+use std::{fmt::Write as _, path::PathBuf};
+
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    camera::{DeviceInfo, FrameRates},
+    config::Config,
+};
+
+/// Streams a Raspberry Pi (or any V4L2) camera over HTTP.
+///
+/// Settings come from environment variables (and `.env`), then the optional
+/// config file; the options below override both for a single run.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    /// TOML config file, overriding CONFIG_FILE
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub overrides: Overrides,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    pub fn config_source(&self) -> ConfigSource {
+        ConfigSource {
+            file: self.config.clone(),
+            overrides: self.overrides.clone(),
+        }
+    }
+}
+
+/// Where the configuration came from, kept by the server so it can be
+/// reloaded with the same file and command-line overrides.
+#[derive(Clone)]
+pub struct ConfigSource {
+    file: Option<PathBuf>,
+    overrides: Overrides,
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<Config> {
+        let mut config = Config::load(self.file.as_deref())?;
+        self.overrides.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the streaming server (the default)
+    Serve,
+    /// List V4L2 capture devices with their formats, sizes and frame rates
+    ListDevices,
+    /// Capture a single frame as the camera delivers it, without overlays
+    Snapshot {
+        /// Where to write the JPEG
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Camera id, as listed at `/cameras`
+        #[arg(long, default_value_t = 0)]
+        camera: usize,
+    },
+    /// Load and validate the configuration, then print it
+    CheckConfig,
+}
+
+#[derive(Args, Clone)]
+pub struct Overrides {
+    /// V4L2 device to open, or empty for the mock camera; replaces CAMERA_DEVICE and CAMERAS
+    #[arg(long, global = true)]
+    pub device: Option<String>,
+    /// Frame width, overriding FRAME_WIDTH
+    #[arg(long, global = true)]
+    pub width: Option<u32>,
+    /// Frame height, overriding FRAME_HEIGHT
+    #[arg(long, global = true)]
+    pub height: Option<u32>,
+    /// Frames per second, overriding FRAME_RATE
+    #[arg(long, global = true)]
+    pub frame_rate: Option<f32>,
+    /// Listen port, overriding BACKEND_PORT
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+}
+
+impl Overrides {
+    /// Applies the options that were given; the caller re-validates.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(device) = &self.device {
+            config.camera_device = (!device.is_empty()).then(|| device.clone());
+            config.cameras.clear();
+        }
+        if let Some(width) = self.width {
+            config.resolution_width = width;
+        }
+        if let Some(height) = self.height {
+            config.resolution_height = height;
+        }
+        if let Some(frame_rate) = self.frame_rate {
+            config.frame_rate = frame_rate;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+    }
+}
+
+/// Human-readable listing for `list-devices`.
+pub fn format_devices(devices: &[DeviceInfo]) -> String {
+    if devices.is_empty() {
+        return "No V4L2 capture devices found\n".to_string();
+    }
+
+    let mut out = String::new();
+    for device in devices {
+        let _ = write!(out, "{}", device.path);
+        if let Some(name) = &device.name {
+            let _ = write!(out, "  {name}");
+        }
+        if let Some(driver) = &device.driver {
+            let _ = write!(out, " ({driver})");
+        }
+        out.push('\n');
+
+        for format in &device.formats {
+            let _ = writeln!(out, "  {}  {}", format.fourcc, format.description);
+            for resolution in &format.resolutions {
+                let _ = writeln!(
+                    out,
+                    "    {}x{}  {}",
+                    resolution.width,
+                    resolution.height,
+                    format_rates(&resolution.frame_rates)
+                );
+            }
+            if let Some(range) = &format.size_range {
+                let _ = writeln!(
+                    out,
+                    "    {}x{} to {}x{} in steps of {}x{}  {}",
+                    range.min_width,
+                    range.min_height,
+                    range.max_width,
+                    range.max_height,
+                    range.step_width,
+                    range.step_height,
+                    format_rates(&range.frame_rates)
+                );
+            }
+        }
+    }
+    out
+}
+
+fn format_rates(rates: &FrameRates) -> String {
+    match rates {
+        FrameRates::Discrete { values } => {
+            let values: Vec<String> = values.iter().map(|fps| format!("{fps}")).collect();
+            format!("{} fps", values.join(", "))
+        }
+        FrameRates::Range { min, max } => format!("{min}-{max} fps"),
+        FrameRates::Unknown => "unknown fps".to_string(),
+    }
+}
@@ -1,84 +1,2570 @@
 use std::{
-    env,
+    env, fmt, fs,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
     time::Duration,
 };
 
 use anyhow::{anyhow, Context, Result};
+use chrono::{
+    format::{Item, StrftimeItems},
+    Datelike, NaiveDateTime, NaiveTime, Weekday,
+};
+use croner::Cron;
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{camera, scaler};
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Config {
+    /// Addresses served on `port`, e.g. both `0.0.0.0` and `::`.
+    #[schema(value_type = Vec<String>)]
+    pub listen_addresses: Vec<IpAddr>,
+    pub port: u16,
+    pub frame_rate: f32,
+    pub resolution_width: u32,
+    pub resolution_height: u32,
+    /// Quality (1-100) of frames the backend encodes itself: uncompressed
+    /// captures, the mock camera and frames redrawn by overlays or white
+    /// balance.
+    pub jpeg_quality: u8,
+    /// V4L2 device, camera URL, or footage to replay: a directory of JPEG
+    /// images or a Motion-JPEG video; other codecs are not supported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_device: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cameras: Vec<String>,
+    /// Which backend opens camera devices.
+    pub camera_backend: CameraBackend,
+    /// V4L2 pixel formats to try, most preferred first.
+    pub camera_formats: Vec<PixelFormat>,
+    /// How RAW Bayer frames are interpolated to RGB.
+    pub bayer_demosaic: Demosaic,
+    /// What the mock camera shows.
+    pub mock_pattern: MockPattern,
+    /// Logs in to network cameras given by URL.
+    #[serde(skip)]
+    pub upstream_auth: Option<UpstreamAuth>,
+    #[serde(skip)]
+    pub auth: AuthConfig,
+    #[serde(skip)]
+    pub tls: Option<TlsConfig>,
+    /// Extra plain-HTTP port served next to HTTPS when TLS is enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_port: Option<u16>,
+    /// Unix domain socket served instead of the TCP port.
+    #[serde(skip)]
+    pub listen_socket: Option<UnixSocketConfig>,
+    /// Path prefix every route is served under, e.g. `/cam1` behind a
+    /// reverse proxy routing by path.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_path: Option<String>,
+    /// Networks clients must, or must not, connect from.
+    #[serde(skip)]
+    pub ip_filter: Option<IpFilterConfig>,
+    /// Reverse proxies whose `X-Forwarded-For` tells the client address.
+    #[serde(skip)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Close idle cameras and reopen them when a client connects.
+    pub on_demand_capture: bool,
+    /// How long after startup `/readyz` reports cameras that have not
+    /// delivered a frame yet as starting rather than unavailable.
+    pub startup_grace_secs: u64,
+    /// An open camera that delivers no frame for this long is reopened;
+    /// zero turns the check off.
+    pub capture_stall_secs: u64,
+    /// Failed captures retried right away before they count as failures.
+    pub capture_retries: u32,
+    /// Consecutive failures after which the watchdog reopens the device.
+    pub capture_reconnect_after: u32,
+    /// Delay before the second reopen attempt, doubled after each further
+    /// one up to `capture_reconnect_max_delay_secs`.
+    pub capture_reconnect_delay_secs: u64,
+    pub capture_reconnect_max_delay_secs: u64,
+    /// What viewers get while captures fail.
+    pub capture_fallback: CaptureFallback,
+    /// Consecutive failures before `capture_fallback` kicks in; until then
+    /// failed frames are skipped and viewers keep the last good one.
+    pub capture_fallback_after: u32,
+    /// MJPEG and WebSocket clients served at once, across all cameras.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_stream_clients: Option<usize>,
+    /// Outbound stream bandwidth in kbit/s, across all viewers; streams are
+    /// degraded while it is exceeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bandwidth_kbps: Option<u32>,
+    /// Live streams are scaled down to at most this width, while recordings
+    /// and snapshots keep the captured resolution.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_width: Option<u32>,
+    /// Viewers only get frames that visibly differ from the last one that
+    /// did, plus a repeat every few seconds to keep connections open.
+    pub stream_skip_unchanged: bool,
+    /// When cameras stream and record; always when unset.
+    #[serde(skip)]
+    pub stream_schedule: Option<StreamSchedule>,
+    /// Stamp every viewer's stream with who they are where the credentials
+    /// tell, or else their address, so leaked footage can be traced.
+    pub viewer_watermark: bool,
+    pub viewer_watermark_corner: OverlayCorner,
+    /// JSON lines file every stream, snapshot, recording, configuration,
+    /// camera control and shared link access is appended to; nothing is
+    /// logged when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub audit_log: Option<PathBuf>,
+    /// Lower the capture rate while the machine is overloaded, too hot or
+    /// throttled, and raise it back once it recovers.
+    pub adaptive_frame_rate: bool,
+    /// CPU usage across all cores, in percent, above which
+    /// `adaptive_frame_rate` lowers the capture rate.
+    pub adaptive_max_cpu: u8,
+    /// SoC temperature in °C above which `adaptive_frame_rate` lowers the
+    /// capture rate.
+    pub adaptive_max_temperature: f32,
+    pub motion_detection: bool,
+    /// Per-pixel luma difference (0-255) that counts as change.
+    pub motion_threshold: u8,
+    /// Percentage of changed pixels that counts as motion.
+    pub motion_min_area: f32,
+    /// Where motion-triggered clips are written; recording is off when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub recordings_dir: Option<PathBuf>,
+    pub recording_format: RecordingFormat,
+    /// How much footage from before the trigger each recording starts with.
+    pub recording_pre_motion_secs: u64,
+    /// How long recording continues after motion has stopped.
+    pub recording_post_motion_secs: u64,
+    /// Clips older than this are deleted; kept forever when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recording_max_age_hours: Option<u64>,
+    /// Oldest clips are deleted while the directory holds more than this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recording_max_size_mb: Option<u64>,
+    #[serde(skip)]
+    pub mqtt: Option<MqttConfig>,
+    /// Periodic stills written to disk; off unless `SNAPSHOT_DIR` is set.
+    #[serde(skip)]
+    pub snapshots: Option<SnapshotSchedule>,
+    /// Bucket that finished recordings and scheduled snapshots are copied to.
+    #[serde(skip)]
+    pub s3: Option<S3Config>,
+    /// URLs notified of motion, finished recordings and camera outages.
+    /// Skipped when serializing, as they often embed a secret token.
+    #[serde(skip)]
+    pub webhooks: Option<WebhookConfig>,
+    #[serde(skip)]
+    pub audio: Option<AudioConfig>,
+    /// Object detection model run on sampled frames; off when unset.
+    #[serde(skip)]
+    pub detection: Option<DetectionConfig>,
+    /// Model whose detections are blurred out of every frame; off when unset.
+    #[serde(skip)]
+    pub privacy_blur: Option<PrivacyBlurConfig>,
+    /// Decides when it is night, e.g. for the IR illuminator.
+    #[serde(skip)]
+    pub night_switch: NightSwitch,
+    /// Pin switching an IR illuminator or IR-cut filter at night.
+    #[serde(skip)]
+    pub ir_gpio: Option<IrGpioConfig>,
+    /// Camera settings applied when it turns day.
+    #[serde(skip)]
+    pub day_profile: Option<ControlProfile>,
+    /// Camera settings applied when it turns night, e.g. longer exposures.
+    #[serde(skip)]
+    pub night_profile: Option<ControlProfile>,
+    /// Burn the capture time into every frame.
+    pub overlay_timestamp: bool,
+    /// chrono `strftime` format of the timestamp overlay.
+    pub overlay_timestamp_format: String,
+    /// Corner of the text label holding the caption and timestamp.
+    pub overlay_corner: OverlayCorner,
+    /// Static text such as the camera name or location.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overlay_caption: Option<String>,
+    /// Add a warning to the label while the Pi firmware throttles the SoC.
+    pub overlay_throttle_warning: bool,
+    /// PNG composited onto frames, honouring its alpha channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub overlay_watermark_path: Option<PathBuf>,
+    pub overlay_watermark: bool,
+    pub overlay_watermark_corner: OverlayCorner,
+    /// Software adjustments from -100 to 100, applied before the overlay is
+    /// drawn; 0 leaves frames as captured.
+    pub image_brightness: i16,
+    pub image_contrast: i16,
+    pub image_saturation: i16,
+    pub image_grayscale: bool,
+    /// Regions hidden on every frame before it is broadcast or recorded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub privacy_masks: Vec<PrivacyMask>,
+    pub privacy_mask_style: MaskStyle,
+    /// Quality JPEG snapshots are re-encoded at; `None` serves the frame as
+    /// captured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot_jpeg_quality: Option<u8>,
+    pub snapshot_webp_quality: u8,
+}
+
+/// Container used for motion-triggered clips. Both hold the camera's JPEG
+/// frames as-is; MP4 additionally keeps the real capture timing. Either way
+/// the clips are Motion-JPEG, which browsers and QuickTime cannot play, so
+/// AVI stays the default until clips can be encoded as H.264.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    Mp4,
+    Avi,
+}
+
+impl RecordingFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Avi => "avi",
+        }
+    }
+}
+
+impl FromStr for RecordingFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "mp4" => Ok(Self::Mp4),
+            "avi" => Ok(Self::Avi),
+            _ => Err(anyhow!("expected mp4 or avi")),
+        }
+    }
+}
+
+/// Pixel formats V4L2 cameras can be opened with, by FourCC. All but `MJPG`
+/// are compressed to JPEG in software.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PixelFormat {
+    Mjpg,
+    /// Packed 4:2:2 YUV, luma first.
+    Yuyv,
+    /// Packed 4:2:2 YUV, chroma first.
+    Uyvy,
+    /// 4:2:0 YUV with a luma plane and an interleaved chroma plane.
+    Nv12,
+    /// RGB565.
+    #[serde(alias = "RGB565")]
+    Rgbp,
+    /// 8-bit greyscale.
+    Grey,
+    /// 8-bit RAW Bayer, BGGR order. Needs the `bayer` feature.
+    Sbggr8,
+    /// 10-bit RAW Bayer, BGGR order, one sample per 16 bits. Needs the
+    /// `bayer` feature.
+    Sbggr10,
+}
+
+impl PixelFormat {
+    /// Tried in this order unless `CAMERA_FORMATS` says otherwise; MJPG
+    /// needs the least bandwidth and no encoding, and RAW Bayer the most
+    /// CPU.
+    pub fn default_order() -> Vec<PixelFormat> {
+        let mut formats = vec![
+            Self::Mjpg,
+            Self::Yuyv,
+            Self::Uyvy,
+            Self::Nv12,
+            Self::Rgbp,
+            Self::Grey,
+        ];
+        if cfg!(feature = "bayer") {
+            formats.extend([Self::Sbggr8, Self::Sbggr10]);
+        }
+        formats
+    }
+
+    pub fn is_bayer(self) -> bool {
+        matches!(self, Self::Sbggr8 | Self::Sbggr10)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn fourcc(self) -> &'static str {
+        match self {
+            Self::Mjpg => "MJPG",
+            Self::Yuyv => "YUYV",
+            Self::Uyvy => "UYVY",
+            Self::Nv12 => "NV12",
+            Self::Rgbp => "RGBP",
+            Self::Grey => "GREY",
+            Self::Sbggr8 => "BA81",
+            Self::Sbggr10 => "BG10",
+        }
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_uppercase().as_str() {
+            "MJPG" => Ok(Self::Mjpg),
+            "YUYV" => Ok(Self::Yuyv),
+            "UYVY" => Ok(Self::Uyvy),
+            "NV12" => Ok(Self::Nv12),
+            "RGBP" | "RGB565" => Ok(Self::Rgbp),
+            "GREY" => Ok(Self::Grey),
+            "SBGGR8" | "BA81" => Ok(Self::Sbggr8),
+            "SBGGR10" | "BG10" => Ok(Self::Sbggr10),
+            _ => Err(anyhow!(
+                "expected MJPG, YUYV, UYVY, NV12, RGBP (RGB565), GREY, SBGGR8 or SBGGR10"
+            )),
+        }
+    }
+}
+
+/// Interpolation filling in the two colours each RAW Bayer pixel lacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Demosaic {
+    /// Each 2x2 cell becomes four pixels of one colour: fastest, at half
+    /// the colour resolution.
+    Nearest,
+    /// Averages the neighbouring samples of each missing colour.
+    Bilinear,
+}
+
+impl FromStr for Demosaic {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Self::Nearest),
+            "bilinear" => Ok(Self::Bilinear),
+            _ => Err(anyhow!("expected nearest or bilinear")),
+        }
+    }
+}
+
+/// Backend that opens camera devices. Which ones exist depends on the
+/// platform the server was built for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CameraBackend {
+    /// Picked per device: URLs are re-served, paths of footage replayed and
+    /// anything else opened with the platform's native backend.
+    Auto,
+    /// Video4Linux2, on Linux.
+    V4l2,
+    /// Media Foundation, on Windows with the `media-foundation` feature.
+    MediaFoundation,
+    /// AVFoundation, on macOS with the `av-foundation` feature.
+    AvFoundation,
+    /// Footage replayed from disk.
+    File,
+    /// Another camera's network stream.
+    Upstream,
+    /// Generated test frames; devices are ignored.
+    Mock,
+}
+
+impl CameraBackend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::V4l2 => "v4l2",
+            Self::MediaFoundation => "mediafoundation",
+            Self::AvFoundation => "avfoundation",
+            Self::File => "file",
+            Self::Upstream => "upstream",
+            Self::Mock => "mock",
+        }
+    }
+}
+
+impl FromStr for CameraBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "v4l2" => Ok(Self::V4l2),
+            "mediafoundation" => Ok(Self::MediaFoundation),
+            "avfoundation" => Ok(Self::AvFoundation),
+            "file" => Ok(Self::File),
+            "upstream" => Ok(Self::Upstream),
+            "mock" => Ok(Self::Mock),
+            _ => Err(anyhow!(
+                "expected auto, v4l2, mediafoundation, avfoundation, file, upstream or mock"
+            )),
+        }
+    }
+}
+
+/// Scene the mock camera renders, e.g. for testing clients without a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MockPattern {
+    /// Drifting colour gradient with vertical lines.
+    Gradient,
+    /// SMPTE colour bars.
+    Bars,
+    /// Random grey noise, different in every frame.
+    Noise,
+    /// A box bouncing off the edges.
+    Box,
+    /// A QR code of the frame's sequence number and capture time, for
+    /// measuring latency and dropped frames end to end.
+    Qr,
+}
+
+impl FromStr for MockPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "gradient" => Ok(Self::Gradient),
+            "bars" => Ok(Self::Bars),
+            "noise" => Ok(Self::Noise),
+            "box" => Ok(Self::Box),
+            "qr" => Ok(Self::Qr),
+            _ => Err(anyhow!("expected gradient, bars, noise, box or qr")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverlayCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for OverlayCorner {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "top-left" => Ok(Self::TopLeft),
+            "top-right" => Ok(Self::TopRight),
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom-right" => Ok(Self::BottomRight),
+            _ => Err(anyhow!(
+                "expected top-left, top-right, bottom-left or bottom-right"
+            )),
+        }
+    }
+}
+
+/// Polygon in fractions (0-1) of the frame size, so masks survive
+/// resolution changes. Rectangles are four-point polygons.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PrivacyMask {
+    pub points: Vec<[f32; 2]>,
+}
+
+impl FromStr for PrivacyMask {
+    type Err = anyhow::Error;
+
+    /// `x,y,w,h` for a rectangle or `x1,y1,x2,y2,x3,y3,...` for a polygon.
+    fn from_str(raw: &str) -> Result<Self> {
+        let values = raw
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .context("expected comma-separated numbers")?;
+
+        let points = match values.as_slice() {
+            &[x, y, width, height] => vec![
+                [x, y],
+                [x + width, y],
+                [x + width, y + height],
+                [x, y + height],
+            ],
+            values if values.len() >= 6 && values.len() % 2 == 0 => {
+                values.chunks(2).map(|point| [point[0], point[1]]).collect()
+            }
+            _ => return Err(anyhow!("expected x,y,w,h or at least three x,y points")),
+        };
+        Ok(Self { points })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskStyle {
+    Black,
+    Pixelate,
+}
+
+/// What stands in for the camera's frames while its captures fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureFallback {
+    /// The "camera unavailable" picture.
+    Placeholder,
+    /// The mock camera's test pattern.
+    Mock,
+}
+
+impl FromStr for CaptureFallback {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "placeholder" => Ok(Self::Placeholder),
+            "mock" => Ok(Self::Mock),
+            _ => Err(anyhow!("expected placeholder or mock")),
+        }
+    }
+}
+
+impl FromStr for MaskStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "black" => Ok(Self::Black),
+            "pixelate" => Ok(Self::Pixelate),
+            _ => Err(anyhow!("expected black or pixelate")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// Unix domain socket for a reverse proxy on the same host, which then
+/// needs no TCP port at all.
+#[derive(Clone, Debug)]
+// Only bound on Unix; elsewhere LISTEN_SOCKET is refused at startup.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    /// Permission bits set on the socket once it is bound.
+    pub mode: u32,
+    /// Group the socket is handed to, by name or id, e.g. the proxy's.
+    pub group: Option<String>,
+}
+
+impl UnixSocketConfig {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let Some(path) = non_empty_var("LISTEN_SOCKET")
+            .map(PathBuf::from)
+            .or_else(|| file.listen_socket.clone())
+        else {
+            return Ok(None);
+        };
+
+        let mode = non_empty_var("LISTEN_SOCKET_MODE")
+            .or_else(|| file.listen_socket_mode.clone())
+            .map(|raw| {
+                u32::from_str_radix(raw.trim(), 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .context("Invalid LISTEN_SOCKET_MODE: expected octal permissions such as 660")
+            })
+            .transpose()?
+            .unwrap_or(0o660);
+        let group =
+            non_empty_var("LISTEN_SOCKET_GROUP").or_else(|| file.listen_socket_group.clone());
+
+        Ok(Some(Self { path, mode, group }))
+    }
+}
+
+/// Client networks let through to any route. Requests from a trusted proxy
+/// are judged by the address it forwarded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IpFilterConfig {
+    /// Empty lets every network through that is not denied.
+    pub allowed: Vec<IpNet>,
+    pub denied: Vec<IpNet>,
+}
+
+impl IpFilterConfig {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let allowed = networks_var("ALLOWED_NETWORKS", file.allowed_networks.as_deref())?;
+        let denied = networks_var("DENIED_NETWORKS", file.denied_networks.as_deref())?;
+        if allowed.is_empty() && denied.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self { allowed, denied }))
+    }
+}
+
+/// CIDR ranges, or single addresses, from a comma-separated variable or
+/// the config file.
+fn networks_var(name: &str, file: Option<&[String]>) -> Result<Vec<IpNet>> {
+    let entries: Vec<String> = match non_empty_var(name) {
+        Some(raw) => raw
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(String::from)
+            .collect(),
+        None => file.unwrap_or_default().to_vec(),
+    };
+    entries
+        .iter()
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| {
+                    anyhow!("Invalid {name} entry '{entry}': expected a CIDR range or address")
+                })
+        })
+        .collect()
+}
+
+/// Credentials protecting every route except `/health`. Never serialized, and
+/// redacted from `Debug` output so they stay out of the logs.
+#[derive(Clone, Default, PartialEq)]
+pub struct AuthConfig {
+    pub token: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub jwt: Option<JwtConfig>,
+    /// Signs the expiring links minted by `POST /links`.
+    pub link_secret: Option<String>,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some() || self.user.is_some() || self.jwt.is_some()
+    }
+}
+
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("jwt", &self.jwt)
+            .field(
+                "link_secret",
+                &self.link_secret.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+/// Credentials for cameras given by `http(s)://` or `rtsp://` URL, kept out
+/// of the URLs so they are not shown by `/cameras` or logged. Redacted from
+/// `Debug` like [`AuthConfig`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct UpstreamAuth {
+    pub user: String,
+    pub password: Option<String>,
+}
+
+impl UpstreamAuth {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let user = non_empty_var("UPSTREAM_USER").or_else(|| file.upstream_user.clone());
+        let password =
+            non_empty_var("UPSTREAM_PASSWORD").or_else(|| file.upstream_password.clone());
+        match (user, password) {
+            (Some(user), password) => Ok(Some(Self { user, password })),
+            (None, Some(_)) => Err(anyhow!("UPSTREAM_PASSWORD requires UPSTREAM_USER")),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+impl fmt::Debug for UpstreamAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpstreamAuth")
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// Where the keys for checking JWTs issued by an external auth service come
+/// from, and which claims they must carry.
+#[derive(Clone, PartialEq)]
+pub struct JwtConfig {
+    pub key: JwtKey,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum JwtKey {
+    /// Shared HS256 secret.
+    Secret(String),
+    /// JWKS endpoint publishing the issuer's public keys.
+    JwksUrl(String),
+}
+
+impl JwtConfig {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let secret = non_empty_var("JWT_SECRET").or_else(|| file.jwt_secret.clone());
+        let jwks_url = non_empty_var("JWT_JWKS_URL").or_else(|| file.jwt_jwks_url.clone());
+        let issuer = non_empty_var("JWT_ISSUER").or_else(|| file.jwt_issuer.clone());
+        let audience = non_empty_var("JWT_AUDIENCE").or_else(|| file.jwt_audience.clone());
+        let key = match (secret, jwks_url) {
+            (Some(secret), None) => JwtKey::Secret(secret),
+            (None, Some(url)) => JwtKey::JwksUrl(url),
+            (Some(_), Some(_)) => {
+                return Err(anyhow!("JWT_SECRET and JWT_JWKS_URL cannot both be set"))
+            }
+            (None, None) if issuer.is_some() || audience.is_some() => {
+                return Err(anyhow!(
+                    "JWT_ISSUER and JWT_AUDIENCE need JWT_SECRET or JWT_JWKS_URL"
+                ))
+            }
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(Self {
+            key,
+            issuer,
+            audience,
+        }))
+    }
+}
+
+impl fmt::Debug for JwtConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key = match &self.key {
+            JwtKey::Secret(_) => "<redacted>",
+            JwtKey::JwksUrl(url) => url.as_str(),
+        };
+        f.debug_struct("JwtConfig")
+            .field("key", &key)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .finish()
+    }
+}
+
+/// Broker connection and topics for MQTT publishing. Skipped when serializing
+/// and redacted from `Debug` like [`AuthConfig`].
+#[derive(Clone, PartialEq)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub topic_prefix: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Seconds between snapshot publishes; zero disables them.
+    pub snapshot_interval_secs: u64,
+}
+
+impl MqttConfig {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let Some(broker) = non_empty_var("MQTT_BROKER").or_else(|| file.mqtt_broker.clone()) else {
+            return Ok(None);
+        };
+
+        let (host, port) = broker_address(&broker).context("Invalid MQTT_BROKER")?;
+
+        let snapshot_interval_secs = env::var("MQTT_SNAPSHOT_INTERVAL_SECS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid MQTT_SNAPSHOT_INTERVAL_SECS"))
+            .transpose()?
+            .or(file.mqtt_snapshot_interval_secs)
+            .unwrap_or(60);
+
+        let config = Self {
+            host,
+            port,
+            client_id: non_empty_var("MQTT_CLIENT_ID")
+                .or_else(|| file.mqtt_client_id.clone())
+                .unwrap_or_else(|| "picam".to_string()),
+            topic_prefix: non_empty_var("MQTT_TOPIC_PREFIX")
+                .or_else(|| file.mqtt_topic_prefix.clone())
+                .map(|prefix| prefix.trim_end_matches('/').to_string())
+                .unwrap_or_else(|| "picam".to_string()),
+            user: non_empty_var("MQTT_USER").or_else(|| file.mqtt_user.clone()),
+            password: non_empty_var("MQTT_PASSWORD").or_else(|| file.mqtt_password.clone()),
+            snapshot_interval_secs,
+        };
+
+        if config.password.is_some() && config.user.is_none() {
+            return Err(anyhow!("MQTT_PASSWORD requires MQTT_USER"));
+        }
+        Ok(Some(config))
+    }
+
+    pub fn topic(&self, suffix: &str) -> String {
+        format!("{}/{suffix}", self.topic_prefix)
+    }
+
+    pub fn snapshot_interval(&self) -> Option<Duration> {
+        (self.snapshot_interval_secs > 0).then(|| Duration::from_secs(self.snapshot_interval_secs))
+    }
+}
+
+/// Splits `host[:port]`, `[ipv6]:port` or `mqtt://host:port` into a host
+/// and port, defaulting to 1883. A bare IPv6 address is taken whole.
+fn broker_address(broker: &str) -> Result<(String, u16)> {
+    if let Ok(ip) = broker.parse::<IpAddr>() {
+        return Ok((ip.to_string(), 1883));
+    }
+    let url = if broker.contains("://") {
+        reqwest::Url::parse(broker)?
+    } else {
+        reqwest::Url::parse(&format!("mqtt://{broker}"))?
+    };
+    if url.scheme() != "mqtt" {
+        return Err(anyhow!("expected host[:port] or an mqtt:// URL"));
+    }
+    let host = url
+        .host_str()
+        .filter(|host| !host.is_empty())
+        .context("no host")?;
+    // IPv6 hosts come back in brackets, which sockets do not accept.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok((host.to_string(), url.port().unwrap_or(1883)))
+}
+
+impl fmt::Debug for MqttConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("client_id", &self.client_id)
+            .field("topic_prefix", &self.topic_prefix)
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("snapshot_interval_secs", &self.snapshot_interval_secs)
+            .finish()
+    }
+}
+
+/// Stills saved on a fixed schedule, e.g. for a weather cam.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotSchedule {
+    pub dir: PathBuf,
+    /// Seconds between snapshots, aligned to local midnight so that 600
+    /// saves at :00, :10, :20 and so on.
+    pub interval_secs: u64,
+    /// Five-field cron expression in local time, such as `*/10 6-20 * * *`;
+    /// replaces `interval_secs` when set.
+    pub cron: Option<String>,
+    /// File name relative to `dir`: `{camera}` is replaced by the camera id,
+    /// then the name goes through chrono `strftime`. May contain `/`.
+    pub filename: String,
+}
+
+impl SnapshotSchedule {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let Some(dir) = non_empty_var("SNAPSHOT_DIR")
+            .map(PathBuf::from)
+            .or_else(|| file.snapshot_dir.clone())
+        else {
+            return Ok(None);
+        };
+
+        let interval_secs = env::var("SNAPSHOT_INTERVAL_SECS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid SNAPSHOT_INTERVAL_SECS"))
+            .transpose()?
+            .or(file.snapshot_interval_secs)
+            .unwrap_or(600);
+        let cron = non_empty_var("SNAPSHOT_CRON").or_else(|| file.snapshot_cron.clone());
+
+        let filename = non_empty_var("SNAPSHOT_FILENAME")
+            .or_else(|| file.snapshot_filename.clone())
+            .unwrap_or_else(|| "cam{camera}-%Y%m%d-%H%M%S.jpg".to_string());
+
+        Ok(Some(Self {
+            dir,
+            interval_secs,
+            cron,
+            filename,
+        }))
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    /// The parsed `cron` expression, if one is set.
+    pub fn cron(&self) -> Result<Option<Cron>> {
+        self.cron
+            .as_deref()
+            .map(|expression| {
+                Cron::new(expression)
+                    .parse()
+                    .map_err(|err| anyhow!("{err}"))
+            })
+            .transpose()
+    }
+}
+
+/// S3-compatible bucket for offsite copies. Skipped when serializing and
+/// redacted from `Debug` like [`AuthConfig`].
+#[derive(Clone, PartialEq)]
+pub struct S3Config {
+    /// Service URL; objects are addressed path-style as `{endpoint}/{bucket}/{key}`.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Prepended to every object key, e.g. `garage/`.
+    pub prefix: String,
+}
+
+impl S3Config {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let Some(bucket) = non_empty_var("S3_BUCKET").or_else(|| file.s3_bucket.clone()) else {
+            return Ok(None);
+        };
+
+        let region = non_empty_var("S3_REGION")
+            .or_else(|| file.s3_region.clone())
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let endpoint = non_empty_var("S3_ENDPOINT")
+            .or_else(|| file.s3_endpoint.clone())
+            .unwrap_or_else(|| format!("https://s3.{region}.amazonaws.com"))
+            .trim_end_matches('/')
+            .to_string();
+        let (Some(access_key_id), Some(secret_access_key)) = (
+            non_empty_var("S3_ACCESS_KEY_ID").or_else(|| file.s3_access_key_id.clone()),
+            non_empty_var("S3_SECRET_ACCESS_KEY").or_else(|| file.s3_secret_access_key.clone()),
+        ) else {
+            return Err(anyhow!(
+                "S3_BUCKET requires S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY"
+            ));
+        };
+
+        Ok(Some(Self {
+            endpoint,
+            bucket,
+            region,
+            access_key_id,
+            secret_access_key,
+            prefix: non_empty_var("S3_PREFIX")
+                .or_else(|| file.s3_prefix.clone())
+                .unwrap_or_default(),
+        }))
+    }
+}
+
+impl fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+/// Redacted from `Debug` like [`AuthConfig`], down to each URL's scheme and
+/// host, as the rest often carries a secret token.
+#[derive(Clone, PartialEq)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    /// Attach the current frame, base64-encoded, to motion notifications.
+    pub snapshot: bool,
+}
+
+impl WebhookConfig {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let urls: Vec<String> = non_empty_var("WEBHOOK_URLS")
+            .map(|raw| {
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .or_else(|| file.webhook_urls.clone())
+            .unwrap_or_default();
+        if urls.is_empty() {
+            return Ok(None);
+        }
+
+        let snapshot = bool_var("WEBHOOK_SNAPSHOT")?
+            .or(file.webhook_snapshot)
+            .unwrap_or(false);
+        Ok(Some(Self { urls, snapshot }))
+    }
+}
+
+impl fmt::Debug for WebhookConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let urls: Vec<String> = self.urls.iter().map(|url| url_origin(url)).collect();
+        f.debug_struct("WebhookConfig")
+            .field("urls", &urls)
+            .field("snapshot", &self.snapshot)
+            .finish()
+    }
+}
+
+/// `url` cut down to its scheme, host and port, which are safe to log.
+pub fn url_origin(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => url.origin().ascii_serialization(),
+        Err(_) => "<invalid url>".to_string(),
+    }
+}
+
+/// Microphone captured over ALSA and served at `/audio.wav`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioConfig {
+    /// ALSA PCM name, e.g. `plughw:1,0` for the second sound card.
+    pub device: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl AudioConfig {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let Some(device) = non_empty_var("AUDIO_DEVICE").or_else(|| file.audio_device.clone())
+        else {
+            return Ok(None);
+        };
+
+        let sample_rate = env::var("AUDIO_SAMPLE_RATE")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid AUDIO_SAMPLE_RATE"))
+            .transpose()?
+            .or(file.audio_sample_rate)
+            .unwrap_or(16_000);
+        let channels = env::var("AUDIO_CHANNELS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid AUDIO_CHANNELS"))
+            .transpose()?
+            .or(file.audio_channels)
+            .unwrap_or(1);
+        Ok(Some(Self {
+            device,
+            sample_rate,
+            channels,
+        }))
+    }
+}
+
+/// ONNX object detection, e.g. a YOLOv8n export, run on sampled frames.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DetectionConfig {
+    pub model: PathBuf,
+    /// Frames analysed per second, per camera.
+    pub fps: f32,
+    /// Minimum class score (0-1) that counts as a detection.
+    pub confidence: f32,
+    /// Classes reported; others are ignored.
+    pub labels: Vec<String>,
+}
+
+impl DetectionConfig {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let Some(model) = non_empty_var("DETECTION_MODEL")
+            .map(PathBuf::from)
+            .or_else(|| file.detection_model.clone())
+        else {
+            return Ok(None);
+        };
+
+        let fps = env::var("DETECTION_FPS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid DETECTION_FPS"))
+            .transpose()?
+            .or(file.detection_fps)
+            .unwrap_or(1.0);
+        let confidence = env::var("DETECTION_CONFIDENCE")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid DETECTION_CONFIDENCE"))
+            .transpose()?
+            .or(file.detection_confidence)
+            .unwrap_or(0.5);
+        let labels = non_empty_var("DETECTION_LABELS")
+            .map(|raw| {
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|label| !label.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .or_else(|| file.detection_labels.clone())
+            .unwrap_or_else(|| {
+                [
+                    "person",
+                    "bicycle",
+                    "car",
+                    "motorcycle",
+                    "bus",
+                    "truck",
+                    "bird",
+                    "cat",
+                    "dog",
+                ]
+                .map(String::from)
+                .to_vec()
+            });
+        Ok(Some(Self {
+            model,
+            fps,
+            confidence,
+            labels,
+        }))
+    }
+}
+
+/// Faces or people found by an ONNX model and blurred before frames are
+/// streamed or recorded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrivacyBlurConfig {
+    pub model: PathBuf,
+    /// Minimum class score (0-1) that gets blurred; kept low, as a missed
+    /// face costs more than a needless blur.
+    pub confidence: f32,
+    /// Classes blurred, e.g. `person`, or `class0` for a single-class face
+    /// model.
+    pub labels: Vec<String>,
+}
+
+impl PrivacyBlurConfig {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let Some(model) = non_empty_var("PRIVACY_BLUR_MODEL")
+            .map(PathBuf::from)
+            .or_else(|| file.privacy_blur_model.clone())
+        else {
+            return Ok(None);
+        };
+
+        let confidence = env::var("PRIVACY_BLUR_CONFIDENCE")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid PRIVACY_BLUR_CONFIDENCE"))
+            .transpose()?
+            .or(file.privacy_blur_confidence)
+            .unwrap_or(0.25);
+        let labels = non_empty_var("PRIVACY_BLUR_LABELS")
+            .map(|raw| {
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|label| !label.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .or_else(|| file.privacy_blur_labels.clone())
+            .unwrap_or_else(|| vec!["person".to_string()]);
+        Ok(Some(Self {
+            model,
+            confidence,
+            labels,
+        }))
+    }
+}
+
+/// What decides between day and night.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NightSwitch {
+    /// Night between two local times, set with `NIGHT_SCHEDULE`.
+    Schedule(TimeWindow),
+    /// Night once the first camera's average luma (0-255) drops below
+    /// `night_below`, day again once it rises above `day_above`. The gap
+    /// keeps the illuminator's own light from switching it straight back.
+    Luminance { night_below: u8, day_above: u8 },
+}
+
+impl NightSwitch {
+    const DEFAULT_NIGHT_LUMA: u8 = 40;
+    const DEFAULT_DAY_LUMA: u8 = 100;
+
+    fn load(file: &FileConfig) -> Result<Self> {
+        if let Some(schedule) = non_empty_var("NIGHT_SCHEDULE")
+            .or_else(|| file.night_schedule.clone())
+            .map(|raw| raw.parse().context("Invalid NIGHT_SCHEDULE"))
+            .transpose()?
+        {
+            return Ok(Self::Schedule(schedule));
+        }
+
+        let night_below = env::var("NIGHT_LUMA")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid NIGHT_LUMA"))
+            .transpose()?
+            .or(file.night_luma)
+            .unwrap_or(Self::DEFAULT_NIGHT_LUMA);
+        let day_above = env::var("DAY_LUMA")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid DAY_LUMA"))
+            .transpose()?
+            .or(file.day_luma)
+            .unwrap_or(Self::DEFAULT_DAY_LUMA);
+        Ok(Self::Luminance {
+            night_below,
+            day_above,
+        })
+    }
+}
+
+impl Default for NightSwitch {
+    fn default() -> Self {
+        Self::Luminance {
+            night_below: Self::DEFAULT_NIGHT_LUMA,
+            day_above: Self::DEFAULT_DAY_LUMA,
+        }
+    }
+}
+
+/// Local times at which a period such as night starts and ends; may span
+/// midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&time)
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = anyhow::Error;
+
+    /// `HH:MM-HH:MM`, e.g. `19:30-06:45`.
+    fn from_str(raw: &str) -> Result<Self> {
+        let (start, end) = raw
+            .split_once('-')
+            .context("expected start and end time such as 19:00-07:00")?;
+        let time = |raw: &str| {
+            NaiveTime::parse_from_str(raw.trim(), "%H:%M")
+                .with_context(|| format!("expected HH:MM, got {raw:?}"))
+        };
+        Ok(Self {
+            start: time(start)?,
+            end: time(end)?,
+        })
+    }
+}
+
+/// Days and hours cameras stream and record in, set with `STREAM_SCHEDULE`;
+/// outside them devices are closed and viewers get an off-duty picture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamSchedule {
+    periods: Vec<ActivePeriod>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ActivePeriod {
+    days: Vec<Weekday>,
+    /// Hours of each of `days`; the whole day when `None`. A window spanning
+    /// midnight starts on one of `days` and runs into the next morning.
+    hours: Option<TimeWindow>,
+}
+
+impl ActivePeriod {
+    fn contains(&self, at: NaiveDateTime) -> bool {
+        let (day, time) = (at.weekday(), at.time());
+        match self.hours {
+            None => self.days.contains(&day),
+            Some(hours) if hours.start < hours.end => {
+                self.days.contains(&day) && hours.contains(time)
+            }
+            // Before the end, the window started the day before.
+            Some(hours) if time < hours.end => self.days.contains(&day.pred()),
+            Some(hours) => self.days.contains(&day) && time >= hours.start,
+        }
+    }
+}
+
+impl StreamSchedule {
+    pub fn is_active(&self, at: NaiveDateTime) -> bool {
+        self.periods.iter().any(|period| period.contains(at))
+    }
+}
+
+impl FromStr for StreamSchedule {
+    type Err = anyhow::Error;
+
+    /// Comma-separated periods of optional days and optional hours, such as
+    /// `sun-fri 18:00-08:00, sat-sun`: every evening through the next
+    /// morning, and all weekend.
+    fn from_str(raw: &str) -> Result<Self> {
+        let periods = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|period| !period.is_empty())
+            .map(|period| {
+                let (days, hours) = match period.split_once(char::is_whitespace) {
+                    Some((days, hours)) => (Some(days), Some(hours.trim())),
+                    None if period.contains(':') => (None, Some(period)),
+                    None => (Some(period), None),
+                };
+                let hours = hours
+                    .map(|hours| {
+                        let hours: TimeWindow = hours.parse()?;
+                        if hours.start == hours.end {
+                            return Err(anyhow!(
+                                "{period:?} must start and end at different times"
+                            ));
+                        }
+                        Ok(hours)
+                    })
+                    .transpose()?;
+                Ok(ActivePeriod {
+                    days: days.map_or_else(|| Ok(ALL_DAYS.to_vec()), parse_days)?,
+                    hours,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if periods.is_empty() {
+            return Err(anyhow!(
+                "expected at least one period such as mon-fri 18:00-08:00"
+            ));
+        }
+        Ok(Self { periods })
+    }
+}
+
+const ALL_DAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// A day such as `sat`, or a range such as `mon-fri` or `fri-mon`.
+fn parse_days(raw: &str) -> Result<Vec<Weekday>> {
+    let day = |raw: &str| {
+        raw.parse::<Weekday>()
+            .map_err(|_| anyhow!("expected a day such as mon, got {raw:?}"))
+    };
+    let (first, last) = match raw.split_once('-') {
+        Some((first, last)) => (day(first)?, day(last)?),
+        None => (day(raw)?, day(raw)?),
+    };
+    let mut days = vec![first];
+    let mut next = first;
+    while next != last {
+        next = next.succ();
+        days.push(next);
+    }
+    Ok(days)
+}
+
+/// Control names accepted in profiles, with their V4L2 ids.
+const PROFILE_CONTROLS: &[(&str, u32)] = &[
+    ("brightness", 0x0098_0900),
+    ("contrast", 0x0098_0901),
+    ("auto_white_balance", 0x0098_090c),
+    ("gain", 0x0098_0913),
+    ("red_balance", 0x0098_090e),
+    ("blue_balance", 0x0098_090f),
+    ("white_balance_temperature", 0x0098_091a),
+    ("auto_exposure", 0x009a_0901),
+    ("exposure", 0x009a_0902),
+];
+
+/// Frame rate and device controls switched together by the day/night switch.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ControlProfile {
+    pub frame_rate: Option<f32>,
+    /// Control ids and values, applied in order to every camera.
+    pub controls: Vec<(u32, i64)>,
+}
+
+impl ControlProfile {
+    fn load(name: &str, file_value: Option<&String>) -> Result<Option<Self>> {
+        non_empty_var(name)
+            .or_else(|| file_value.cloned())
+            .map(|raw| raw.parse().with_context(|| format!("Invalid {name}")))
+            .transpose()
+    }
+}
+
+impl FromStr for ControlProfile {
+    type Err = anyhow::Error;
+
+    /// Comma-separated `name=value` pairs such as
+    /// `frame_rate=5,auto_exposure=1,exposure=1000`. Names are `frame_rate`,
+    /// one of `PROFILE_CONTROLS` or a numeric control id as listed by `/controls`.
+    fn from_str(raw: &str) -> Result<Self> {
+        let mut profile = Self::default();
+        for setting in raw.split(',').filter(|setting| !setting.trim().is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .with_context(|| format!("expected name=value, got {setting:?}"))?;
+            let (name, value) = (name.trim(), value.trim());
+            if name == "frame_rate" {
+                profile.frame_rate = Some(value.parse().context("invalid frame_rate")?);
+                continue;
+            }
+
+            let id = PROFILE_CONTROLS
+                .iter()
+                .find_map(|(known, id)| (*known == name).then_some(*id))
+                .or_else(|| name.parse().ok())
+                .with_context(|| format!("unknown control {name:?}"))?;
+            let value = value
+                .parse()
+                .with_context(|| format!("invalid value for {name}"))?;
+            profile.controls.push((id, value));
+        }
+        Ok(profile)
+    }
+}
+
+/// Raspberry Pi GPIO pin driving an IR LED or IR-cut filter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IrGpioConfig {
+    /// BCM pin number, not the header position.
+    pub pin: u8,
+    /// Drive the pin low rather than high at night.
+    pub active_low: bool,
+}
+
+impl IrGpioConfig {
+    fn load(file: &FileConfig) -> Result<Option<Self>> {
+        let Some(pin) = env::var("IR_GPIO_PIN")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid IR_GPIO_PIN"))
+            .transpose()?
+            .or(file.ir_gpio_pin)
+        else {
+            return Ok(None);
+        };
+
+        let active_low = bool_var("IR_GPIO_ACTIVE_LOW")?
+            .or(file.ir_gpio_active_low)
+            .unwrap_or(false);
+        Ok(Some(Self { pin, active_low }))
+    }
+}
+
+/// `backend_host` in the configuration file: a single address, as before it
+/// took a list, or an array of them.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Hosts {
+    One(IpAddr),
+    Many(Vec<IpAddr>),
+}
+
+/// Contents of the optional TOML configuration file. Keys are the
+/// environment variable names in lowercase; list settings such as `cameras`
+/// and `privacy_masks` take arrays instead of delimited strings.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    backend_host: Option<Hosts>,
+    backend_port: Option<u16>,
+    frame_rate: Option<f32>,
+    frame_width: Option<u32>,
+    frame_height: Option<u32>,
+    jpeg_quality: Option<u8>,
+    camera_device: Option<String>,
+    cameras: Option<Vec<String>>,
+    camera_backend: Option<CameraBackend>,
+    camera_formats: Option<Vec<PixelFormat>>,
+    bayer_demosaic: Option<Demosaic>,
+    mock_pattern: Option<MockPattern>,
+    upstream_user: Option<String>,
+    upstream_password: Option<String>,
+    auth_token: Option<String>,
+    auth_user: Option<String>,
+    auth_password: Option<String>,
+    jwt_secret: Option<String>,
+    jwt_jwks_url: Option<String>,
+    jwt_issuer: Option<String>,
+    jwt_audience: Option<String>,
+    link_secret: Option<String>,
+    allowed_networks: Option<Vec<String>>,
+    denied_networks: Option<Vec<String>>,
+    trusted_proxies: Option<Vec<String>>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    http_port: Option<u16>,
+    listen_socket: Option<PathBuf>,
+    base_path: Option<String>,
+    listen_socket_mode: Option<String>,
+    listen_socket_group: Option<String>,
+    on_demand_capture: Option<bool>,
+    startup_grace_secs: Option<u64>,
+    capture_stall_secs: Option<u64>,
+    capture_retries: Option<u32>,
+    capture_reconnect_after: Option<u32>,
+    capture_reconnect_delay_secs: Option<u64>,
+    capture_reconnect_max_delay_secs: Option<u64>,
+    capture_fallback: Option<CaptureFallback>,
+    capture_fallback_after: Option<u32>,
+    max_stream_clients: Option<usize>,
+    max_bandwidth_kbps: Option<u32>,
+    stream_width: Option<u32>,
+    stream_skip_unchanged: Option<bool>,
+    stream_schedule: Option<String>,
+    viewer_watermark: Option<bool>,
+    viewer_watermark_position: Option<OverlayCorner>,
+    audit_log: Option<PathBuf>,
+    adaptive_frame_rate: Option<bool>,
+    adaptive_max_cpu: Option<u8>,
+    adaptive_max_temperature: Option<f32>,
+    motion_detection: Option<bool>,
+    motion_threshold: Option<u8>,
+    motion_min_area: Option<f32>,
+    recordings_dir: Option<PathBuf>,
+    recording_format: Option<RecordingFormat>,
+    recording_pre_motion_secs: Option<u64>,
+    recording_post_motion_secs: Option<u64>,
+    recording_max_age_hours: Option<u64>,
+    recording_max_size_mb: Option<u64>,
+    mqtt_broker: Option<String>,
+    mqtt_topic_prefix: Option<String>,
+    mqtt_client_id: Option<String>,
+    mqtt_user: Option<String>,
+    mqtt_password: Option<String>,
+    mqtt_snapshot_interval_secs: Option<u64>,
+    snapshot_dir: Option<PathBuf>,
+    snapshot_interval_secs: Option<u64>,
+    snapshot_cron: Option<String>,
+    snapshot_filename: Option<String>,
+    s3_endpoint: Option<String>,
+    s3_bucket: Option<String>,
+    s3_region: Option<String>,
+    s3_access_key_id: Option<String>,
+    s3_secret_access_key: Option<String>,
+    s3_prefix: Option<String>,
+    webhook_urls: Option<Vec<String>>,
+    webhook_snapshot: Option<bool>,
+    audio_device: Option<String>,
+    audio_sample_rate: Option<u32>,
+    audio_channels: Option<u16>,
+    detection_model: Option<PathBuf>,
+    detection_fps: Option<f32>,
+    detection_confidence: Option<f32>,
+    detection_labels: Option<Vec<String>>,
+    privacy_blur_model: Option<PathBuf>,
+    privacy_blur_confidence: Option<f32>,
+    privacy_blur_labels: Option<Vec<String>>,
+    night_schedule: Option<String>,
+    night_luma: Option<u8>,
+    day_luma: Option<u8>,
+    ir_gpio_pin: Option<u8>,
+    ir_gpio_active_low: Option<bool>,
+    day_profile: Option<String>,
+    night_profile: Option<String>,
+    overlay_timestamp: Option<bool>,
+    overlay_timestamp_format: Option<String>,
+    overlay_position: Option<OverlayCorner>,
+    overlay_caption: Option<String>,
+    overlay_throttle_warning: Option<bool>,
+    overlay_watermark: Option<PathBuf>,
+    overlay_watermark_position: Option<OverlayCorner>,
+    image_brightness: Option<i16>,
+    image_contrast: Option<i16>,
+    image_saturation: Option<i16>,
+    image_grayscale: Option<bool>,
+    privacy_masks: Option<Vec<PrivacyMask>>,
+    privacy_mask_style: Option<MaskStyle>,
+    snapshot_jpeg_quality: Option<u8>,
+    snapshot_webp_quality: Option<u8>,
+}
+
+impl FileConfig {
+    fn read(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&raw).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+}
+
+/// Settings that can be changed at runtime through `PUT /config`.
+#[derive(Clone, Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigUpdate {
+    pub frame_rate: Option<f32>,
+    pub resolution_width: Option<u32>,
+    pub resolution_height: Option<u32>,
+    pub jpeg_quality: Option<u8>,
+    pub overlay_timestamp: Option<bool>,
+    pub overlay_timestamp_format: Option<String>,
+    pub overlay_corner: Option<OverlayCorner>,
+    /// An empty caption removes it.
+    pub overlay_caption: Option<String>,
+    pub overlay_throttle_warning: Option<bool>,
+    pub overlay_watermark: Option<bool>,
+    pub overlay_watermark_corner: Option<OverlayCorner>,
+    pub image_brightness: Option<i16>,
+    pub image_contrast: Option<i16>,
+    pub image_saturation: Option<i16>,
+    pub image_grayscale: Option<bool>,
+    pub privacy_masks: Option<Vec<PrivacyMask>>,
+    pub privacy_mask_style: Option<MaskStyle>,
+}
+
+impl Config {
+    /// Loads settings from the environment, falling back to the TOML file at
+    /// `config_file` (or `CONFIG_FILE`) and then to defaults.
+    pub fn load(config_file: Option<&Path>) -> Result<Self> {
+        let config_file = config_file
+            .map(PathBuf::from)
+            .or_else(|| non_empty_var("CONFIG_FILE").map(PathBuf::from));
+        let file = match config_file {
+            Some(path) => FileConfig::read(&path)?,
+            None => FileConfig::default(),
+        };
+        let mqtt = MqttConfig::load(&file)?;
+        let upstream_auth = UpstreamAuth::load(&file)?;
+        let snapshots = SnapshotSchedule::load(&file)?;
+        let s3 = S3Config::load(&file)?;
+        let webhooks = WebhookConfig::load(&file)?;
+        let audio = AudioConfig::load(&file)?;
+        let detection = DetectionConfig::load(&file)?;
+        let privacy_blur = PrivacyBlurConfig::load(&file)?;
+        let night_switch = NightSwitch::load(&file)?;
+        let ir_gpio = IrGpioConfig::load(&file)?;
+        let day_profile = ControlProfile::load("DAY_PROFILE", file.day_profile.as_ref())?;
+        let night_profile = ControlProfile::load("NIGHT_PROFILE", file.night_profile.as_ref())?;
+        let listen_socket = UnixSocketConfig::load(&file)?;
+        // Leading and trailing slashes are optional; `/` alone means none.
+        let base_path = non_empty_var("BASE_PATH")
+            .or_else(|| file.base_path.clone())
+            .map(|path| format!("/{}", path.trim_matches('/')))
+            .filter(|path| path != "/");
+        let jwt = JwtConfig::load(&file)?;
+        let ip_filter = IpFilterConfig::load(&file)?;
+        let trusted_proxies = networks_var("TRUSTED_PROXIES", file.trusted_proxies.as_deref())?;
+
+        // IPv6 addresses may be bracketed, as in `0.0.0.0,[::]`.
+        let listen_addresses = non_empty_var("BACKEND_HOST")
+            .map(|raw| {
+                raw.split(',')
+                    .map(|host| host.trim().trim_start_matches('[').trim_end_matches(']'))
+                    .map(|host| host.parse().context("Invalid BACKEND_HOST"))
+                    .collect::<Result<Vec<IpAddr>>>()
+            })
+            .transpose()?
+            .or(match file.backend_host {
+                Some(Hosts::One(host)) => Some(vec![host]),
+                Some(Hosts::Many(hosts)) => Some(hosts),
+                None => None,
+            })
+            .unwrap_or_else(|| vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)]);
+
+        let port = env::var("BACKEND_PORT")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid BACKEND_PORT"))
+            .transpose()?
+            .or(file.backend_port)
+            .unwrap_or(8080);
+
+        let frame_rate = env::var("FRAME_RATE")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid FRAME_RATE"))
+            .transpose()?
+            .or(file.frame_rate)
+            .unwrap_or(12.0);
+
+        let resolution_width = env::var("FRAME_WIDTH")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid FRAME_WIDTH"))
+            .transpose()?
+            .or(file.frame_width)
+            .unwrap_or(1280);
+
+        let resolution_height = env::var("FRAME_HEIGHT")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid FRAME_HEIGHT"))
+            .transpose()?
+            .or(file.frame_height)
+            .unwrap_or(720);
+
+        let jpeg_quality = env::var("JPEG_QUALITY")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid JPEG_QUALITY"))
+            .transpose()?
+            .or(file.jpeg_quality)
+            .unwrap_or(85);
+
+        let cameras: Vec<String> = env::var("CAMERAS")
+            .ok()
+            .map(|raw| {
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|device| !device.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .or(file.cameras)
+            .unwrap_or_default();
+
+        let camera_backend = non_empty_var("CAMERA_BACKEND")
+            .map(|raw| raw.parse().context("Invalid CAMERA_BACKEND"))
+            .transpose()?
+            .or(file.camera_backend)
+            .unwrap_or(CameraBackend::Auto);
+
+        let camera_formats = match non_empty_var("CAMERA_FORMATS") {
+            Some(raw) => raw
+                .split(',')
+                .map(str::trim)
+                .filter(|format| !format.is_empty())
+                .map(|format| {
+                    format
+                        .parse()
+                        .with_context(|| format!("Invalid CAMERA_FORMATS entry '{format}'"))
+                })
+                .collect::<Result<_>>()?,
+            None => file
+                .camera_formats
+                .unwrap_or_else(PixelFormat::default_order),
+        };
+
+        let bayer_demosaic = non_empty_var("BAYER_DEMOSAIC")
+            .map(|raw| raw.parse().context("Invalid BAYER_DEMOSAIC"))
+            .transpose()?
+            .or(file.bayer_demosaic)
+            .unwrap_or(Demosaic::Bilinear);
+
+        let mock_pattern = non_empty_var("MOCK_PATTERN")
+            .map(|raw| raw.parse().context("Invalid MOCK_PATTERN"))
+            .transpose()?
+            .or(file.mock_pattern)
+            .unwrap_or(MockPattern::Gradient);
+
+        let camera_device = env::var("CAMERA_DEVICE")
+            .ok()
+            .and_then(|value| {
+                if value.trim().is_empty() {
+                    None
+                } else {
+                    Some(value)
+                }
+            })
+            .or(file.camera_device)
+            .or_else(Self::default_camera_device);
+
+        // CAMERAS takes over from CAMERA_DEVICE; its first entry is the default camera.
+        let camera_device = cameras.first().cloned().or(camera_device);
+
+        let auth = AuthConfig {
+            token: non_empty_var("AUTH_TOKEN").or(file.auth_token),
+            user: non_empty_var("AUTH_USER").or(file.auth_user),
+            password: non_empty_var("AUTH_PASSWORD").or(file.auth_password),
+            jwt,
+            link_secret: non_empty_var("LINK_SECRET").or(file.link_secret),
+        };
+
+        let tls_cert = non_empty_var("TLS_CERT")
+            .map(PathBuf::from)
+            .or(file.tls_cert);
+        let tls_key = non_empty_var("TLS_KEY").map(PathBuf::from).or(file.tls_key);
+        let tls = match (tls_cert, tls_key) {
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                cert_path,
+                key_path,
+            }),
+            (None, None) => None,
+            _ => return Err(anyhow!("TLS_CERT and TLS_KEY must be set together")),
+        };
+
+        let http_port = env::var("HTTP_PORT")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid HTTP_PORT"))
+            .transpose()?
+            .or(file.http_port);
+
+        if http_port.is_some() && tls.is_none() {
+            return Err(anyhow!("HTTP_PORT requires TLS_CERT and TLS_KEY"));
+        }
+
+        if listen_socket.is_some() && tls.is_some() {
+            return Err(anyhow!(
+                "LISTEN_SOCKET serves plain HTTP and cannot be combined with TLS_CERT"
+            ));
+        }
+        if listen_socket.is_some() && cfg!(not(unix)) {
+            return Err(anyhow!("LISTEN_SOCKET is only supported on Unix"));
+        }
+
+        let recordings_dir = non_empty_var("RECORDINGS_DIR")
+            .map(PathBuf::from)
+            .or(file.recordings_dir);
+
+        let on_demand_capture = bool_var("ON_DEMAND_CAPTURE")?
+            .or(file.on_demand_capture)
+            .unwrap_or(false);
+
+        let startup_grace_secs = env::var("STARTUP_GRACE_SECS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid STARTUP_GRACE_SECS"))
+            .transpose()?
+            .or(file.startup_grace_secs)
+            .unwrap_or(30);
+
+        let capture_stall_secs = env::var("CAPTURE_STALL_SECS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid CAPTURE_STALL_SECS"))
+            .transpose()?
+            .or(file.capture_stall_secs)
+            .unwrap_or(10);
+
+        let capture_retries = env::var("CAPTURE_RETRIES")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid CAPTURE_RETRIES"))
+            .transpose()?
+            .or(file.capture_retries)
+            .unwrap_or(0);
+
+        let capture_reconnect_after = env::var("CAPTURE_RECONNECT_AFTER")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid CAPTURE_RECONNECT_AFTER"))
+            .transpose()?
+            .or(file.capture_reconnect_after)
+            .unwrap_or(5);
+
+        let capture_reconnect_delay_secs = env::var("CAPTURE_RECONNECT_DELAY_SECS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid CAPTURE_RECONNECT_DELAY_SECS"))
+            .transpose()?
+            .or(file.capture_reconnect_delay_secs)
+            .unwrap_or(1);
+
+        let capture_reconnect_max_delay_secs = env::var("CAPTURE_RECONNECT_MAX_DELAY_SECS")
+            .ok()
+            .map(|raw| {
+                raw.parse()
+                    .context("Invalid CAPTURE_RECONNECT_MAX_DELAY_SECS")
+            })
+            .transpose()?
+            .or(file.capture_reconnect_max_delay_secs)
+            .unwrap_or(60);
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Config {
-    pub listen_address: IpAddr,
-    pub port: u16,
-    pub frame_rate: f32,
-    pub resolution_width: u32,
-    pub resolution_height: u32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub camera_device: Option<String>,
-}
+        let capture_fallback = non_empty_var("CAPTURE_FALLBACK")
+            .map(|raw| raw.parse().context("Invalid CAPTURE_FALLBACK"))
+            .transpose()?
+            .or(file.capture_fallback)
+            .unwrap_or(CaptureFallback::Placeholder);
 
-impl Config {
-    pub fn from_env() -> Result<Self> {
-        let listen_address = env::var("BACKEND_HOST")
+        let capture_fallback_after = env::var("CAPTURE_FALLBACK_AFTER")
             .ok()
-            .map(|raw| raw.parse().context("Invalid BACKEND_HOST"))
+            .map(|raw| raw.parse().context("Invalid CAPTURE_FALLBACK_AFTER"))
             .transpose()?
-            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            .or(file.capture_fallback_after)
+            .unwrap_or(1);
 
-        let port = env::var("BACKEND_PORT")
+        let max_stream_clients = env::var("MAX_STREAM_CLIENTS")
             .ok()
-            .map(|raw| raw.parse().context("Invalid BACKEND_PORT"))
+            .map(|raw| raw.parse().context("Invalid MAX_STREAM_CLIENTS"))
             .transpose()?
-            .unwrap_or(8080);
+            .or(file.max_stream_clients);
 
-        let frame_rate = env::var("FRAME_RATE")
+        let max_bandwidth_kbps = env::var("MAX_BANDWIDTH_KBPS")
             .ok()
-            .map(|raw| raw.parse().context("Invalid FRAME_RATE"))
+            .map(|raw| raw.parse().context("Invalid MAX_BANDWIDTH_KBPS"))
             .transpose()?
-            .unwrap_or(12.0);
+            .or(file.max_bandwidth_kbps);
 
-        if !(1.0..=60.0).contains(&frame_rate) {
-            return Err(anyhow!("FRAME_RATE must be between 1 and 60"));
-        }
+        let stream_width = env::var("STREAM_WIDTH")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid STREAM_WIDTH"))
+            .transpose()?
+            .or(file.stream_width);
+        let stream_skip_unchanged = bool_var("STREAM_SKIP_UNCHANGED")?
+            .or(file.stream_skip_unchanged)
+            .unwrap_or(false);
+        let stream_schedule = non_empty_var("STREAM_SCHEDULE")
+            .or_else(|| file.stream_schedule.clone())
+            .map(|raw| raw.parse().context("Invalid STREAM_SCHEDULE"))
+            .transpose()?;
+        let viewer_watermark = bool_var("VIEWER_WATERMARK")?
+            .or(file.viewer_watermark)
+            .unwrap_or(false);
+        let viewer_watermark_corner = non_empty_var("VIEWER_WATERMARK_POSITION")
+            .map(|raw| raw.parse().context("Invalid VIEWER_WATERMARK_POSITION"))
+            .transpose()?
+            .or(file.viewer_watermark_position)
+            .unwrap_or(OverlayCorner::BottomLeft);
+        let audit_log = non_empty_var("AUDIT_LOG")
+            .map(PathBuf::from)
+            .or(file.audit_log);
 
-        let resolution_width = env::var("FRAME_WIDTH")
+        let adaptive_frame_rate = bool_var("ADAPTIVE_FRAME_RATE")?
+            .or(file.adaptive_frame_rate)
+            .unwrap_or(false);
+        let adaptive_max_cpu = env::var("ADAPTIVE_MAX_CPU")
             .ok()
-            .map(|raw| raw.parse().context("Invalid FRAME_WIDTH"))
+            .map(|raw| raw.parse().context("Invalid ADAPTIVE_MAX_CPU"))
             .transpose()?
-            .unwrap_or(1280);
+            .or(file.adaptive_max_cpu)
+            .unwrap_or(90);
+        let adaptive_max_temperature = env::var("ADAPTIVE_MAX_TEMPERATURE")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid ADAPTIVE_MAX_TEMPERATURE"))
+            .transpose()?
+            .or(file.adaptive_max_temperature)
+            .unwrap_or(75.0);
 
-        let resolution_height = env::var("FRAME_HEIGHT")
+        // Recording is driven by motion events, so it implies motion detection.
+        let motion_detection = bool_var("MOTION_DETECTION")?
+            .or(file.motion_detection)
+            .unwrap_or(false)
+            || recordings_dir.is_some();
+
+        let motion_threshold = env::var("MOTION_THRESHOLD")
             .ok()
-            .map(|raw| raw.parse().context("Invalid FRAME_HEIGHT"))
+            .map(|raw| raw.parse().context("Invalid MOTION_THRESHOLD"))
             .transpose()?
-            .unwrap_or(720);
+            .or(file.motion_threshold)
+            .unwrap_or(25);
 
-        if resolution_width == 0 || resolution_height == 0 {
-            return Err(anyhow!(
-                "FRAME_WIDTH and FRAME_HEIGHT must be greater than zero"
-            ));
-        }
+        let motion_min_area = env::var("MOTION_MIN_AREA")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid MOTION_MIN_AREA"))
+            .transpose()?
+            .or(file.motion_min_area)
+            .unwrap_or(1.0);
 
-        let camera_device = env::var("CAMERA_DEVICE")
+        let recording_format = non_empty_var("RECORDING_FORMAT")
+            .map(|raw| raw.parse().context("Invalid RECORDING_FORMAT"))
+            .transpose()?
+            .or(file.recording_format)
+            .unwrap_or(RecordingFormat::Avi);
+
+        let recording_pre_motion_secs = env::var("RECORDING_PRE_MOTION_SECS")
             .ok()
-            .and_then(|value| {
-                if value.trim().is_empty() {
-                    None
-                } else {
-                    Some(value)
-                }
+            .map(|raw| raw.parse().context("Invalid RECORDING_PRE_MOTION_SECS"))
+            .transpose()?
+            .or(file.recording_pre_motion_secs)
+            .unwrap_or(3);
+
+        let recording_post_motion_secs = env::var("RECORDING_POST_MOTION_SECS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid RECORDING_POST_MOTION_SECS"))
+            .transpose()?
+            .or(file.recording_post_motion_secs)
+            .unwrap_or(5);
+
+        let recording_max_age_hours = env::var("RECORDING_MAX_AGE_HOURS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid RECORDING_MAX_AGE_HOURS"))
+            .transpose()?
+            .or(file.recording_max_age_hours);
+
+        let recording_max_size_mb = env::var("RECORDING_MAX_SIZE_MB")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid RECORDING_MAX_SIZE_MB"))
+            .transpose()?
+            .or(file.recording_max_size_mb);
+
+        let overlay_timestamp = bool_var("OVERLAY_TIMESTAMP")?
+            .or(file.overlay_timestamp)
+            .unwrap_or(false);
+        let overlay_timestamp_format = non_empty_var("OVERLAY_TIMESTAMP_FORMAT")
+            .or(file.overlay_timestamp_format)
+            .unwrap_or_else(|| "%Y-%m-%d %H:%M:%S".to_string());
+        let overlay_corner = non_empty_var("OVERLAY_POSITION")
+            .map(|raw| raw.parse().context("Invalid OVERLAY_POSITION"))
+            .transpose()?
+            .or(file.overlay_position)
+            .unwrap_or(OverlayCorner::TopLeft);
+        let overlay_caption = non_empty_var("OVERLAY_CAPTION").or(file.overlay_caption);
+        let overlay_throttle_warning = bool_var("OVERLAY_THROTTLE_WARNING")?
+            .or(file.overlay_throttle_warning)
+            .unwrap_or(false);
+        let overlay_watermark_path = non_empty_var("OVERLAY_WATERMARK")
+            .map(PathBuf::from)
+            .or(file.overlay_watermark);
+        let overlay_watermark_corner = non_empty_var("OVERLAY_WATERMARK_POSITION")
+            .map(|raw| raw.parse().context("Invalid OVERLAY_WATERMARK_POSITION"))
+            .transpose()?
+            .or(file.overlay_watermark_position)
+            .unwrap_or(OverlayCorner::BottomRight);
+
+        let image_brightness = env::var("IMAGE_BRIGHTNESS")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid IMAGE_BRIGHTNESS"))
+            .transpose()?
+            .or(file.image_brightness)
+            .unwrap_or(0);
+        let image_contrast = env::var("IMAGE_CONTRAST")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid IMAGE_CONTRAST"))
+            .transpose()?
+            .or(file.image_contrast)
+            .unwrap_or(0);
+        let image_saturation = env::var("IMAGE_SATURATION")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid IMAGE_SATURATION"))
+            .transpose()?
+            .or(file.image_saturation)
+            .unwrap_or(0);
+        let image_grayscale = bool_var("IMAGE_GRAYSCALE")?
+            .or(file.image_grayscale)
+            .unwrap_or(false);
+
+        let privacy_masks = non_empty_var("PRIVACY_MASKS")
+            .map(|raw| {
+                raw.split(';')
+                    .filter(|mask| !mask.trim().is_empty())
+                    .map(|mask| mask.parse().context("Invalid PRIVACY_MASKS"))
+                    .collect::<Result<Vec<_>>>()
             })
-            .or_else(Self::default_camera_device);
+            .transpose()?
+            .or(file.privacy_masks)
+            .unwrap_or_default();
+        let privacy_mask_style = non_empty_var("PRIVACY_MASK_STYLE")
+            .map(|raw| raw.parse().context("Invalid PRIVACY_MASK_STYLE"))
+            .transpose()?
+            .or(file.privacy_mask_style)
+            .unwrap_or(MaskStyle::Black);
 
-        Ok(Self {
-            listen_address,
+        let snapshot_jpeg_quality = env::var("SNAPSHOT_JPEG_QUALITY")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid SNAPSHOT_JPEG_QUALITY"))
+            .transpose()?
+            .or(file.snapshot_jpeg_quality);
+        let snapshot_webp_quality = env::var("SNAPSHOT_WEBP_QUALITY")
+            .ok()
+            .map(|raw| raw.parse().context("Invalid SNAPSHOT_WEBP_QUALITY"))
+            .transpose()?
+            .or(file.snapshot_webp_quality)
+            .unwrap_or(80);
+
+        let config = Self {
+            listen_addresses,
             port,
             frame_rate,
             resolution_width,
             resolution_height,
+            jpeg_quality,
             camera_device,
-        })
+            cameras,
+            camera_backend,
+            camera_formats,
+            bayer_demosaic,
+            mock_pattern,
+            upstream_auth,
+            auth,
+            tls,
+            http_port,
+            listen_socket,
+            base_path,
+            ip_filter,
+            trusted_proxies,
+            on_demand_capture,
+            startup_grace_secs,
+            capture_stall_secs,
+            capture_retries,
+            capture_reconnect_after,
+            capture_reconnect_delay_secs,
+            capture_reconnect_max_delay_secs,
+            capture_fallback,
+            capture_fallback_after,
+            max_stream_clients,
+            max_bandwidth_kbps,
+            stream_width,
+            stream_skip_unchanged,
+            stream_schedule,
+            viewer_watermark,
+            viewer_watermark_corner,
+            audit_log,
+            adaptive_frame_rate,
+            adaptive_max_cpu,
+            adaptive_max_temperature,
+            motion_detection,
+            motion_threshold,
+            motion_min_area,
+            recordings_dir,
+            recording_format,
+            recording_pre_motion_secs,
+            recording_post_motion_secs,
+            recording_max_age_hours,
+            recording_max_size_mb,
+            mqtt,
+            snapshots,
+            s3,
+            webhooks,
+            audio,
+            detection,
+            privacy_blur,
+            night_switch,
+            ir_gpio,
+            day_profile,
+            night_profile,
+            overlay_timestamp,
+            overlay_timestamp_format,
+            overlay_corner,
+            overlay_caption,
+            overlay_throttle_warning,
+            overlay_watermark: overlay_watermark_path.is_some(),
+            overlay_watermark_path,
+            overlay_watermark_corner,
+            image_brightness,
+            image_contrast,
+            image_saturation,
+            image_grayscale,
+            privacy_masks,
+            privacy_mask_style,
+            snapshot_jpeg_quality,
+            snapshot_webp_quality,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(1.0..=60.0).contains(&self.frame_rate) {
+            return Err(anyhow!("FRAME_RATE must be between 1 and 60"));
+        }
+
+        if self.listen_addresses.is_empty() {
+            return Err(anyhow!("BACKEND_HOST must name at least one address"));
+        }
+
+        if let Some(path) = &self.base_path {
+            if path.contains("//")
+                || !path
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"/-._~".contains(&b))
+            {
+                return Err(anyhow!("BASE_PATH must be a URL path such as /cam1"));
+            }
+        }
+
+        if self.camera_backend != CameraBackend::Auto
+            && !camera::backends().any(|backend| backend == self.camera_backend)
+        {
+            let available: Vec<_> = camera::backends().map(CameraBackend::name).collect();
+            return Err(anyhow!(
+                "CAMERA_BACKEND {} is not available in this build; expected auto, {}",
+                self.camera_backend.name(),
+                available.join(", ")
+            ));
+        }
+
+        if self.camera_formats.is_empty() {
+            return Err(anyhow!("CAMERA_FORMATS must name at least one format"));
+        }
+
+        if !cfg!(feature = "bayer") && self.camera_formats.iter().any(|f| f.is_bayer()) {
+            return Err(anyhow!(
+                "RAW Bayer formats in CAMERA_FORMATS need a build with the `bayer` feature"
+            ));
+        }
+
+        if self.resolution_width == 0 || self.resolution_height == 0 {
+            return Err(anyhow!(
+                "FRAME_WIDTH and FRAME_HEIGHT must be greater than zero"
+            ));
+        }
+
+        if !(0.0..=100.0).contains(&self.motion_min_area) {
+            return Err(anyhow!("MOTION_MIN_AREA must be between 0 and 100"));
+        }
+
+        if self.capture_reconnect_after == 0 || self.capture_fallback_after == 0 {
+            return Err(anyhow!(
+                "CAPTURE_RECONNECT_AFTER and CAPTURE_FALLBACK_AFTER must be greater than zero"
+            ));
+        }
+
+        if self.capture_reconnect_delay_secs == 0
+            || self.capture_reconnect_max_delay_secs < self.capture_reconnect_delay_secs
+        {
+            return Err(anyhow!(
+                "CAPTURE_RECONNECT_DELAY_SECS must be greater than zero and at most CAPTURE_RECONNECT_MAX_DELAY_SECS"
+            ));
+        }
+
+        if self.max_stream_clients == Some(0) {
+            return Err(anyhow!("MAX_STREAM_CLIENTS must be greater than zero"));
+        }
+
+        if self.max_bandwidth_kbps == Some(0) {
+            return Err(anyhow!("MAX_BANDWIDTH_KBPS must be greater than zero"));
+        }
+
+        if let Some(width) = self.stream_width {
+            if !(scaler::MIN_WIDTH..=scaler::MAX_WIDTH).contains(&width) {
+                return Err(anyhow!(
+                    "STREAM_WIDTH must be between {} and {}",
+                    scaler::MIN_WIDTH,
+                    scaler::MAX_WIDTH
+                ));
+            }
+        }
+
+        if !(1..=100).contains(&self.adaptive_max_cpu) {
+            return Err(anyhow!("ADAPTIVE_MAX_CPU must be between 1 and 100"));
+        }
+
+        if !(self.adaptive_max_temperature.is_finite() && self.adaptive_max_temperature > 0.0) {
+            return Err(anyhow!(
+                "ADAPTIVE_MAX_TEMPERATURE must be greater than zero"
+            ));
+        }
+
+        if !(1..=100).contains(&self.jpeg_quality) {
+            return Err(anyhow!("JPEG_QUALITY must be between 1 and 100"));
+        }
+
+        if self
+            .snapshot_jpeg_quality
+            .is_some_and(|quality| !(1..=100).contains(&quality))
+            || !(1..=100).contains(&self.snapshot_webp_quality)
+        {
+            return Err(anyhow!(
+                "SNAPSHOT_JPEG_QUALITY and SNAPSHOT_WEBP_QUALITY must be between 1 and 100"
+            ));
+        }
+
+        if self.recording_max_age_hours == Some(0) || self.recording_max_size_mb == Some(0) {
+            return Err(anyhow!(
+                "RECORDING_MAX_AGE_HOURS and RECORDING_MAX_SIZE_MB must be greater than zero"
+            ));
+        }
+
+        if StrftimeItems::new(&self.overlay_timestamp_format)
+            .any(|item| matches!(item, Item::Error))
+        {
+            return Err(anyhow!("Invalid OVERLAY_TIMESTAMP_FORMAT"));
+        }
+
+        if let Some(s3) = &self.s3 {
+            if !s3.endpoint.starts_with("http://") && !s3.endpoint.starts_with("https://") {
+                return Err(anyhow!("S3_ENDPOINT must be an http:// or https:// URL"));
+            }
+        }
+
+        if let Some(webhooks) = &self.webhooks {
+            if webhooks
+                .urls
+                .iter()
+                .any(|url| !url.starts_with("http://") && !url.starts_with("https://"))
+            {
+                return Err(anyhow!("WEBHOOK_URLS must be http:// or https:// URLs"));
+            }
+        }
+
+        if let Some(audio) = &self.audio {
+            if cfg!(not(all(feature = "audio", target_os = "linux"))) {
+                return Err(anyhow!(
+                    "AUDIO_DEVICE needs a Linux build with the `audio` feature"
+                ));
+            }
+            if !(8_000..=48_000).contains(&audio.sample_rate) {
+                return Err(anyhow!("AUDIO_SAMPLE_RATE must be between 8000 and 48000"));
+            }
+            if !(1..=2).contains(&audio.channels) {
+                return Err(anyhow!("AUDIO_CHANNELS must be 1 or 2"));
+            }
+        }
+
+        if let Some(detection) = &self.detection {
+            if cfg!(not(feature = "detection")) {
+                return Err(anyhow!(
+                    "DETECTION_MODEL needs a build with the `detection` feature"
+                ));
+            }
+            if !(detection.fps > 0.0 && detection.fps <= 30.0) {
+                return Err(anyhow!("DETECTION_FPS must be above 0 and at most 30"));
+            }
+            if !(0.0..=1.0).contains(&detection.confidence) {
+                return Err(anyhow!("DETECTION_CONFIDENCE must be between 0 and 1"));
+            }
+        }
+
+        if let Some(privacy_blur) = &self.privacy_blur {
+            if cfg!(not(feature = "detection")) {
+                return Err(anyhow!(
+                    "PRIVACY_BLUR_MODEL needs a build with the `detection` feature"
+                ));
+            }
+            if !(0.0..=1.0).contains(&privacy_blur.confidence) {
+                return Err(anyhow!("PRIVACY_BLUR_CONFIDENCE must be between 0 and 1"));
+            }
+            if privacy_blur.labels.is_empty() {
+                return Err(anyhow!("PRIVACY_BLUR_LABELS must name at least one class"));
+            }
+        }
+
+        match &self.night_switch {
+            NightSwitch::Schedule(schedule) if schedule.start == schedule.end => {
+                return Err(anyhow!(
+                    "NIGHT_SCHEDULE must start and end at different times"
+                ));
+            }
+            NightSwitch::Luminance {
+                night_below,
+                day_above,
+            } if night_below >= day_above => {
+                return Err(anyhow!("NIGHT_LUMA must be lower than DAY_LUMA"));
+            }
+            _ => {}
+        }
+
+        if let Some(ir_gpio) = &self.ir_gpio {
+            if cfg!(not(all(feature = "gpio", target_os = "linux"))) {
+                return Err(anyhow!(
+                    "IR_GPIO_PIN needs a Linux build with the `gpio` feature"
+                ));
+            }
+            if ir_gpio.pin > 27 {
+                return Err(anyhow!(
+                    "IR_GPIO_PIN must be a BCM pin number between 0 and 27"
+                ));
+            }
+        }
+
+        for (name, profile) in [
+            ("DAY_PROFILE", &self.day_profile),
+            ("NIGHT_PROFILE", &self.night_profile),
+        ] {
+            if profile
+                .as_ref()
+                .and_then(|profile| profile.frame_rate)
+                .is_some_and(|frame_rate| !(1.0..=60.0).contains(&frame_rate))
+            {
+                return Err(anyhow!("{name} frame_rate must be between 1 and 60"));
+            }
+        }
+
+        if let Some(snapshots) = &self.snapshots {
+            if snapshots.interval_secs == 0 {
+                return Err(anyhow!("SNAPSHOT_INTERVAL_SECS must be greater than zero"));
+            }
+            snapshots.cron().context("Invalid SNAPSHOT_CRON")?;
+            if StrftimeItems::new(&snapshots.filename).any(|item| matches!(item, Item::Error)) {
+                return Err(anyhow!("Invalid SNAPSHOT_FILENAME"));
+            }
+        }
+
+        if [
+            self.image_brightness,
+            self.image_contrast,
+            self.image_saturation,
+        ]
+        .iter()
+        .any(|value| !(-100..=100).contains(value))
+        {
+            return Err(anyhow!(
+                "IMAGE_BRIGHTNESS, IMAGE_CONTRAST and IMAGE_SATURATION must be between -100 and 100"
+            ));
+        }
+
+        if self.overlay_watermark && self.overlay_watermark_path.is_none() {
+            return Err(anyhow!("Enabling the watermark requires OVERLAY_WATERMARK"));
+        }
+
+        for mask in &self.privacy_masks {
+            if mask.points.len() < 3 {
+                return Err(anyhow!("Privacy masks need at least three points"));
+            }
+            if mask
+                .points
+                .iter()
+                .flatten()
+                .any(|value| !(0.0..=1.0).contains(value))
+            {
+                return Err(anyhow!(
+                    "Privacy mask coordinates must be fractions between 0 and 1"
+                ));
+            }
+        }
+
+        if self.auth.user.is_some() != self.auth.password.is_some() {
+            return Err(anyhow!("AUTH_USER and AUTH_PASSWORD must be set together"));
+        }
+
+        Ok(())
+    }
+
+    /// Returns a copy of this config with `update` applied, or an error if the
+    /// result would be invalid.
+    pub fn updated(&self, update: &ConfigUpdate) -> Result<Self> {
+        let mut config = self.clone();
+        if let Some(frame_rate) = update.frame_rate {
+            config.frame_rate = frame_rate;
+        }
+        if let Some(width) = update.resolution_width {
+            config.resolution_width = width;
+        }
+        if let Some(height) = update.resolution_height {
+            config.resolution_height = height;
+        }
+        if let Some(timestamp) = update.overlay_timestamp {
+            config.overlay_timestamp = timestamp;
+        }
+        if let Some(format) = &update.overlay_timestamp_format {
+            config.overlay_timestamp_format = format.clone();
+        }
+        if let Some(corner) = update.overlay_corner {
+            config.overlay_corner = corner;
+        }
+        if let Some(caption) = &update.overlay_caption {
+            config.overlay_caption = Some(caption.clone()).filter(|caption| !caption.is_empty());
+        }
+        if let Some(warning) = update.overlay_throttle_warning {
+            config.overlay_throttle_warning = warning;
+        }
+        if let Some(watermark) = update.overlay_watermark {
+            config.overlay_watermark = watermark;
+        }
+        if let Some(corner) = update.overlay_watermark_corner {
+            config.overlay_watermark_corner = corner;
+        }
+        if let Some(brightness) = update.image_brightness {
+            config.image_brightness = brightness;
+        }
+        if let Some(contrast) = update.image_contrast {
+            config.image_contrast = contrast;
+        }
+        if let Some(saturation) = update.image_saturation {
+            config.image_saturation = saturation;
+        }
+        if let Some(grayscale) = update.image_grayscale {
+            config.image_grayscale = grayscale;
+        }
+        if let Some(masks) = &update.privacy_masks {
+            config.privacy_masks = masks.clone();
+        }
+        if let Some(style) = update.privacy_mask_style {
+            config.privacy_mask_style = style;
+        }
+        if let Some(quality) = update.jpeg_quality {
+            config.jpeg_quality = quality;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Takes the settings that can change without a restart from `fresh`, the
+    /// result of re-reading the environment and config file, and keeps the rest.
+    pub fn reloaded(&self, fresh: &Config) -> Result<Self> {
+        let mut config = self.clone();
+        config.frame_rate = fresh.frame_rate;
+        config.resolution_width = fresh.resolution_width;
+        config.resolution_height = fresh.resolution_height;
+        config.camera_backend = fresh.camera_backend;
+        config.camera_formats = fresh.camera_formats.clone();
+        config.bayer_demosaic = fresh.bayer_demosaic;
+        config.mock_pattern = fresh.mock_pattern;
+        config.upstream_auth = fresh.upstream_auth.clone();
+        config.auth = fresh.auth.clone();
+        config.overlay_timestamp = fresh.overlay_timestamp;
+        config.overlay_timestamp_format = fresh.overlay_timestamp_format.clone();
+        config.overlay_corner = fresh.overlay_corner;
+        config.overlay_caption = fresh.overlay_caption.clone();
+        config.overlay_watermark_path = fresh.overlay_watermark_path.clone();
+        config.overlay_throttle_warning = fresh.overlay_throttle_warning;
+        config.overlay_watermark = fresh.overlay_watermark;
+        config.overlay_watermark_corner = fresh.overlay_watermark_corner;
+        config.image_brightness = fresh.image_brightness;
+        config.image_contrast = fresh.image_contrast;
+        config.image_saturation = fresh.image_saturation;
+        config.image_grayscale = fresh.image_grayscale;
+        config.privacy_masks = fresh.privacy_masks.clone();
+        config.privacy_mask_style = fresh.privacy_mask_style;
+        config.snapshot_jpeg_quality = fresh.snapshot_jpeg_quality;
+        config.jpeg_quality = fresh.jpeg_quality;
+        config.snapshot_webp_quality = fresh.snapshot_webp_quality;
+        config.recording_format = fresh.recording_format;
+        config.recording_post_motion_secs = fresh.recording_post_motion_secs;
+        config.recording_max_age_hours = fresh.recording_max_age_hours;
+        config.recording_max_size_mb = fresh.recording_max_size_mb;
+        config.startup_grace_secs = fresh.startup_grace_secs;
+        config.max_stream_clients = fresh.max_stream_clients;
+        config.max_bandwidth_kbps = fresh.max_bandwidth_kbps;
+        config.stream_width = fresh.stream_width;
+        config.stream_skip_unchanged = fresh.stream_skip_unchanged;
+        config.stream_schedule = fresh.stream_schedule.clone();
+        config.viewer_watermark = fresh.viewer_watermark;
+        config.viewer_watermark_corner = fresh.viewer_watermark_corner;
+        config.adaptive_frame_rate = fresh.adaptive_frame_rate;
+        config.adaptive_max_cpu = fresh.adaptive_max_cpu;
+        config.adaptive_max_temperature = fresh.adaptive_max_temperature;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Whether `other` changes settings that only take effect after a
+    /// restart, such as the listen address or the set of cameras.
+    pub fn restart_differs(&self, other: &Config) -> bool {
+        self.listen_socket_addrs() != other.listen_socket_addrs()
+            || self.http_port != other.http_port
+            || self.listen_socket.as_ref().map(|socket| &socket.path)
+                != other.listen_socket.as_ref().map(|socket| &socket.path)
+            || self.ip_filter != other.ip_filter
+            || self.trusted_proxies != other.trusted_proxies
+            || self.base_path != other.base_path
+            || self.camera_devices() != other.camera_devices()
+            || self.on_demand_capture != other.on_demand_capture
+            || self.capture_stall_secs != other.capture_stall_secs
+            || self.capture_retries != other.capture_retries
+            || self.capture_reconnect_after != other.capture_reconnect_after
+            || self.capture_reconnect_delay_secs != other.capture_reconnect_delay_secs
+            || self.capture_reconnect_max_delay_secs != other.capture_reconnect_max_delay_secs
+            || self.capture_fallback != other.capture_fallback
+            || self.capture_fallback_after != other.capture_fallback_after
+            || self.motion_detection != other.motion_detection
+            || self.motion_threshold != other.motion_threshold
+            || self.motion_min_area != other.motion_min_area
+            || self.recordings_dir != other.recordings_dir
+            || self.audit_log != other.audit_log
+            || self.recording_pre_motion_secs != other.recording_pre_motion_secs
+            || self.tls != other.tls
+            || self.mqtt != other.mqtt
+            || self.s3 != other.s3
+            || self.webhooks != other.webhooks
+            || self.snapshots != other.snapshots
+            || self.detection != other.detection
+            || self.audio != other.audio
+            || self.privacy_blur != other.privacy_blur
+            || self.night_switch != other.night_switch
+            || self.ir_gpio != other.ir_gpio
+            || self.day_profile != other.day_profile
+            || self.night_profile != other.night_profile
+    }
+
+    /// Whether switching to `other` requires the capture pipeline to restart.
+    pub fn capture_differs(&self, other: &Config) -> bool {
+        self.frame_rate != other.frame_rate
+            || self.resolution_width != other.resolution_width
+            || self.resolution_height != other.resolution_height
+            || self.camera_backend != other.camera_backend
+            || self.camera_formats != other.camera_formats
+            || self.bayer_demosaic != other.bayer_demosaic
+            || self.mock_pattern != other.mock_pattern
+            || self.upstream_auth != other.upstream_auth
+            || self.stream_skip_unchanged != other.stream_skip_unchanged
+    }
+
+    /// Whether switching to `other` requires the frame overlay to be rebuilt.
+    pub fn overlay_differs(&self, other: &Config) -> bool {
+        self.overlay_timestamp != other.overlay_timestamp
+            || self.overlay_timestamp_format != other.overlay_timestamp_format
+            || self.overlay_corner != other.overlay_corner
+            || self.overlay_caption != other.overlay_caption
+            || self.overlay_throttle_warning != other.overlay_throttle_warning
+            || self.overlay_watermark != other.overlay_watermark
+            || self.overlay_watermark_corner != other.overlay_watermark_corner
+            || self.image_brightness != other.image_brightness
+            || self.image_contrast != other.image_contrast
+            || self.image_saturation != other.image_saturation
+            || self.image_grayscale != other.image_grayscale
+            || self.privacy_masks != other.privacy_masks
+            || self.privacy_mask_style != other.privacy_mask_style
+            || self.jpeg_quality != other.jpeg_quality
     }
 
     pub fn frame_interval(&self) -> Duration {
@@ -86,8 +2572,71 @@ impl Config {
         Duration::from_secs_f64(1.0 / rate as f64)
     }
 
-    pub fn listen_socket_addr(&self) -> SocketAddr {
-        SocketAddr::new(self.listen_address, self.port)
+    /// Devices to open, in camera id order. `None` stands for the mock camera.
+    pub fn camera_devices(&self) -> Vec<Option<&str>> {
+        if self.cameras.is_empty() {
+            vec![self.camera_device.as_deref()]
+        } else {
+            self.cameras
+                .iter()
+                .map(|device| Some(device.as_str()))
+                .collect()
+        }
+    }
+
+    /// Depth of the per-camera frame history; only kept while recording is enabled.
+    pub fn pre_event_buffer(&self) -> Duration {
+        if self.recordings_dir.is_some() {
+            Duration::from_secs(self.recording_pre_motion_secs)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// How long an open camera may go without a frame; `None` when the
+    /// check is off.
+    pub fn capture_stall_timeout(&self) -> Option<Duration> {
+        (self.capture_stall_secs > 0).then(|| Duration::from_secs(self.capture_stall_secs))
+    }
+
+    pub fn capture_reconnect_delay(&self) -> Duration {
+        Duration::from_secs(self.capture_reconnect_delay_secs)
+    }
+
+    pub fn capture_reconnect_max_delay(&self) -> Duration {
+        Duration::from_secs(self.capture_reconnect_max_delay_secs)
+    }
+
+    pub fn recording_post_motion(&self) -> Duration {
+        Duration::from_secs(self.recording_post_motion_secs)
+    }
+
+    pub fn recording_max_age(&self) -> Option<Duration> {
+        self.recording_max_age_hours
+            .map(|hours| Duration::from_secs(hours * 60 * 60))
+    }
+
+    pub fn recording_max_size(&self) -> Option<u64> {
+        self.recording_max_size_mb.map(|mb| mb * 1024 * 1024)
+    }
+
+    pub fn listen_socket_addrs(&self) -> Vec<SocketAddr> {
+        self.listen_addresses
+            .iter()
+            .map(|&address| SocketAddr::new(address, self.port))
+            .collect()
+    }
+
+    /// Where plain HTTP is served next to HTTPS; empty without `HTTP_PORT`.
+    pub fn http_socket_addrs(&self) -> Vec<SocketAddr> {
+        self.http_port
+            .map(|port| {
+                self.listen_addresses
+                    .iter()
+                    .map(|&address| SocketAddr::new(address, port))
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
     fn default_camera_device() -> Option<String> {
@@ -96,9 +2645,189 @@ impl Config {
             Some("/dev/video0".to_string())
         }
 
-        #[cfg(not(target_os = "linux"))]
+        #[cfg(any(
+            all(windows, feature = "media-foundation"),
+            all(target_os = "macos", feature = "av-foundation")
+        ))]
+        {
+            Some("0".to_string())
+        }
+
+        #[cfg(not(any(
+            target_os = "linux",
+            all(windows, feature = "media-foundation"),
+            all(target_os = "macos", feature = "av-foundation")
+        )))]
         {
             None
         }
     }
 }
+
+fn non_empty_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn bool_var(name: &str) -> Result<Option<bool>> {
+    let Some(raw) = non_empty_var(name) else {
+        return Ok(None);
+    };
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(Some(true)),
+        "0" | "false" | "no" | "off" => Ok(Some(false)),
+        _ => Err(anyhow!("Invalid {name}: expected true or false")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    /// `time` on the day of the week given, in a week starting on Monday
+    /// 2024-01-01.
+    fn at(day: Weekday, time: &str) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1 + day.num_days_from_monday())
+            .unwrap()
+            .and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+    }
+
+    fn schedule(raw: &str) -> StreamSchedule {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_day_ranges() {
+        let days = |raw| parse_days(raw).unwrap();
+        assert_eq!(days("sat"), [Weekday::Sat]);
+        assert_eq!(days("mon-wed"), [Weekday::Mon, Weekday::Tue, Weekday::Wed]);
+        assert_eq!(
+            days("fri-mon"),
+            [Weekday::Fri, Weekday::Sat, Weekday::Sun, Weekday::Mon]
+        );
+    }
+
+    #[test]
+    fn days_without_hours_cover_the_whole_day() {
+        let weekend = schedule("sat-sun");
+        assert!(weekend.is_active(at(Weekday::Sat, "00:00")));
+        assert!(weekend.is_active(at(Weekday::Sun, "23:59")));
+        assert!(!weekend.is_active(at(Weekday::Fri, "23:59")));
+        assert!(!weekend.is_active(at(Weekday::Mon, "00:00")));
+    }
+
+    #[test]
+    fn hours_without_days_apply_every_day() {
+        let office = schedule("08:00-18:00");
+        for day in ALL_DAYS {
+            assert!(office.is_active(at(day, "08:00")));
+            assert!(office.is_active(at(day, "17:59")));
+            assert!(!office.is_active(at(day, "18:00")));
+            assert!(!office.is_active(at(day, "07:59")));
+        }
+    }
+
+    #[test]
+    fn overnight_window_runs_into_the_next_day() {
+        let friday_night = schedule("fri 22:00-06:00");
+        assert!(friday_night.is_active(at(Weekday::Fri, "22:00")));
+        assert!(friday_night.is_active(at(Weekday::Sat, "05:59")));
+        assert!(!friday_night.is_active(at(Weekday::Sat, "06:00")));
+        assert!(!friday_night.is_active(at(Weekday::Fri, "05:59")));
+        assert!(!friday_night.is_active(at(Weekday::Fri, "21:59")));
+        assert!(!friday_night.is_active(at(Weekday::Sat, "22:00")));
+    }
+
+    #[test]
+    fn overnight_window_wraps_around_the_week() {
+        let sunday_night = schedule("sun 20:00-07:00");
+        assert!(sunday_night.is_active(at(Weekday::Sun, "23:00")));
+        assert!(sunday_night.is_active(at(Weekday::Mon, "06:00")));
+        assert!(!sunday_night.is_active(at(Weekday::Mon, "20:00")));
+    }
+
+    #[test]
+    fn any_period_makes_the_schedule_active() {
+        let off_hours = schedule("sun-fri 18:00-08:00, sat-sun");
+        assert!(off_hours.is_active(at(Weekday::Wed, "19:00")));
+        assert!(off_hours.is_active(at(Weekday::Thu, "07:00")));
+        assert!(!off_hours.is_active(at(Weekday::Thu, "12:00")));
+        assert!(off_hours.is_active(at(Weekday::Sat, "12:00")));
+        // Sunday night runs into Monday morning.
+        assert!(off_hours.is_active(at(Weekday::Mon, "07:59")));
+        assert!(!off_hours.is_active(at(Weekday::Mon, "08:00")));
+
+        // Without Sunday night, Monday morning is left out.
+        let weekday_nights = schedule("mon-fri 18:00-08:00, sat-sun");
+        assert!(weekday_nights.is_active(at(Weekday::Sat, "07:00")));
+        assert!(!weekday_nights.is_active(at(Weekday::Mon, "07:00")));
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        for raw in [
+            "",
+            " , ",
+            "someday",
+            "mon-someday",
+            "mon 18:00",
+            "mon 18-08",
+            "mon 25:00-08:00",
+            "mon 08:00-08:00",
+            "18:00-08:00-09:00",
+        ] {
+            assert!(raw.parse::<StreamSchedule>().is_err(), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn webhook_urls_are_logged_without_their_secret() {
+        let webhooks = WebhookConfig {
+            urls: vec![
+                "https://hooks.example.com:8443/services/T0/B0/s3cr3t?token=abc".to_string(),
+                "not a url".to_string(),
+            ],
+            snapshot: false,
+        };
+        let logged = format!("{webhooks:?}");
+        assert!(
+            logged.contains("https://hooks.example.com:8443"),
+            "{logged}"
+        );
+        assert!(logged.contains("<invalid url>"), "{logged}");
+        assert!(
+            !logged.contains("s3cr3t") && !logged.contains("abc"),
+            "{logged}"
+        );
+    }
+
+    #[test]
+    fn mqtt_broker_addresses_accept_ipv6_and_urls() {
+        let parsed = |broker| broker_address(broker).unwrap();
+        assert_eq!(parsed("broker.lan"), ("broker.lan".to_string(), 1883));
+        assert_eq!(parsed("broker.lan:8883"), ("broker.lan".to_string(), 8883));
+        assert_eq!(
+            parsed("192.168.1.5:1884"),
+            ("192.168.1.5".to_string(), 1884)
+        );
+        assert_eq!(parsed("::1"), ("::1".to_string(), 1883));
+        assert_eq!(parsed("fe80::1:2"), ("fe80::1:2".to_string(), 1883));
+        assert_eq!(parsed("[::1]:1884"), ("::1".to_string(), 1884));
+        assert_eq!(parsed("[fd00::5]"), ("fd00::5".to_string(), 1883));
+        assert_eq!(
+            parsed("mqtt://broker.lan:1884"),
+            ("broker.lan".to_string(), 1884)
+        );
+        assert_eq!(parsed("mqtt://[::1]"), ("::1".to_string(), 1883));
+
+        for invalid in [
+            "broker.lan:abc",
+            "broker.lan:70000",
+            "http://broker.lan",
+            "mqtt://",
+        ] {
+            assert!(broker_address(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Decides whether it is day or night, from a schedule or from how bright
+//! the first camera's picture is, for the IR illuminator and the day/night
+//! control profiles.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Local;
+use tokio::{
+    sync::watch,
+    task,
+    time::{interval, MissedTickBehavior},
+};
+
+use crate::{
+    capture::{CameraHandle, FRAME_TIMEOUT},
+    config::{NightSwitch, TimeWindow},
+    jpeg,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Brightness has to stay past a threshold for this many checks in a row,
+/// so headlights or a passing cloud do not flip the mode.
+const CHECKS_BEFORE_SWITCH: u32 = 3;
+/// Frames are measured at about this width; the decoder scales for free.
+const MEASURE_WIDTH: u32 = 80;
+
+/// Starts following `switch`; the receiver holds `true` while it is night.
+pub fn spawn(switch: &NightSwitch, camera: CameraHandle) -> watch::Receiver<bool> {
+    match switch.clone() {
+        NightSwitch::Schedule(schedule) => {
+            let (night, receiver) = watch::channel(schedule.contains(Local::now().time()));
+            tokio::spawn(follow_schedule(schedule, night));
+            receiver
+        }
+        NightSwitch::Luminance {
+            night_below,
+            day_above,
+        } => {
+            // Starts out as day until the picture has been measured.
+            let (night, receiver) = watch::channel(false);
+            tokio::spawn(follow_luminance(night_below, day_above, camera, night));
+            receiver
+        }
+    }
+}
+
+async fn follow_schedule(schedule: TimeWindow, night: watch::Sender<bool>) {
+    let mut ticker = interval(CHECK_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let is_night = schedule.contains(Local::now().time());
+        // Only wakes receivers when the mode actually flips.
+        if night.send_if_modified(|night| std::mem::replace(night, is_night) != is_night) {
+            tracing::info!(night = is_night, "Switched day/night mode on schedule");
+        }
+    }
+}
+
+async fn follow_luminance(
+    night_below: u8,
+    day_above: u8,
+    camera: CameraHandle,
+    night: watch::Sender<bool>,
+) {
+    let mut ticker = interval(CHECK_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut past_threshold = 0;
+
+    loop {
+        ticker.tick().await;
+        let frame = match camera.next_frame(FRAME_TIMEOUT).await {
+            Ok(frame) => frame.data,
+            Err(err) => {
+                tracing::debug!(error = %err, "No frame to measure brightness");
+                continue;
+            }
+        };
+        let luma = match task::spawn_blocking(move || average_luma(&frame))
+            .await
+            .expect("spawn_blocking failed")
+        {
+            Ok(luma) => luma,
+            Err(err) => {
+                tracing::debug!(error = %err, "Skipping undecodable frame");
+                continue;
+            }
+        };
+
+        let is_night = *night.borrow();
+        let crossed = if is_night {
+            luma > day_above
+        } else {
+            luma < night_below
+        };
+        past_threshold = if crossed { past_threshold + 1 } else { 0 };
+        if past_threshold >= CHECKS_BEFORE_SWITCH {
+            past_threshold = 0;
+            night.send_replace(!is_night);
+            tracing::info!(
+                night = !is_night,
+                luma,
+                "Switched day/night mode on brightness"
+            );
+        }
+    }
+}
+
+/// Mean BT.601 luma of the frame, 0-255.
+fn average_luma(frame: &[u8]) -> Result<u8> {
+    let image = jpeg::decode_scaled(frame, MEASURE_WIDTH)?;
+    let pixels = u64::from(image.width()) * u64::from(image.height());
+    let total: u64 = image
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b] = pixel.0;
+            (299 * u64::from(r) + 587 * u64::from(g) + 114 * u64::from(b)) / 1000
+        })
+        .sum();
+    Ok((total / pixels.max(1)) as u8)
+}
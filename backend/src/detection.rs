@@ -0,0 +1,443 @@
+//! Object detection on sampled frames: an ONNX model such as a YOLOv8n
+//! export finds people, vehicles and animals, which are reported as events
+//! with labels and bounding boxes.
+//!
+//! Needs the `detection` feature. Inference runs on the CPU through tract,
+//! on frames scaled down to the model's input size.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::{
+    sync::broadcast,
+    task,
+    time::{interval, MissedTickBehavior},
+};
+use utoipa::ToSchema;
+
+use crate::{
+    capture::{self, CameraHandle, FrameEvent},
+    config::DetectionConfig,
+    jpeg,
+};
+
+pub use backend::Model;
+
+/// An object found in a frame.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Detection {
+    pub label: String,
+    pub confidence: f32,
+    /// `x,y,w,h` as 0-1 fractions of the frame.
+    pub bbox: [f32; 4],
+}
+
+/// Objects found in a frame, or none after a frame that had some.
+#[derive(Clone, Debug)]
+pub struct DetectionEvent {
+    pub camera: usize,
+    /// Number of the analysed frame, as in `X-Frame-Number`.
+    pub frame: u64,
+    pub detections: Vec<Detection>,
+}
+
+/// Every camera's latest detections, for viewers who want boxes drawn onto
+/// their stream.
+pub struct RecentDetections {
+    cameras: Vec<Mutex<Option<Detected>>>,
+    /// Results older than this are no longer shown, so boxes do not linger
+    /// when the model falls behind or the camera stops.
+    max_age: Duration,
+}
+
+struct Detected {
+    detections: Vec<Detection>,
+    detected_at: Instant,
+}
+
+impl RecentDetections {
+    pub fn new(cameras: usize, config: &DetectionConfig) -> Self {
+        Self {
+            cameras: (0..cameras).map(|_| Mutex::new(None)).collect(),
+            max_age: Duration::from_secs_f32(3.0 / config.fps).max(Duration::from_secs(1)),
+        }
+    }
+
+    /// The camera's detections, empty once they are stale.
+    pub fn latest(&self, camera: usize) -> Vec<Detection> {
+        match &*self.cameras[camera].lock().expect("detections poisoned") {
+            Some(detected) if detected.detected_at.elapsed() < self.max_age => {
+                detected.detections.clone()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn update(&self, camera: usize, detections: Vec<Detection>) {
+        *self.cameras[camera].lock().expect("detections poisoned") = Some(Detected {
+            detections,
+            detected_at: Instant::now(),
+        });
+    }
+}
+
+/// Loads the model once and runs it on every camera's latest frame
+/// `config.fps` times a second, publishing frames with objects of the
+/// configured labels on `events`, and the first frame without, and keeping
+/// the latest in `recent`.
+pub fn spawn(
+    config: &DetectionConfig,
+    cameras: &[CameraHandle],
+    events: broadcast::Sender<DetectionEvent>,
+    recent: Arc<RecentDetections>,
+) -> Result<()> {
+    let model = Arc::new(Model::load(&config.model)?);
+    tracing::info!(model = %config.model.display(), "Object detection model loaded");
+    let period = Duration::from_secs_f32(1.0 / config.fps);
+    let labels: Arc<[String]> = config.labels.clone().into();
+
+    for (camera, handle) in cameras.iter().enumerate() {
+        let model = model.clone();
+        let labels = labels.clone();
+        let confidence = config.confidence;
+        let mut frames = handle.subscribe();
+        let events = events.clone();
+        let recent = recent.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = interval(period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let mut had_objects = false;
+            loop {
+                ticker.tick().await;
+                let (data, frame) = match capture::recv_latest(&mut frames).await {
+                    Some(FrameEvent::Frame { data, number, .. }) => (data, number),
+                    Some(FrameEvent::Error(_)) => continue,
+                    None => break,
+                };
+
+                let model = model.clone();
+                let detected = task::spawn_blocking(move || {
+                    let image = jpeg::decode_scaled(&data, model.input_size())?;
+                    model.detect(&image, confidence)
+                })
+                .await
+                .expect("spawn_blocking failed");
+                let detections: Vec<Detection> = match detected {
+                    Ok(detections) => detections
+                        .into_iter()
+                        .filter(|detection| labels.contains(&detection.label))
+                        .collect(),
+                    Err(err) => {
+                        tracing::debug!(camera, error = %err, "Object detection failed");
+                        continue;
+                    }
+                };
+                recent.update(camera, detections.clone());
+                // One empty event tells clients to clear what they drew.
+                let had_before = std::mem::replace(&mut had_objects, !detections.is_empty());
+                if detections.is_empty() && !had_before {
+                    continue;
+                }
+
+                tracing::debug!(
+                    camera,
+                    objects = ?detections.iter().map(|detection| &detection.label).collect::<Vec<_>>(),
+                    "Objects detected"
+                );
+                let _ = events.send(DetectionEvent {
+                    camera,
+                    frame,
+                    detections,
+                });
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(feature = "detection")]
+mod backend {
+    use std::path::Path;
+
+    use anyhow::{bail, Context, Result};
+    use image::{
+        imageops::{self, FilterType},
+        Rgb, RgbImage,
+    };
+    use tract_onnx::prelude::*;
+
+    use super::Detection;
+
+    /// Input size assumed for models that leave it open.
+    const DEFAULT_INPUT_SIZE: usize = 640;
+    /// Boxes of one class overlapping a better one by more than this
+    /// intersection over union are dropped as duplicates.
+    const NMS_IOU: f32 = 0.45;
+    /// The grey Ultralytics pads letterboxed images with.
+    const PADDING: Rgb<u8> = Rgb([114, 114, 114]);
+    /// Class names of models trained on COCO, the usual YOLO exports.
+    const COCO_LABELS: [&str; 80] = [
+        "person",
+        "bicycle",
+        "car",
+        "motorcycle",
+        "airplane",
+        "bus",
+        "train",
+        "truck",
+        "boat",
+        "traffic light",
+        "fire hydrant",
+        "stop sign",
+        "parking meter",
+        "bench",
+        "bird",
+        "cat",
+        "dog",
+        "horse",
+        "sheep",
+        "cow",
+        "elephant",
+        "bear",
+        "zebra",
+        "giraffe",
+        "backpack",
+        "umbrella",
+        "handbag",
+        "tie",
+        "suitcase",
+        "frisbee",
+        "skis",
+        "snowboard",
+        "sports ball",
+        "kite",
+        "baseball bat",
+        "baseball glove",
+        "skateboard",
+        "surfboard",
+        "tennis racket",
+        "bottle",
+        "wine glass",
+        "cup",
+        "fork",
+        "knife",
+        "spoon",
+        "bowl",
+        "banana",
+        "apple",
+        "sandwich",
+        "orange",
+        "broccoli",
+        "carrot",
+        "hot dog",
+        "pizza",
+        "donut",
+        "cake",
+        "chair",
+        "couch",
+        "potted plant",
+        "bed",
+        "dining table",
+        "toilet",
+        "tv",
+        "laptop",
+        "mouse",
+        "remote",
+        "keyboard",
+        "cell phone",
+        "microwave",
+        "oven",
+        "toaster",
+        "sink",
+        "refrigerator",
+        "book",
+        "clock",
+        "vase",
+        "scissors",
+        "teddy bear",
+        "hair drier",
+        "toothbrush",
+    ];
+
+    /// A YOLOv8-style detector: a square RGB input scaled to 0-1, and an
+    /// output of `cx, cy, w, h` plus one score per class for every box.
+    pub struct Model {
+        plan: TypedRunnableModel<TypedModel>,
+        size: usize,
+    }
+
+    struct Candidate {
+        class: usize,
+        score: f32,
+        bbox: [f32; 4],
+    }
+
+    impl Model {
+        pub fn load(path: &Path) -> Result<Self> {
+            let model = tract_onnx::onnx()
+                .model_for_path(path)
+                .with_context(|| format!("Failed to load detection model {}", path.display()))?;
+            let size = model
+                .input_fact(0)?
+                .shape
+                .as_concrete_finite()?
+                .filter(|shape| shape.len() == 4)
+                .map_or(DEFAULT_INPUT_SIZE, |shape| shape[2]);
+            let plan = model
+                .with_input_fact(0, f32::fact([1, 3, size, size]).into())?
+                .into_optimized()?
+                .into_runnable()
+                .with_context(|| format!("Unsupported detection model {}", path.display()))?;
+            Ok(Self { plan, size })
+        }
+
+        /// Side of the square input; larger frames are scaled down to it.
+        pub fn input_size(&self) -> u32 {
+            self.size as u32
+        }
+
+        /// Objects in `image` scoring at least `confidence`, best first.
+        pub fn detect(&self, image: &RgbImage, confidence: f32) -> Result<Vec<Detection>> {
+            let size = self.input_size();
+            let (width, height) = image.dimensions();
+
+            // Letterboxed, so objects keep their proportions.
+            let scale = (size as f32 / width as f32).min(size as f32 / height as f32);
+            let scaled = imageops::resize(
+                image,
+                ((width as f32 * scale).round() as u32).clamp(1, size),
+                ((height as f32 * scale).round() as u32).clamp(1, size),
+                FilterType::Triangle,
+            );
+            let left = (size - scaled.width()) / 2;
+            let top = (size - scaled.height()) / 2;
+            let mut input = RgbImage::from_pixel(size, size, PADDING);
+            imageops::replace(&mut input, &scaled, i64::from(left), i64::from(top));
+
+            let tensor: Tensor = tract_ndarray::Array4::from_shape_fn(
+                (1, 3, self.size, self.size),
+                |(_, channel, y, x)| {
+                    f32::from(input.get_pixel(x as u32, y as u32)[channel]) / 255.0
+                },
+            )
+            .into();
+            let outputs = self.plan.run(tvec!(tensor.into()))?;
+            let output = outputs[0].to_array_view::<f32>()?;
+
+            // `[1, 4 + classes, boxes]`, or transposed by some exports.
+            let shape = output.shape();
+            if shape.len() != 3 || shape[1].min(shape[2]) <= 4 {
+                bail!("Unexpected detection output shape {shape:?}");
+            }
+            let transposed = shape[1] > shape[2];
+            let (attributes, boxes) = if transposed {
+                (shape[2], shape[1])
+            } else {
+                (shape[1], shape[2])
+            };
+            let value = |index: usize, attribute: usize| {
+                if transposed {
+                    output[[0, index, attribute]]
+                } else {
+                    output[[0, attribute, index]]
+                }
+            };
+
+            let mut candidates = Vec::new();
+            for index in 0..boxes {
+                let Some((class, score)) = (4..attributes)
+                    .map(|attribute| (attribute - 4, value(index, attribute)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                else {
+                    continue;
+                };
+                if score < confidence {
+                    continue;
+                }
+
+                // From the letterboxed input back to fractions of the frame.
+                let (cx, cy) = (value(index, 0), value(index, 1));
+                let (w, h) = (value(index, 2), value(index, 3));
+                let x = ((cx - w / 2.0 - left as f32) / scale / width as f32).clamp(0.0, 1.0);
+                let y = ((cy - h / 2.0 - top as f32) / scale / height as f32).clamp(0.0, 1.0);
+                let w = (w / scale / width as f32).min(1.0 - x);
+                let h = (h / scale / height as f32).min(1.0 - y);
+                candidates.push(Candidate {
+                    class,
+                    score,
+                    bbox: [x, y, w, h],
+                });
+            }
+
+            let classes = attributes - 4;
+            Ok(suppress_overlaps(candidates)
+                .into_iter()
+                .map(|candidate| Detection {
+                    label: match COCO_LABELS.get(candidate.class) {
+                        Some(label) if classes == COCO_LABELS.len() => label.to_string(),
+                        _ => format!("class{}", candidate.class),
+                    },
+                    confidence: candidate.score,
+                    bbox: candidate.bbox,
+                })
+                .collect())
+        }
+    }
+
+    /// Keeps the best of each group of overlapping boxes of the same class.
+    fn suppress_overlaps(mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let mut kept: Vec<Candidate> = Vec::new();
+        for candidate in candidates {
+            if kept.iter().all(|better| {
+                better.class != candidate.class || iou(&better.bbox, &candidate.bbox) <= NMS_IOU
+            }) {
+                kept.push(candidate);
+            }
+        }
+        kept
+    }
+
+    fn iou(a: &[f32; 4], b: &[f32; 4]) -> f32 {
+        let width = ((a[0] + a[2]).min(b[0] + b[2]) - a[0].max(b[0])).max(0.0);
+        let height = ((a[1] + a[3]).min(b[1] + b[3]) - a[1].max(b[1])).max(0.0);
+        let intersection = width * height;
+        let union = a[2] * a[3] + b[2] * b[3] - intersection;
+        if union <= 0.0 {
+            0.0
+        } else {
+            intersection / union
+        }
+    }
+}
+
+#[cfg(not(feature = "detection"))]
+mod backend {
+    use std::path::Path;
+
+    use anyhow::{bail, Result};
+    use image::RgbImage;
+
+    use super::Detection;
+
+    pub struct Model;
+
+    impl Model {
+        pub fn load(_path: &Path) -> Result<Self> {
+            bail!("Built without object detection; enable the `detection` feature")
+        }
+
+        pub fn input_size(&self) -> u32 {
+            0
+        }
+
+        pub fn detect(&self, _image: &RgbImage, _confidence: f32) -> Result<Vec<Detection>> {
+            Ok(Vec::new())
+        }
+    }
+}
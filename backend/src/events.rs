@@ -0,0 +1,313 @@
+//! One stream of status events for `GET /events`, so frontends can subscribe
+//! once instead of polling `/health`, `/recordings` and `/config`.
+
+use std::{convert::Infallible, path::Path, time::Duration};
+
+use axum::response::{
+    sse::{Event, KeepAlive, Sse},
+    IntoResponse, Response,
+};
+use chrono::Local;
+use serde::Serialize;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    watch,
+};
+use utoipa::ToSchema;
+
+use crate::{
+    capture::CameraHandle,
+    config::Config,
+    detection::{Detection, DetectionEvent},
+    motion::{MotionEvent, MotionRegion},
+    recorder::RecordingEvent,
+    wait_for_shutdown,
+};
+
+/// Events a slow client may fall behind before it misses some.
+const CHANNEL_CAPACITY: usize = 64;
+/// Comment lines sent while idle, so proxies keep the connection open.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Sent as an SSE event of the same name, with the fields, the name as
+/// `event` and a `timestamp` as JSON data.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StatusEvent {
+    /// The watchdog is reopening a failing device.
+    CameraError {
+        camera: usize,
+    },
+    CameraRecovered {
+        camera: usize,
+    },
+    /// The camera delivered no frame for `CAPTURE_STALL_SECS` and is being
+    /// reopened.
+    CameraStalled {
+        camera: usize,
+    },
+    MotionStarted {
+        camera: usize,
+        #[serde(flatten)]
+        region: MotionRegion,
+    },
+    /// Where the motion is now, at most twice a second while it lasts.
+    MotionUpdated {
+        camera: usize,
+        #[serde(flatten)]
+        region: MotionRegion,
+    },
+    MotionStopped {
+        camera: usize,
+    },
+    /// Objects the detection model found in a sampled frame; empty once
+    /// they are gone.
+    ObjectsDetected {
+        camera: usize,
+        frame: u64,
+        detections: Vec<Detection>,
+    },
+    /// `recording` is the clip id, as served at `/recordings/{id}`.
+    RecordingStarted {
+        camera: usize,
+        recording: String,
+    },
+    RecordingFinished {
+        camera: usize,
+        recording: String,
+    },
+    /// Carries the settings now in effect.
+    ConfigChanged {
+        config: Box<Config>,
+    },
+}
+
+impl StatusEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::CameraError { .. } => "camera_error",
+            Self::CameraRecovered { .. } => "camera_recovered",
+            Self::CameraStalled { .. } => "camera_stalled",
+            Self::MotionStarted { .. } => "motion_started",
+            Self::MotionUpdated { .. } => "motion_updated",
+            Self::MotionStopped { .. } => "motion_stopped",
+            Self::ObjectsDetected { .. } => "objects_detected",
+            Self::RecordingStarted { .. } => "recording_started",
+            Self::RecordingFinished { .. } => "recording_finished",
+            Self::ConfigChanged { .. } => "config_changed",
+        }
+    }
+
+    /// The camera the event is about; `None` for server-wide events.
+    fn camera(&self) -> Option<usize> {
+        match self {
+            Self::CameraError { camera }
+            | Self::CameraRecovered { camera }
+            | Self::CameraStalled { camera }
+            | Self::MotionStarted { camera, .. }
+            | Self::MotionUpdated { camera, .. }
+            | Self::MotionStopped { camera }
+            | Self::ObjectsDetected { camera, .. }
+            | Self::RecordingStarted { camera, .. }
+            | Self::RecordingFinished { camera, .. } => Some(*camera),
+            Self::ConfigChanged { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct StampedEvent {
+    #[serde(flatten)]
+    event: StatusEvent,
+    /// RFC 3339 time the event happened.
+    timestamp: String,
+}
+
+/// The next event about `camera` or the whole server, skipping any missed
+/// while the receiver was behind; `None` once the bus is gone.
+pub async fn recv_for_camera(
+    events: &mut broadcast::Receiver<StampedEvent>,
+    camera: usize,
+) -> Option<StampedEvent> {
+    loop {
+        match events.recv().await {
+            Ok(event) if event.event.camera().is_some_and(|other| other != camera) => {}
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Broadcasts status events to every `/events` client.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<StampedEvent>,
+}
+
+impl EventBus {
+    pub fn send(&self, event: StatusEvent) {
+        // Sending only fails while nobody is subscribed, which is fine.
+        let _ = self.sender.send(StampedEvent {
+            event,
+            timestamp: Local::now().to_rfc3339(),
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StampedEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Collects camera outages, motion, detections and recordings into one bus,
+/// which config changes are sent to directly.
+pub fn spawn(
+    cameras: &[CameraHandle],
+    motion: Option<broadcast::Receiver<MotionEvent>>,
+    detections: Option<broadcast::Receiver<DetectionEvent>>,
+    recordings: Option<broadcast::Receiver<RecordingEvent>>,
+) -> EventBus {
+    let events = EventBus {
+        sender: broadcast::channel(CHANNEL_CAPACITY).0,
+    };
+
+    for (camera, handle) in cameras.iter().enumerate() {
+        let mut reconnecting = handle.watch_reconnecting();
+        let mut stalls = handle.watch_stalls();
+        let events = events.clone();
+        tokio::spawn(async move {
+            loop {
+                // A stall is reported before the reconnect it leads to.
+                let event = tokio::select! {
+                    biased;
+                    changed = stalls.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        StatusEvent::CameraStalled { camera }
+                    }
+                    changed = reconnecting.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        if *reconnecting.borrow_and_update() {
+                            StatusEvent::CameraError { camera }
+                        } else {
+                            StatusEvent::CameraRecovered { camera }
+                        }
+                    }
+                };
+                events.send(event);
+            }
+        });
+    }
+
+    if let Some(mut motion) = motion {
+        let events = events.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = match motion.recv().await {
+                    Ok(MotionEvent::Started { camera, region }) => {
+                        StatusEvent::MotionStarted { camera, region }
+                    }
+                    Ok(MotionEvent::Updated { camera, region }) => {
+                        StatusEvent::MotionUpdated { camera, region }
+                    }
+                    Ok(MotionEvent::Stopped { camera }) => StatusEvent::MotionStopped { camera },
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                events.send(event);
+            }
+        });
+    }
+
+    if let Some(mut detections) = detections {
+        let events = events.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = match detections.recv().await {
+                    Ok(DetectionEvent {
+                        camera,
+                        frame,
+                        detections,
+                    }) => StatusEvent::ObjectsDetected {
+                        camera,
+                        frame,
+                        detections,
+                    },
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                events.send(event);
+            }
+        });
+    }
+
+    if let Some(mut recordings) = recordings {
+        let events = events.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = match recordings.recv().await {
+                    Ok(RecordingEvent::Started { camera, path }) => StatusEvent::RecordingStarted {
+                        camera,
+                        recording: recording_id(&path),
+                    },
+                    Ok(RecordingEvent::Finished { camera, path }) => {
+                        StatusEvent::RecordingFinished {
+                            camera,
+                            recording: recording_id(&path),
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                events.send(event);
+            }
+        });
+    }
+
+    events
+}
+
+fn recording_id(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Streams events as they happen, until shutdown; nothing is replayed on
+/// connect.
+pub fn sse_response(
+    mut events: broadcast::Receiver<StampedEvent>,
+    shutdown: watch::Receiver<bool>,
+) -> Response {
+    let stream = async_stream::stream! {
+        let stopping = wait_for_shutdown(shutdown);
+        tokio::pin!(stopping);
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                () = &mut stopping => break,
+            };
+            let event = match event {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::debug!(missed, "Event client behind; skipping events");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            match Event::default().event(event.event.name()).json_data(&event) {
+                Ok(sse) => yield Ok::<Event, Infallible>(sse),
+                Err(err) => tracing::error!(error = %err, "Serializing status event failed"),
+            }
+        }
+    };
+
+    (
+        [(crate::X_ACCEL_BUFFERING, "no")],
+        Sse::new(stream).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL)),
+    )
+        .into_response()
+}
@@ -0,0 +1,70 @@
+//! Drives a Raspberry Pi GPIO pin at night, to light an IR illuminator or
+//! swing an IR-cut filter out of the way, without a separate daemon.
+//!
+//! Needs the `gpio` feature. The pin is released when the backend exits,
+//! which turns the illuminator off again.
+
+use anyhow::Result;
+use tokio::sync::watch;
+
+use crate::config::IrGpioConfig;
+
+/// Switches the pin whenever `night` changes, until the backend exits.
+pub fn spawn(config: &IrGpioConfig, mut night: watch::Receiver<bool>) -> Result<()> {
+    let mut pin = backend::OutputPin::open(config.pin)?;
+    let active_low = config.active_low;
+    let pin_number = config.pin;
+
+    tokio::spawn(async move {
+        loop {
+            let is_night = *night.borrow_and_update();
+            pin.set(is_night != active_low);
+            tracing::info!(pin = pin_number, night = is_night, "IR GPIO switched");
+            if night.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+mod backend {
+    use anyhow::{Context, Result};
+    use rppal::gpio::Gpio;
+
+    pub struct OutputPin(rppal::gpio::OutputPin);
+
+    impl OutputPin {
+        pub fn open(pin: u8) -> Result<Self> {
+            let gpio = Gpio::new().context("Failed to open GPIO; is this a Raspberry Pi?")?;
+            let pin = gpio
+                .get(pin)
+                .with_context(|| format!("Failed to claim GPIO pin {pin}"))?;
+            Ok(Self(pin.into_output_low()))
+        }
+
+        pub fn set(&mut self, high: bool) {
+            if high {
+                self.0.set_high();
+            } else {
+                self.0.set_low();
+            }
+        }
+    }
+}
+
+#[cfg(not(all(feature = "gpio", target_os = "linux")))]
+mod backend {
+    use anyhow::{bail, Result};
+
+    pub struct OutputPin;
+
+    impl OutputPin {
+        pub fn open(_pin: u8) -> Result<Self> {
+            bail!("Built without GPIO support; enable the `gpio` feature")
+        }
+
+        pub fn set(&mut self, _high: bool) {}
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{request::Parts, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+
+use crate::config::IpFilterConfig;
+
+/// The client's address as [`client_ip`] resolves it through the trusted
+/// proxies; `None` when it cannot be told.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Self>()
+            .copied()
+            .unwrap_or(Self(None)))
+    }
+}
+
+/// Adds the [`ClientIp`] to the request's extensions, for the middleware and
+/// handlers that run after it.
+pub async fn resolve_client(
+    State(trusted_proxies): State<Arc<[IpNet]>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = client_ip(&trusted_proxies, peer, request.headers());
+    request.extensions_mut().insert(ClientIp(client));
+    next.run(request).await
+}
+
+/// Turns away clients from denied networks, or from outside the allowed
+/// ones, before any handler runs. Runs inside `resolve_client`.
+pub async fn filter_clients(
+    State(filter): State<Arc<IpFilterConfig>>,
+    ClientIp(client): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !permits(&filter, client) {
+        tracing::debug!(client = ?client, "Client network not allowed");
+        return (StatusCode::FORBIDDEN, "forbidden").into_response();
+    }
+    next.run(request).await
+}
+
+/// The peer's address, unless it is a trusted proxy: then the nearest
+/// `X-Forwarded-For` hop that is not a trusted proxy itself. Unix socket
+/// connections have no peer address, so their proxy is always trusted.
+/// `None` when a forwarded address cannot be read.
+fn client_ip(
+    trusted_proxies: &[IpNet],
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|net| net.contains(&ip));
+    let mut client = peer.map(|ip| ip.to_canonical());
+    if client.is_some_and(|ip| !is_trusted(ip)) {
+        return client;
+    }
+
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    // Proxies append the address they received the request from, so the
+    // rightmost untrusted hop is the one no client could have forged.
+    for hop in hops.iter().rev() {
+        let ip = hop.parse::<IpAddr>().ok()?.to_canonical();
+        client = Some(ip);
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Clients of unknown address only pass when no allowlist is set.
+fn permits(filter: &IpFilterConfig, client: Option<IpAddr>) -> bool {
+    let Some(ip) = client else {
+        return filter.allowed.is_empty();
+    };
+    !filter.denied.iter().any(|net| net.contains(&ip))
+        && (filter.allowed.is_empty() || filter.allowed.iter().any(|net| net.contains(&ip)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn networks(list: &[&str]) -> Vec<IpNet> {
+        list.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    fn filter(allowed: &[&str], denied: &[&str]) -> IpFilterConfig {
+        IpFilterConfig {
+            allowed: networks(allowed),
+            denied: networks(denied),
+        }
+    }
+
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let trusted = networks(&["10.0.0.0/8"]);
+        let headers = forwarded(&["203.0.113.9"]);
+        assert_eq!(
+            client_ip(&trusted, Some(ip("192.168.1.5")), &headers),
+            Some(ip("192.168.1.5"))
+        );
+    }
+
+    #[test]
+    fn trusted_peer_forwards_the_rightmost_untrusted_hop() {
+        let trusted = networks(&["10.0.0.0/8"]);
+        // The client claims to be 198.51.100.1; the first proxy saw
+        // 203.0.113.9 and a second trusted proxy 10.0.0.2.
+        let headers = forwarded(&["198.51.100.1, 203.0.113.9, 10.0.0.2"]);
+        assert_eq!(
+            client_ip(&trusted, Some(ip("10.0.0.1")), &headers),
+            Some(ip("203.0.113.9"))
+        );
+    }
+
+    #[test]
+    fn hops_are_read_across_headers() {
+        let trusted = networks(&["10.0.0.0/8"]);
+        let headers = forwarded(&["203.0.113.9", "10.0.0.2"]);
+        assert_eq!(
+            client_ip(&trusted, Some(ip("10.0.0.1")), &headers),
+            Some(ip("203.0.113.9"))
+        );
+    }
+
+    #[test]
+    fn all_hops_trusted_gives_the_leftmost() {
+        let trusted = networks(&["10.0.0.0/8"]);
+        let headers = forwarded(&["10.0.0.3, 10.0.0.2"]);
+        assert_eq!(
+            client_ip(&trusted, Some(ip("10.0.0.1")), &headers),
+            Some(ip("10.0.0.3"))
+        );
+    }
+
+    #[test]
+    fn trusted_peer_without_header_is_the_client() {
+        let trusted = networks(&["10.0.0.0/8"]);
+        assert_eq!(
+            client_ip(&trusted, Some(ip("10.0.0.1")), &HeaderMap::new()),
+            Some(ip("10.0.0.1"))
+        );
+    }
+
+    #[test]
+    fn unix_socket_trusts_its_proxy() {
+        let trusted = networks(&[]);
+        let headers = forwarded(&["203.0.113.9"]);
+        assert_eq!(client_ip(&trusted, None, &headers), Some(ip("203.0.113.9")));
+        assert_eq!(client_ip(&trusted, None, &HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_are_canonical() {
+        let trusted = networks(&["10.0.0.0/8"]);
+        assert_eq!(
+            client_ip(&trusted, Some(ip("::ffff:192.168.1.5")), &HeaderMap::new()),
+            Some(ip("192.168.1.5"))
+        );
+        // A mapped proxy address still counts as trusted.
+        let headers = forwarded(&["::ffff:203.0.113.9"]);
+        assert_eq!(
+            client_ip(&trusted, Some(ip("::ffff:10.0.0.1")), &headers),
+            Some(ip("203.0.113.9"))
+        );
+    }
+
+    #[test]
+    fn unreadable_hops_leave_the_client_unknown() {
+        let trusted = networks(&["10.0.0.0/8"]);
+        let peer = Some(ip("10.0.0.1"));
+        assert_eq!(client_ip(&trusted, peer, &forwarded(&[""])), None);
+        assert_eq!(client_ip(&trusted, peer, &forwarded(&["unknown"])), None);
+        assert_eq!(
+            client_ip(&trusted, peer, &forwarded(&["203.0.113.9:443"])),
+            None
+        );
+        assert_eq!(
+            client_ip(&trusted, peer, &forwarded(&["203.0.113.9, garbage"])),
+            None
+        );
+    }
+
+    #[test]
+    fn hops_left_of_the_client_are_not_read() {
+        let trusted = networks(&["10.0.0.0/8"]);
+        let headers = forwarded(&["garbage, 203.0.113.9"]);
+        assert_eq!(
+            client_ip(&trusted, Some(ip("10.0.0.1")), &headers),
+            Some(ip("203.0.113.9"))
+        );
+    }
+
+    #[test]
+    fn allowed_networks_match_by_prefix() {
+        let filter = filter(&["192.168.1.0/24", "2001:db8::/32"], &[]);
+        assert!(permits(&filter, Some(ip("192.168.1.200"))));
+        assert!(permits(&filter, Some(ip("2001:db8::1"))));
+        assert!(!permits(&filter, Some(ip("192.168.2.1"))));
+        assert!(!permits(&filter, Some(ip("2001:db9::1"))));
+    }
+
+    #[test]
+    fn denied_networks_win_over_allowed_ones() {
+        let filter = filter(&["192.168.0.0/16"], &["192.168.1.7/32"]);
+        assert!(permits(&filter, Some(ip("192.168.1.6"))));
+        assert!(!permits(&filter, Some(ip("192.168.1.7"))));
+    }
+
+    #[test]
+    fn without_allowlist_everyone_not_denied_passes() {
+        let filter = filter(&[], &["198.51.100.0/24"]);
+        assert!(permits(&filter, Some(ip("203.0.113.9"))));
+        assert!(!permits(&filter, Some(ip("198.51.100.1"))));
+    }
+
+    #[test]
+    fn unknown_clients_only_pass_without_allowlist() {
+        assert!(permits(&filter(&[], &["198.51.100.0/24"]), None));
+        assert!(!permits(&filter(&["192.168.1.0/24"], &[]), None));
+    }
+}
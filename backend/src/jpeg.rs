@@ -0,0 +1,408 @@
+//! JPEG encoding and decoding for captured frames.
+//!
+//! Uses the pure-Rust `image` codecs by default. Building with the
+//! `turbojpeg` feature switches to libjpeg-turbo, which is several times
+//! faster on the Pi and compresses YUV frames without an RGB round trip.
+
+use std::io::Cursor;
+
+use anyhow::Result;
+use image::{
+    codecs::jpeg::JpegDecoder,
+    imageops::{self, FilterType},
+    DynamicImage, RgbImage,
+};
+
+use crate::config::Demosaic;
+
+/// Uncompressed pixel layouts V4L2 cameras deliver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+// Other backends deliver fewer of them.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub enum RawFormat {
+    /// Packed 4:2:2 YUV as Y0 U Y1 V.
+    Yuyv,
+    /// Packed 4:2:2 YUV as U Y0 V Y1.
+    Uyvy,
+    /// 4:2:0 YUV: a luma plane followed by interleaved U V pairs.
+    Nv12,
+    /// 16-bit little-endian RGB with 5, 6 and 5 bits per channel.
+    Rgb565,
+    /// 8-bit luma only.
+    Grey,
+    /// 8-bit RAW Bayer in BGGR order, interpolated as given.
+    Sbggr8(Demosaic),
+    /// 10-bit RAW Bayer in BGGR order, one sample per 16 bits.
+    Sbggr10(Demosaic),
+}
+
+impl RawFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            RawFormat::Yuyv => "YUYV",
+            RawFormat::Uyvy => "UYVY",
+            RawFormat::Nv12 => "NV12",
+            RawFormat::Rgb565 => "RGB565",
+            RawFormat::Grey => "GREY",
+            RawFormat::Sbggr8(_) => "SBGGR8",
+            RawFormat::Sbggr10(_) => "SBGGR10",
+        }
+    }
+
+    fn frame_len(self, width: usize, height: usize) -> usize {
+        match self {
+            RawFormat::Yuyv | RawFormat::Uyvy | RawFormat::Rgb565 | RawFormat::Sbggr10(_) => {
+                width * height * 2
+            }
+            RawFormat::Nv12 => width * height * 3 / 2,
+            RawFormat::Grey | RawFormat::Sbggr8(_) => width * height,
+        }
+    }
+
+    /// Positions of Y0, U, Y1 and V within each four bytes of a packed
+    /// 4:2:2 format.
+    fn packed_order(self) -> [usize; 4] {
+        match self {
+            RawFormat::Uyvy => [1, 0, 3, 2],
+            _ => [0, 1, 2, 3],
+        }
+    }
+}
+
+/// Encodes an uncompressed frame into `output`. Converted pixels go to
+/// `scratch`, which keeps its allocation for the next frame.
+// Unused by builds without a native camera backend.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub fn encode_raw(
+    format: RawFormat,
+    frame: &[u8],
+    width: u32,
+    height: u32,
+    quality: u8,
+    scratch: &mut Vec<u8>,
+    output: &mut Vec<u8>,
+) -> Result<()> {
+    let expected_len = format.frame_len(width as usize, height as usize);
+    if frame.len() < expected_len {
+        anyhow::bail!(
+            "{} frame length {} smaller than expected {} for resolution {}x{}",
+            format.name(),
+            frame.len(),
+            expected_len,
+            width,
+            height
+        );
+    }
+
+    backend::encode_raw(
+        format,
+        &frame[..expected_len],
+        width,
+        height,
+        quality,
+        scratch,
+        output,
+    )
+}
+
+pub fn decode_rgb(jpeg: &[u8]) -> Result<RgbImage> {
+    backend::decode_rgb(jpeg)
+}
+
+pub fn encode_rgb(image: &RgbImage, quality: u8) -> Result<Vec<u8>> {
+    backend::encode_rgb(image, quality)
+}
+
+/// Decodes a frame no wider than `max_width`, keeping its aspect ratio.
+///
+/// Most of the reduction happens inside the decoder, which can skip detail
+/// in steps of up to 1/8, so small previews of large frames stay cheap.
+pub fn decode_scaled(jpeg: &[u8], max_width: u32) -> Result<RgbImage> {
+    let mut decoder = JpegDecoder::new(Cursor::new(jpeg))?;
+    // The decoder stops shrinking once either axis reaches the requested
+    // size, so an unreachable height leaves only the width to count.
+    decoder.scale(max_width.min(u32::from(u16::MAX)) as u16, u16::MAX)?;
+    let image = DynamicImage::from_decoder(decoder)?.to_rgb8();
+    if image.width() <= max_width {
+        return Ok(image);
+    }
+    Ok(downscale(&image, max_width))
+}
+
+/// `image` no wider than `max_width`, keeping its aspect ratio.
+pub fn downscale(image: &RgbImage, max_width: u32) -> RgbImage {
+    if image.width() <= max_width {
+        return image.clone();
+    }
+    let height = (image.height() * max_width / image.width()).max(1);
+    imageops::resize(image, max_width, height, FilterType::Triangle)
+}
+
+/// Expands RGB565 pixels to 8-bit RGB in `rgb`.
+fn rgb565_to_rgb(frame: &[u8], rgb: &mut [u8]) {
+    for (bytes, pixel) in frame.chunks_exact(2).zip(rgb.chunks_exact_mut(3)) {
+        let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let red = (value >> 11) as u8;
+        let green = ((value >> 5) & 0x3f) as u8;
+        let blue = (value & 0x1f) as u8;
+        // Repeating the high bits in the low ones maps full scale to 255.
+        pixel[0] = (red << 3) | (red >> 2);
+        pixel[1] = (green << 2) | (green >> 4);
+        pixel[2] = (blue << 3) | (blue >> 2);
+    }
+}
+
+#[cfg(not(feature = "turbojpeg"))]
+mod backend {
+    use std::mem;
+
+    use anyhow::{Context, Result};
+    use image::{codecs::jpeg::JpegEncoder, ColorType, ImageFormat, RgbImage};
+
+    use super::{rgb565_to_rgb, RawFormat};
+    use crate::{
+        bayer::{self, Depth},
+        timings::{self, Stage},
+        yuv,
+    };
+
+    pub fn encode_raw(
+        format: RawFormat,
+        frame: &[u8],
+        width: u32,
+        height: u32,
+        quality: u8,
+        scratch: &mut Vec<u8>,
+        output: &mut Vec<u8>,
+    ) -> Result<()> {
+        if format == RawFormat::Grey {
+            return timings::time(Stage::Encode, || {
+                JpegEncoder::new_with_quality(output, quality).encode(
+                    frame,
+                    width,
+                    height,
+                    ColorType::L8,
+                )
+            })
+            .context("Failed to encode GREY frame to JPEG");
+        }
+
+        let (columns, rows) = (width as usize, height as usize);
+        timings::time(Stage::Convert, || {
+            scratch.clear();
+            scratch.resize(columns * rows * 3, 0);
+            match format {
+                RawFormat::Nv12 => yuv::nv12_to_rgb(frame, columns, scratch),
+                RawFormat::Rgb565 => rgb565_to_rgb(frame, scratch),
+                RawFormat::Sbggr8(method) => {
+                    return bayer::demosaic(frame, Depth::Eight, columns, rows, method, scratch)
+                }
+                RawFormat::Sbggr10(method) => {
+                    return bayer::demosaic(frame, Depth::Ten, columns, rows, method, scratch)
+                }
+                _ => yuv::packed_to_rgb(frame, format.packed_order(), scratch),
+            }
+            Ok(())
+        })?;
+
+        let image = RgbImage::from_vec(width, height, mem::take(scratch))
+            .with_context(|| format!("Failed to build RGB buffer from {} data", format.name()))?;
+        let encoded = timings::time(Stage::Encode, || encode_rgb_into(&image, quality, output))
+            .with_context(|| format!("Failed to encode {} frame to JPEG", format.name()));
+        *scratch = image.into_raw();
+        encoded
+    }
+
+    pub fn decode_rgb(jpeg: &[u8]) -> Result<RgbImage> {
+        Ok(image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg)?.to_rgb8())
+    }
+
+    pub fn encode_rgb(image: &RgbImage, quality: u8) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        encode_rgb_into(image, quality, &mut output)?;
+        Ok(output)
+    }
+
+    fn encode_rgb_into(image: &RgbImage, quality: u8, output: &mut Vec<u8>) -> Result<()> {
+        JpegEncoder::new_with_quality(output, quality).encode_image(image)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "turbojpeg")]
+mod backend {
+    use std::time::Instant;
+
+    use anyhow::{Context, Result};
+    use image::RgbImage;
+    use turbojpeg::{Compressor, Image, PixelFormat, Subsamp, YuvImage};
+
+    use super::{rgb565_to_rgb, RawFormat};
+    use crate::{
+        bayer::{self, Depth},
+        timings::{self, Stage},
+    };
+
+    pub fn encode_raw(
+        format: RawFormat,
+        frame: &[u8],
+        width: u32,
+        height: u32,
+        quality: u8,
+        scratch: &mut Vec<u8>,
+        output: &mut Vec<u8>,
+    ) -> Result<()> {
+        let (width, height) = (width as usize, height as usize);
+
+        // TurboJPEG takes planar YUV, so YUV formats are split into Y, U and
+        // V planes at the subsampling the camera already applied. RGB565 and
+        // Bayer are expanded to RGB, and greyscale is compressed as is.
+        let subsamp = match format {
+            RawFormat::Yuyv | RawFormat::Uyvy => Subsamp::Sub2x1,
+            RawFormat::Grey => Subsamp::Gray,
+            _ => Subsamp::Sub2x2,
+        };
+        if format != RawFormat::Grey {
+            timings::time(Stage::Convert, || {
+                scratch.clear();
+                match format {
+                    RawFormat::Yuyv | RawFormat::Uyvy => {
+                        packed_to_planar(frame, format.packed_order(), width * height, scratch)
+                    }
+                    RawFormat::Nv12 => nv12_to_planar(frame, width, height, scratch),
+                    _ => {
+                        scratch.resize(width * height * 3, 0);
+                        match format {
+                            RawFormat::Sbggr8(method) => {
+                                return bayer::demosaic(
+                                    frame,
+                                    Depth::Eight,
+                                    width,
+                                    height,
+                                    method,
+                                    scratch,
+                                )
+                            }
+                            RawFormat::Sbggr10(method) => {
+                                return bayer::demosaic(
+                                    frame,
+                                    Depth::Ten,
+                                    width,
+                                    height,
+                                    method,
+                                    scratch,
+                                )
+                            }
+                            _ => rgb565_to_rgb(frame, scratch),
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        let started = Instant::now();
+        let mut compressor = compressor(quality)?;
+        compressor
+            .set_subsamp(subsamp)
+            .context("Failed to configure TurboJPEG subsampling")?;
+        // Compressing into a slice of the worst-case size lets `output`
+        // be reused, unlike the buffers TurboJPEG allocates itself.
+        output.resize(compressor.buf_len(width, height)?, 0);
+        let compressed = match format {
+            RawFormat::Grey => compressor.compress_to_slice(
+                Image {
+                    pixels: frame,
+                    width,
+                    pitch: width,
+                    height,
+                    format: PixelFormat::GRAY,
+                },
+                output,
+            ),
+            RawFormat::Yuyv | RawFormat::Uyvy | RawFormat::Nv12 => compressor
+                .compress_yuv_to_slice(
+                    YuvImage {
+                        pixels: &scratch[..],
+                        width,
+                        align: 1,
+                        height,
+                        subsamp,
+                    },
+                    output,
+                ),
+            _ => compressor.compress_to_slice(
+                Image {
+                    pixels: &scratch[..],
+                    width,
+                    pitch: width * 3,
+                    height,
+                    format: PixelFormat::RGB,
+                },
+                output,
+            ),
+        };
+        let len = compressed
+            .with_context(|| format!("Failed to encode {} frame to JPEG", format.name()))?;
+        output.truncate(len);
+        timings::record(Stage::Encode, started.elapsed());
+        Ok(())
+    }
+
+    pub fn decode_rgb(jpeg: &[u8]) -> Result<RgbImage> {
+        let image = turbojpeg::decompress(jpeg, PixelFormat::RGB)?;
+        RgbImage::from_vec(image.width as u32, image.height as u32, image.pixels)
+            .context("Decoded JPEG has an unexpected buffer size")
+    }
+
+    pub fn encode_rgb(image: &RgbImage, quality: u8) -> Result<Vec<u8>> {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut compressor = compressor(quality)?;
+        compressor
+            .set_subsamp(Subsamp::Sub2x2)
+            .context("Failed to configure TurboJPEG subsampling")?;
+        Ok(compressor.compress_to_vec(Image {
+            pixels: image.as_raw().as_slice(),
+            width,
+            pitch: width * 3,
+            height,
+            format: PixelFormat::RGB,
+        })?)
+    }
+
+    /// Splits packed 4:2:2 YUV whose Y0, U, Y1 and V bytes sit at `order`
+    /// into planes.
+    fn packed_to_planar(frame: &[u8], order: [usize; 4], luma_len: usize, planes: &mut Vec<u8>) {
+        let [y0, cb, y1, cr] = order;
+        planes.resize(luma_len * 2, 0);
+        let (luma, chroma) = planes.split_at_mut(luma_len);
+        let (u, v) = chroma.split_at_mut(luma_len / 2);
+        for (index, chunk) in frame.chunks_exact(4).enumerate() {
+            luma[2 * index] = chunk[y0];
+            u[index] = chunk[cb];
+            luma[2 * index + 1] = chunk[y1];
+            v[index] = chunk[cr];
+        }
+    }
+
+    /// Turns NV12 into I420 by splitting the interleaved chroma plane.
+    fn nv12_to_planar(frame: &[u8], width: usize, height: usize, planes: &mut Vec<u8>) {
+        let luma_len = width * height;
+        let chroma_len = luma_len / 4;
+        planes.extend_from_slice(&frame[..luma_len]);
+        planes.resize(luma_len + 2 * chroma_len, 0);
+        let (u, v) = planes[luma_len..].split_at_mut(chroma_len);
+        for ((uv, u), v) in frame[luma_len..].chunks_exact(2).zip(u).zip(v) {
+            *u = uv[0];
+            *v = uv[1];
+        }
+    }
+
+    fn compressor(quality: u8) -> Result<Compressor> {
+        let mut compressor = Compressor::new().context("Failed to initialise TurboJPEG")?;
+        compressor
+            .set_quality(i32::from(quality))
+            .context("Invalid JPEG quality")?;
+        Ok(compressor)
+    }
+}
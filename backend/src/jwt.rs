@@ -0,0 +1,251 @@
+//! Short-lived viewer tokens issued by an external auth service, checked
+//! against a shared secret or the issuer's published keys, so public pages
+//! never need to embed the long-lived `AUTH_TOKEN`.
+
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::config::{JwtConfig, JwtKey};
+
+/// Needed in the `scope` claim for anything but reading.
+pub const ADMIN_SCOPE: &str = "admin";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Tokens are still accepted this long after `exp`, allowing for clocks
+/// that are slightly off.
+const LEEWAY_SECS: u64 = 60;
+/// Keys are fetched again after this long to pick up rotations.
+const JWKS_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+/// Unknown key IDs trigger a fetch at most this often, so a stream of forged
+/// tokens cannot hammer the issuer.
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+/// Algorithms accepted with published keys. HMAC is left out so a public key
+/// can never be used as a shared secret.
+const JWKS_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+pub struct JwtVerifier {
+    keys: Keys,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+enum Keys {
+    Secret(DecodingKey),
+    Jwks {
+        client: Client,
+        url: String,
+        cache: RwLock<JwksCache>,
+        /// Held while fetching, so one request fetches and the others
+        /// wait for it without blocking lookups of cached keys.
+        fetching: Mutex<()>,
+    },
+}
+
+#[derive(Default)]
+struct JwksCache {
+    keys: Vec<Jwk>,
+    fetched_at: Option<Instant>,
+    attempted_at: Option<Instant>,
+}
+
+/// The claims looked at beyond the standard ones `jsonwebtoken` validates.
+#[derive(Deserialize)]
+pub struct Claims {
+    /// Space-separated, as in OAuth 2.
+    #[serde(default)]
+    scope: String,
+    sub: Option<String>,
+    /// Unix seconds; required, so always present.
+    exp: u64,
+}
+
+impl Claims {
+    /// Whom the token was issued to, if it says.
+    pub fn subject(&self) -> Option<&str> {
+        self.sub.as_deref()
+    }
+
+    /// When the token stops being accepted, in Unix seconds.
+    pub fn expires(&self) -> u64 {
+        self.exp.saturating_add(LEEWAY_SECS)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope
+            .split_whitespace()
+            .any(|granted| granted == scope)
+    }
+}
+
+impl JwtVerifier {
+    pub fn new(config: &JwtConfig) -> Result<Self> {
+        let keys = match &config.key {
+            JwtKey::Secret(secret) => Keys::Secret(DecodingKey::from_secret(secret.as_bytes())),
+            JwtKey::JwksUrl(url) => Keys::Jwks {
+                client: Client::builder()
+                    .timeout(REQUEST_TIMEOUT)
+                    .build()
+                    .context("Failed to build JWKS HTTP client")?,
+                url: url.clone(),
+                cache: RwLock::default(),
+                fetching: Mutex::default(),
+            },
+        };
+        Ok(Self {
+            keys,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+        })
+    }
+
+    /// The token's claims if it is signed by the issuer, unexpired and meant
+    /// for us.
+    pub async fn verify(&self, token: &str) -> Result<Claims> {
+        let header = decode_header(token).context("Malformed token")?;
+        let (key, algorithm) = match &self.keys {
+            Keys::Secret(key) => (key.clone(), Algorithm::HS256),
+            Keys::Jwks {
+                client,
+                url,
+                cache,
+                fetching,
+            } => {
+                if !JWKS_ALGORITHMS.contains(&header.alg) {
+                    return Err(anyhow!("Unsupported algorithm {:?}", header.alg));
+                }
+                let jwk =
+                    published_key(client, url, cache, fetching, header.kid.as_deref()).await?;
+                if let Some(expected) = jwk.common.key_algorithm {
+                    if Algorithm::from_str(&expected.to_string()).ok() != Some(header.alg) {
+                        return Err(anyhow!("Key is not meant for {:?}", header.alg));
+                    }
+                }
+                let key = DecodingKey::from_jwk(&jwk).context("Unusable published key")?;
+                (key, header.alg)
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = LEEWAY_SECS;
+        let mut required = vec!["exp"];
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            required.push("iss");
+        }
+        match &self.audience {
+            Some(audience) => {
+                validation.set_audience(&[audience]);
+                required.push("aud");
+            }
+            None => validation.validate_aud = false,
+        }
+        validation.set_required_spec_claims(&required);
+
+        let data = decode::<Claims>(token, &key, &validation).context("Invalid token")?;
+        Ok(data.claims)
+    }
+}
+
+/// The key `kid` names, fetching the key set when it is missing or stale.
+/// Stale keys stay in use while the issuer is unreachable or being asked.
+async fn published_key(
+    client: &Client,
+    url: &str,
+    cache: &RwLock<JwksCache>,
+    fetching: &Mutex<()>,
+    kid: Option<&str>,
+) -> Result<Jwk> {
+    {
+        let cache = cache.read().await;
+        let fresh = cache
+            .fetched_at
+            .is_some_and(|fetched_at| fetched_at.elapsed() < JWKS_MAX_AGE);
+        if let Some(jwk) = find_key(&cache.keys, kid).filter(|_| fresh) {
+            return Ok(jwk.clone());
+        }
+    }
+
+    let _fetching = match fetching.try_lock() {
+        Ok(fetching) => fetching,
+        Err(_) => {
+            // Another request is asking the issuer; a stale key will do
+            // until it answers.
+            if let Some(jwk) = find_key(&cache.read().await.keys, kid) {
+                return Ok(jwk.clone());
+            }
+            fetching.lock().await
+        }
+    };
+    // Another request may have fetched while this one waited for the lock.
+    let due = cache
+        .read()
+        .await
+        .attempted_at
+        .is_none_or(|attempted_at| attempted_at.elapsed() >= JWKS_MIN_REFRESH);
+    if due {
+        cache.write().await.attempted_at = Some(Instant::now());
+        // The cache is not locked meanwhile, as the issuer may take up to
+        // REQUEST_TIMEOUT to answer.
+        match fetch_keys(client, url).await {
+            Ok(keys) => {
+                tracing::debug!(url, keys = keys.len(), "Fetched JWT keys");
+                let mut cache = cache.write().await;
+                cache.keys = keys;
+                cache.fetched_at = Some(Instant::now());
+            }
+            Err(err) => tracing::warn!(url, error = format!("{err:#}"), "Fetching JWT keys failed"),
+        }
+    }
+    find_key(&cache.read().await.keys, kid)
+        .cloned()
+        .ok_or_else(|| anyhow!("Unknown key {kid:?}"))
+}
+
+/// Tokens without a `kid` are only accepted while the issuer publishes a
+/// single key.
+fn find_key<'a>(keys: &'a [Jwk], kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => keys
+            .iter()
+            .find(|jwk| jwk.common.key_id.as_deref() == Some(kid)),
+        None => match keys {
+            [jwk] => Some(jwk),
+            _ => None,
+        },
+    }
+}
+
+async fn fetch_keys(client: &Client, url: &str) -> Result<Vec<Jwk>> {
+    let body = client
+        .get(url)
+        .send()
+        .await
+        .context("Request failed")?
+        .error_for_status()?
+        .bytes()
+        .await
+        .context("Reading response failed")?;
+    let set: JwkSet = serde_json::from_slice(&body).context("Invalid JWKS")?;
+    Ok(set.keys)
+}
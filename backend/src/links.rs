@@ -0,0 +1,152 @@
+//! Expiring signed URLs, for sharing a view of a camera without handing out
+//! credentials. A link signs its path and expiry with `LINK_SECRET`, so
+//! changing the secret revokes every link at once.
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// How long a link lasts when the request does not say.
+pub const DEFAULT_LIFETIME_SECS: u64 = 60 * 60;
+pub const MAX_LIFETIME_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Whether `path` only shows what a camera sees, and may be shared.
+pub fn is_shareable(path: &str) -> bool {
+    const VIEWS: [&str; 4] = ["stream", "snapshot", "thumbnail", "preview.gif"];
+    match path.strip_prefix("/cameras/") {
+        Some(rest) => rest.split_once('/').is_some_and(|(id, view)| {
+            !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()) && VIEWS.contains(&view)
+        }),
+        None => {
+            matches!(path, "/ws" | "/audio.wav")
+                || path
+                    .strip_prefix('/')
+                    .is_some_and(|view| VIEWS.contains(&view))
+        }
+    }
+}
+
+/// The query string granting access to `path` until `expires` (Unix
+/// seconds).
+pub fn sign(secret: &str, path: &str, expires: i64) -> String {
+    let signature = hex::encode(mac(secret, path, expires).finalize().into_bytes());
+    format!("exp={expires}&sig={signature}")
+}
+
+/// Whether `signature` was made for `path` with this secret and has not
+/// expired.
+pub fn verify(secret: &str, path: &str, expires: &str, signature: &str) -> bool {
+    let Ok(expires) = expires.parse::<i64>() else {
+        return false;
+    };
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    expires > Utc::now().timestamp()
+        && is_shareable(path)
+        && mac(secret, path, expires).verify_slice(&signature).is_ok()
+}
+
+fn mac(secret: &str, path: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{path}\n{expires}").as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    /// `exp` and `sig` from a signed query string.
+    fn signed(path: &str, expires: i64) -> (String, String) {
+        let query = sign(SECRET, path, expires);
+        let (expires, signature) = query.split_once('&').unwrap();
+        (
+            expires.strip_prefix("exp=").unwrap().to_string(),
+            signature.strip_prefix("sig=").unwrap().to_string(),
+        )
+    }
+
+    fn in_an_hour() -> i64 {
+        Utc::now().timestamp() + 60 * 60
+    }
+
+    #[test]
+    fn views_are_shareable() {
+        for path in [
+            "/stream",
+            "/snapshot",
+            "/thumbnail",
+            "/preview.gif",
+            "/ws",
+            "/audio.wav",
+            "/cameras/0/stream",
+            "/cameras/12/snapshot",
+        ] {
+            assert!(is_shareable(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn other_routes_are_not_shareable() {
+        for path in [
+            "/",
+            "/config",
+            "/links",
+            "/audit",
+            "/camera/pause",
+            "/recordings/clip.mp4",
+            "stream",
+            "/stream/",
+            "/cameras/0/config",
+            "/cameras/0/ws",
+            "/cameras//stream",
+            "/cameras/x/stream",
+            "/cameras/0",
+        ] {
+            assert!(!is_shareable(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn verifies_own_signature() {
+        let (expires, signature) = signed("/cameras/1/stream", in_an_hour());
+        assert!(verify(SECRET, "/cameras/1/stream", &expires, &signature));
+    }
+
+    #[test]
+    fn rejects_other_secret_or_path() {
+        let (expires, signature) = signed("/stream", in_an_hour());
+        assert!(!verify("other", "/stream", &expires, &signature));
+        assert!(!verify(SECRET, "/snapshot", &expires, &signature));
+    }
+
+    #[test]
+    fn rejects_expired_or_extended_links() {
+        let (expires, signature) = signed("/stream", Utc::now().timestamp() - 1);
+        assert!(!verify(SECRET, "/stream", &expires, &signature));
+
+        let (expires, signature) = signed("/stream", in_an_hour());
+        let extended = (expires.parse::<i64>().unwrap() + 1).to_string();
+        assert!(!verify(SECRET, "/stream", &extended, &signature));
+    }
+
+    #[test]
+    fn rejects_unshareable_paths_even_when_signed() {
+        let (expires, signature) = signed("/config", in_an_hour());
+        assert!(!verify(SECRET, "/config", &expires, &signature));
+    }
+
+    #[test]
+    fn rejects_malformed_parameters() {
+        let (expires, signature) = signed("/stream", in_an_hour());
+        assert!(!verify(SECRET, "/stream", "soon", &signature));
+        assert!(!verify(SECRET, "/stream", "", &signature));
+        assert!(!verify(SECRET, "/stream", &expires, "not-hex"));
+        assert!(!verify(SECRET, "/stream", &expires, ""));
+        assert!(!verify(SECRET, "/stream", &expires, &signature[..16]));
+    }
+}
@@ -0,0 +1,133 @@
+//! With `ADAPTIVE_FRAME_RATE`, lowers the capture rate while the machine is
+//! overloaded, too hot or throttled by the Pi firmware, and raises it back
+//! once there is headroom again, so a struggling Pi drops frames on purpose
+//! instead of falling behind.
+//!
+//! Every camera captures at the same share of `FRAME_RATE`, which the
+//! capture loops pick up on their next frame.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    sync::RwLock,
+    task,
+    time::{interval, MissedTickBehavior},
+};
+
+use crate::{
+    config::Config,
+    system::{Sampler, SystemLoad},
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Shares of `FRAME_RATE` captured at, stepped down one per check while
+/// overloaded.
+const SHARES: [f32; 4] = [1.0, 0.75, 0.5, 0.25];
+/// CPU usage has to stay this many points below `ADAPTIVE_MAX_CPU` before
+/// stepping back up, as the higher rate will use more.
+const CPU_RECOVERY_MARGIN: f32 = 20.0;
+/// Degrees below `ADAPTIVE_MAX_TEMPERATURE` the SoC has to cool to.
+const TEMPERATURE_RECOVERY_MARGIN: f32 = 5.0;
+const CALM_CHECKS_BEFORE_RECOVERY: u32 = 10;
+
+/// The latest reading and the share cameras capture at, shared by the
+/// governor, the capture loops and `/stats`.
+#[derive(Default)]
+pub struct Load {
+    level: AtomicUsize,
+    reading: Mutex<SystemLoad>,
+}
+
+impl Load {
+    /// Share of `FRAME_RATE` cameras currently capture at.
+    pub fn frame_rate_share(&self) -> f32 {
+        SHARES[self.level.load(Ordering::Relaxed)]
+    }
+
+    pub fn reading(&self) -> SystemLoad {
+        *self.reading.lock().expect("load reading poisoned")
+    }
+}
+
+/// Reads the system load every two seconds and moves `load` between shares.
+/// Reads the settings each time, so a reload can change or lift them.
+pub fn spawn(load: Arc<Load>, config: Arc<RwLock<Config>>) {
+    tokio::spawn(async move {
+        let mut sampler = Sampler::default();
+        let mut ticker = interval(CHECK_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut calm_checks = 0;
+
+        loop {
+            ticker.tick().await;
+            let (returned, reading) = task::spawn_blocking(move || {
+                let reading = sampler.read();
+                (sampler, reading)
+            })
+            .await
+            .expect("spawn_blocking failed");
+            sampler = returned;
+            *load.reading.lock().expect("load reading poisoned") = reading;
+
+            let level = load.level.load(Ordering::Relaxed);
+            let config = config.read().await;
+            if !config.adaptive_frame_rate {
+                if level > 0 {
+                    load.level.store(0, Ordering::Relaxed);
+                    tracing::info!("Adaptive frame rate off; full frame rate restored");
+                }
+                continue;
+            }
+            let max_cpu = f32::from(config.adaptive_max_cpu);
+            let max_temperature = config.adaptive_max_temperature;
+            drop(config);
+
+            let overloaded = reading.cpu_percent.is_some_and(|cpu| cpu > max_cpu)
+                || reading
+                    .temperature_celsius
+                    .is_some_and(|temperature| temperature > max_temperature)
+                || reading.is_throttled();
+            let calm = reading
+                .cpu_percent
+                .is_none_or(|cpu| cpu < max_cpu - CPU_RECOVERY_MARGIN)
+                && reading.temperature_celsius.is_none_or(|temperature| {
+                    temperature < max_temperature - TEMPERATURE_RECOVERY_MARGIN
+                })
+                && !reading.is_throttled();
+
+            if overloaded {
+                calm_checks = 0;
+                if level + 1 < SHARES.len() {
+                    load.level.store(level + 1, Ordering::Relaxed);
+                    tracing::warn!(
+                        cpu_percent = reading.cpu_percent,
+                        temperature_celsius = reading.temperature_celsius,
+                        throttled = reading.is_throttled(),
+                        frame_rate_share = SHARES[level + 1],
+                        "System overloaded; lowering frame rate"
+                    );
+                }
+            } else if level > 0 && calm {
+                calm_checks += 1;
+                if calm_checks >= CALM_CHECKS_BEFORE_RECOVERY {
+                    calm_checks = 0;
+                    load.level.store(level - 1, Ordering::Relaxed);
+                    tracing::info!(
+                        cpu_percent = reading.cpu_percent,
+                        temperature_celsius = reading.temperature_celsius,
+                        frame_rate_share = SHARES[level - 1],
+                        "System load back down; raising frame rate"
+                    );
+                }
+            } else {
+                calm_checks = 0;
+            }
+        }
+    });
+}
@@ -0,0 +1,119 @@
+//! Log output, as text for people or as JSON lines for log aggregators,
+//! and the per-request context every line logged while serving carries.
+
+use std::{convert::Infallible, env, net::SocketAddr};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, MatchedPath, Request},
+    http::{request::Parts, Extensions},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::{
+    ip_filter::ClientIp,
+    telemetry::{self, Telemetry},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line; fields of the enclosing request span are
+    /// nested under `span`.
+    Json,
+}
+
+impl LogFormat {
+    /// Read from `LOG_FORMAT` directly, as logging starts before the
+    /// configuration is loaded.
+    fn from_env() -> Result<Self> {
+        match env::var("LOG_FORMAT") {
+            Err(_) => Ok(Self::Text),
+            Ok(raw) => match raw.trim().to_ascii_lowercase().as_str() {
+                "" | "text" => Ok(Self::Text),
+                "json" => Ok(Self::Json),
+                _ => Err(anyhow!("Invalid LOG_FORMAT: expected text or json")),
+            },
+        }
+    }
+}
+
+/// Logs to stdout and, with `OTEL_EXPORTER_OTLP_ENDPOINT` set, also exports
+/// spans and metrics; the returned handle flushes them on exit.
+pub fn init() -> Result<Option<Telemetry>> {
+    let format = LogFormat::from_env()?;
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (telemetry, exporter) = if telemetry::enabled() {
+        let (telemetry, layer) = telemetry::start()?;
+        (Some(telemetry), Some(layer))
+    } else {
+        (None, None)
+    };
+    let json = (format == LogFormat::Json).then(|| {
+        fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+    });
+    let text = (format == LogFormat::Text).then(fmt::layer);
+
+    tracing_subscriber::registry()
+        .with(exporter)
+        .with(filter)
+        .with(json)
+        .with(text)
+        .try_init()
+        .map_err(|err| anyhow!("Failed to initialize tracing subscriber: {err}"))?;
+    Ok(telemetry)
+}
+
+/// Runs each request inside a `request` span holding the client address,
+/// method, route and HTTP version, so everything logged while handling it
+/// can be told apart by client. Runs inside `ip_filter::resolve_client`.
+pub async fn request_span(request: Request, next: Next) -> Response {
+    let route = request.extensions().get::<MatchedPath>().map_or_else(
+        || request.uri().path().to_string(),
+        |path| path.as_str().to_string(),
+    );
+    let span = tracing::info_span!(
+        "request",
+        client = %client_address(request.extensions()),
+        method = %request.method(),
+        route,
+        version = ?request.version(),
+    );
+    next.run(request).instrument(span).await
+}
+
+/// The client's address, as logged with every request.
+pub struct ClientAddr(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientAddr {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(client_address(&parts.extensions)))
+    }
+}
+
+/// The [`ClientIp`], with the port when it is the peer itself rather than
+/// an address a trusted proxy forwarded. Unix socket connections without a
+/// forwarded address are `unix`, other clients that cannot be told `unknown`.
+fn client_address(extensions: &Extensions) -> String {
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    let client = extensions.get::<ClientIp>().and_then(|ClientIp(ip)| *ip);
+    match (client, peer) {
+        (Some(ip), Some(addr)) if addr.ip().to_canonical() == ip => addr.to_string(),
+        (Some(ip), _) => ip.to_string(),
+        (None, None) => "unix".to_string(),
+        (None, Some(_)) => "unknown".to_string(),
+    }
+}
@@ -1,113 +1,2683 @@
+mod audio;
+mod audit;
+mod auth;
+mod bandwidth;
+mod bayer;
+mod buffer_pool;
 mod camera;
+mod capture;
+mod cli;
 mod config;
+mod daynight;
+mod detection;
+mod events;
+mod gpio;
+mod ip_filter;
+mod jpeg;
+mod jwt;
+mod links;
+mod load;
+mod logging;
+mod mjpeg;
+mod motion;
+mod mqtt;
+mod openapi;
+mod overlay;
+mod placeholder;
+mod preview;
+mod recorder;
+mod scaler;
+mod schedule;
+mod shutdown;
+mod snapshot_format;
+mod snapshots;
+mod stats;
+mod system;
+mod telemetry;
+mod thumbnail;
+mod timings;
+#[cfg(unix)]
+mod unix_socket;
+mod upload;
+mod viewer;
+mod webhook;
+#[cfg(not(feature = "turbojpeg"))]
+mod yuv;
 
-use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use std::{
+    convert::Infallible,
+    io::Write,
+    net::SocketAddr,
+    path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Context;
+use audio::AudioHandle;
+use audit::{AuditEntry, AuditLog, AuditQuery};
 use axum::{
     body::Body,
-    extract::State,
-    http::{header, Method, StatusCode},
-    response::{AppendHeaders, IntoResponse, Response},
-    routing::get,
-    Json, Router,
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        FromRequestParts, Path, Query, Request, State,
+    },
+    http::{header, HeaderMap, HeaderName, Method, StatusCode},
+    middleware,
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use bandwidth::Bandwidth;
+use buffer_pool::BufferPool;
+use bytes::Bytes;
+use camera::{
+    Camera, CameraError, Capabilities, CaptureFormat, ControlChange, ControlInfo, DeviceInfo,
+    FocusRequest, FocusState, PtzMove, PtzPosition,
+};
+use capture::{CameraHandle, FrameEvent, FRAME_TIMEOUT};
+use clap::Parser;
+use cli::{Cli, Command, ConfigSource};
+use config::{Config, ConfigUpdate, OverlayCorner, TlsConfig};
+use detection::RecentDetections;
+use events::{EventBus, StampedEvent, StatusEvent};
+use ip_filter::ClientIp;
+use ipnet::IpNet;
+use load::Load;
+use logging::ClientAddr;
+use overlay::{Overlay, PrivacyBlur};
+use placeholder::Placeholders;
+use recorder::RecordingInfo;
+use scaler::{Crop, Scaler, Variant};
+use serde::{Deserialize, Serialize};
+use shutdown::Drain;
+use snapshot_format::SnapshotFormat;
+use stats::{ConnectionStats, StreamClient, StreamStats};
+use system::SystemLoad;
+use thumbnail::Thumbnails;
+use timings::Stage;
+use tokio::{
+    net::{TcpListener, TcpSocket},
+    signal,
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch, RwLock,
+    },
+    task, time,
+};
+use tower_http::{
+    cors::{AllowHeaders, Any, CorsLayer},
+    services::ServeFile,
 };
-use bytes::{Bytes, BytesMut};
-#[cfg(target_os = "linux")]
-use camera::V4l2Camera;
-use camera::{Camera, MockCamera};
-use config::Config;
-use tokio::{net::TcpListener, signal, time::interval};
-use tower_http::cors::{Any, CorsLayer};
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing::{Instrument, Span};
+use upload::Uploader;
+use utoipa::{IntoParams, ToSchema};
+
+/// Prefix of the current API version; breaking changes go under the next.
+const API_V1: &str = "/api/v1";
+const MOTION_CHANNEL_CAPACITY: usize = 16;
+const RECORDING_CHANNEL_CAPACITY: usize = 16;
+const DETECTION_CHANNEL_CAPACITY: usize = 16;
+/// Linux doubles this, leaving room for a few 720p frames per connection.
+const SOCKET_SEND_BUFFER: u32 = 128 * 1024;
+const LISTEN_BACKLOG: u32 = 1024;
+/// Suggested wait for clients turned away by `MAX_STREAM_CLIENTS`.
+const STREAM_RETRY_AFTER_SECS: u64 = 30;
+/// With `STREAM_SKIP_UNCHANGED`, a static scene is still resent this often,
+/// so proxies and players do not give up on the connection.
+const UNCHANGED_REPEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// `/readyz` fails once an open camera's last frame is older than this.
+const READY_MAX_FRAME_AGE: Duration = Duration::from_secs(10);
+/// Streams that sent nothing for this long, e.g. while the camera is being
+/// reopened, get a heartbeat so proxies and browsers keep the connection.
+const STREAM_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+/// While captures fail, MJPEG viewers get a placeholder picture this often.
+const PLACEHOLDER_INTERVAL: Duration = Duration::from_secs(1);
+/// Tells nginx to pass a streamed response on as it comes instead of
+/// buffering it.
+const X_ACCEL_BUFFERING: HeaderName = HeaderName::from_static("x-accel-buffering");
+/// Spare multipart chunk buffers kept across MJPEG viewers.
+const MAX_POOLED_CHUNKS: usize = 32;
+/// At shutdown, how long open clips get to be finished, and then how long
+/// MQTT and webhooks get to deliver what is left. Together they stay below
+/// the ten seconds Docker waits before killing a container.
+const RECORDINGS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+const EVENT_SINKS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 struct AppState {
-    camera: Arc<dyn Camera>,
-    config: Config,
+    /// Cameras indexed by id; id 0 backs the unprefixed routes.
+    cameras: Arc<Vec<CameraHandle>>,
+    config: Arc<RwLock<Config>>,
+    overlay: Arc<watch::Sender<Option<Arc<Overlay>>>>,
+    /// Kept for rebuilding the overlay; `None` unless `PRIVACY_BLUR_MODEL`
+    /// is set.
+    blur: Option<Arc<PrivacyBlur>>,
+    /// Re-read by `POST /config/reload` and on SIGHUP.
+    config_source: Arc<ConfigSource>,
+    /// Shared with the auth middleware, which sees reloaded credentials.
+    auth: auth::Auth,
+    thumbnails: Arc<Thumbnails>,
+    placeholders: Arc<Placeholders>,
+    streams: Arc<StreamStats>,
+    bandwidth: Arc<Bandwidth>,
+    load: Arc<Load>,
+    scaler: Arc<Scaler>,
+    /// Buffers MJPEG parts are assembled in, returned once written out.
+    chunks: BufferPool,
+    /// Feeds `/events`; config changes are sent here directly.
+    events: EventBus,
+    /// `None` unless `AUDIO_DEVICE` is set.
+    audio: Option<AudioHandle>,
+    /// Does nothing unless `AUDIT_LOG` is set.
+    audit: AuditLog,
+    /// Set once the server is stopping; ends streams so their connections
+    /// can close.
+    shutdown: watch::Receiver<bool>,
+}
+
+impl AppState {
+    fn camera(&self, id: usize) -> Option<&CameraHandle> {
+        self.cameras.get(id)
+    }
+
+    fn default_camera(&self) -> &CameraHandle {
+        &self.cameras[0]
+    }
+}
+
+/// Answer to `/camera/pause` and `/camera/resume`.
+#[derive(Serialize, ToSchema)]
+struct PauseState {
+    /// The device stays closed until the camera is resumed; a resumed camera
+    /// outside `STREAM_SCHEDULE` stays closed all the same.
+    paused: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CameraInfo {
+    id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    /// `None` while an on-demand camera is closed.
+    backend: Option<&'static str>,
+    /// `None` while an on-demand camera is closed.
+    capabilities: Option<Capabilities>,
+}
+
+/// What a camera supports, with the current values and ranges of its
+/// controls, for building settings forms.
+#[derive(Serialize, ToSchema)]
+struct CameraCapabilities {
+    backend: &'static str,
+    /// Format the camera is streaming in, where the backend knows it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<CaptureFormat>,
+    capabilities: Capabilities,
+    controls: Vec<ControlInfo>,
+    /// Pan/tilt/zoom ranges, for cameras that have them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ptz: Option<PtzPosition>,
+    /// Focus range and mode, for cameras that have them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    focus: Option<FocusState>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StreamQuery {
+    /// Scales frames down to this width.
+    width: Option<u32>,
+    /// `x,y,w,h` region to serve, as fractions of the frame.
+    crop: Option<String>,
+    /// Outlines detected objects; needs `DETECTION_MODEL`.
+    #[serde(default)]
+    boxes: bool,
+    /// Interleaves the camera's status events as JSON text messages;
+    /// WebSocket only.
+    #[serde(default)]
+    events: bool,
+}
+
+/// Who is watching a stream, for `VIEWER_WATERMARK`.
+#[derive(FromRequestParts)]
+struct Viewer {
+    client_ip: ClientIp,
+    identity: Option<Extension<auth::Identity>>,
+}
+
+impl Viewer {
+    /// Who they were let in as, which cannot be forged the way an address
+    /// can, or else their address.
+    fn label(self) -> String {
+        match (self.identity, self.client_ip) {
+            (Some(Extension(auth::Identity(identity))), _) => identity,
+            (None, ClientIp(Some(ip))) => ip.to_string(),
+            (None, ClientIp(None)) => "unknown client".to_string(),
+        }
+    }
+}
+
+/// How a viewer asked for its frames to be rendered.
+#[derive(Clone)]
+struct ViewerOptions {
+    crop: Option<Crop>,
+    width: Option<u32>,
+    boxes: bool,
+    /// With `VIEWER_WATERMARK`, who is watching, stamped on every frame.
+    stamp: Option<Arc<Stamp>>,
+}
+
+struct Stamp {
+    lines: Vec<String>,
+    corner: OverlayCorner,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SnapshotQuery {
+    /// Wins over the `Accept` header.
+    format: Option<SnapshotFormat>,
+    /// `x,y,w,h` region to serve, as fractions of the frame.
+    crop: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ThumbnailQuery {
+    width: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HealthReport {
+    /// `degraded` while any camera is failing.
+    status: &'static str,
+    cameras: Vec<CameraHealth>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CameraHealth {
+    id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    /// `None` while an on-demand camera is closed.
+    backend: Option<&'static str>,
+    /// Pixel format and size negotiated with the device; `None` while closed.
+    format: Option<CaptureFormat>,
+    /// RFC 3339 time of the last frame; `None` before the first one.
+    last_frame_at: Option<String>,
+    /// Failed captures over the last minute.
+    recent_errors: usize,
+    /// Captures that failed since the last one that worked.
+    consecutive_failures: u32,
+    /// Set from the third failed capture in a row, or while reconnecting.
+    failing: bool,
+    reconnecting: bool,
+    reconnect_attempts: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+struct StatsReport {
+    uptime_secs: u64,
+    /// MJPEG and WebSocket clients across all cameras.
+    stream_clients: usize,
+    bytes_sent: u64,
+    /// Stream output over the last second.
+    bandwidth_kbps: u64,
+    /// How far `MAX_BANDWIDTH_KBPS` currently degrades streams.
+    throttle: bandwidth::Level,
+    system: SystemLoad,
+    /// Share of `FRAME_RATE` cameras capture at; below 1 while
+    /// `ADAPTIVE_FRAME_RATE` holds them back.
+    frame_rate_share: f32,
+    cameras: Vec<CameraStats>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CameraStats {
+    id: usize,
+    /// Frames per second actually delivered, over the last five seconds.
+    fps: f32,
+    /// Frames per second captures are paced at, after `ADAPTIVE_FRAME_RATE`.
+    target_fps: f32,
+    frames_captured: u64,
+    capture_errors: u64,
+    reconnect_attempts: u32,
+    stream_clients: usize,
+    bytes_sent: u64,
+    clients: Vec<ConnectionStats>,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().ok();
-    init_tracing()?;
+    let cli = Cli::parse();
+    let telemetry = logging::init()?;
 
-    let config = Config::from_env()?;
+    let source = cli.config_source();
+    let command = cli.command.unwrap_or(Command::Serve);
+    if let Command::ListDevices = command {
+        print!("{}", cli::format_devices(&camera::list_devices()));
+        return Ok(());
+    }
+
+    let config = source.load()?;
+
+    let result = match command {
+        Command::Serve => serve(config, source).await,
+        Command::Snapshot { output, camera } => snapshot(&config, camera, &output).await,
+        Command::CheckConfig => check_config(&config).await,
+        Command::ListDevices => unreachable!("handled before loading the configuration"),
+    };
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown().await;
+    }
+    result
+}
+
+async fn serve(config: Config, config_source: ConfigSource) -> anyhow::Result<()> {
     tracing::info!(?config, "Loaded configuration");
 
-    let camera = build_camera(&config);
+    let blur = config
+        .privacy_blur
+        .as_ref()
+        .map(PrivacyBlur::load)
+        .transpose()?
+        .map(Arc::new);
+    let load = Arc::new(Load::default());
+    let overlay = Overlay::from_config(&config, blur.clone(), &load)?.map(Arc::new);
+    let (overlay, overlay_rx) = watch::channel(overlay);
+    let (off_duty, off_duty_rx) = watch::channel(schedule::off_duty(&config));
+
+    let cameras: Vec<CameraHandle> = config
+        .camera_devices()
+        .into_iter()
+        .map(|device| {
+            CameraHandle::start(
+                device.map(String::from),
+                &config,
+                overlay_rx.clone(),
+                load.clone(),
+                off_duty_rx.clone(),
+            )
+        })
+        .collect();
+    let addrs = config.listen_socket_addrs();
+    let http_addrs = config.http_socket_addrs();
+    let tls = config.tls.clone();
+    #[cfg(unix)]
+    let listen_socket = config.listen_socket.clone();
+    if !config.auth.is_enabled() {
+        tracing::warn!("No AUTH_TOKEN, AUTH_USER or JWT key configured; all routes are public");
+    }
+    let auth = auth::Auth::new(config.auth.clone())?;
+    let ip_filter = config.ip_filter.clone().map(Arc::new);
+    let trusted_proxies: Arc<[IpNet]> = config.trusted_proxies.clone().into();
+    let base_path = config.base_path.clone();
+
+    if let Some(dir) = config.recordings_dir.as_deref() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create RECORDINGS_DIR {}", dir.display()))?;
+    }
+    let motion_enabled = config.motion_detection;
+    let recordings_dir = config.recordings_dir.clone();
+    let mqtt = config.mqtt.clone();
+    let snapshot_schedule = config.snapshots.clone();
+    let webhooks = config.webhooks.clone();
+    let uploader = config.s3.as_ref().map(Uploader::spawn).transpose()?;
+    let audio = config.audio.as_ref().map(AudioHandle::start);
+    let night_switch = config.night_switch.clone();
+    let ir_gpio = config.ir_gpio.clone();
+    let has_profiles = config.day_profile.is_some() || config.night_profile.is_some();
+
+    let motion_events = motion_enabled.then(|| broadcast::channel(MOTION_CHANNEL_CAPACITY).0);
+    let detection = config.detection.clone();
+    let detection_events = detection
+        .is_some()
+        .then(|| broadcast::channel(DETECTION_CHANNEL_CAPACITY).0);
+    let recent_detections = detection
+        .as_ref()
+        .map(|detection| Arc::new(RecentDetections::new(cameras.len(), detection)));
+    let recording_events = recordings_dir
+        .is_some()
+        .then(|| broadcast::channel(RECORDING_CHANNEL_CAPACITY).0);
+    let events = events::spawn(
+        &cameras,
+        motion_events.as_ref().map(broadcast::Sender::subscribe),
+        detection_events.as_ref().map(broadcast::Sender::subscribe),
+        recording_events.as_ref().map(broadcast::Sender::subscribe),
+    );
 
-    let state = AppState { camera, config };
-    let addr: SocketAddr = state.config.listen_socket_addr();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(true);
+    });
+    let recordings_drain = Drain::new("recordings");
+    let event_sinks_drain = Drain::new("event sinks");
+    let audit = match config.audit_log.as_deref() {
+        Some(path) => AuditLog::open(path, event_sinks_drain.guard()).await?,
+        None => AuditLog::default(),
+    };
+
+    let state = AppState {
+        thumbnails: Arc::new(Thumbnails::new(cameras.len())),
+        placeholders: Arc::new(Placeholders::new(cameras.len())),
+        streams: Arc::new(StreamStats::new(cameras.len())),
+        bandwidth: Arc::new(Bandwidth::default()),
+        load,
+        scaler: Arc::new(Scaler::new(
+            cameras.len(),
+            recent_detections.clone(),
+            config.jpeg_quality,
+        )),
+        chunks: BufferPool::new(MAX_POOLED_CHUNKS),
+        cameras: Arc::new(cameras),
+        config: Arc::new(RwLock::new(config)),
+        overlay: Arc::new(overlay),
+        blur,
+        config_source: Arc::new(config_source),
+        auth: auth.clone(),
+        events,
+        audio,
+        audit: audit.clone(),
+        shutdown: shutdown_rx.clone(),
+    };
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(state.clone()));
+    telemetry::observe(&state.cameras, state.streams.clone());
+    tokio::spawn(timings::log_periodically());
+    bandwidth::spawn(
+        state.bandwidth.clone(),
+        state.streams.clone(),
+        state.cameras.len(),
+        state.config.clone(),
+    );
+    load::spawn(state.load.clone(), state.config.clone());
+    schedule::spawn(off_duty, state.config.clone());
+
+    if let Some(mqtt) = mqtt.as_ref() {
+        mqtt::spawn(
+            mqtt,
+            &state.cameras,
+            motion_events.as_ref().map(broadcast::Sender::subscribe),
+            event_sinks_drain.guard(),
+        );
+    }
+
+    if let Some(schedule) = snapshot_schedule.as_ref() {
+        snapshots::spawn(schedule, &state.cameras, uploader.as_ref());
+    }
+
+    if let (Some(uploader), Some(recording_events)) = (uploader.as_ref(), recording_events.as_ref())
+    {
+        uploader.upload_recordings(recording_events.subscribe());
+    }
+
+    if let Some(webhooks) = webhooks.as_ref() {
+        webhook::spawn(
+            webhooks,
+            &state.cameras,
+            motion_events.as_ref().map(broadcast::Sender::subscribe),
+            recording_events.as_ref().map(broadcast::Sender::subscribe),
+            event_sinks_drain.guard(),
+        )?;
+    }
+
+    if ir_gpio.is_some() || has_profiles {
+        let night = daynight::spawn(&night_switch, state.default_camera().clone());
+        if let Some(ir_gpio) = ir_gpio.as_ref() {
+            gpio::spawn(ir_gpio, night.clone())?;
+        }
+        if has_profiles {
+            tokio::spawn(follow_profiles(state.clone(), night));
+        }
+    }
+
+    if let (Some(detection), Some(detection_events), Some(recent_detections)) =
+        (detection.as_ref(), detection_events, recent_detections)
+    {
+        detection::spawn(
+            detection,
+            &state.cameras,
+            detection_events,
+            recent_detections,
+        )?;
+    }
+
+    if let Some(dir) = recordings_dir.as_ref() {
+        recorder::spawn_retention(dir.clone(), state.config.clone());
+    }
+
+    if let Some(motion_events) = motion_events {
+        let config = state.config.read().await.clone();
+        for (id, handle) in state.cameras.iter().enumerate() {
+            if let (Some(dir), Some(recording_events)) =
+                (recordings_dir.as_ref(), recording_events.as_ref())
+            {
+                recorder::spawn(
+                    id,
+                    handle,
+                    motion_events.subscribe(),
+                    dir.clone(),
+                    state.config.clone(),
+                    recording_events.clone(),
+                    recordings_drain.guard(),
+                );
+            }
+            motion::spawn_detector(id, handle.subscribe(), &config, motion_events.clone());
+        }
+    }
 
-    let app = Router::new()
+    let api = Router::new()
         .route("/stream", get(stream_handler))
-        .route("/config", get(config_handler))
+        .route("/ws", get(ws_handler))
+        .route("/snapshot", get(snapshot_handler))
+        .route("/preview.gif", get(preview_handler))
+        .route("/audio.wav", get(audio_handler))
+        .route("/thumbnail", get(thumbnail_handler))
+        .route("/cameras", get(cameras_handler))
+        .route("/devices", get(devices_handler))
+        .route("/camera/capabilities", get(capabilities_handler))
+        .route(
+            "/cameras/:id/capabilities",
+            get(camera_capabilities_handler),
+        )
+        .route("/camera/pause", post(pause_handler))
+        .route("/camera/resume", post(resume_handler))
+        .route("/cameras/:id/pause", post(camera_pause_handler))
+        .route("/cameras/:id/resume", post(camera_resume_handler))
+        .route("/cameras/:id/stream", get(camera_stream_handler))
+        .route("/cameras/:id/snapshot", get(camera_snapshot_handler))
+        .route("/cameras/:id/preview.gif", get(camera_preview_handler))
+        .route("/cameras/:id/thumbnail", get(camera_thumbnail_handler))
+        .route("/controls", get(controls_handler).post(set_control_handler))
+        .route(
+            "/cameras/:id/controls",
+            get(camera_controls_handler).post(camera_set_control_handler),
+        )
+        .route("/ptz", get(ptz_handler).post(ptz_move_handler))
+        .route(
+            "/cameras/:id/ptz",
+            get(camera_ptz_handler).post(camera_ptz_move_handler),
+        )
+        .route("/focus", get(focus_handler).post(refocus_handler))
+        .route(
+            "/cameras/:id/focus",
+            get(camera_focus_handler).post(camera_refocus_handler),
+        )
+        .route("/recordings", get(recordings_handler))
+        .route("/recordings/:id", get(recording_handler))
+        .route("/config", get(config_handler).put(update_config_handler))
+        .route("/config/reload", post(reload_config_handler))
+        .route("/links", post(create_link_handler))
+        .route("/stats", get(stats_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/events", get(events_handler))
+        .route("/audit", get(audit_handler))
+        .route_layer(middleware::from_fn_with_state(
+            auth.clone(),
+            auth::require_auth,
+        ))
+        .route_layer(middleware::from_fn_with_state(audit, audit::record))
         .route("/health", get(health_handler))
+        .route("/healthz", get(liveness_handler))
+        .route("/readyz", get(readiness_handler));
+    // Every API route is also served at its original path, for frontends
+    // deployed before it was versioned.
+    let mut app = Router::new()
+        .route("/", get(viewer::index_handler))
+        .route_layer(middleware::from_fn_with_state(auth, auth::require_auth))
+        .nest(API_V1, api.clone())
+        .merge(api);
+    // The OpenAPI document is public, as it only describes the routes.
+    let spec = openapi::document(base_path.as_deref().unwrap_or_default());
+    app = app.route("/api/openapi.json", get(|| async { Json(spec) }));
+    #[cfg(feature = "swagger-ui")]
+    {
+        app = app.merge(utoipa_swagger_ui::SwaggerUi::new("/api/docs").config(
+            utoipa_swagger_ui::Config::from(format!(
+                "{}/api/openapi.json",
+                base_path.as_deref().unwrap_or_default()
+            )),
+        ));
+    }
+    if let Some(filter) = ip_filter {
+        app = app.layer(middleware::from_fn_with_state(
+            filter,
+            ip_filter::filter_clients,
+        ));
+    }
+    let app = app
+        .layer(middleware::from_fn(logging::request_span))
+        .layer(middleware::from_fn_with_state(
+            trusted_proxies,
+            ip_filter::resolve_client,
+        ))
         .with_state(state.clone())
         .layer(
             CorsLayer::new()
-                .allow_methods([Method::GET])
+                .allow_methods([Method::GET, Method::POST, Method::PUT])
                 .allow_origin(Any)
-                .allow_headers(Any),
+                .allow_headers(AllowHeaders::mirror_request()),
         );
+    // Routes, middleware included, see their path without the prefix.
+    let app = match base_path {
+        Some(base_path) => {
+            let root = base_path.clone();
+            Router::new()
+                .route(
+                    &format!("{base_path}/"),
+                    get(|| async move { Redirect::permanent(&root) }),
+                )
+                .nest(&base_path, app)
+        }
+        None => app,
+    };
 
-    let listener = TcpListener::bind(addr)
-        .await
-        .with_context(|| format!("Failed to bind to {}", addr))?;
+    let served = async {
+        #[cfg(unix)]
+        if let Some(socket) = listen_socket {
+            return unix_socket::serve(&socket, app, shutdown_rx).await;
+        }
 
+        // Next to other addresses, `::` must leave IPv4 to `0.0.0.0`.
+        let only_v6 = addrs.len() > 1;
+        let mut servers = task::JoinSet::new();
+        match tls {
+            Some(tls) => {
+                let rustls_config = load_tls(&tls).await?;
+                for addr in addrs {
+                    let listener = bind_listener(addr, only_v6)?;
+                    servers.spawn(serve_https(
+                        listener,
+                        rustls_config.clone(),
+                        app.clone(),
+                        shutdown_rx.clone(),
+                    ));
+                }
+                for addr in http_addrs {
+                    let listener = bind_listener(addr, only_v6)?;
+                    servers.spawn(serve_http(listener, app.clone(), shutdown_rx.clone()));
+                }
+            }
+            None => {
+                for addr in addrs {
+                    let listener = bind_listener(addr, only_v6)?;
+                    servers.spawn(serve_http(listener, app.clone(), shutdown_rx.clone()));
+                }
+            }
+        }
+        // The first listener to fail takes the others down with it.
+        while let Some(served) = servers.join_next().await {
+            served.context("Server task panicked")??;
+        }
+        Ok(())
+    }
+    .await;
+
+    // Clips are finished first, as webhooks announce them.
+    recordings_drain.run(RECORDINGS_SHUTDOWN_TIMEOUT).await;
+    event_sinks_drain.run(EVENT_SINKS_SHUTDOWN_TIMEOUT).await;
+    served
+}
+
+/// Serves HTTP/1.1 and, to clients that know it is supported, such as
+/// proxies configured for h2c, cleartext HTTP/2.
+async fn serve_http(
+    listener: TcpListener,
+    app: Router,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let addr = listener
+        .local_addr()
+        .context("Failed to read listener address")?;
     tracing::info!(%addr, "Backend listening");
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(wait_for_shutdown(shutdown))
+    .await
+    .context("Server error")
+}
+
+async fn load_tls(tls: &TlsConfig) -> anyhow::Result<RustlsConfig> {
+    // Only fails if a provider is already installed, which is what we want anyway.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to load TLS certificate {} / key {}",
+                tls.cert_path.display(),
+                tls.key_path.display()
+            )
+        })
+}
+
+/// Serves HTTPS, with HTTP/2 offered by ALPN so a browser tab can keep
+/// several streams, snapshots and `/events` on one connection.
+async fn serve_https(
+    listener: TcpListener,
+    rustls_config: RustlsConfig,
+    app: Router,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let addr = listener
+        .local_addr()
+        .context("Failed to read listener address")?;
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            wait_for_shutdown(shutdown).await;
+            handle.graceful_shutdown(None);
+        }
+    });
+
+    tracing::info!(%addr, "Backend listening with TLS");
+
+    let listener = listener
+        .into_std()
+        .context("Failed to hand listener to the TLS server")?;
+
+    axum_server::from_tcp_rustls(listener, rustls_config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
-        .context("Server error")
+        .with_context(|| format!("TLS server error on {}", addr))
+}
+
+/// Binds with a capped kernel send buffer, which accepted connections inherit.
+/// Linux would otherwise autotune it to megabytes, letting a slow stream
+/// client fall seconds behind before `recv_latest` gets to skip any frames.
+/// With `only_v6`, an IPv6 socket takes no IPv4 connections, so `::` can be
+/// bound next to `0.0.0.0`; otherwise the system default applies.
+fn bind_listener(addr: SocketAddr, only_v6: bool) -> anyhow::Result<TcpListener> {
+    let bind = || -> std::io::Result<TcpListener> {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            let socket = TcpSocket::new_v6()?;
+            if only_v6 {
+                socket2::SockRef::from(&socket).set_only_v6(true)?;
+            }
+            socket
+        };
+        socket.set_reuseaddr(true)?;
+        socket.set_send_buffer_size(SOCKET_SEND_BUFFER)?;
+        socket.bind(addr)?;
+        socket.listen(LISTEN_BACKLOG)
+    };
+    bind().with_context(|| format!("Failed to bind to {}", addr))
+}
+
+async fn wait_for_shutdown(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stopping| *stopping).await;
+}
+
+/// Resolves once the credentials a stream was opened with expire; never
+/// for credentials that do not.
+async fn wait_for_expiry(expiry: Option<auth::Expiry>) {
+    match expiry {
+        Some(auth::Expiry(at)) => time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/stream",
+    tag = "streams",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "MJPEG stream; each part carries `X-Timestamp`, `X-Frame-Number` and `X-Frame-Duration`, or `X-Camera-Error` on a placeholder picture while captures fail", content_type = "multipart/x-mixed-replace"),
+        (status = 400, description = "Invalid `width`, `crop` or `boxes`"),
+        (status = 503, description = "`too-many-clients`, with `Retry-After`"),
+    )
+)]
+async fn stream_handler(
+    Query(query): Query<StreamQuery>,
+    ClientAddr(peer): ClientAddr,
+    viewer: Viewer,
+    expiry: Option<Extension<auth::Expiry>>,
+    State(state): State<AppState>,
+) -> Response {
+    let options = match viewer_options(&state, &query, viewer).await {
+        Ok(options) => options,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let Some(client) = connect_stream_client(&state, 0, peer, "mjpeg").await else {
+        return too_many_clients_response();
+    };
+    let frames = state.default_camera().subscribe();
+    mjpeg_response(
+        frames,
+        client,
+        state,
+        options,
+        expiry.map(|Extension(expiry)| expiry),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/cameras/{id}/stream",
+    tag = "streams",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`"), StreamQuery),
+    responses(
+        (status = 200, description = "MJPEG stream; each part carries `X-Timestamp`, `X-Frame-Number` and `X-Frame-Duration`, or `X-Camera-Error` on a placeholder picture while captures fail", content_type = "multipart/x-mixed-replace"),
+        (status = 400, description = "Invalid `width`, `crop` or `boxes`"),
+        (status = 503, description = "`too-many-clients`, with `Retry-After`"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_stream_handler(
+    Path(id): Path<usize>,
+    Query(query): Query<StreamQuery>,
+    ClientAddr(peer): ClientAddr,
+    viewer: Viewer,
+    expiry: Option<Extension<auth::Expiry>>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(handle) = state.camera(id) else {
+        return unknown_camera_response();
+    };
+    let options = match viewer_options(&state, &query, viewer).await {
+        Ok(options) => options,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let Some(client) = connect_stream_client(&state, id, peer, "mjpeg").await else {
+        return too_many_clients_response();
+    };
+    let frames = handle.subscribe();
+    mjpeg_response(
+        frames,
+        client,
+        state,
+        options,
+        expiry.map(|Extension(expiry)| expiry),
+    )
+}
+
+/// The options in `query`, or why they are invalid. `STREAM_WIDTH` caps
+/// the width as the viewer connects; with `VIEWER_WATERMARK`, who the viewer
+/// was let in as, or else their address, is stamped on their frames.
+async fn viewer_options(
+    state: &AppState,
+    query: &StreamQuery,
+    viewer: Viewer,
+) -> Result<ViewerOptions, String> {
+    if let Some(width) = query.width {
+        if !(scaler::MIN_WIDTH..=scaler::MAX_WIDTH).contains(&width) {
+            return Err(format!(
+                "width must be between {} and {}",
+                scaler::MIN_WIDTH,
+                scaler::MAX_WIDTH
+            ));
+        }
+    }
+    if query.boxes && !state.scaler.draws_boxes() {
+        return Err("boxes needs object detection; set DETECTION_MODEL".to_string());
+    }
+    let (limit, stamp) = {
+        let config = state.config.read().await;
+        // Frames captured no wider than the limit go out as they are.
+        let limit = config
+            .stream_width
+            .filter(|&limit| limit < config.resolution_width);
+        let stamp = config.viewer_watermark.then(|| {
+            Arc::new(Stamp {
+                lines: vec![viewer.label()],
+                corner: config.viewer_watermark_corner,
+            })
+        });
+        (limit, stamp)
+    };
+    let width = match (query.width, limit) {
+        (Some(width), Some(limit)) => Some(width.min(limit)),
+        (width, limit) => width.or(limit),
+    };
+    Ok(ViewerOptions {
+        crop: parse_crop(query.crop.as_deref())?,
+        width,
+        boxes: query.boxes,
+        stamp,
+    })
+}
+
+fn parse_crop(crop: Option<&str>) -> Result<Option<Crop>, String> {
+    crop.map(str::parse)
+        .transpose()
+        .map_err(|err: anyhow::Error| format!("Invalid crop: {err}"))
+}
+
+/// A frame as the viewer asked for it and the bandwidth budget allows, or
+/// `None` if it could not be stamped: it must not go out unmarked.
+async fn render_for_viewer(
+    state: &AppState,
+    camera: usize,
+    frame: Bytes,
+    options: &ViewerOptions,
+) -> Option<Bytes> {
+    // Bandwidth levels only ever lower the quality.
+    let configured = state.scaler.jpeg_quality();
+    let quality = state
+        .bandwidth
+        .level()
+        .quality
+        .map(|level| level.min(configured));
+    let variant = Variant {
+        crop: options.crop,
+        width: options.width,
+        quality,
+        boxes: options.boxes,
+    };
+    let frame = state.scaler.render(camera, frame, variant).await;
+    let Some(stamp) = options.stamp.clone() else {
+        return Some(frame);
+    };
+    // Unlike the shared variants, every viewer's frames are re-encoded.
+    let stamped = task::spawn_blocking(move || {
+        let quality = quality.unwrap_or(configured);
+        overlay::stamp(&frame, &stamp.lines, stamp.corner, quality)
+    })
+    .await
+    .expect("spawn_blocking failed");
+    match stamped {
+        Ok(stamped) => Some(Bytes::from(stamped)),
+        Err(err) => {
+            tracing::warn!(
+                error = format!("{err:#}"),
+                "Stamping viewer frame failed; dropping it"
+            );
+            None
+        }
+    }
 }
 
-async fn stream_handler(State(state): State<AppState>) -> Response {
+async fn connect_stream_client(
+    state: &AppState,
+    camera: usize,
+    peer: String,
+    protocol: &'static str,
+) -> Option<StreamClient> {
+    let limit = state.config.read().await.max_stream_clients;
+    let client = state.streams.connect(camera, limit, peer, protocol);
+    if client.is_none() {
+        tracing::warn!(
+            camera,
+            limit,
+            "Stream client limit reached; rejecting client"
+        );
+    }
+    client
+}
+
+fn too_many_clients_response() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, STREAM_RETRY_AFTER_SECS.to_string())],
+        "too-many-clients",
+    )
+        .into_response()
+}
+
+/// Each part carries the frame's capture time (`X-Timestamp`), number
+/// (`X-Frame-Number`) and time since the previous frame (`X-Frame-Duration`).
+/// While captures fail, a placeholder picture marked `X-Camera-Error` is
+/// sent once a second instead. When no part was sent for a while, an empty
+/// line goes out between parts as a heartbeat; multipart parsers ignore it,
+/// as JPEG decoders do with bytes after the image. `client` is held by the
+/// body, so it counts until the connection closes. At shutdown, and once
+/// `expiry` passes, the stream ends with the closing boundary, so players
+/// see a complete last frame.
+fn mjpeg_response(
+    mut frames: broadcast::Receiver<FrameEvent>,
+    client: StreamClient,
+    state: AppState,
+    options: ViewerOptions,
+    expiry: Option<auth::Expiry>,
+) -> Response {
     let boundary = "frame";
-    let mut ticker = interval(state.config.frame_interval());
-    let camera = state.camera.clone();
+    // The body is polled after the handler returned, outside its request span.
+    let request = Span::current();
 
     let stream = async_stream::stream! {
+        let mut sequence = 0;
+        let mut last_sent: Option<Instant> = None;
+        let stopping = wait_for_shutdown(state.shutdown.clone());
+        tokio::pin!(stopping);
+        let expired = wait_for_expiry(expiry);
+        tokio::pin!(expired);
+        let idle = time::sleep(STREAM_KEEPALIVE_INTERVAL);
+        tokio::pin!(idle);
+        // The body is only polled once the previous chunk was written, so a
+        // slow connection gets the newest frame instead of a growing backlog.
         loop {
-            ticker.tick().await;
-            match camera.capture_frame().await {
-                Ok(frame) => {
-                    let mut chunk = BytesMut::with_capacity(frame.len() + 128);
-                    chunk.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
-                    chunk.extend_from_slice(b"Content-Type: image/jpeg\r\n");
-                    chunk.extend_from_slice(format!("Content-Length: {}\r\n\r\n", frame.len()).as_bytes());
+            let event = tokio::select! {
+                event = capture::recv_latest(&mut frames) => match event {
+                    Some(event) => Some(event),
+                    None => break,
+                },
+                () = &mut stopping => break,
+                () = &mut expired => break,
+                () = &mut idle => None,
+            };
+            let Some(event) = event else {
+                idle.as_mut().reset(time::Instant::now() + STREAM_KEEPALIVE_INTERVAL);
+                client.sent(2);
+                yield Ok::<Bytes, Infallible>(Bytes::from_static(b"\r\n"));
+                continue;
+            };
+            match event {
+                FrameEvent::Frame {
+                    data: frame,
+                    captured_at,
+                    number,
+                    duration,
+                    changed,
+                } => {
+                    if !changed && last_sent.is_some_and(|sent| sent.elapsed() < UNCHANGED_REPEAT_INTERVAL) {
+                        continue;
+                    }
+                    sequence += 1;
+                    if !state.bandwidth.sends(sequence - 1) {
+                        continue;
+                    }
+                    last_sent = Some(Instant::now());
+                    let Some(frame) = render_for_viewer(&state, client.camera(), frame, &options).await else {
+                        continue;
+                    };
+                    let mut chunk = state.chunks.take();
+                    chunk.reserve(frame.len() + 128);
+                    let timestamp = captured_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    // Writing to a `Vec` cannot fail.
+                    let _ = write!(
+                        chunk,
+                        "--{boundary}\r\nContent-Type: image/jpeg\r\nX-Timestamp: {}\r\nX-Frame-Number: {number}\r\n",
+                        decimal_seconds(timestamp)
+                    );
+                    if let Some(duration) = duration {
+                        let _ = write!(chunk, "X-Frame-Duration: {}\r\n", decimal_seconds(duration));
+                    }
+                    let _ = write!(chunk, "Content-Length: {}\r\n\r\n", frame.len());
                     chunk.extend_from_slice(&frame);
                     chunk.extend_from_slice(b"\r\n");
-                    yield Ok::<Bytes, Infallible>(chunk.freeze());
+                    client.frame_sent(chunk.len());
+                    // The body is polled again once the chunk is written, so
+                    // the span lasts as long as the write.
+                    let write = tracing::info_span!(
+                        parent: &request,
+                        "stream_write",
+                        protocol = "mjpeg",
+                        bytes = chunk.len()
+                    );
+                    let started = Instant::now();
+                    idle.as_mut().reset(time::Instant::now() + STREAM_KEEPALIVE_INTERVAL);
+                    yield Ok::<Bytes, Infallible>(state.chunks.freeze(chunk));
+                    timings::record(Stage::StreamWrite, started.elapsed());
+                    drop(write);
                 }
-                Err(err) => {
-                    tracing::error!(error = %err, "Camera capture failed");
-                    let mut chunk = BytesMut::new();
-                    chunk.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
-                    chunk.extend_from_slice(b"Content-Type: text/plain\r\n\r\n");
-                    chunk.extend_from_slice(b"camera-error\r\n");
-                    yield Ok::<Bytes, Infallible>(chunk.freeze());
+                FrameEvent::Error(err) => {
+                    // Failures come at the frame rate, but the picture only
+                    // changes once a second.
+                    if last_sent.is_some_and(|sent| sent.elapsed() < PLACEHOLDER_INTERVAL) {
+                        continue;
+                    }
+                    let (width, height, quality) = {
+                        let config = state.config.read().await;
+                        (config.resolution_width, config.resolution_height, config.jpeg_quality)
+                    };
+                    let handle = &state.cameras[client.camera()];
+                    let picture = match state.placeholders.get(client.camera(), handle, &err, width, height, quality).await {
+                        Ok(picture) => picture,
+                        Err(err) => {
+                            tracing::warn!(error = format!("{err:#}"), "Rendering placeholder failed");
+                            continue;
+                        }
+                    };
+                    last_sent = Some(Instant::now());
+                    let mut chunk = state.chunks.take();
+                    chunk.reserve(picture.len() + 128);
+                    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                    let _ = write!(
+                        chunk,
+                        "--{boundary}\r\nContent-Type: image/jpeg\r\nX-Timestamp: {}\r\nX-Camera-Error: 1\r\nContent-Length: {}\r\n\r\n",
+                        decimal_seconds(timestamp),
+                        picture.len()
+                    );
+                    chunk.extend_from_slice(&picture);
+                    chunk.extend_from_slice(b"\r\n");
+                    client.sent(chunk.len());
+                    idle.as_mut().reset(time::Instant::now() + STREAM_KEEPALIVE_INTERVAL);
+                    yield Ok::<Bytes, Infallible>(state.chunks.freeze(chunk));
                 }
             }
         }
+        yield Ok(Bytes::from(format!("--{boundary}--\r\n")));
     };
 
-    let headers = AppendHeaders([(
-        header::CONTENT_TYPE,
-        format!("multipart/x-mixed-replace; boundary={boundary}"),
-    )]);
+    let headers = AppendHeaders([
+        (
+            header::CONTENT_TYPE,
+            format!("multipart/x-mixed-replace; boundary={boundary}"),
+        ),
+        (header::CACHE_CONTROL, "no-store".to_string()),
+        (X_ACCEL_BUFFERING, "no".to_string()),
+    ]);
     let body = Body::from_stream(stream);
     (headers, body).into_response()
 }
 
+/// Seconds with microsecond precision, as in the multipart frame headers.
+fn decimal_seconds(duration: Duration) -> String {
+    format!("{}.{:06}", duration.as_secs(), duration.subsec_micros())
+}
+
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "streams",
+    params(StreamQuery),
+    responses(
+        (status = 101, description = "WebSocket pushing each frame as a binary message: an 8-byte big-endian capture timestamp in milliseconds, then the JPEG; with `events`, status events follow as JSON text messages"),
+        (status = 400, description = "Invalid `width`, `crop` or `boxes`"),
+        (status = 503, description = "`too-many-clients`, with `Retry-After`"),
+    )
+)]
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Query(query): Query<StreamQuery>,
+    ClientAddr(peer): ClientAddr,
+    viewer: Viewer,
+    expiry: Option<Extension<auth::Expiry>>,
+    access: Option<Extension<audit::Access>>,
+    State(state): State<AppState>,
+) -> Response {
+    let options = match viewer_options(&state, &query, viewer).await {
+        Ok(options) => options,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let Some(client) = connect_stream_client(&state, 0, peer, "ws").await else {
+        return too_many_clients_response();
+    };
+    let frames = state.default_camera().subscribe();
+    let events = query.events.then(|| state.events.subscribe());
+    // The session runs on its own task once upgraded; keep the request span.
+    let request = Span::current();
+    let expiry = expiry.map(|Extension(expiry)| expiry);
+    ws.on_upgrade(move |socket| {
+        async move {
+            ws_session(socket, frames, events, client, state, options, expiry).await;
+            // The audit entry covers the whole session.
+            drop(access);
+        }
+        .instrument(request)
+    })
+}
+
+/// Pushes each frame as a binary message: an 8-byte big-endian capture
+/// timestamp (milliseconds since the Unix epoch) followed by the JPEG data.
+/// With `events`, the camera's status events follow as JSON text messages,
+/// shaped like the `/events` data. A ping goes out when nothing else was sent
+/// for a while. At shutdown the socket is closed with code 1001 (going away),
+/// and once `expiry` passes with 1008 (policy violation).
+async fn ws_session(
+    mut socket: WebSocket,
+    mut frames: broadcast::Receiver<FrameEvent>,
+    mut events: Option<broadcast::Receiver<StampedEvent>>,
+    client: StreamClient,
+    state: AppState,
+    options: ViewerOptions,
+    expiry: Option<auth::Expiry>,
+) {
+    let mut sequence = 0;
+    let mut last_sent: Option<Instant> = None;
+    let mut camera_status: Option<&'static str> = None;
+    let stopping = wait_for_shutdown(state.shutdown.clone());
+    tokio::pin!(stopping);
+    let expired = wait_for_expiry(expiry);
+    tokio::pin!(expired);
+    let idle = time::sleep(STREAM_KEEPALIVE_INTERVAL);
+    tokio::pin!(idle);
+    loop {
+        let next_event = async {
+            match events.as_mut() {
+                Some(events) => events::recv_for_camera(events, client.camera()).await,
+                None => std::future::pending().await,
+            }
+        };
+        let message = tokio::select! {
+            received = capture::recv_latest(&mut frames) => match received {
+                Some(FrameEvent::Frame {
+                    data,
+                    captured_at,
+                    changed,
+                    ..
+                }) => {
+                    if !changed
+                        && last_sent.is_some_and(|sent| sent.elapsed() < UNCHANGED_REPEAT_INTERVAL)
+                    {
+                        continue;
+                    }
+                    sequence += 1;
+                    if !state.bandwidth.sends(sequence - 1) {
+                        continue;
+                    }
+                    last_sent = Some(Instant::now());
+                    camera_status = None;
+                    let Some(data) = render_for_viewer(&state, client.camera(), data, &options).await
+                    else {
+                        continue;
+                    };
+                    let timestamp_ms = captured_at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64;
+                    let mut payload = Vec::with_capacity(data.len() + 8);
+                    payload.extend_from_slice(&timestamp_ms.to_be_bytes());
+                    payload.extend_from_slice(&data);
+                    Message::Binary(payload)
+                }
+                Some(FrameEvent::Error(err)) => {
+                    let status = match *err {
+                        CameraError::OffDuty => "camera-off-duty",
+                        CameraError::Paused => "camera-paused",
+                        _ => "camera-error",
+                    };
+                    // A failing camera reports every capture attempt; viewers
+                    // only need to hear when the state changes.
+                    if camera_status.replace(status) == Some(status) {
+                        continue;
+                    }
+                    Message::Text(status.to_string())
+                }
+                None => break,
+            },
+            event = next_event => match event.map(|event| serde_json::to_string(&event)) {
+                Some(Ok(json)) => Message::Text(json),
+                Some(Err(err)) => {
+                    tracing::error!(error = %err, "Serializing status event failed");
+                    continue;
+                }
+                None => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            () = &mut stopping => Message::Close(Some(CloseFrame {
+                code: close_code::AWAY,
+                reason: "server-shutdown".into(),
+            })),
+            () = &mut expired => Message::Close(Some(CloseFrame {
+                code: close_code::POLICY,
+                reason: "credentials-expired".into(),
+            })),
+            () = &mut idle => Message::Ping(Vec::new()),
+        };
+        let closing = matches!(message, Message::Close(_));
+
+        let (len, is_frame) = match &message {
+            Message::Binary(payload) => (payload.len(), true),
+            Message::Text(text) => (text.len(), false),
+            _ => (0, false),
+        };
+        let write = tracing::info_span!("stream_write", protocol = "ws", bytes = len);
+        let started = Instant::now();
+        if socket.send(message).instrument(write).await.is_err() || closing {
+            break;
+        }
+        idle.as_mut()
+            .reset(time::Instant::now() + STREAM_KEEPALIVE_INTERVAL);
+        if is_frame {
+            timings::record(Stage::StreamWrite, started.elapsed());
+            client.frame_sent(len);
+        } else {
+            client.sent(len);
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/snapshot",
+    tag = "snapshots",
+    params(SnapshotQuery),
+    responses(
+        (status = 200, description = "The next frame, as the stream shows it", content(("image/jpeg"), ("image/png"), ("image/webp"))),
+        (status = 400, description = "Invalid `crop`"),
+        (status = 406, description = "`not-acceptable`: no format in `Accept` can be served"),
+        (status = 501, description = "The format is not supported by this build"),
+        (status = 503, description = "`camera-not-found`, `camera-busy`, `camera-disconnected`, `camera-error`, or `camera-paused` or `camera-off-duty` while it is paused or outside `STREAM_SCHEDULE`: the camera cannot deliver a frame"),
+        (status = 504, description = "`camera-timeout`: the camera did not deliver a frame in time"),
+        (status = 500, description = "`camera-format-unsupported` or `encode-failed`"),
+    )
+)]
+async fn snapshot_handler(
+    Query(query): Query<SnapshotQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    snapshot_response(&state, state.default_camera(), query, &headers).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/cameras/{id}/snapshot",
+    tag = "snapshots",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`"), SnapshotQuery),
+    responses(
+        (status = 200, description = "The next frame, as the stream shows it", content(("image/jpeg"), ("image/png"), ("image/webp"))),
+        (status = 400, description = "Invalid `crop`"),
+        (status = 406, description = "`not-acceptable`: no format in `Accept` can be served"),
+        (status = 501, description = "The format is not supported by this build"),
+        (status = 503, description = "`camera-not-found`, `camera-busy`, `camera-disconnected`, `camera-error`, or `camera-paused` or `camera-off-duty` while it is paused or outside `STREAM_SCHEDULE`: the camera cannot deliver a frame"),
+        (status = 504, description = "`camera-timeout`: the camera did not deliver a frame in time"),
+        (status = 500, description = "`camera-format-unsupported` or `encode-failed`"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_snapshot_handler(
+    Path(id): Path<usize>,
+    Query(query): Query<SnapshotQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    match state.camera(id) {
+        Some(handle) => snapshot_response(&state, handle, query, &headers).await,
+        None => unknown_camera_response(),
+    }
+}
+
+async fn snapshot_response(
+    state: &AppState,
+    handle: &CameraHandle,
+    query: SnapshotQuery,
+    headers: &HeaderMap,
+) -> Response {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok());
+    let format = match (query.format, accept) {
+        (Some(format), _) => format,
+        (None, Some(accept)) => match SnapshotFormat::negotiate(accept) {
+            Some(format) => format,
+            None => return (StatusCode::NOT_ACCEPTABLE, "not-acceptable").into_response(),
+        },
+        (None, None) => SnapshotFormat::Jpeg,
+    };
+    if !format.is_supported() {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            format!("{}-unsupported", format.name()),
+        )
+            .into_response();
+    }
+    let crop = match parse_crop(query.crop.as_deref()) {
+        Ok(crop) => crop,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    // Taking the next broadcast frame keeps snapshots identical to what the
+    // stream shows, overlays included.
+    let frame = match handle.next_frame(FRAME_TIMEOUT).await {
+        Ok(frame) => frame.data,
+        Err(err) => {
+            if !err.is_hold() {
+                tracing::error!(error = %err, "Snapshot capture failed");
+            }
+            return camera_error_response(&err);
+        }
+    };
+
+    let config = state.config.read().await.clone();
+    let encoded =
+        task::spawn_blocking(move || snapshot_format::encode(frame, format, crop, &config))
+            .await
+            .expect("spawn_blocking failed");
+    match encoded {
+        Ok(image) => {
+            let headers = [
+                (header::CONTENT_TYPE, format.content_type().to_string()),
+                (header::CONTENT_LENGTH, image.len().to_string()),
+                (
+                    header::CACHE_CONTROL,
+                    "no-cache, no-store, must-revalidate".to_string(),
+                ),
+                (header::PRAGMA, "no-cache".to_string()),
+                (header::VARY, "Accept".to_string()),
+            ];
+            (headers, image).into_response()
+        }
+        Err(err) => {
+            tracing::error!(
+                format = format.name(),
+                error = format!("{err:#}"),
+                "Encoding snapshot failed"
+            );
+            (StatusCode::INTERNAL_SERVER_ERROR, "encode-failed").into_response()
+        }
+    }
+}
+
+/// Answers a failed capture with its [`CameraError::code`]: `504` when the
+/// device timed out, `500` when retrying cannot help, `503` otherwise.
+fn camera_error_response(err: &CameraError) -> Response {
+    let status = match err {
+        CameraError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        CameraError::FormatUnsupported(_) | CameraError::Encode(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        CameraError::DeviceNotFound(_)
+        | CameraError::Busy(_)
+        | CameraError::Disconnected(_)
+        | CameraError::Device(_)
+        | CameraError::OffDuty
+        | CameraError::Paused => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, err.code()).into_response()
+}
+
+/// Streams the microphone as WAV until the client disconnects or its
+/// credentials expire.
+#[utoipa::path(
+    get,
+    path = "/audio.wav",
+    tag = "streams",
+    responses(
+        (status = 200, description = "Endless 16-bit PCM WAV from the microphone", content_type = "audio/wav"),
+        (status = 404, description = "`audio-disabled`: no `AUDIO_DEVICE` is set"),
+    )
+)]
+async fn audio_handler(
+    expiry: Option<Extension<auth::Expiry>>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(audio) = state.audio.clone() else {
+        return (StatusCode::NOT_FOUND, "audio-disabled").into_response();
+    };
+
+    let mut chunks = audio.subscribe();
+    let stopping = wait_for_shutdown(state.shutdown.clone());
+    let expired = wait_for_expiry(expiry.map(|Extension(expiry)| expiry));
+    let stream = async_stream::stream! {
+        yield Ok::<Bytes, Infallible>(audio.wav_header());
+        tokio::pin!(stopping);
+        tokio::pin!(expired);
+        loop {
+            let chunk = tokio::select! {
+                chunk = chunks.recv() => chunk,
+                () = &mut stopping => break,
+                () = &mut expired => break,
+            };
+            match chunk {
+                Ok(chunk) => yield Ok(chunk),
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::debug!(missed, "Audio client behind; skipping chunks");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+
+    let headers = [
+        (header::CONTENT_TYPE, "audio/wav"),
+        (header::CACHE_CONTROL, "no-cache, no-store, must-revalidate"),
+        (X_ACCEL_BUFFERING, "no"),
+    ];
+    (headers, Body::from_stream(stream)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/preview.gif",
+    tag = "snapshots",
+    responses(
+        (status = 200, description = "Looping GIF of about three seconds, 320 pixels wide", content_type = "image/gif"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+    )
+)]
+async fn preview_handler(State(state): State<AppState>) -> Response {
+    preview_response(state.default_camera()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/cameras/{id}/preview.gif",
+    tag = "snapshots",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    responses(
+        (status = 200, description = "Looping GIF of about three seconds, 320 pixels wide", content_type = "image/gif"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_preview_handler(Path(id): Path<usize>, State(state): State<AppState>) -> Response {
+    match state.camera(id) {
+        Some(handle) => preview_response(handle).await,
+        None => unknown_camera_response(),
+    }
+}
+
+async fn preview_response(handle: &CameraHandle) -> Response {
+    match preview::render_gif(handle).await {
+        Ok(gif) => {
+            let headers = [
+                (header::CONTENT_TYPE, "image/gif".to_string()),
+                (header::CONTENT_LENGTH, gif.len().to_string()),
+                (
+                    header::CACHE_CONTROL,
+                    "no-cache, no-store, must-revalidate".to_string(),
+                ),
+                (header::PRAGMA, "no-cache".to_string()),
+            ];
+            (headers, gif).into_response()
+        }
+        Err(err) => {
+            tracing::error!(error = format!("{err:#}"), "Preview capture failed");
+            (StatusCode::SERVICE_UNAVAILABLE, "camera-error").into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/thumbnail",
+    tag = "snapshots",
+    params(ThumbnailQuery),
+    responses(
+        (status = 200, description = "Small JPEG, rendered at most once every five seconds per width", content_type = "image/jpeg"),
+        (status = 400, description = "Invalid `width`"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+    )
+)]
+async fn thumbnail_handler(
+    Query(query): Query<ThumbnailQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    thumbnail_response(&state, 0, query).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/cameras/{id}/thumbnail",
+    tag = "snapshots",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`"), ThumbnailQuery),
+    responses(
+        (status = 200, description = "Small JPEG, rendered at most once every five seconds per width", content_type = "image/jpeg"),
+        (status = 400, description = "Invalid `width`"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_thumbnail_handler(
+    Path(id): Path<usize>,
+    Query(query): Query<ThumbnailQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if state.camera(id).is_none() {
+        return unknown_camera_response();
+    }
+    thumbnail_response(&state, id, query).await
+}
+
+async fn thumbnail_response(state: &AppState, id: usize, query: ThumbnailQuery) -> Response {
+    let width = query.width.unwrap_or(thumbnail::DEFAULT_WIDTH);
+    if !(thumbnail::MIN_WIDTH..=thumbnail::MAX_WIDTH).contains(&width) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "width must be between {} and {}",
+                thumbnail::MIN_WIDTH,
+                thumbnail::MAX_WIDTH
+            ),
+        )
+            .into_response();
+    }
+
+    let quality = state.config.read().await.jpeg_quality;
+    match state
+        .thumbnails
+        .get(id, &state.cameras[id], width, quality)
+        .await
+    {
+        Ok(thumbnail) => {
+            let headers = [
+                (header::CONTENT_TYPE, "image/jpeg".to_string()),
+                (header::CONTENT_LENGTH, thumbnail.len().to_string()),
+                (header::CACHE_CONTROL, "no-cache".to_string()),
+            ];
+            (headers, thumbnail).into_response()
+        }
+        Err(err) => {
+            tracing::error!(
+                camera = id,
+                error = format!("{err:#}"),
+                "Thumbnail capture failed"
+            );
+            (StatusCode::SERVICE_UNAVAILABLE, "camera-error").into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/cameras",
+    tag = "cameras",
+    responses(
+        (status = 200, body = Vec<CameraInfo>),
+    )
+)]
+async fn cameras_handler(State(state): State<AppState>) -> Json<Vec<CameraInfo>> {
+    let mut cameras = Vec::with_capacity(state.cameras.len());
+    for (id, handle) in state.cameras.iter().enumerate() {
+        cameras.push(CameraInfo {
+            id,
+            device: handle.device().map(String::from),
+            backend: handle.backend_name().await,
+            capabilities: handle.capabilities().await,
+        });
+    }
+    Json(cameras)
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices",
+    tag = "cameras",
+    responses(
+        (status = 200, description = "V4L2 devices and the formats they offer", body = Vec<DeviceInfo>),
+    )
+)]
+async fn devices_handler() -> Json<Vec<DeviceInfo>> {
+    let devices = task::spawn_blocking(camera::list_devices)
+        .await
+        .expect("spawn_blocking failed");
+    Json(devices)
+}
+
+#[utoipa::path(
+    get,
+    path = "/camera/capabilities",
+    tag = "cameras",
+    responses(
+        (status = 200, body = CameraCapabilities),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+    )
+)]
+async fn capabilities_handler(State(state): State<AppState>) -> Response {
+    capabilities_response(state.default_camera()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/cameras/{id}/capabilities",
+    tag = "cameras",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    responses(
+        (status = 200, body = CameraCapabilities),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_capabilities_handler(
+    Path(id): Path<usize>,
+    State(state): State<AppState>,
+) -> Response {
+    match state.camera(id) {
+        Some(handle) => capabilities_response(handle).await,
+        None => unknown_camera_response(),
+    }
+}
+
+/// Opens an on-demand camera, like `/controls` does, to ask it.
+async fn capabilities_response(handle: &CameraHandle) -> Response {
+    match describe_camera(&*handle.camera().await).await {
+        Ok(capabilities) => Json(capabilities).into_response(),
+        Err(err) => {
+            tracing::error!(error = %err, "Reading camera capabilities failed");
+            (StatusCode::SERVICE_UNAVAILABLE, "camera-error").into_response()
+        }
+    }
+}
+
+async fn describe_camera(camera: &dyn Camera) -> anyhow::Result<CameraCapabilities> {
+    let ptz = match camera.ptz() {
+        Some(ptz) => Some(ptz.position().await?),
+        None => None,
+    };
+    let focus = match camera.focus() {
+        Some(focus) => Some(focus.state().await?),
+        None => None,
+    };
+    Ok(CameraCapabilities {
+        backend: camera.backend_name(),
+        format: camera.format(),
+        capabilities: camera.capabilities(),
+        controls: camera.list_controls().await?,
+        ptz,
+        focus,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/camera/pause",
+    tag = "cameras",
+    responses(
+        (status = 200, body = PauseState),
+    )
+)]
+async fn pause_handler(State(state): State<AppState>) -> Json<PauseState> {
+    pause_response(0, state.default_camera(), true).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/cameras/{id}/pause",
+    tag = "cameras",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    responses(
+        (status = 200, body = PauseState),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_pause_handler(Path(id): Path<usize>, State(state): State<AppState>) -> Response {
+    match state.camera(id) {
+        Some(handle) => pause_response(id, handle, true).await.into_response(),
+        None => unknown_camera_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/camera/resume",
+    tag = "cameras",
+    responses(
+        (status = 200, body = PauseState),
+    )
+)]
+async fn resume_handler(State(state): State<AppState>) -> Json<PauseState> {
+    pause_response(0, state.default_camera(), false).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/cameras/{id}/resume",
+    tag = "cameras",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    responses(
+        (status = 200, body = PauseState),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_resume_handler(Path(id): Path<usize>, State(state): State<AppState>) -> Response {
+    match state.camera(id) {
+        Some(handle) => pause_response(id, handle, false).await.into_response(),
+        None => unknown_camera_response(),
+    }
+}
+
+/// Pauses or resumes the camera. Viewers stay connected and get a "Camera
+/// paused" picture meanwhile.
+async fn pause_response(id: usize, handle: &CameraHandle, paused: bool) -> Json<PauseState> {
+    if handle.is_paused() != paused {
+        if paused {
+            handle.pause().await;
+            tracing::info!(camera = id, "Camera paused");
+        } else {
+            handle.resume().await;
+            tracing::info!(camera = id, "Camera resumed");
+        }
+    }
+    Json(PauseState { paused })
+}
+
+#[utoipa::path(
+    get,
+    path = "/controls",
+    tag = "controls",
+    responses(
+        (status = 200, body = Vec<ControlInfo>),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+    )
+)]
+async fn controls_handler(State(state): State<AppState>) -> Response {
+    controls_response(state.default_camera()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/cameras/{id}/controls",
+    tag = "controls",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    responses(
+        (status = 200, body = Vec<ControlInfo>),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_controls_handler(Path(id): Path<usize>, State(state): State<AppState>) -> Response {
+    match state.camera(id) {
+        Some(handle) => controls_response(handle).await,
+        None => unknown_camera_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/controls",
+    tag = "controls",
+    request_body = ControlChange,
+    responses(
+        (status = 200, description = "The controls after the change", body = Vec<ControlInfo>),
+        (status = 400, description = "The device rejected the change"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+    )
+)]
+async fn set_control_handler(
+    State(state): State<AppState>,
+    Json(change): Json<ControlChange>,
+) -> Response {
+    set_control_response(state.default_camera(), change).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/cameras/{id}/controls",
+    tag = "controls",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    request_body = ControlChange,
+    responses(
+        (status = 200, description = "The controls after the change", body = Vec<ControlInfo>),
+        (status = 400, description = "The device rejected the change"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_set_control_handler(
+    Path(id): Path<usize>,
+    State(state): State<AppState>,
+    Json(change): Json<ControlChange>,
+) -> Response {
+    match state.camera(id) {
+        Some(handle) => set_control_response(handle, change).await,
+        None => unknown_camera_response(),
+    }
+}
+
+async fn controls_response(handle: &CameraHandle) -> Response {
+    match handle.camera().await.list_controls().await {
+        Ok(controls) => Json(controls).into_response(),
+        Err(err) => {
+            tracing::error!(error = %err, "Listing camera controls failed");
+            (StatusCode::SERVICE_UNAVAILABLE, "camera-error").into_response()
+        }
+    }
+}
+
+/// Applies `change` and responds with the refreshed control list.
+async fn set_control_response(handle: &CameraHandle, change: ControlChange) -> Response {
+    if let Err(err) = handle.camera().await.set_control(change).await {
+        tracing::warn!(id = change.id, value = change.value, error = %err, "Setting camera control failed");
+        return (StatusCode::BAD_REQUEST, format!("{err:#}")).into_response();
+    }
+
+    tracing::info!(
+        id = change.id,
+        value = change.value,
+        "Camera control updated"
+    );
+    controls_response(handle).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/ptz",
+    tag = "controls",
+    responses(
+        (status = 200, body = PtzPosition),
+        (status = 501, description = "`ptz-unsupported`"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+    )
+)]
+async fn ptz_handler(State(state): State<AppState>) -> Response {
+    ptz_response(state.default_camera()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/cameras/{id}/ptz",
+    tag = "controls",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    responses(
+        (status = 200, body = PtzPosition),
+        (status = 501, description = "`ptz-unsupported`"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_ptz_handler(Path(id): Path<usize>, State(state): State<AppState>) -> Response {
+    match state.camera(id) {
+        Some(handle) => ptz_response(handle).await,
+        None => unknown_camera_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/ptz",
+    tag = "controls",
+    request_body = PtzMove,
+    responses(
+        (status = 200, description = "The new position", body = PtzPosition),
+        (status = 400, description = "The device rejected the move"),
+        (status = 501, description = "`ptz-unsupported`"),
+    )
+)]
+async fn ptz_move_handler(State(state): State<AppState>, Json(request): Json<PtzMove>) -> Response {
+    ptz_move_response(state.default_camera(), request).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/cameras/{id}/ptz",
+    tag = "controls",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    request_body = PtzMove,
+    responses(
+        (status = 200, description = "The new position", body = PtzPosition),
+        (status = 400, description = "The device rejected the move"),
+        (status = 501, description = "`ptz-unsupported`"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_ptz_move_handler(
+    Path(id): Path<usize>,
+    State(state): State<AppState>,
+    Json(request): Json<PtzMove>,
+) -> Response {
+    match state.camera(id) {
+        Some(handle) => ptz_move_response(handle, request).await,
+        None => unknown_camera_response(),
+    }
+}
+
+async fn ptz_response(handle: &CameraHandle) -> Response {
+    let camera = handle.camera().await;
+    let Some(ptz) = camera.ptz() else {
+        return ptz_unsupported_response();
+    };
+    match ptz.position().await {
+        Ok(position) => Json(position).into_response(),
+        Err(err) => {
+            tracing::error!(error = %err, "Reading PTZ position failed");
+            (StatusCode::SERVICE_UNAVAILABLE, "camera-error").into_response()
+        }
+    }
+}
+
+/// Moves the camera and responds with its new position.
+async fn ptz_move_response(handle: &CameraHandle, request: PtzMove) -> Response {
+    let camera = handle.camera().await;
+    let Some(ptz) = camera.ptz() else {
+        return ptz_unsupported_response();
+    };
+    match ptz.move_to(request).await {
+        Ok(position) => {
+            tracing::info!(?request, "Camera moved");
+            Json(position).into_response()
+        }
+        Err(err) => {
+            tracing::warn!(?request, error = %err, "Moving camera failed");
+            (StatusCode::BAD_REQUEST, format!("{err:#}")).into_response()
+        }
+    }
+}
+
+fn ptz_unsupported_response() -> Response {
+    (StatusCode::NOT_IMPLEMENTED, "ptz-unsupported").into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/focus",
+    tag = "controls",
+    responses(
+        (status = 200, body = FocusState),
+        (status = 501, description = "`focus-unsupported`"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+    )
+)]
+async fn focus_handler(State(state): State<AppState>) -> Response {
+    focus_response(state.default_camera()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/cameras/{id}/focus",
+    tag = "controls",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    responses(
+        (status = 200, body = FocusState),
+        (status = 501, description = "`focus-unsupported`"),
+        (status = 503, description = "`camera-error`: the camera cannot deliver a frame"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_focus_handler(Path(id): Path<usize>, State(state): State<AppState>) -> Response {
+    match state.camera(id) {
+        Some(handle) => focus_response(handle).await,
+        None => unknown_camera_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/focus",
+    tag = "controls",
+    request_body = FocusRequest,
+    responses(
+        (status = 200, description = "The new focus state", body = FocusState),
+        (status = 400, description = "The device rejected the request"),
+        (status = 501, description = "`focus-unsupported`"),
+    )
+)]
+async fn refocus_handler(
+    State(state): State<AppState>,
+    Json(request): Json<FocusRequest>,
+) -> Response {
+    refocus_response(state.default_camera(), request).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/cameras/{id}/focus",
+    tag = "controls",
+    params(("id" = usize, Path, description = "Camera id, as listed by `/cameras`")),
+    request_body = FocusRequest,
+    responses(
+        (status = 200, description = "The new focus state", body = FocusState),
+        (status = 400, description = "The device rejected the request"),
+        (status = 501, description = "`focus-unsupported`"),
+        (status = 404, description = "`unknown-camera`"),
+    )
+)]
+async fn camera_refocus_handler(
+    Path(id): Path<usize>,
+    State(state): State<AppState>,
+    Json(request): Json<FocusRequest>,
+) -> Response {
+    match state.camera(id) {
+        Some(handle) => refocus_response(handle, request).await,
+        None => unknown_camera_response(),
+    }
+}
+
+async fn focus_response(handle: &CameraHandle) -> Response {
+    let camera = handle.camera().await;
+    let Some(focus) = camera.focus() else {
+        return focus_unsupported_response();
+    };
+    match focus.state().await {
+        Ok(state) => Json(state).into_response(),
+        Err(err) => {
+            tracing::error!(error = %err, "Reading focus state failed");
+            (StatusCode::SERVICE_UNAVAILABLE, "camera-error").into_response()
+        }
+    }
+}
+
+/// Refocuses the camera and responds with its new focus state.
+async fn refocus_response(handle: &CameraHandle, request: FocusRequest) -> Response {
+    let camera = handle.camera().await;
+    let Some(focus) = camera.focus() else {
+        return focus_unsupported_response();
+    };
+    match focus.refocus(request).await {
+        Ok(state) => {
+            tracing::info!(?request, "Camera refocused");
+            Json(state).into_response()
+        }
+        Err(err) => {
+            tracing::warn!(?request, error = %err, "Refocusing camera failed");
+            (StatusCode::BAD_REQUEST, format!("{err:#}")).into_response()
+        }
+    }
+}
+
+fn focus_unsupported_response() -> Response {
+    (StatusCode::NOT_IMPLEMENTED, "focus-unsupported").into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/recordings",
+    tag = "recordings",
+    responses(
+        (status = 200, description = "Clips in `RECORDINGS_DIR`, newest first; they are Motion-JPEG, for download and desktop players rather than browsers", body = Vec<RecordingInfo>),
+        (status = 500, description = "`recordings-unavailable`"),
+    )
+)]
+async fn recordings_handler(State(state): State<AppState>) -> Response {
+    let Some(dir) = state.config.read().await.recordings_dir.clone() else {
+        return Json(Vec::<RecordingInfo>::new()).into_response();
+    };
+    match task::spawn_blocking(move || recorder::list_recordings(&dir)).await {
+        Ok(Ok(recordings)) => Json(recordings).into_response(),
+        Ok(Err(err)) => {
+            tracing::error!(error = %err, "Listing recordings failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "recordings-unavailable").into_response()
+        }
+        Err(err) => {
+            tracing::error!(error = %err, "Listing recordings panicked");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Serves a clip with `Range` support, so players can seek in it. Clips are
+/// Motion-JPEG, which browsers cannot play; they are meant for download.
+#[utoipa::path(
+    get,
+    path = "/recordings/{id}",
+    tag = "recordings",
+    params(("id" = String, Path, description = "Recording id, as listed by `/recordings`")),
+    responses(
+        (status = 200, description = "The clip as Motion-JPEG, which VLC and mpv play but browsers and QuickTime do not; `Range` requests are answered with 206", content(("video/mp4"), ("video/x-msvideo"))),
+        (status = 404, description = "`unknown-recording`"),
+    )
+)]
+async fn recording_handler(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    request: Request,
+) -> Response {
+    let path = state
+        .config
+        .read()
+        .await
+        .recordings_dir
+        .as_deref()
+        .and_then(|dir| recorder::recording_path(dir, &id));
+    let Some(path) = path else {
+        return (StatusCode::NOT_FOUND, "unknown-recording").into_response();
+    };
+
+    match ServeFile::new(path).try_call(request).await {
+        Ok(response) => response.map(Body::new),
+        Err(err) => {
+            tracing::error!(id, error = %err, "Serving recording failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "recording-unavailable").into_response()
+        }
+    }
+}
+
+fn unknown_camera_response() -> Response {
+    (StatusCode::NOT_FOUND, "unknown-camera").into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/config",
+    tag = "config",
+    responses(
+        (status = 200, body = Config),
+    )
+)]
 async fn config_handler(State(state): State<AppState>) -> Json<Config> {
-    Json(state.config.clone())
+    Json(state.config.read().await.clone())
+}
+
+#[utoipa::path(
+    put,
+    path = "/config",
+    tag = "config",
+    request_body = ConfigUpdate,
+    responses(
+        (status = 200, description = "The settings now in effect", body = Config),
+        (status = 400, description = "The result would be invalid"),
+    )
+)]
+async fn update_config_handler(
+    State(state): State<AppState>,
+    Json(update): Json<ConfigUpdate>,
+) -> Response {
+    // Holding the write lock serializes concurrent updates and their restarts.
+    let mut config = state.config.write().await;
+    let result = match config.updated(&update) {
+        Ok(updated) => apply_config(&state, &mut config, updated).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(updated) => Json(updated).into_response(),
+        Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/config/reload",
+    tag = "config",
+    responses(
+        (status = 200, description = "The settings now in effect", body = Config),
+        (status = 400, description = "The environment or config file is invalid"),
+    )
+)]
+async fn reload_config_handler(State(state): State<AppState>) -> Response {
+    match reload_config(&state).await {
+        Ok(updated) => Json(updated).into_response(),
+        Err(err) => (StatusCode::BAD_REQUEST, format!("{err:#}")).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+struct LinkRequest {
+    path: String,
+    /// Seconds until the link stops working.
+    #[serde(default = "default_link_lifetime")]
+    expires_in: u64,
+}
+
+fn default_link_lifetime() -> u64 {
+    links::DEFAULT_LIFETIME_SECS
 }
 
-async fn health_handler() -> impl IntoResponse {
-    (StatusCode::OK, "ok")
+#[derive(Serialize, ToSchema)]
+struct SignedLink {
+    url: String,
+    /// Unix seconds.
+    expires: i64,
+}
+
+/// Mints a link to one view that works without credentials until it
+/// expires.
+#[utoipa::path(
+    post,
+    path = "/links",
+    tag = "links",
+    request_body = LinkRequest,
+    responses(
+        (status = 200, body = SignedLink),
+        (status = 400, description = "The path cannot be shared, or `expires_in` is out of range"),
+        (status = 404, description = "`links-disabled`: no `LINK_SECRET` is set"),
+    )
+)]
+async fn create_link_handler(
+    State(state): State<AppState>,
+    Json(request): Json<LinkRequest>,
+) -> Response {
+    let Some(secret) = state.config.read().await.auth.link_secret.clone() else {
+        return (StatusCode::NOT_FOUND, "links-disabled").into_response();
+    };
+    // Links work under both prefixes, as routes see their path without it
+    // (or `BASE_PATH`).
+    let base_path = state.config.read().await.base_path.clone();
+    let path = base_path
+        .as_deref()
+        .and_then(|base_path| request.path.strip_prefix(base_path))
+        .unwrap_or(&request.path);
+    let path = path.strip_prefix(API_V1).unwrap_or(path);
+    if !links::is_shareable(path) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "path must be a stream, snapshot, thumbnail, preview or audio route",
+        )
+            .into_response();
+    }
+    if !(1..=links::MAX_LIFETIME_SECS).contains(&request.expires_in) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "expires_in must be between 1 and {} seconds",
+                links::MAX_LIFETIME_SECS
+            ),
+        )
+            .into_response();
+    }
+
+    let expires = chrono::Utc::now().timestamp() + request.expires_in as i64;
+    let query = links::sign(&secret, path, expires);
+    Json(SignedLink {
+        url: format!("{}?{query}", request.path),
+        expires,
+    })
+    .into_response()
+}
+
+/// JWTs need the `admin` scope here, as for changes.
+#[utoipa::path(
+    get,
+    path = "/audit",
+    tag = "audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Accesses in `AUDIT_LOG` that match, newest first, among its latest 100000 lines", body = Vec<AuditEntry>),
+        (status = 400, description = "Invalid `since` or `until`"),
+        (status = 403, description = "The token lacks the `admin` scope"),
+        (status = 404, description = "`audit-log-disabled`: no `AUDIT_LOG` is set"),
+        (status = 500, description = "`audit-log-unavailable`"),
+    )
+)]
+async fn audit_handler(Query(query): Query<AuditQuery>, State(state): State<AppState>) -> Response {
+    let Some(path) = state.audit.path().map(ToOwned::to_owned) else {
+        return (StatusCode::NOT_FOUND, "audit-log-disabled").into_response();
+    };
+    let filter = match audit::Filter::try_from(query) {
+        Ok(filter) => filter,
+        Err(err) => return (StatusCode::BAD_REQUEST, format!("{err:#}")).into_response(),
+    };
+    match task::spawn_blocking(move || audit::query(&path, &filter)).await {
+        Ok(Ok(entries)) => Json(entries).into_response(),
+        Ok(Err(err)) => {
+            tracing::error!(error = format!("{err:#}"), "Querying audit log failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "audit-log-unavailable").into_response()
+        }
+        Err(err) => {
+            tracing::error!(error = %err, "Querying audit log panicked");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(state: AppState) {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+        .expect("failed to install signal handler");
+    while hangup.recv().await.is_some() {
+        tracing::info!("SIGHUP received; reloading configuration");
+        if let Err(err) = reload_config(&state).await {
+            tracing::error!(error = format!("{err:#}"), "Configuration reload failed");
+        }
+    }
+}
+
+/// Re-reads the environment and config file and applies what can change
+/// while running. Streams stay connected; cameras restart only if the
+/// capture settings changed.
+async fn reload_config(state: &AppState) -> anyhow::Result<Config> {
+    let fresh = state.config_source.load()?;
+    let mut config = state.config.write().await;
+    if config.restart_differs(&fresh) {
+        tracing::warn!("Some changed settings only take effect after a restart");
+    }
+    let updated = config.reloaded(&fresh)?;
+    apply_config(state, &mut config, updated).await
+}
+
+/// Rebuilds the overlay and restarts capture as needed, then stores `updated`.
+async fn apply_config(
+    state: &AppState,
+    config: &mut Config,
+    updated: Config,
+) -> anyhow::Result<Config> {
+    if config.auth != updated.auth {
+        state.auth.replace(updated.auth.clone())?;
+        if !updated.auth.is_enabled() {
+            tracing::warn!("No AUTH_TOKEN, AUTH_USER or JWT key configured; all routes are public");
+        }
+    }
+
+    if config.overlay_differs(&updated) {
+        let overlay = Overlay::from_config(&updated, state.blur.clone(), &state.load)?;
+        state.overlay.send_replace(overlay.map(Arc::new));
+    }
+
+    if config.jpeg_quality != updated.jpeg_quality {
+        for handle in state.cameras.iter() {
+            handle.set_jpeg_quality(updated.jpeg_quality).await;
+        }
+        state.scaler.set_jpeg_quality(updated.jpeg_quality);
+    }
+
+    if config.capture_differs(&updated) {
+        for handle in state.cameras.iter() {
+            handle.restart(&updated).await;
+        }
+    }
+
+    tracing::info!(config = ?updated, "Configuration updated");
+    *config = updated.clone();
+    state.events.send(StatusEvent::ConfigChanged {
+        config: Box::new(updated.clone()),
+    });
+    Ok(updated)
+}
+
+/// Applies the day or night profile whenever the day/night switch flips.
+async fn follow_profiles(state: AppState, mut night: watch::Receiver<bool>) {
+    loop {
+        let is_night = *night.borrow_and_update();
+        apply_profile(&state, is_night).await;
+        if night.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Changes the frame rate through the usual config path, so `/config` shows
+/// it, then sets the profile's controls on every camera. Cameras lacking a
+/// control are logged and skipped.
+async fn apply_profile(state: &AppState, is_night: bool) {
+    let mut config = state.config.write().await;
+    let profile = if is_night {
+        config.night_profile.clone()
+    } else {
+        config.day_profile.clone()
+    };
+    let Some(profile) = profile else {
+        return;
+    };
+    let name = if is_night { "night" } else { "day" };
+
+    if let Some(frame_rate) = profile.frame_rate.filter(|rate| *rate != config.frame_rate) {
+        let mut updated = config.clone();
+        updated.frame_rate = frame_rate;
+        if let Err(err) = apply_config(state, &mut config, updated).await {
+            tracing::error!(
+                profile = name,
+                error = format!("{err:#}"),
+                "Applying profile frame rate failed"
+            );
+        }
+    }
+    drop(config);
+
+    for (camera, handle) in state.cameras.iter().enumerate() {
+        let device = handle.camera().await;
+        for &(id, value) in &profile.controls {
+            if let Err(err) = device.set_control(ControlChange { id, value }).await {
+                tracing::warn!(
+                    profile = name,
+                    camera,
+                    id,
+                    value,
+                    error = format!("{err:#}"),
+                    "Setting profile control failed"
+                );
+            }
+        }
+    }
+    tracing::info!(profile = name, "Camera profile applied");
+}
+
+/// Responds 503 while any camera's captures keep failing, so plain status
+/// checks notice a lost or broken device too.
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "status",
+    security(()),
+    responses(
+        (status = 200, body = HealthReport),
+        (status = 503, description = "A camera is failing or reconnecting", body = HealthReport),
+    )
+)]
+async fn health_handler(State(state): State<AppState>) -> Response {
+    let mut cameras = Vec::with_capacity(state.cameras.len());
+    for (id, handle) in state.cameras.iter().enumerate() {
+        let status = handle.status();
+        cameras.push(CameraHealth {
+            id,
+            device: handle.device().map(String::from),
+            backend: handle.backend_name().await,
+            format: handle.format().await,
+            last_frame_at: status
+                .last_frame_at
+                .map(|at| chrono::DateTime::<chrono::Local>::from(at).to_rfc3339()),
+            recent_errors: status.recent_errors,
+            consecutive_failures: status.consecutive_failures,
+            failing: status.is_failing(),
+            reconnecting: status.reconnecting,
+            reconnect_attempts: status.reconnect_attempts,
+        });
+    }
+
+    let healthy = cameras.iter().all(|camera| !camera.failing);
+    let (code, status) = if healthy {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "degraded")
+    };
+    (code, Json(HealthReport { status, cameras })).into_response()
+}
+
+/// Answers as long as the server does, for liveness probes that should only
+/// restart a hung process, not one whose camera is unplugged.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "status",
+    security(()),
+    responses(
+        (status = 200, description = "The server is running", body = String, content_type = "text/plain"),
+    )
+)]
+async fn liveness_handler() -> &'static str {
+    "ok"
+}
+
+/// Ready once every open camera delivers frames; closed on-demand cameras
+/// count as ready, since the next request opens them, as do cameras paused
+/// or off duty outside `STREAM_SCHEDULE`. Cameras that have not
+/// produced a frame within `STARTUP_GRACE_SECS` of startup are reported as
+/// starting rather than unavailable.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "status",
+    security(()),
+    responses(
+        (status = 200, description = "Every open camera delivers frames", body = String, content_type = "text/plain"),
+        (status = 503, description = "`starting` during the startup grace period, `not ready` after it", body = String, content_type = "text/plain"),
+    )
+)]
+async fn readiness_handler(State(state): State<AppState>) -> Response {
+    let mut ready = true;
+    for handle in state.cameras.iter() {
+        if handle.backend_name().await.is_none() || handle.is_held() {
+            continue;
+        }
+        let status = handle.status();
+        let fresh = status
+            .last_frame_at
+            .and_then(|at| at.elapsed().ok())
+            .is_some_and(|age| age < READY_MAX_FRAME_AGE);
+        ready &= fresh && !status.is_failing();
+    }
+    if ready {
+        return (StatusCode::OK, "ready").into_response();
+    }
+
+    let grace = Duration::from_secs(state.config.read().await.startup_grace_secs);
+    let body = if state.streams.uptime() < grace {
+        "starting"
+    } else {
+        "not ready"
+    };
+    (StatusCode::SERVICE_UNAVAILABLE, body).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "status",
+    responses(
+        (status = 200, description = "Server-sent events named after their `event` field", body = StampedEvent, content_type = "text/event-stream"),
+    )
+)]
+async fn events_handler(State(state): State<AppState>) -> Response {
+    events::sse_response(state.events.subscribe(), state.shutdown.clone())
+}
+
+/// Time spent per frame in each pipeline stage and the machine's load, for
+/// Prometheus.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "status",
+    responses(
+        (status = 200, description = "`picam_stage_duration_seconds` histograms by `stage`, and CPU, SoC temperature and throttling gauges", body = String, content_type = "text/plain"),
+    )
+)]
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let mut metrics = timings::prometheus();
+    metrics.push_str(&system::prometheus(
+        &state.load.reading(),
+        state.load.frame_rate_share(),
+    ));
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics,
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "status",
+    responses(
+        (status = 200, body = StatsReport),
+    )
+)]
+async fn stats_handler(State(state): State<AppState>) -> Json<StatsReport> {
+    let cameras: Vec<CameraStats> = state
+        .cameras
+        .iter()
+        .enumerate()
+        .map(|(id, handle)| {
+            let capture = handle.capture_stats();
+            CameraStats {
+                id,
+                fps: (capture.fps * 10.0).round() / 10.0,
+                target_fps: (capture.target_fps * 10.0).round() / 10.0,
+                frames_captured: capture.frames_captured,
+                capture_errors: capture.capture_errors,
+                reconnect_attempts: handle.status().reconnect_attempts,
+                stream_clients: state.streams.clients(id),
+                bytes_sent: state.streams.bytes_sent(id),
+                clients: state.streams.connections(id),
+            }
+        })
+        .collect();
+
+    Json(StatsReport {
+        uptime_secs: state.streams.uptime().as_secs(),
+        stream_clients: cameras.iter().map(|camera| camera.stream_clients).sum(),
+        bytes_sent: cameras.iter().map(|camera| camera.bytes_sent).sum(),
+        bandwidth_kbps: state.bandwidth.kbps(),
+        throttle: state.bandwidth.level(),
+        system: state.load.reading(),
+        frame_rate_share: state.load.frame_rate_share(),
+        cameras,
+    })
 }
 
 async fn shutdown_signal() {
@@ -136,41 +2706,43 @@ async fn shutdown_signal() {
     tracing::info!("Shutdown signal received");
 }
 
-fn init_tracing() -> anyhow::Result<()> {
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    fmt()
-        .with_env_filter(filter)
-        .try_init()
-        .map_err(|err| anyhow::anyhow!("Failed to initialize tracing subscriber: {err}"))?;
+/// `snapshot` subcommand. Opens the device directly, without the mock
+/// fallback, so a broken camera fails loudly.
+async fn snapshot(config: &Config, camera: usize, output: &path::Path) -> anyhow::Result<()> {
+    let devices = config.camera_devices();
+    let device = *devices
+        .get(camera)
+        .with_context(|| format!("No camera {camera}; {} configured", devices.len()))?;
+
+    let frame = camera::open_camera(config, device)?
+        .capture_frame()
+        .await
+        .context("Failed to capture frame")?;
+    std::fs::write(output, &frame.data)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    tracing::info!(
+        path = %output.display(),
+        bytes = frame.data.len(),
+        width = frame.width,
+        height = frame.height,
+        "Snapshot written"
+    );
     Ok(())
 }
 
-fn build_camera(config: &Config) -> Arc<dyn Camera> {
-    #[cfg(target_os = "linux")]
-    {
-        if let Some(device) = config.camera_device.as_deref() {
-            match V4l2Camera::new(
-                device,
-                config.resolution_width,
-                config.resolution_height,
-                config.frame_rate,
-            ) {
-                Ok(real_camera) => {
-                    tracing::info!(device, "Using V4L2 camera device");
-                    let camera: Arc<dyn Camera> = Arc::new(real_camera);
-                    return camera;
-                }
-                Err(err) => {
-                    tracing::error!(device, error = %err, "Falling back to mock camera");
-                }
-            }
-        } else {
-            tracing::warn!("No camera device configured; using mock camera");
-        }
+/// `check-config` subcommand: also loads the files the server would need.
+async fn check_config(config: &Config) -> anyhow::Result<()> {
+    let blur = config
+        .privacy_blur
+        .as_ref()
+        .map(PrivacyBlur::load)
+        .transpose()?
+        .map(Arc::new);
+    Overlay::from_config(config, blur, &Arc::new(Load::default()))?;
+    if let Some(tls) = &config.tls {
+        load_tls(tls).await?;
     }
-
-    Arc::new(MockCamera::new(
-        config.resolution_width,
-        config.resolution_height,
-    ))
+    println!("{config:#?}");
+    println!("Configuration OK");
+    Ok(())
 }
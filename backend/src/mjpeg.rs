@@ -0,0 +1,437 @@
+//! Checks of the JPEG frames MJPG cameras deliver, which are otherwise
+//! passed through untouched. Cheap UVC cameras leave out the Huffman tables
+//! or hand over frames cut short, which browsers show as corrupt images.
+
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, Bytes, BytesMut};
+
+const SOI: [u8; 2] = [0xff, 0xd8];
+const EOI: [u8; 2] = [0xff, 0xd9];
+const SOS: u8 = 0xda;
+const DHT: u8 = 0xc4;
+/// Start of a JPEG image: SOI and the first byte of the marker after it.
+const IMAGE_START: [u8; 3] = [0xff, 0xd8, 0xff];
+/// Longest image a [`StreamSplitter`] waits for before it gives up on one.
+const MAX_IMAGE_LEN: usize = 16 * 1024 * 1024;
+
+// The tables of ITU T.81 section K.3 that MJPEG (and the AVI1 format)
+// assumes when a frame has no DHT segment, as (class and id, code lengths,
+// values).
+const DC_LUMINANCE: (u8, [u8; 16], &[u8]) = (
+    0x00,
+    [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+);
+const DC_CHROMINANCE: (u8, [u8; 16], &[u8]) = (
+    0x01,
+    [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+);
+const AC_LUMINANCE: (u8, [u8; 16], &[u8]) = (
+    0x10,
+    [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d],
+    &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61,
+        0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52,
+        0xd1, 0xf0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25,
+        0x26, 0x27, 0x28, 0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45,
+        0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64,
+        0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83,
+        0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99,
+        0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6,
+        0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3,
+        0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8,
+        0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+    ],
+);
+const AC_CHROMINANCE: (u8, [u8; 16], &[u8]) = (
+    0x11,
+    [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61,
+        0x71, 0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33,
+        0x52, 0xf0, 0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18,
+        0x19, 0x1a, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44,
+        0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63,
+        0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a,
+        0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97,
+        0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4,
+        0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca,
+        0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7,
+        0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+    ],
+);
+
+/// Copies a camera's JPEG frame into `output`, adding the standard Huffman
+/// tables if it has none and dropping padding after the end of the image.
+/// Fails for frames that do not start with SOI, end with EOI or have an
+/// intact header, which decoders could only show in part.
+pub fn repair(frame: &[u8], output: &mut Vec<u8>) -> Result<()> {
+    if !frame.starts_with(&SOI) {
+        bail!("MJPEG frame does not start with SOI");
+    }
+    // Some cameras hand over the whole buffer, zeroed past the image.
+    let len = frame
+        .iter()
+        .rposition(|&byte| byte != 0)
+        .map_or(0, |last| last + 1);
+    let frame = &frame[..len];
+    if frame.len() < 4 || !frame.ends_with(&EOI) {
+        bail!("MJPEG frame is truncated: it does not end with EOI");
+    }
+
+    // Walk the header segments up to the scan, looking for Huffman tables.
+    let mut position = SOI.len();
+    let mut has_tables = false;
+    loop {
+        let marker = match frame.get(position..position + 2) {
+            Some(&[0xff, 0xff]) => {
+                // Fill byte before a marker.
+                position += 1;
+                continue;
+            }
+            Some(&[0xff, marker]) => marker,
+            _ => bail!("MJPEG frame has a malformed header at byte {position}"),
+        };
+        if marker == SOS {
+            break;
+        }
+        has_tables |= marker == DHT;
+        let segment_len = frame
+            .get(position + 2..position + 4)
+            .map(|len| usize::from(u16::from_be_bytes([len[0], len[1]])))
+            .context("MJPEG frame header is truncated")?;
+        position += 2 + segment_len;
+        if position >= frame.len() {
+            bail!("MJPEG frame header is truncated");
+        }
+    }
+
+    output.clear();
+    if has_tables {
+        output.extend_from_slice(frame);
+    } else {
+        output.extend_from_slice(&frame[..position]);
+        write_default_tables(output);
+        output.extend_from_slice(&frame[position..]);
+    }
+    Ok(())
+}
+
+/// Picks the JPEG images out of a byte stream as it arrives, such as the
+/// body of an HTTP MJPEG stream, skipping whatever framing is around them.
+#[derive(Default)]
+pub struct StreamSplitter {
+    buffer: BytesMut,
+}
+
+impl StreamSplitter {
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The next complete image, once it has arrived.
+    pub fn next_image(&mut self) -> Option<Bytes> {
+        loop {
+            let Some(start) = find_image(&self.buffer) else {
+                // The start of an image may be cut off at the end.
+                let keep = self.buffer.len().min(IMAGE_START.len() - 1);
+                self.buffer.advance(self.buffer.len() - keep);
+                return None;
+            };
+            self.buffer.advance(start);
+            match image_len(&self.buffer) {
+                Image::Complete(len) => return Some(self.buffer.split_to(len).freeze()),
+                Image::Truncated if self.buffer.len() <= MAX_IMAGE_LEN => return None,
+                Image::Truncated | Image::Malformed => self.buffer.advance(SOI.len()),
+            }
+        }
+    }
+}
+
+/// Width and height from the frame header of a JPEG image.
+pub fn dimensions(frame: &[u8]) -> Option<(u32, u32)> {
+    if !frame.starts_with(&SOI) {
+        return None;
+    }
+    let mut position = SOI.len();
+    loop {
+        let marker = match *frame.get(position..position + 2)? {
+            [0xff, 0xff] => {
+                position += 1;
+                continue;
+            }
+            [0xff, marker] => marker,
+            _ => return None,
+        };
+        let segment = frame.get(position + 2..)?;
+        // SOF0 to SOF15, except for the DHT, JPG and DAC markers among them.
+        if (0xc0..=0xcf).contains(&marker) && ![DHT, 0xc8, 0xcc].contains(&marker) {
+            // Length, sample precision, then height and width.
+            let size = segment.get(3..7)?;
+            let height = u16::from_be_bytes([size[0], size[1]]);
+            let width = u16::from_be_bytes([size[2], size[3]]);
+            return Some((u32::from(width), u32::from(height)));
+        }
+        if marker == SOS {
+            return None;
+        }
+        let segment_len = usize::from(u16::from_be_bytes([*segment.first()?, *segment.get(1)?]));
+        position += 2 + segment_len;
+    }
+}
+
+fn find_image(data: &[u8]) -> Option<usize> {
+    data.windows(IMAGE_START.len())
+        .position(|bytes| bytes == IMAGE_START)
+}
+
+/// How much of a JPEG image a buffer holds.
+enum Image {
+    /// The image ends after this many bytes, with its EOI.
+    Complete(usize),
+    /// The image continues past the end of the buffer.
+    Truncated,
+    Malformed,
+}
+
+fn image_len(data: &[u8]) -> Image {
+    let mut position = SOI.len();
+    loop {
+        let marker = match data.get(position..position + 2) {
+            None => return Image::Truncated,
+            Some(&[0xff, 0xff]) => {
+                position += 1;
+                continue;
+            }
+            // Marker codes start at 0xc0, bar TEM, which images do not use.
+            Some(&[0xff, marker]) if marker >= 0xc0 => marker,
+            Some(_) => return Image::Malformed,
+        };
+        if marker == EOI[1] {
+            return Image::Complete(position + 2);
+        }
+        let Some(segment_len) = data.get(position + 2..position + 4) else {
+            return Image::Truncated;
+        };
+        position += 2 + usize::from(u16::from_be_bytes([segment_len[0], segment_len[1]]));
+        if marker == SOS {
+            match scan_end(data, position) {
+                Some(end) => position = end,
+                None => return Image::Truncated,
+            }
+        }
+    }
+}
+
+/// Skips the entropy-coded data of a scan starting at `position`, returning
+/// where the marker after it starts, or `None` if the data ends first.
+fn scan_end(data: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        position += data
+            .get(position..)?
+            .iter()
+            .position(|&byte| byte == 0xff)?;
+        match *data.get(position + 1)? {
+            // Stuffed zero bytes and restart markers are part of the scan.
+            0x00 | 0xd0..=0xd7 => position += 2,
+            // Fill byte before the next marker.
+            0xff => position += 1,
+            _ => return Some(position),
+        }
+    }
+}
+
+fn write_default_tables(output: &mut Vec<u8>) {
+    let tables = [DC_LUMINANCE, AC_LUMINANCE, DC_CHROMINANCE, AC_CHROMINANCE];
+    let len = 2 + tables
+        .iter()
+        .map(|(_, lengths, values)| 1 + lengths.len() + values.len())
+        .sum::<usize>();
+    output.extend_from_slice(&[0xff, DHT]);
+    output.extend_from_slice(&(len as u16).to_be_bytes());
+    for (class_and_id, lengths, values) in tables {
+        output.push(class_and_id);
+        output.extend_from_slice(&lengths);
+        output.extend_from_slice(values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::jpeg::JpegEncoder, ColorType, GenericImageView};
+
+    use super::*;
+
+    /// A 32x16 baseline JPEG, which the encoder writes with the standard
+    /// Huffman tables.
+    fn encoded() -> Vec<u8> {
+        let pixels: Vec<u8> = (0..32 * 16)
+            .flat_map(|i: u32| [(i * 7) as u8, (i / 2) as u8, 255 - i as u8])
+            .collect();
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 90)
+            .encode(&pixels, 32, 16, ColorType::Rgb8)
+            .unwrap();
+        jpeg
+    }
+
+    /// Header segments of `jpeg` as (offset, marker, total length), up to
+    /// and including SOS.
+    fn segments(jpeg: &[u8]) -> Vec<(usize, u8, usize)> {
+        let mut found = Vec::new();
+        let mut position = SOI.len();
+        loop {
+            let marker = jpeg[position + 1];
+            let len = 2 + usize::from(u16::from_be_bytes([jpeg[position + 2], jpeg[position + 3]]));
+            found.push((position, marker, len));
+            if marker == SOS {
+                return found;
+            }
+            position += len;
+        }
+    }
+
+    /// `jpeg` with its DHT segments removed, as cheap UVC cameras send it.
+    fn without_tables(jpeg: &[u8]) -> Vec<u8> {
+        let mut stripped = jpeg.to_vec();
+        for (offset, _, len) in segments(jpeg)
+            .into_iter()
+            .rev()
+            .filter(|&(_, marker, _)| marker == DHT)
+        {
+            stripped.drain(offset..offset + len);
+        }
+        stripped
+    }
+
+    fn repaired(frame: &[u8]) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        repair(frame, &mut output).map(|()| output)
+    }
+
+    #[test]
+    fn frame_without_tables_gets_the_standard_ones_before_its_scan() {
+        let original = encoded();
+        let stripped = without_tables(&original);
+        assert!(segments(&stripped)
+            .iter()
+            .all(|&(_, marker, _)| marker != DHT));
+        assert!(image::load_from_memory(&stripped).is_err());
+
+        let output = repaired(&stripped).unwrap();
+        let layout = segments(&output);
+        let (_, marker, len) = layout[layout.len() - 2];
+        assert_eq!((marker, len), (DHT, 420));
+        assert_eq!(layout.last().unwrap().1, SOS);
+        assert_eq!(output.len(), stripped.len() + len);
+
+        let decoded = image::load_from_memory(&output).unwrap();
+        let expected = image::load_from_memory(&original).unwrap();
+        assert_eq!(decoded.dimensions(), (32, 16));
+        assert_eq!(decoded.to_rgb8(), expected.to_rgb8());
+    }
+
+    #[test]
+    fn frame_with_tables_passes_through_unchanged() {
+        let original = encoded();
+        assert!(segments(&original)
+            .iter()
+            .any(|&(_, marker, _)| marker == DHT));
+        assert_eq!(repaired(&original).unwrap(), original);
+    }
+
+    #[test]
+    fn zero_padding_after_the_image_is_trimmed() {
+        let original = encoded();
+        let mut padded = original.clone();
+        padded.resize(original.len() + 4096, 0);
+        assert_eq!(repaired(&padded).unwrap(), original);
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let original = encoded();
+        let sos = segments(&original).last().unwrap().0;
+
+        let cut_in_scan = &original[..original.len() - 10];
+        assert!(repaired(cut_in_scan)
+            .unwrap_err()
+            .to_string()
+            .contains("EOI"));
+        let mut cut_in_header = original[..sos - 3].to_vec();
+        cut_in_header.extend_from_slice(&EOI);
+        assert!(repaired(&cut_in_header).is_err());
+        assert!(repaired(&original[1..]).is_err());
+        assert!(repaired(&[0xff, 0xd8, 0xff, 0xd9]).is_err());
+    }
+
+    #[test]
+    fn fill_bytes_before_markers_are_skipped() {
+        let stripped = without_tables(&encoded());
+        let sof = segments(&stripped)
+            .into_iter()
+            .find(|&(_, marker, _)| marker == 0xc0)
+            .unwrap()
+            .0;
+        let sos = segments(&stripped).last().unwrap().0;
+        let mut filled = stripped.clone();
+        filled.splice(sos..sos, [0xff, 0xff]);
+        filled.splice(sof..sof, [0xff]);
+
+        assert_eq!(dimensions(&filled), Some((32, 16)));
+        let output = repaired(&filled).unwrap();
+        assert!(image::load_from_memory(&output).is_ok());
+    }
+
+    #[test]
+    fn dimensions_come_from_the_frame_header() {
+        let original = encoded();
+        assert_eq!(dimensions(&original), Some((32, 16)));
+        assert_eq!(dimensions(&original[2..]), None);
+
+        let sof = segments(&original)
+            .into_iter()
+            .find(|&(_, marker, _)| marker == 0xc0)
+            .unwrap();
+        let mut no_sof = original.clone();
+        no_sof.drain(sof.0..sof.0 + sof.2);
+        assert_eq!(dimensions(&no_sof), None);
+    }
+
+    #[test]
+    fn splitter_finds_images_split_across_pushes() {
+        let jpeg = encoded();
+        let mut stream = Vec::new();
+        for _ in 0..2 {
+            stream.extend_from_slice(b"--frame\r\nContent-Type: image/jpeg\r\n\r\n");
+            stream.extend_from_slice(&jpeg);
+            stream.extend_from_slice(b"\r\n");
+        }
+
+        for split in 0..stream.len() {
+            let mut splitter = StreamSplitter::default();
+            let mut images = Vec::new();
+            for part in [&stream[..split], &stream[split..]] {
+                splitter.push(part);
+                while let Some(image) = splitter.next_image() {
+                    images.push(image);
+                }
+            }
+            assert_eq!(images.len(), 2, "split at {split}");
+            assert!(images.iter().all(|image| image[..] == jpeg[..]));
+        }
+    }
+
+    #[test]
+    fn splitter_skips_a_malformed_image() {
+        let jpeg = encoded();
+        let mut stream = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x12, 0x34, 0x56];
+        stream.extend_from_slice(&jpeg);
+
+        let mut splitter = StreamSplitter::default();
+        splitter.push(&stream);
+        assert_eq!(splitter.next_image().as_deref(), Some(&jpeg[..]));
+        assert_eq!(splitter.next_image(), None);
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    io::Cursor,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use image::{codecs::jpeg::JpegDecoder, DynamicImage, GrayImage};
+use serde::Serialize;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    task,
+};
+use utoipa::ToSchema;
+
+use crate::{capture::FrameEvent, config::Config};
+
+/// Frames are compared at roughly this size; the JPEG decoder scales down
+/// by up to 1/8 while decoding, which keeps detection cheap.
+const ANALYSIS_WIDTH: u16 = 160;
+const ANALYSIS_HEIGHT: u16 = 120;
+
+/// Motion counts as stopped once no changed frame was seen for this long.
+const MOTION_STOP_DELAY: Duration = Duration::from_secs(2);
+/// While motion lasts, where it is gets reported at most this often.
+const MOTION_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+/// For `STREAM_SKIP_UNCHANGED`, frames differ visibly once more than this
+/// fraction of their pixels changed by more than `UNCHANGED_THRESHOLD`;
+/// sensor noise mostly averages out at the analysis size.
+const UNCHANGED_THRESHOLD: u8 = 12;
+const UNCHANGED_MAX_FRACTION: f32 = 0.001;
+
+#[derive(Clone, Debug)]
+pub enum MotionEvent {
+    Started {
+        camera: usize,
+        region: MotionRegion,
+    },
+    /// Where the motion has moved to, while it lasts.
+    Updated {
+        camera: usize,
+        region: MotionRegion,
+    },
+    Stopped {
+        camera: usize,
+    },
+}
+
+impl MotionEvent {
+    pub fn camera(&self) -> usize {
+        match self {
+            Self::Started { camera, .. }
+            | Self::Updated { camera, .. }
+            | Self::Stopped { camera, .. } => *camera,
+        }
+    }
+}
+
+/// What changed between two frames.
+#[derive(Clone, Copy, Debug, Serialize, ToSchema)]
+pub struct MotionRegion {
+    /// Number of the frame the change was seen in, as in `X-Frame-Number`.
+    pub frame: u64,
+    /// Percentage of the frame that changed.
+    pub area: f32,
+    /// `x,y,w,h` around every changed pixel, as 0-1 fractions of the frame.
+    pub bbox: [f32; 4],
+}
+
+/// Watches `frames` for a camera and publishes start/stop transitions on `events`.
+pub fn spawn_detector(
+    camera: usize,
+    mut frames: broadcast::Receiver<FrameEvent>,
+    config: &Config,
+    events: broadcast::Sender<MotionEvent>,
+) {
+    let threshold = config.motion_threshold;
+    let min_area = config.motion_min_area;
+
+    tokio::spawn(async move {
+        let mut previous: Option<GrayImage> = None;
+        let mut last_motion: Option<Instant> = None;
+        let mut last_update = Instant::now();
+
+        loop {
+            let (data, number) = match frames.recv().await {
+                Ok(FrameEvent::Frame { data, number, .. }) => (data, number),
+                Ok(FrameEvent::Error(err)) => {
+                    // Nothing moves on a paused or off-duty camera, and its
+                    // first frame afterwards is not compared with the last.
+                    if err.is_hold() {
+                        previous = None;
+                        if last_motion.take().is_some() {
+                            tracing::info!(camera, "Motion stopped");
+                            let _ = events.send(MotionEvent::Stopped { camera });
+                        }
+                    }
+                    continue;
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+
+            let current = match task::spawn_blocking(move || analysis_frame(&data))
+                .await
+                .expect("spawn_blocking failed")
+            {
+                Ok(current) => current,
+                Err(err) => {
+                    tracing::debug!(camera, error = %err, "Skipping undecodable frame");
+                    continue;
+                }
+            };
+
+            let moved = previous
+                .as_ref()
+                .and_then(|previous| changed_region(previous, &current, threshold, number))
+                .filter(|region| region.area >= min_area);
+            previous = Some(current);
+
+            let now = Instant::now();
+            if let Some(region) = moved {
+                if last_motion.is_none() {
+                    tracing::info!(camera, "Motion started");
+                    let _ = events.send(MotionEvent::Started { camera, region });
+                    last_update = now;
+                } else if now - last_update >= MOTION_UPDATE_INTERVAL {
+                    let _ = events.send(MotionEvent::Updated { camera, region });
+                    last_update = now;
+                }
+                last_motion = Some(now);
+            } else if last_motion.is_some_and(|at| now - at >= MOTION_STOP_DELAY) {
+                tracing::info!(camera, "Motion stopped");
+                let _ = events.send(MotionEvent::Stopped { camera });
+                last_motion = None;
+            }
+        }
+    });
+}
+
+pub fn analysis_frame(jpeg: &Bytes) -> Result<GrayImage> {
+    let mut decoder = JpegDecoder::new(Cursor::new(jpeg.as_ref()))?;
+    decoder.scale(ANALYSIS_WIDTH, ANALYSIS_HEIGHT)?;
+    Ok(DynamicImage::from_decoder(decoder)?.to_luma8())
+}
+
+/// The pixels of frame `number` whose luma changed by more than `threshold`,
+/// or `None` if none did. Frames of different sizes (e.g. after a
+/// resolution change) count as unchanged.
+fn changed_region(
+    previous: &GrayImage,
+    current: &GrayImage,
+    threshold: u8,
+    number: u64,
+) -> Option<MotionRegion> {
+    if previous.dimensions() != current.dimensions() || current.is_empty() {
+        return None;
+    }
+
+    let (width, height) = current.dimensions();
+    let mut changed = 0u32;
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for (x, y, pixel) in current.enumerate_pixels() {
+        if previous.get_pixel(x, y).0[0].abs_diff(pixel.0[0]) <= threshold {
+            continue;
+        }
+        changed += 1;
+        left = left.min(x);
+        top = top.min(y);
+        right = right.max(x + 1);
+        bottom = bottom.max(y + 1);
+    }
+    if changed == 0 {
+        return None;
+    }
+
+    let (width, height) = (width as f32, height as f32);
+    Some(MotionRegion {
+        frame: number,
+        area: changed as f32 / (width * height) * 100.0,
+        bbox: [
+            left as f32 / width,
+            top as f32 / height,
+            (right - left) as f32 / width,
+            (bottom - top) as f32 / height,
+        ],
+    })
+}
+
+/// Whether `current` shows anything `previous` did not, as told by their
+/// analysis frames.
+pub fn visibly_differs(previous: &GrayImage, current: &GrayImage) -> bool {
+    if previous.dimensions() != current.dimensions() || current.is_empty() {
+        return true;
+    }
+    let changed = previous
+        .as_raw()
+        .iter()
+        .zip(current.as_raw())
+        .filter(|(a, b)| a.abs_diff(**b) > UNCHANGED_THRESHOLD)
+        .count();
+    changed as f32 / current.as_raw().len() as f32 > UNCHANGED_MAX_FRACTION
+}
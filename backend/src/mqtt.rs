@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::{self, Interval},
+};
+
+use crate::{
+    capture::{CameraHandle, FrameEvent},
+    config::MqttConfig,
+    motion::MotionEvent,
+    shutdown::DrainGuard,
+};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const REQUEST_CHANNEL_CAPACITY: usize = 32;
+/// Snapshots go out as a single publish, so allow full-size JPEG payloads.
+const MAX_PACKET_SIZE: usize = 16 * 1024 * 1024;
+
+/// Connects to the broker and publishes, below the configured prefix:
+///
+/// - `status`: `online`, or `offline` through the last will (retained)
+/// - `cameras/{id}/status`: `online` or `error` whenever capture changes state (retained)
+/// - `cameras/{id}/motion`: `ON` / `OFF` while motion detection runs (retained)
+/// - `cameras/{id}/snapshot`: the latest JPEG once per snapshot interval (retained)
+///
+/// At shutdown, queued messages are sent and `status` is set to `offline`
+/// before disconnecting, as a clean disconnect skips the last will.
+pub fn spawn(
+    config: &MqttConfig,
+    cameras: &[CameraHandle],
+    motion: Option<broadcast::Receiver<MotionEvent>>,
+    shutdown: DrainGuard,
+) {
+    let availability = config.topic("status");
+
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+    options.set_last_will(LastWill::new(
+        &availability,
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(user) = &config.user {
+        options.set_credentials(user, config.password.clone().unwrap_or_default());
+    }
+
+    let (client, mut eventloop) = AsyncClient::new(options, REQUEST_CHANNEL_CAPACITY);
+
+    // Queued behind everything published so far, so those go out first.
+    tokio::spawn({
+        let client = client.clone();
+        let availability = availability.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown.requested().await;
+            let _ = client
+                .publish(&availability, QoS::AtLeastOnce, true, "offline")
+                .await;
+            let _ = client.disconnect().await;
+        }
+    });
+
+    let announcer = client.clone();
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    tracing::info!("Connected to MQTT broker");
+                    // Awaiting here would stall the event loop that drains the queue.
+                    let _ = announcer.try_publish(&availability, QoS::AtLeastOnce, true, "online");
+                }
+                Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                    tracing::info!("Disconnected from MQTT broker");
+                    break;
+                }
+                Ok(_) => {}
+                Err(err) if shutdown.is_requested() => {
+                    tracing::warn!(error = %err, "MQTT broker unreachable at shutdown; dropping queued messages");
+                    break;
+                }
+                Err(err) => {
+                    tracing::warn!(error = %err, "MQTT connection failed; retrying");
+                    tokio::select! {
+                        () = time::sleep(RECONNECT_DELAY) => {}
+                        () = shutdown.requested() => {}
+                    }
+                }
+            }
+        }
+    });
+
+    for (id, handle) in cameras.iter().enumerate() {
+        tokio::spawn(publish_camera(
+            client.clone(),
+            config.clone(),
+            id,
+            handle.subscribe(),
+        ));
+    }
+
+    if let Some(motion) = motion {
+        tokio::spawn(publish_motion(client, config.clone(), motion));
+    }
+}
+
+async fn publish_camera(
+    client: AsyncClient,
+    config: MqttConfig,
+    camera: usize,
+    mut frames: broadcast::Receiver<FrameEvent>,
+) {
+    let status_topic = config.topic(&format!("cameras/{camera}/status"));
+    let snapshot_topic = config.topic(&format!("cameras/{camera}/snapshot"));
+    let mut snapshots = config.snapshot_interval().map(time::interval);
+    let mut latest: Option<Bytes> = None;
+    let mut healthy: Option<bool> = None;
+
+    loop {
+        tokio::select! {
+            frame = frames.recv() => {
+                let ok = match frame {
+                    Ok(FrameEvent::Frame { data, .. }) => {
+                        latest = Some(data);
+                        true
+                    }
+                    Ok(FrameEvent::Error(_)) => false,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+
+                if healthy != Some(ok) {
+                    healthy = Some(ok);
+                    let status = if ok { "online" } else { "error" };
+                    if let Err(err) = client.publish(&status_topic, QoS::AtLeastOnce, true, status).await {
+                        tracing::warn!(camera, error = %err, "MQTT publish failed");
+                    }
+                }
+            }
+            _ = next_tick(&mut snapshots) => {
+                if let Some(frame) = latest.take() {
+                    // Snapshots are dropped rather than queued while the broker is unreachable.
+                    let _ = client.try_publish(&snapshot_topic, QoS::AtMostOnce, true, frame);
+                }
+            }
+        }
+    }
+}
+
+async fn publish_motion(
+    client: AsyncClient,
+    config: MqttConfig,
+    mut motion: broadcast::Receiver<MotionEvent>,
+) {
+    loop {
+        let (camera, payload) = match motion.recv().await {
+            Ok(MotionEvent::Started { camera, .. }) => (camera, "ON"),
+            Ok(MotionEvent::Stopped { camera }) => (camera, "OFF"),
+            Ok(MotionEvent::Updated { .. }) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+
+        let topic = config.topic(&format!("cameras/{camera}/motion"));
+        if let Err(err) = client.publish(topic, QoS::AtLeastOnce, true, payload).await {
+            tracing::warn!(camera, error = %err, "MQTT publish failed");
+        }
+    }
+}
+
+async fn next_tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,97 @@
+//! OpenAPI document for the routes under `/api/v1`, generated from the
+//! handlers and the types they take and return.
+
+use utoipa::{
+    openapi::{
+        security::{Http, HttpAuthScheme, SecurityScheme},
+        OpenApi as Document,
+    },
+    Modify, OpenApi,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "PiCam",
+        description = "Live streams, snapshots, recordings and control of the cameras attached to a PiCam backend. Every route is also served without the `/api/v1` prefix.",
+        license(name = "MIT")
+    ),
+    servers((url = "/api/v1")),
+    paths(
+        crate::stream_handler,
+        crate::camera_stream_handler,
+        crate::ws_handler,
+        crate::audio_handler,
+        crate::snapshot_handler,
+        crate::camera_snapshot_handler,
+        crate::preview_handler,
+        crate::camera_preview_handler,
+        crate::thumbnail_handler,
+        crate::camera_thumbnail_handler,
+        crate::cameras_handler,
+        crate::devices_handler,
+        crate::capabilities_handler,
+        crate::camera_capabilities_handler,
+        crate::pause_handler,
+        crate::camera_pause_handler,
+        crate::resume_handler,
+        crate::camera_resume_handler,
+        crate::controls_handler,
+        crate::camera_controls_handler,
+        crate::set_control_handler,
+        crate::camera_set_control_handler,
+        crate::ptz_handler,
+        crate::camera_ptz_handler,
+        crate::ptz_move_handler,
+        crate::camera_ptz_move_handler,
+        crate::focus_handler,
+        crate::camera_focus_handler,
+        crate::refocus_handler,
+        crate::camera_refocus_handler,
+        crate::recordings_handler,
+        crate::recording_handler,
+        crate::config_handler,
+        crate::update_config_handler,
+        crate::reload_config_handler,
+        crate::create_link_handler,
+        crate::audit_handler,
+        crate::stats_handler,
+        crate::metrics_handler,
+        crate::events_handler,
+        crate::health_handler,
+        crate::liveness_handler,
+        crate::readiness_handler,
+    ),
+    // Query parameters only refer to their schemas.
+    components(schemas(crate::snapshot_format::SnapshotFormat, crate::audit::AccessKind)),
+    modifiers(&Credentials),
+    security(("bearer" = []), ("basic" = []))
+)]
+pub struct ApiDoc;
+
+/// Declares the schemes `AUTH_TOKEN`, JWTs and `AUTH_USER` are checked with.
+struct Credentials;
+
+impl Modify for Credentials {
+    fn modify(&self, openapi: &mut Document) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        components.add_security_scheme(
+            "basic",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+    }
+}
+
+/// The document, with its server URL under `base_path` (`BASE_PATH`, or
+/// empty).
+pub fn document(base_path: &str) -> Document {
+    let mut document = ApiDoc::openapi();
+    for server in document.servers.iter_mut().flatten() {
+        server.url = format!("{base_path}{}", server.url);
+    }
+    document
+}
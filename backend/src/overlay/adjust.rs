@@ -0,0 +1,61 @@
+use image::RgbImage;
+
+/// Software brightness, contrast and saturation for cameras whose own
+/// controls are missing or useless. Each is -100 to 100, 0 leaving the image
+/// as captured.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adjustments {
+    pub brightness: i16,
+    pub contrast: i16,
+    pub saturation: i16,
+    pub grayscale: bool,
+}
+
+impl Adjustments {
+    pub fn is_neutral(&self) -> bool {
+        self.brightness == 0 && self.contrast == 0 && self.saturation == 0 && !self.grayscale
+    }
+
+    pub fn apply(&self, image: &mut RgbImage) {
+        let table = self.tone_table();
+        // -100 removes all colour, 100 doubles its distance from grey.
+        let saturation = if self.grayscale {
+            0.0
+        } else {
+            1.0 + f32::from(self.saturation) / 100.0
+        };
+
+        for pixel in image.pixels_mut() {
+            let [r, g, b] = pixel.0.map(|channel| table[usize::from(channel)]);
+            if saturation == 1.0 {
+                pixel.0 = [r, g, b];
+                continue;
+            }
+            let luma = 0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b);
+            pixel.0 = [r, g, b].map(|channel| {
+                (luma + (f32::from(channel) - luma) * saturation)
+                    .round()
+                    .clamp(0.0, 255.0) as u8
+            });
+        }
+    }
+
+    /// Brightness then contrast for every channel value, so each pixel
+    /// costs a lookup instead of the arithmetic.
+    fn tone_table(&self) -> [u8; 256] {
+        // 100 shifts by half the range; contrast stretches around mid-grey,
+        // -100 flattening to it and 100 quadrupling the slope.
+        let offset = f32::from(self.brightness) * 1.275;
+        let slope = if self.contrast >= 0 {
+            1.0 + 3.0 * f32::from(self.contrast) / 100.0
+        } else {
+            1.0 + f32::from(self.contrast) / 100.0
+        };
+        std::array::from_fn(|value| {
+            let brightened = value as f32 + offset;
+            ((brightened - 127.5) * slope + 127.5)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        })
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{Context, Result};
+use image::{
+    imageops::{self, FilterType},
+    RgbImage,
+};
+
+use crate::{config::PrivacyBlurConfig, detection::Model};
+
+/// Boxes grow by this fraction of their size on every side, as detectors
+/// tend to cut off hair, hands and the edges of a moving person.
+const MARGIN: f32 = 0.1;
+/// Detail left across the shorter side of a blurred box.
+const CELLS_ACROSS: u32 = 4;
+const MIN_CELL_SIZE: u32 = 8;
+
+/// Runs a detection model on every frame and blurs what it finds, so faces
+/// and people never reach viewers or recordings.
+pub struct PrivacyBlur {
+    model: Model,
+    confidence: f32,
+    labels: Vec<String>,
+}
+
+impl PrivacyBlur {
+    pub fn load(config: &PrivacyBlurConfig) -> Result<Self> {
+        let model = Model::load(&config.model)?;
+        tracing::info!(model = %config.model.display(), "Privacy blur model loaded");
+        Ok(Self {
+            model,
+            confidence: config.confidence,
+            labels: config.labels.clone(),
+        })
+    }
+
+    /// Blurs every matching object in `image`.
+    pub fn apply(&self, image: &mut RgbImage) -> Result<()> {
+        let detections = self
+            .model
+            .detect(image, self.confidence)
+            .context("Privacy blur detection failed")?;
+
+        let (width, height) = image.dimensions();
+        for detection in detections
+            .iter()
+            .filter(|detection| self.labels.contains(&detection.label))
+        {
+            let [x, y, w, h] = detection.bbox;
+            let left = ((x - w * MARGIN) * width as f32).max(0.0) as u32;
+            let top = ((y - h * MARGIN) * height as f32).max(0.0) as u32;
+            let right = (((x + w * (1.0 + MARGIN)) * width as f32).ceil() as u32).min(width);
+            let bottom = (((y + h * (1.0 + MARGIN)) * height as f32).ceil() as u32).min(height);
+            if right <= left || bottom <= top {
+                continue;
+            }
+            blur(image, left, top, right - left, bottom - top);
+        }
+        Ok(())
+    }
+}
+
+/// Shrinks the region to a few cells and stretches it back, which smears it
+/// far beyond recognition at any resolution.
+fn blur(image: &mut RgbImage, left: u32, top: u32, width: u32, height: u32) {
+    let cell = (width.min(height) / CELLS_ACROSS).max(MIN_CELL_SIZE);
+    let region = imageops::crop_imm(image, left, top, width, height).to_image();
+    let small = imageops::resize(
+        &region,
+        width.div_ceil(cell),
+        height.div_ceil(cell),
+        FilterType::Triangle,
+    );
+    let smeared = imageops::resize(&small, width, height, FilterType::Triangle);
+    imageops::replace(image, &smeared, i64::from(left), i64::from(top));
+}
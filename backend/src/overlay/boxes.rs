@@ -0,0 +1,52 @@
+use image::{Rgb, RgbImage};
+
+use super::{darken, draw_text, fill, GLYPH_HEIGHT, GLYPH_WIDTH, SCALE_STEP_HEIGHT};
+use crate::detection::Detection;
+
+const BOX_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+
+/// Outlines every detection and tags it with its label and confidence, for
+/// checking what the model sees.
+pub fn draw_detections(image: &mut RgbImage, detections: &[Detection]) {
+    let (width, height) = image.dimensions();
+    let scale = (height / SCALE_STEP_HEIGHT).max(1);
+    let thickness = 2 * scale;
+
+    for detection in detections {
+        let [x, y, w, h] = detection.bbox;
+        let left = ((x * width as f32) as u32).min(width - 1);
+        let top = ((y * height as f32) as u32).min(height - 1);
+        let right = (((x + w) * width as f32) as u32).clamp(left + 1, width);
+        let bottom = (((y + h) * height as f32) as u32).clamp(top + 1, height);
+
+        for edge_x in (left..right).step_by(thickness as usize) {
+            fill(image, edge_x, top, thickness, BOX_COLOR);
+            fill(
+                image,
+                edge_x,
+                bottom.saturating_sub(thickness),
+                thickness,
+                BOX_COLOR,
+            );
+        }
+        for edge_y in (top..bottom).step_by(thickness as usize) {
+            fill(image, left, edge_y, thickness, BOX_COLOR);
+            fill(
+                image,
+                right.saturating_sub(thickness),
+                edge_y,
+                thickness,
+                BOX_COLOR,
+            );
+        }
+
+        let tag = format!("{} {:.0}%", detection.label, detection.confidence * 100.0);
+        let padding = scale;
+        let tag_width = tag.chars().count() as u32 * GLYPH_WIDTH * scale + 2 * padding;
+        let tag_height = GLYPH_HEIGHT * scale + 2 * padding;
+        // Above the box, or inside it when the box touches the top.
+        let tag_top = top.checked_sub(tag_height).unwrap_or(top);
+        darken(image, left, tag_top, tag_width, tag_height);
+        draw_text(image, &tag, left + padding, tag_top + padding, scale);
+    }
+}
@@ -0,0 +1,114 @@
+use image::RgbImage;
+
+use crate::config::{MaskStyle, PrivacyMask};
+
+/// Pixelation cells are at least this large, and grow with the frame so
+/// faces and text stay unreadable at any resolution.
+const MIN_CELL_SIZE: u32 = 16;
+const CELLS_PER_HEIGHT: u32 = 20;
+
+/// Hides every pixel whose centre lies inside one of `masks`.
+pub fn apply(image: &mut RgbImage, masks: &[PrivacyMask], style: MaskStyle) {
+    let (width, height) = image.dimensions();
+    let mut covered = vec![false; (width * height) as usize];
+
+    for mask in masks {
+        let points: Vec<(f32, f32)> = mask
+            .points
+            .iter()
+            .map(|[x, y]| (x * width as f32, y * height as f32))
+            .collect();
+        let (left, top, right, bottom) = bounds(&points, width, height);
+
+        for y in top..bottom {
+            for x in left..right {
+                if contains(&points, x as f32 + 0.5, y as f32 + 0.5) {
+                    covered[(y * width + x) as usize] = true;
+                }
+            }
+        }
+    }
+
+    match style {
+        MaskStyle::Black => {
+            for (pixel, covered) in image.pixels_mut().zip(&covered) {
+                if *covered {
+                    pixel.0 = [0, 0, 0];
+                }
+            }
+        }
+        MaskStyle::Pixelate => pixelate(image, &covered),
+    }
+}
+
+/// Replaces covered pixels with the average colour of their cell.
+fn pixelate(image: &mut RgbImage, covered: &[bool]) {
+    let (width, height) = image.dimensions();
+    let cell = (height / CELLS_PER_HEIGHT).max(MIN_CELL_SIZE);
+
+    for cell_top in (0..height).step_by(cell as usize) {
+        for cell_left in (0..width).step_by(cell as usize) {
+            let xs = cell_left..(cell_left + cell).min(width);
+            let ys = cell_top..(cell_top + cell).min(height);
+
+            let cell_covered = ys
+                .clone()
+                .any(|y| xs.clone().any(|x| covered[(y * width + x) as usize]));
+            if !cell_covered {
+                continue;
+            }
+
+            let mut sum = [0u64; 3];
+            let mut count = 0u64;
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    for (total, channel) in sum.iter_mut().zip(image.get_pixel(x, y).0) {
+                        *total += u64::from(channel);
+                    }
+                    count += 1;
+                }
+            }
+            let average = sum.map(|total| (total / count) as u8);
+
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    if covered[(y * width + x) as usize] {
+                        image.get_pixel_mut(x, y).0 = average;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Pixel bounding box of `points`, clamped to the frame.
+fn bounds(points: &[(f32, f32)], width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+    let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+    for &(x, y) in points {
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+    (
+        (min_x.floor().max(0.0) as u32).min(width),
+        (min_y.floor().max(0.0) as u32).min(height),
+        (max_x.ceil().max(0.0) as u32).min(width),
+        (max_y.ceil().max(0.0) as u32).min(height),
+    )
+}
+
+/// Even-odd point-in-polygon test.
+fn contains(points: &[(f32, f32)], x: f32, y: f32) -> bool {
+    let mut inside = false;
+    let mut previous = points[points.len() - 1];
+    for &current in points {
+        let ((x1, y1), (x2, y2)) = (previous, current);
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+        previous = current;
+    }
+    inside
+}
@@ -0,0 +1,341 @@
+mod adjust;
+mod blur;
+mod boxes;
+mod font;
+mod mask;
+
+use std::{sync::Arc, time::SystemTime};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use image::{Rgb, RgbImage, RgbaImage};
+use tokio::sync::watch;
+
+use crate::{
+    config::{Config, MaskStyle, OverlayCorner, PrivacyMask},
+    jpeg,
+    load::Load,
+};
+use adjust::Adjustments;
+pub use blur::PrivacyBlur;
+pub use boxes::draw_detections;
+use font::{GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// Glyphs are scaled up by one step per this many rows of frame height.
+const SCALE_STEP_HEIGHT: u32 = 240;
+const TEXT_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+const PLACEHOLDER_COLOR: Rgb<u8> = Rgb([48, 48, 48]);
+
+/// Current overlay for the capture loops; `None` while every overlay is off.
+/// `PUT /config` swaps it without restarting capture.
+pub type OverlayReceiver = watch::Receiver<Option<Arc<Overlay>>>;
+
+/// Privacy blur and image adjustments, then privacy masks, caption,
+/// timestamp, throttling warning and watermark drawn onto every frame before
+/// it is broadcast. Frames are only decoded and re-encoded when at least one
+/// of them is enabled, and for the warning only while it shows.
+pub struct Overlay {
+    blur: Option<Arc<PrivacyBlur>>,
+    adjustments: Adjustments,
+    masks: Vec<PrivacyMask>,
+    mask_style: MaskStyle,
+    caption: Option<String>,
+    timestamp_format: Option<String>,
+    /// Read for the throttling warning, when it is enabled.
+    load: Option<Arc<Load>>,
+    label_corner: OverlayCorner,
+    watermark: Option<(RgbaImage, OverlayCorner)>,
+    quality: u8,
+}
+
+impl Overlay {
+    /// `blur` is loaded once by the caller, as the model is too slow to load
+    /// again whenever the overlay changes.
+    pub fn from_config(
+        config: &Config,
+        blur: Option<Arc<PrivacyBlur>>,
+        load: &Arc<Load>,
+    ) -> Result<Option<Self>> {
+        let watermark = match (&config.overlay_watermark_path, config.overlay_watermark) {
+            (Some(path), true) => {
+                let image = image::open(path)
+                    .with_context(|| format!("Failed to load watermark {}", path.display()))?
+                    .to_rgba8();
+                Some((image, config.overlay_watermark_corner))
+            }
+            _ => None,
+        };
+
+        let overlay = Self {
+            blur,
+            adjustments: Adjustments {
+                brightness: config.image_brightness,
+                contrast: config.image_contrast,
+                saturation: config.image_saturation,
+                grayscale: config.image_grayscale,
+            },
+            masks: config.privacy_masks.clone(),
+            mask_style: config.privacy_mask_style,
+            caption: config.overlay_caption.clone(),
+            timestamp_format: config
+                .overlay_timestamp
+                .then(|| config.overlay_timestamp_format.clone()),
+            load: config.overlay_throttle_warning.then(|| load.clone()),
+            label_corner: config.overlay_corner,
+            watermark,
+            quality: config.jpeg_quality,
+        };
+
+        let enabled = overlay.hides_regions()
+            || !overlay.adjustments.is_neutral()
+            || overlay.caption.is_some()
+            || overlay.timestamp_format.is_some()
+            || overlay.load.is_some()
+            || overlay.watermark.is_some();
+        Ok(enabled.then_some(overlay))
+    }
+
+    /// With masks or the privacy blur set, frames that fail to render must
+    /// be dropped, not sent as captured.
+    pub fn hides_regions(&self) -> bool {
+        !self.masks.is_empty() || self.blur.is_some()
+    }
+
+    /// Whether frames need drawing on right now; an overlay that only warns
+    /// about throttling leaves frames alone while the SoC is not throttled.
+    pub fn is_active(&self) -> bool {
+        self.hides_regions()
+            || !self.adjustments.is_neutral()
+            || self.caption.is_some()
+            || self.timestamp_format.is_some()
+            || self.watermark.is_some()
+            || self.throttle_warning().is_some()
+    }
+
+    /// `SoC throttled: under voltage, throttled (81.5C)` while the Pi
+    /// firmware throttles the SoC and the warning is enabled.
+    fn throttle_warning(&self) -> Option<String> {
+        let reading = self.load.as_ref()?.reading();
+        let now = reading
+            .throttling
+            .filter(|throttling| throttling.now.any())?
+            .now;
+        let conditions: Vec<String> = now
+            .conditions()
+            .into_iter()
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| name.replace('_', " "))
+            .collect();
+        let mut warning = format!("SoC throttled: {}", conditions.join(", "));
+        if let Some(temperature) = reading.temperature_celsius {
+            warning.push_str(&format!(" ({temperature:.1}C)"));
+        }
+        Some(warning)
+    }
+
+    pub fn apply(&self, frame: &[u8], captured_at: SystemTime) -> Result<Vec<u8>> {
+        let mut image = jpeg::decode_rgb(frame).context("Failed to decode frame for overlay")?;
+
+        // Detected on the frame as captured, which the model knows best.
+        if let Some(blur) = &self.blur {
+            blur.apply(&mut image)?;
+        }
+
+        // Adjusted first, so masks stay black and labels white.
+        if !self.adjustments.is_neutral() {
+            self.adjustments.apply(&mut image);
+        }
+
+        if !self.masks.is_empty() {
+            mask::apply(&mut image, &self.masks, self.mask_style);
+        }
+
+        if let Some((watermark, corner)) = &self.watermark {
+            draw_watermark(&mut image, watermark, *corner);
+        }
+
+        let mut lines = Vec::new();
+        if let Some(caption) = &self.caption {
+            lines.push(caption.clone());
+        }
+        if let Some(format) = &self.timestamp_format {
+            lines.push(
+                DateTime::<Local>::from(captured_at)
+                    .format(format)
+                    .to_string(),
+            );
+        }
+        if let Some(warning) = self.throttle_warning() {
+            lines.push(warning);
+        }
+        if !lines.is_empty() {
+            draw_label(&mut image, &lines, self.label_corner);
+        }
+
+        jpeg::encode_rgb(&image, self.quality).context("Failed to encode frame after overlay")
+    }
+}
+
+/// A dark frame with `heading` in large type and `lines` below it, all
+/// centred, standing in for frames a camera failed to deliver. Lines too
+/// long for the frame are cut short.
+pub fn placeholder(
+    width: u32,
+    height: u32,
+    quality: u8,
+    heading: &str,
+    lines: &[String],
+) -> Result<Vec<u8>> {
+    let mut image = RgbImage::from_pixel(width, height, PLACEHOLDER_COLOR);
+    let scale = (height / SCALE_STEP_HEIGHT).max(1);
+    let text = std::iter::once((heading, 2 * scale))
+        .chain(lines.iter().map(|line| (line.as_str(), scale)));
+    let total_height: u32 = text
+        .clone()
+        .map(|(_, scale)| 2 * GLYPH_HEIGHT * scale)
+        .sum();
+
+    let mut top = height.saturating_sub(total_height) / 2;
+    for (line, scale) in text {
+        let fits = (width.saturating_sub(8 * scale) / (GLYPH_WIDTH * scale)) as usize;
+        let line: String = if line.chars().count() > fits {
+            let mut shortened: String = line.chars().take(fits.saturating_sub(3)).collect();
+            shortened.push_str("...");
+            shortened
+        } else {
+            line.to_string()
+        };
+        let line_width = line.chars().count() as u32 * GLYPH_WIDTH * scale;
+        draw_text(
+            &mut image,
+            &line,
+            width.saturating_sub(line_width) / 2,
+            top,
+            scale,
+        );
+        top += 2 * GLYPH_HEIGHT * scale;
+    }
+
+    jpeg::encode_rgb(&image, quality).context("Failed to encode placeholder frame")
+}
+
+/// `frame` with `lines` in a label in `corner`, re-encoded at `quality`;
+/// marks the frames of a single viewer's stream.
+pub fn stamp(
+    frame: &[u8],
+    lines: &[String],
+    corner: OverlayCorner,
+    quality: u8,
+) -> Result<Vec<u8>> {
+    let mut image = jpeg::decode_rgb(frame).context("Failed to decode frame for stamping")?;
+    draw_label(&mut image, lines, corner);
+    jpeg::encode_rgb(&image, quality).context("Failed to encode stamped frame")
+}
+
+/// Left/top position of a `width` x `height` box placed in `corner`.
+fn place(
+    image: &RgbImage,
+    width: u32,
+    height: u32,
+    margin: u32,
+    corner: OverlayCorner,
+) -> (u32, u32) {
+    let left = match corner {
+        OverlayCorner::TopLeft | OverlayCorner::BottomLeft => margin,
+        OverlayCorner::TopRight | OverlayCorner::BottomRight => {
+            image.width().saturating_sub(width + margin)
+        }
+    };
+    let top = match corner {
+        OverlayCorner::TopLeft | OverlayCorner::TopRight => margin,
+        OverlayCorner::BottomLeft | OverlayCorner::BottomRight => {
+            image.height().saturating_sub(height + margin)
+        }
+    };
+    (left, top)
+}
+
+/// Draws `lines` in white on a darkened box in the given corner.
+fn draw_label(image: &mut RgbImage, lines: &[String], corner: OverlayCorner) {
+    let scale = (image.height() / SCALE_STEP_HEIGHT).max(1);
+    let padding = 2 * scale;
+    let line_height = GLYPH_HEIGHT * scale;
+
+    let longest = lines
+        .iter()
+        .map(|line| line.chars().count() as u32)
+        .max()
+        .unwrap_or(0);
+    let box_width = longest * GLYPH_WIDTH * scale + 2 * padding;
+    let box_height = lines.len() as u32 * line_height + 2 * padding;
+    let (left, top) = place(image, box_width, box_height, 4 * scale, corner);
+
+    darken(image, left, top, box_width, box_height);
+
+    for (line_index, line) in lines.iter().enumerate() {
+        let line_top = top + padding + line_index as u32 * line_height;
+        draw_text(image, line, left + padding, line_top, scale);
+    }
+}
+
+/// Draws `text` in white with its top left corner at `left`, `top`.
+fn draw_text(image: &mut RgbImage, text: &str, left: u32, top: u32, scale: u32) {
+    for (index, c) in text.chars().enumerate() {
+        let glyph_left = left + index as u32 * GLYPH_WIDTH * scale;
+        for (row, bits) in font::glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                let x = glyph_left + column * scale;
+                let y = top + row as u32 * scale;
+                fill(image, x, y, scale, TEXT_COLOR);
+            }
+        }
+    }
+}
+
+/// Alpha-blends `watermark` at its native size into the given corner.
+fn draw_watermark(image: &mut RgbImage, watermark: &RgbaImage, corner: OverlayCorner) {
+    let scale = (image.height() / SCALE_STEP_HEIGHT).max(1);
+    let (left, top) = place(
+        image,
+        watermark.width(),
+        watermark.height(),
+        4 * scale,
+        corner,
+    );
+
+    for (x, y, source) in watermark.enumerate_pixels() {
+        let (x, y) = (left + x, top + y);
+        if x >= image.width() || y >= image.height() {
+            continue;
+        }
+        let alpha = u16::from(source.0[3]);
+        let target = image.get_pixel_mut(x, y);
+        for channel in 0..3 {
+            let blended = (u16::from(source.0[channel]) * alpha
+                + u16::from(target.0[channel]) * (255 - alpha))
+                / 255;
+            target.0[channel] = blended as u8;
+        }
+    }
+}
+
+/// Dims a box so white text on it stays readable.
+fn darken(image: &mut RgbImage, left: u32, top: u32, width: u32, height: u32) {
+    for y in top..(top + height).min(image.height()) {
+        for x in left..(left + width).min(image.width()) {
+            let pixel = image.get_pixel_mut(x, y);
+            pixel.0 = pixel.0.map(|channel| channel / 3);
+        }
+    }
+}
+
+fn fill(image: &mut RgbImage, left: u32, top: u32, size: u32, color: Rgb<u8>) {
+    for y in top..(top + size).min(image.height()) {
+        for x in left..(left + size).min(image.width()) {
+            image.put_pixel(x, y, color);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! "Camera offline" pictures sent to MJPEG viewers while captures fail, and
+//! "Camera paused" or "Camera off duty" ones while the device is closed on
+//! purpose, so browsers keep showing a valid stream instead of a broken
+//! image.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use chrono::Local;
+use tokio::{sync::Mutex, task};
+
+use crate::{camera::CameraError, capture::CameraHandle, overlay};
+
+/// Placeholders show the time to the second, so they are redrawn once it
+/// has moved on.
+const MAX_AGE: Duration = Duration::from_secs(1);
+
+/// The last placeholder per camera, shared by all of its viewers.
+pub struct Placeholders {
+    cameras: Vec<Mutex<Option<Placeholder>>>,
+}
+
+struct Placeholder {
+    data: Bytes,
+    title: &'static str,
+    size: (u32, u32),
+    quality: u8,
+    rendered_at: Instant,
+}
+
+impl Placeholders {
+    pub fn new(cameras: usize) -> Self {
+        Self {
+            cameras: (0..cameras).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    /// A `width` x `height` picture at JPEG `quality` saying why the camera
+    /// delivers no frames, with the reason or its last error and the
+    /// current time.
+    pub async fn get(
+        &self,
+        camera: usize,
+        handle: &CameraHandle,
+        error: &CameraError,
+        width: u32,
+        height: u32,
+        quality: u8,
+    ) -> Result<Bytes> {
+        let (title, detail) = match error {
+            CameraError::OffDuty => ("Camera off duty", Some(error.to_string())),
+            CameraError::Paused => ("Camera paused", None),
+            _ => ("Camera offline", handle.last_error()),
+        };
+        let mut cached = self.cameras[camera].lock().await;
+        if let Some(placeholder) = cached.as_ref() {
+            if placeholder.title == title
+                && placeholder.size == (width, height)
+                && placeholder.quality == quality
+                && placeholder.rendered_at.elapsed() < MAX_AGE
+            {
+                return Ok(placeholder.data.clone());
+            }
+        }
+
+        let mut lines = Vec::new();
+        if let Some(detail) = detail {
+            lines.push(detail);
+        }
+        lines.push(Local::now().format("%Y-%m-%d %H:%M:%S").to_string());
+        let data = task::spawn_blocking(move || {
+            overlay::placeholder(width, height, quality, title, &lines)
+        })
+        .await
+        .expect("spawn_blocking failed")
+        .context("Failed to render placeholder")?;
+        let data = Bytes::from(data);
+
+        *cached = Some(Placeholder {
+            data: data.clone(),
+            title,
+            size: (width, height),
+            quality,
+            rendered_at: Instant::now(),
+        });
+        Ok(data)
+    }
+}
@@ -0,0 +1,116 @@
+//! Short animated previews for clients that cannot play MJPEG, such as chat
+//! integrations and dashboards that only embed images.
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, DynamicImage, Frame,
+};
+use tokio::{
+    sync::broadcast::error::RecvError,
+    task,
+    time::{self, Instant},
+};
+
+use crate::{
+    capture::{BufferedFrame, CameraHandle, FrameEvent, FRAME_TIMEOUT},
+    jpeg,
+};
+
+/// Length of the animation.
+const PREVIEW_DURATION: Duration = Duration::from_secs(3);
+/// Frames closer together than this are skipped, keeping the GIF small.
+const PREVIEW_FRAME_INTERVAL: Duration = Duration::from_millis(200);
+const PREVIEW_WIDTH: u32 = 320;
+/// NeuQuant sampling factor, from 1 (best) to 30 (fastest).
+const QUANTIZE_SPEED: i32 = 10;
+
+/// Renders the last few seconds of a camera as a looping GIF.
+///
+/// Uses the pre-motion buffer when recording keeps one; otherwise the
+/// preview is built from the frames of the next few seconds.
+pub async fn render_gif(handle: &CameraHandle) -> Result<Vec<u8>> {
+    let frames = match buffered_frames(handle) {
+        Some(frames) => frames,
+        None => live_frames(handle).await?,
+    };
+    task::spawn_blocking(move || encode_gif(&frames))
+        .await
+        .expect("spawn_blocking failed")
+}
+
+/// Frames from the last `PREVIEW_DURATION` of history, if it holds enough.
+fn buffered_frames(handle: &CameraHandle) -> Option<Vec<BufferedFrame>> {
+    let since = SystemTime::now() - PREVIEW_DURATION;
+    let frames: Vec<BufferedFrame> = handle
+        .history()
+        .recent()
+        .into_iter()
+        .filter(|frame| frame.captured_at >= since)
+        .collect();
+    let span = frames
+        .first()
+        .zip(frames.last())
+        .and_then(|(first, last)| last.captured_at.duration_since(first.captured_at).ok())?;
+    (span >= PREVIEW_DURATION / 2).then_some(frames)
+}
+
+async fn live_frames(handle: &CameraHandle) -> Result<Vec<BufferedFrame>> {
+    let mut frames = vec![handle.next_frame(FRAME_TIMEOUT).await?];
+    let mut events = handle.subscribe();
+    let deadline = Instant::now() + PREVIEW_DURATION;
+
+    loop {
+        let event = match time::timeout_at(deadline, events.recv()).await {
+            Ok(Ok(event)) => event,
+            Ok(Err(RecvError::Lagged(_))) => continue,
+            Ok(Err(RecvError::Closed)) | Err(_) => break,
+        };
+        if let FrameEvent::Frame {
+            data, captured_at, ..
+        } = event
+        {
+            frames.push(BufferedFrame { data, captured_at });
+        }
+    }
+    Ok(frames)
+}
+
+fn encode_gif(frames: &[BufferedFrame]) -> Result<Vec<u8>> {
+    let mut selected: Vec<&BufferedFrame> = Vec::new();
+    for frame in frames {
+        let due = selected.last().is_none_or(|previous| {
+            frame
+                .captured_at
+                .duration_since(previous.captured_at)
+                .is_ok_and(|gap| gap >= PREVIEW_FRAME_INTERVAL)
+        });
+        if due {
+            selected.push(frame);
+        }
+    }
+
+    let mut gif = Vec::new();
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut gif, QUANTIZE_SPEED);
+        encoder.set_repeat(Repeat::Infinite)?;
+        for (index, frame) in selected.iter().enumerate() {
+            // The last frame has no successor to time it by.
+            let shown_for = selected
+                .get(index + 1)
+                .and_then(|next| next.captured_at.duration_since(frame.captured_at).ok())
+                .unwrap_or(PREVIEW_FRAME_INTERVAL);
+            let image =
+                DynamicImage::from(jpeg::decode_scaled(&frame.data, PREVIEW_WIDTH)?).to_rgba8();
+            encoder.encode_frame(Frame::from_parts(
+                image,
+                0,
+                0,
+                Delay::from_saturating_duration(shown_for),
+            ))?;
+        }
+    }
+    Ok(gif)
+}
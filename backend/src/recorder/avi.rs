@@ -0,0 +1,186 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+// Byte offsets of the header fields patched once the clip is complete.
+const RIFF_SIZE_OFFSET: u64 = 4;
+const AVIH_MICROS_PER_FRAME_OFFSET: u64 = 32;
+const AVIH_TOTAL_FRAMES_OFFSET: u64 = 48;
+const STRH_LENGTH_OFFSET: u64 = 140;
+const MOVI_SIZE_OFFSET: u64 = 216;
+/// Position of the `movi` fourcc; index offsets are relative to it.
+const MOVI_FOURCC_OFFSET: u32 = 220;
+const HEADER_LEN: u32 = 224;
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+/// Minimal Motion-JPEG AVI writer: one video stream, every frame a keyframe,
+/// with an `idx1` index so players can seek.
+pub struct AviWriter {
+    file: BufWriter<File>,
+    index: Vec<(u32, u32)>,
+    position: u32,
+}
+
+impl AviWriter {
+    pub fn create(path: &Path, width: u32, height: u32, frame_rate: f32) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        let mut writer = Self {
+            file: BufWriter::new(file),
+            index: Vec::new(),
+            position: HEADER_LEN,
+        };
+        writer.write_header(width, height, frame_rate)?;
+        Ok(writer)
+    }
+
+    pub fn write_frame(&mut self, jpeg: &[u8]) -> Result<()> {
+        let size = u32::try_from(jpeg.len()).context("Frame too large for AVI")?;
+        self.index.push((self.position - MOVI_FOURCC_OFFSET, size));
+
+        self.file.write_all(b"00dc")?;
+        self.file.write_all(&size.to_le_bytes())?;
+        self.file.write_all(jpeg)?;
+        let padding = size % 2;
+        if padding == 1 {
+            self.file.write_all(&[0])?;
+        }
+
+        self.position += 8 + size + padding;
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Writes the index and patches the sizes and frame counts in the header.
+    pub fn finish(mut self) -> Result<()> {
+        let movi_size = self.position - MOVI_FOURCC_OFFSET;
+        let frames = self.index.len() as u32;
+
+        self.file.write_all(b"idx1")?;
+        self.file.write_all(&(frames * 16).to_le_bytes())?;
+        for (offset, size) in &self.index {
+            self.file.write_all(b"00dc")?;
+            self.file.write_all(&AVIIF_KEYFRAME.to_le_bytes())?;
+            self.file.write_all(&offset.to_le_bytes())?;
+            self.file.write_all(&size.to_le_bytes())?;
+        }
+        let file_len = self.position + 8 + frames * 16;
+
+        for (offset, value) in [
+            (RIFF_SIZE_OFFSET, file_len - 8),
+            (AVIH_TOTAL_FRAMES_OFFSET, frames),
+            (STRH_LENGTH_OFFSET, frames),
+            (MOVI_SIZE_OFFSET, movi_size),
+        ] {
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+
+        self.file.flush()?;
+        self.file
+            .get_ref()
+            .sync_all()
+            .context("Failed to sync recording")
+    }
+
+    fn write_header(&mut self, width: u32, height: u32, frame_rate: f32) -> Result<()> {
+        let rate = (frame_rate * 1000.0).round() as u32;
+        let micros_per_frame = (1_000_000.0 / frame_rate).round() as u32;
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+
+        header.extend_from_slice(b"RIFF");
+        push_u32(&mut header, 0); // patched in finish()
+        header.extend_from_slice(b"AVI ");
+
+        header.extend_from_slice(b"LIST");
+        push_u32(&mut header, 192);
+        header.extend_from_slice(b"hdrl");
+
+        header.extend_from_slice(b"avih");
+        push_u32(&mut header, 56);
+        push_u32(&mut header, micros_per_frame);
+        push_u32(&mut header, 0); // max bytes per second
+        push_u32(&mut header, 0); // padding granularity
+        push_u32(&mut header, AVIF_HASINDEX);
+        push_u32(&mut header, 0); // total frames, patched in finish()
+        push_u32(&mut header, 0); // initial frames
+        push_u32(&mut header, 1); // streams
+        push_u32(&mut header, 0); // suggested buffer size
+        push_u32(&mut header, width);
+        push_u32(&mut header, height);
+        header.extend_from_slice(&[0; 16]);
+
+        header.extend_from_slice(b"LIST");
+        push_u32(&mut header, 116);
+        header.extend_from_slice(b"strl");
+
+        header.extend_from_slice(b"strh");
+        push_u32(&mut header, 56);
+        header.extend_from_slice(b"vids");
+        header.extend_from_slice(b"MJPG");
+        push_u32(&mut header, 0); // flags
+        push_u32(&mut header, 0); // priority + language
+        push_u32(&mut header, 0); // initial frames
+        push_u32(&mut header, 1000); // scale
+        push_u32(&mut header, rate);
+        push_u32(&mut header, 0); // start
+        push_u32(&mut header, 0); // length, patched in finish()
+        push_u32(&mut header, 0); // suggested buffer size
+        push_u32(&mut header, u32::MAX); // quality: driver default
+        push_u32(&mut header, 0); // sample size
+        header.extend_from_slice(&[0; 4]); // frame rect left/top
+        header.extend_from_slice(&(width as u16).to_le_bytes());
+        header.extend_from_slice(&(height as u16).to_le_bytes());
+
+        header.extend_from_slice(b"strf");
+        push_u32(&mut header, 40);
+        push_u32(&mut header, 40);
+        push_u32(&mut header, width);
+        push_u32(&mut header, height);
+        header.extend_from_slice(&1u16.to_le_bytes()); // planes
+        header.extend_from_slice(&24u16.to_le_bytes()); // bit count
+        header.extend_from_slice(b"MJPG");
+        push_u32(&mut header, width * height * 3);
+        header.extend_from_slice(&[0; 16]);
+
+        header.extend_from_slice(b"LIST");
+        push_u32(&mut header, 0); // movi size, patched in finish()
+        header.extend_from_slice(b"movi");
+
+        debug_assert_eq!(header.len(), HEADER_LEN as usize);
+        self.file.write_all(&header)?;
+        Ok(())
+    }
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Length of a clip written by [`AviWriter`], or `None` while it is still
+/// being recorded.
+pub fn read_duration(path: &Path) -> Result<Option<Duration>> {
+    let mut header = [0; (AVIH_TOTAL_FRAMES_OFFSET + 4) as usize];
+    File::open(path)?.read_exact(&mut header)?;
+    let field = |offset: u64| {
+        let offset = offset as usize;
+        u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap())
+    };
+
+    let frames = field(AVIH_TOTAL_FRAMES_OFFSET);
+    let micros_per_frame = field(AVIH_MICROS_PER_FRAME_OFFSET);
+    Ok(
+        (frames > 0)
+            .then(|| Duration::from_micros(u64::from(frames) * u64::from(micros_per_frame))),
+    )
+}
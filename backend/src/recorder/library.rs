@@ -0,0 +1,111 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{avi, mp4};
+use crate::config::RecordingFormat;
+
+/// A clip in `RECORDINGS_DIR`, as listed by `/recordings`.
+#[derive(Serialize, ToSchema)]
+pub struct RecordingInfo {
+    /// File name, also used as the id in `/recordings/{id}`.
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera: Option<usize>,
+    pub format: RecordingFormat,
+    /// RFC 3339 start time, from the file name or else the file's mtime.
+    pub started_at: String,
+    /// `None` while the clip is still being recorded.
+    pub duration_secs: Option<f64>,
+    pub size: u64,
+    /// What started the clip; only motion triggers recordings so far.
+    pub trigger: &'static str,
+}
+
+/// Format of a clip judged by its extension, or `None` for other files.
+pub fn clip_format(path: &Path) -> Option<RecordingFormat> {
+    match path.extension()?.to_str()? {
+        "mp4" => Some(RecordingFormat::Mp4),
+        "avi" => Some(RecordingFormat::Avi),
+        _ => None,
+    }
+}
+
+/// Clips in `dir`, newest first.
+pub fn list_recordings(dir: &Path) -> io::Result<Vec<RecordingInfo>> {
+    let mut recordings = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        let (Some(format), Some(id)) = (clip_format(&path), path.file_name()) else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let id = id.to_string_lossy().into_owned();
+        let (camera, started_at) = parse_name(&id);
+        let started_at = match started_at {
+            Some(started_at) => started_at,
+            None => DateTime::<Local>::from(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)),
+        };
+        let duration = match format {
+            RecordingFormat::Mp4 => mp4::read_duration(&path),
+            RecordingFormat::Avi => avi::read_duration(&path),
+        };
+        let duration_secs = match duration {
+            Ok(duration) => duration.map(|duration| duration.as_secs_f64()),
+            Err(err) => {
+                tracing::debug!(path = %path.display(), error = %err, "Could not read clip duration");
+                None
+            }
+        };
+
+        let info = RecordingInfo {
+            id,
+            camera,
+            format,
+            started_at: started_at.to_rfc3339(),
+            duration_secs,
+            size: metadata.len(),
+            trigger: "motion",
+        };
+        recordings.push((started_at, info));
+    }
+
+    recordings.sort_by_key(|(started_at, _)| std::cmp::Reverse(*started_at));
+    Ok(recordings.into_iter().map(|(_, info)| info).collect())
+}
+
+/// Path of the clip named `id`, refusing anything but a plain clip file name.
+pub fn recording_path(dir: &Path, id: &str) -> Option<PathBuf> {
+    let valid = !id.is_empty()
+        && !id.starts_with('.')
+        && !id.contains(['/', '\\'])
+        && clip_format(Path::new(id)).is_some();
+    valid.then(|| dir.join(id)).filter(|path| path.is_file())
+}
+
+/// Splits `cam{id}-{YYYYmmdd-HHMMSS}.{ext}`, as written by the recorder.
+fn parse_name(name: &str) -> (Option<usize>, Option<DateTime<Local>>) {
+    let Some((camera, timestamp)) = name
+        .split_once('.')
+        .and_then(|(stem, _)| stem.strip_prefix("cam"))
+        .and_then(|rest| rest.split_once('-'))
+    else {
+        return (None, None);
+    };
+
+    let started_at = NaiveDateTime::parse_from_str(timestamp, "%Y%m%d-%H%M%S")
+        .ok()
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest());
+    (camera.parse().ok(), started_at)
+}
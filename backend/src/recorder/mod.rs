@@ -0,0 +1,258 @@
+mod avi;
+mod library;
+mod mp4;
+mod retention;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use chrono::Local;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        RwLock,
+    },
+    task,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    capture::{CameraHandle, FrameEvent, FrameHistory},
+    config::{Config, RecordingFormat},
+    motion::MotionEvent,
+    shutdown::DrainGuard,
+};
+
+use avi::AviWriter;
+pub use library::{list_recordings, recording_path, RecordingInfo};
+use mp4::Mp4Writer;
+pub use retention::spawn_retention;
+
+enum ClipWriter {
+    Avi(AviWriter),
+    Mp4(Mp4Writer),
+}
+
+impl ClipWriter {
+    fn create(
+        format: RecordingFormat,
+        path: &Path,
+        width: u32,
+        height: u32,
+        frame_rate: f32,
+    ) -> anyhow::Result<Self> {
+        Ok(match format {
+            RecordingFormat::Avi => Self::Avi(AviWriter::create(path, width, height, frame_rate)?),
+            RecordingFormat::Mp4 => Self::Mp4(Mp4Writer::create(path, width, height, frame_rate)?),
+        })
+    }
+
+    fn write_frame(&mut self, jpeg: &[u8], captured_at: SystemTime) -> anyhow::Result<()> {
+        match self {
+            Self::Avi(writer) => writer.write_frame(jpeg),
+            Self::Mp4(writer) => writer.write_frame(jpeg, captured_at),
+        }
+    }
+
+    fn frame_count(&self) -> usize {
+        match self {
+            Self::Avi(writer) => writer.frame_count(),
+            Self::Mp4(writer) => writer.frame_count(),
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Self::Avi(writer) => writer.finish(),
+            Self::Mp4(writer) => writer.finish(),
+        }
+    }
+}
+
+struct Recording {
+    writer: ClipWriter,
+    path: PathBuf,
+    /// Set once motion stops; the clip closes when this passes.
+    stop_at: Option<Instant>,
+    /// Capture time of the newest frame written, used to skip frames that
+    /// were already taken from the pre-event buffer.
+    last_captured_at: Option<SystemTime>,
+}
+
+impl Recording {
+    fn write(&mut self, data: &[u8], captured_at: SystemTime) -> anyhow::Result<()> {
+        if self
+            .last_captured_at
+            .is_some_and(|last| captured_at <= last)
+        {
+            return Ok(());
+        }
+        task::block_in_place(|| self.writer.write_frame(data, captured_at))?;
+        self.last_captured_at = Some(captured_at);
+        Ok(())
+    }
+}
+
+/// Announced by the recorders on the channel passed to [`spawn`].
+#[derive(Clone, Debug)]
+pub enum RecordingEvent {
+    /// Motion opened a new clip, which grows until it is finished.
+    Started { camera: usize, path: PathBuf },
+    /// A clip was closed and is complete on disk.
+    Finished { camera: usize, path: PathBuf },
+}
+
+/// Records motion-triggered MJPEG clips for one camera into `dir`. A clip
+/// still open at shutdown is finished, so it stays playable.
+pub fn spawn(
+    camera: usize,
+    handle: &CameraHandle,
+    mut motion: broadcast::Receiver<MotionEvent>,
+    dir: PathBuf,
+    config: Arc<RwLock<Config>>,
+    events: broadcast::Sender<RecordingEvent>,
+    shutdown: DrainGuard,
+) {
+    let mut frames = handle.subscribe();
+    let history = handle.history();
+    tokio::spawn(async move {
+        let mut recording: Option<Recording> = None;
+
+        loop {
+            let stop_at = recording.as_ref().and_then(|recording| recording.stop_at);
+
+            tokio::select! {
+                event = motion.recv() => match event {
+                    Ok(event) if event.camera() != camera => {}
+                    Ok(MotionEvent::Started { .. }) => match recording.as_mut() {
+                        Some(recording) => recording.stop_at = None,
+                        None => recording = start_clip(camera, &dir, &config, &history, &events).await,
+                    },
+                    Ok(MotionEvent::Updated { .. }) => {}
+                    Ok(MotionEvent::Stopped { .. }) => {
+                        if let Some(recording) = recording.as_mut() {
+                            let post_motion = config.read().await.recording_post_motion();
+                            recording.stop_at = Some(Instant::now() + post_motion);
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                },
+                frame = frames.recv() => match frame {
+                    Ok(FrameEvent::Frame {
+                        data, captured_at, ..
+                    }) => {
+                        if let Some(active) = recording.as_mut() {
+                            if let Err(err) = active.write(&data, captured_at) {
+                                tracing::error!(camera, path = %active.path.display(), error = %err, "Writing recording failed");
+                                if let Some(failed) = recording.take() {
+                                    finish_clip(failed, camera, Some(&events));
+                                }
+                            }
+                        }
+                    }
+                    Ok(FrameEvent::Error(_)) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                },
+                _ = sleep_until(stop_at.unwrap_or_else(Instant::now)), if stop_at.is_some() => {
+                    if let Some(finished) = recording.take() {
+                        finish_clip(finished, camera, Some(&events));
+                    }
+                }
+                () = shutdown.requested() => break,
+            }
+        }
+
+        if let Some(unfinished) = recording.take() {
+            finish_clip(unfinished, camera, Some(&events));
+        }
+    });
+}
+
+async fn start_clip(
+    camera: usize,
+    dir: &Path,
+    config: &RwLock<Config>,
+    history: &FrameHistory,
+    events: &broadcast::Sender<RecordingEvent>,
+) -> Option<Recording> {
+    let (format, width, height, frame_rate) = {
+        let config = config.read().await;
+        (
+            config.recording_format,
+            config.resolution_width,
+            config.resolution_height,
+            config.frame_rate,
+        )
+    };
+
+    let filename = format!(
+        "cam{camera}-{}.{}",
+        Local::now().format("%Y%m%d-%H%M%S"),
+        format.extension()
+    );
+    let path = dir.join(filename);
+
+    let writer =
+        match task::block_in_place(|| ClipWriter::create(format, &path, width, height, frame_rate))
+        {
+            Ok(writer) => writer,
+            Err(err) => {
+                tracing::error!(camera, error = %err, "Could not start recording");
+                return None;
+            }
+        };
+
+    let mut recording = Recording {
+        writer,
+        path,
+        stop_at: None,
+        last_captured_at: None,
+    };
+
+    let buffered = history.recent();
+    for frame in &buffered {
+        if let Err(err) = recording.write(&frame.data, frame.captured_at) {
+            tracing::error!(camera, path = %recording.path.display(), error = %err, "Writing recording failed");
+            finish_clip(recording, camera, None);
+            return None;
+        }
+    }
+
+    tracing::info!(
+        camera,
+        path = %recording.path.display(),
+        pre_event_frames = buffered.len(),
+        "Recording started"
+    );
+    let _ = events.send(RecordingEvent::Started {
+        camera,
+        path: recording.path.clone(),
+    });
+    Some(recording)
+}
+
+fn finish_clip(
+    recording: Recording,
+    camera: usize,
+    events: Option<&broadcast::Sender<RecordingEvent>>,
+) {
+    let frames = recording.writer.frame_count();
+    match task::block_in_place(|| recording.writer.finish()) {
+        Ok(()) => {
+            tracing::info!(path = %recording.path.display(), frames, "Recording finished");
+            if let Some(events) = events {
+                let _ = events.send(RecordingEvent::Finished {
+                    camera,
+                    path: recording.path,
+                });
+            }
+        }
+        Err(err) => {
+            tracing::error!(path = %recording.path.display(), error = %err, "Finalizing recording failed")
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+/// Sample timestamps are stored in milliseconds.
+const TIMESCALE: u32 = 1000;
+/// MPEG-4 Systems object type for JPEG images (ISO/IEC 14496-1).
+const OBJECT_TYPE_JPEG: u8 = 0x6C;
+const STREAM_TYPE_VISUAL: u8 = 0x04;
+
+const FTYP: &[u8] = &[
+    0, 0, 0, 24, b'f', b't', b'y', b'p', b'i', b's', b'o', b'm', 0, 0, 2, 0, b'i', b's', b'o',
+    b'm', b'm', b'p', b'4', b'1',
+];
+const MDAT_SIZE_OFFSET: u64 = FTYP.len() as u64;
+
+/// Minimal Motion-JPEG MP4 writer. Samples are streamed into `mdat` and the
+/// `moov` index is appended on [`Mp4Writer::finish`], with each sample's
+/// duration taken from the capture timestamps rather than a nominal rate.
+/// FFmpeg-based players read the result; browsers and QuickTime do not
+/// decode JPEG samples in MP4.
+pub struct Mp4Writer {
+    file: BufWriter<File>,
+    width: u32,
+    height: u32,
+    nominal_duration: u32,
+    samples: Vec<Sample>,
+    position: u64,
+}
+
+struct Sample {
+    offset: u32,
+    size: u32,
+    captured_at: SystemTime,
+}
+
+impl Mp4Writer {
+    pub fn create(path: &Path, width: u32, height: u32, frame_rate: f32) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        let mut file = BufWriter::new(file);
+        file.write_all(FTYP)?;
+        file.write_all(&0u32.to_be_bytes())?; // mdat size, patched in finish()
+        file.write_all(b"mdat")?;
+
+        Ok(Self {
+            file,
+            width,
+            height,
+            nominal_duration: (TIMESCALE as f32 / frame_rate).round() as u32,
+            samples: Vec::new(),
+            position: MDAT_SIZE_OFFSET + 8,
+        })
+    }
+
+    pub fn write_frame(&mut self, jpeg: &[u8], captured_at: SystemTime) -> Result<()> {
+        let size = u32::try_from(jpeg.len()).context("Frame too large for MP4")?;
+        let offset = u32::try_from(self.position)
+            .map_err(|_| anyhow!("Recording exceeds the 4 GiB MP4 limit"))?;
+
+        self.file.write_all(jpeg)?;
+        self.samples.push(Sample {
+            offset,
+            size,
+            captured_at,
+        });
+        self.position += u64::from(size);
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Writes the `moov` index and patches the `mdat` size.
+    pub fn finish(mut self) -> Result<()> {
+        let mdat_size = u32::try_from(self.position - MDAT_SIZE_OFFSET)
+            .map_err(|_| anyhow!("Recording exceeds the 4 GiB MP4 limit"))?;
+
+        let moov = self.moov();
+        self.file.write_all(&moov)?;
+        self.file.seek(SeekFrom::Start(MDAT_SIZE_OFFSET))?;
+        self.file.write_all(&mdat_size.to_be_bytes())?;
+
+        self.file.flush()?;
+        self.file
+            .get_ref()
+            .sync_all()
+            .context("Failed to sync recording")
+    }
+
+    /// Per-sample durations in timescale units. The last frame has no
+    /// successor, so it is shown for one nominal frame interval.
+    fn durations(&self) -> Vec<u32> {
+        let mut durations: Vec<u32> = self
+            .samples
+            .windows(2)
+            .map(|pair| {
+                let delta = pair[1]
+                    .captured_at
+                    .duration_since(pair[0].captured_at)
+                    .unwrap_or(Duration::ZERO);
+                (delta.as_millis() as u32).max(1)
+            })
+            .collect();
+        if !self.samples.is_empty() {
+            durations.push(self.nominal_duration.max(1));
+        }
+        durations
+    }
+
+    fn moov(&self) -> Vec<u8> {
+        let durations = self.durations();
+        let duration: u32 = durations.iter().sum();
+
+        let mut stts: Vec<(u32, u32)> = Vec::new();
+        for &delta in &durations {
+            match stts.last_mut() {
+                Some((count, last)) if *last == delta => *count += 1,
+                _ => stts.push((1, delta)),
+            }
+        }
+
+        let mut stts_body = u32_bytes(stts.len() as u32);
+        for (count, delta) in stts {
+            stts_body.extend_from_slice(&count.to_be_bytes());
+            stts_body.extend_from_slice(&delta.to_be_bytes());
+        }
+
+        let mut stsz_body = u32_bytes(0); // sizes differ per sample
+        stsz_body.extend_from_slice(&(self.samples.len() as u32).to_be_bytes());
+        let mut stco_body = u32_bytes(self.samples.len() as u32);
+        for sample in &self.samples {
+            stsz_body.extend_from_slice(&sample.size.to_be_bytes());
+            stco_body.extend_from_slice(&sample.offset.to_be_bytes());
+        }
+
+        // One sample per chunk, so every chunk offset is a sample offset.
+        let stsc_body = [1u32, 1, 1, 1]
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect::<Vec<_>>();
+
+        let stbl = mp4_box(
+            b"stbl",
+            &[
+                full_box(b"stsd", 0, &[u32_bytes(1), self.sample_entry()].concat()),
+                full_box(b"stts", 0, &stts_body),
+                full_box(b"stsc", 0, &stsc_body),
+                full_box(b"stsz", 0, &stsz_body),
+                full_box(b"stco", 0, &stco_body),
+            ]
+            .concat(),
+        );
+
+        let dinf = mp4_box(
+            b"dinf",
+            &full_box(
+                b"dref",
+                0,
+                &[u32_bytes(1), full_box(b"url ", 1, &[])].concat(),
+            ),
+        );
+        let minf = mp4_box(
+            b"minf",
+            &[full_box(b"vmhd", 1, &[0; 8]), dinf, stbl].concat(),
+        );
+
+        let mut mdhd = vec![0; 8]; // creation and modification time
+        mdhd.extend_from_slice(&TIMESCALE.to_be_bytes());
+        mdhd.extend_from_slice(&duration.to_be_bytes());
+        mdhd.extend_from_slice(&0x55C4u16.to_be_bytes()); // language "und"
+        mdhd.extend_from_slice(&[0; 2]);
+
+        let mut hdlr = vec![0; 4];
+        hdlr.extend_from_slice(b"vide");
+        hdlr.extend_from_slice(&[0; 12]);
+        hdlr.extend_from_slice(b"VideoHandler\0");
+
+        let mdia = mp4_box(
+            b"mdia",
+            &[
+                full_box(b"mdhd", 0, &mdhd),
+                full_box(b"hdlr", 0, &hdlr),
+                minf,
+            ]
+            .concat(),
+        );
+
+        let mut tkhd = vec![0; 8]; // creation and modification time
+        tkhd.extend_from_slice(&1u32.to_be_bytes()); // track id
+        tkhd.extend_from_slice(&[0; 4]);
+        tkhd.extend_from_slice(&duration.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 16]); // reserved, layer, group, volume
+        tkhd.extend_from_slice(&IDENTITY_MATRIX);
+        tkhd.extend_from_slice(&(self.width << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(self.height << 16).to_be_bytes());
+
+        // Track enabled and used in the presentation.
+        let trak = mp4_box(b"trak", &[full_box(b"tkhd", 3, &tkhd), mdia].concat());
+
+        let mut mvhd = vec![0; 8]; // creation and modification time
+        mvhd.extend_from_slice(&TIMESCALE.to_be_bytes());
+        mvhd.extend_from_slice(&duration.to_be_bytes());
+        mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // rate 1.0
+        mvhd.extend_from_slice(&0x0100u16.to_be_bytes()); // volume 1.0
+        mvhd.extend_from_slice(&[0; 10]);
+        mvhd.extend_from_slice(&IDENTITY_MATRIX);
+        mvhd.extend_from_slice(&[0; 24]);
+        mvhd.extend_from_slice(&2u32.to_be_bytes()); // next track id
+
+        mp4_box(b"moov", &[full_box(b"mvhd", 0, &mvhd), trak].concat())
+    }
+
+    /// `mp4v` visual sample entry whose `esds` marks the stream as JPEG.
+    fn sample_entry(&self) -> Vec<u8> {
+        let mut entry = vec![0; 6];
+        entry.extend_from_slice(&1u16.to_be_bytes()); // data reference index
+        entry.extend_from_slice(&[0; 16]);
+        entry.extend_from_slice(&(self.width as u16).to_be_bytes());
+        entry.extend_from_slice(&(self.height as u16).to_be_bytes());
+        entry.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // 72 dpi
+        entry.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+        entry.extend_from_slice(&[0; 4]);
+        entry.extend_from_slice(&1u16.to_be_bytes()); // frames per sample
+        entry.extend_from_slice(&[0; 32]); // compressor name
+        entry.extend_from_slice(&0x0018u16.to_be_bytes()); // depth
+        entry.extend_from_slice(&(-1i16).to_be_bytes());
+
+        let max_size = self
+            .samples
+            .iter()
+            .map(|sample| sample.size)
+            .max()
+            .unwrap_or(0);
+        let mut decoder_config = vec![OBJECT_TYPE_JPEG, (STREAM_TYPE_VISUAL << 2) | 1];
+        decoder_config.extend_from_slice(&max_size.to_be_bytes()[1..]);
+        decoder_config.extend_from_slice(&[0; 8]); // max and average bitrate
+
+        let mut es = vec![0, 1, 0]; // ES id 1, no flags
+        es.extend_from_slice(&descriptor(0x04, &decoder_config));
+        es.extend_from_slice(&descriptor(0x06, &[0x02])); // predefined SL config
+
+        entry.extend_from_slice(&full_box(b"esds", 0, &descriptor(0x03, &es)));
+        mp4_box(b"mp4v", &entry)
+    }
+}
+
+const IDENTITY_MATRIX: [u8; 36] = [
+    0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, //
+    0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, //
+    0, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0, 0,
+];
+
+fn u32_bytes(value: u32) -> Vec<u8> {
+    value.to_be_bytes().to_vec()
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = u32_bytes(8 + body.len() as u32);
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+/// Box with a version byte (always 0 here) and 24-bit flags.
+fn full_box(kind: &[u8; 4], flags: u32, body: &[u8]) -> Vec<u8> {
+    let mut content = u32_bytes(flags & 0x00FF_FFFF);
+    content.extend_from_slice(body);
+    mp4_box(kind, &content)
+}
+
+/// MPEG-4 descriptor; every descriptor written here is shorter than 128 bytes,
+/// so the size fits in a single byte.
+fn descriptor(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag, body.len() as u8];
+    out.extend_from_slice(body);
+    out
+}
+
+/// Length of a clip written by [`Mp4Writer`], taken from the `mvhd` box that
+/// follows `mdat`. `None` while the clip is still being recorded.
+pub fn read_duration(path: &Path) -> Result<Option<Duration>> {
+    let mut file = File::open(path)?;
+    let mut mdat_size = [0; 4];
+    file.seek(SeekFrom::Start(MDAT_SIZE_OFFSET))?;
+    file.read_exact(&mut mdat_size)?;
+    let mdat_size = u32::from_be_bytes(mdat_size);
+    if mdat_size == 0 {
+        return Ok(None);
+    }
+
+    // moov header, mvhd header, version and flags, creation and
+    // modification time, then the timescale and duration.
+    let mut moov = [0; 36];
+    file.seek(SeekFrom::Start(MDAT_SIZE_OFFSET + u64::from(mdat_size)))?;
+    file.read_exact(&mut moov)?;
+    if &moov[4..8] != b"moov" || &moov[12..16] != b"mvhd" {
+        return Err(anyhow!("Unexpected MP4 layout"));
+    }
+    let timescale = u32::from_be_bytes(moov[28..32].try_into().unwrap());
+    let duration = u32::from_be_bytes(moov[32..36].try_into().unwrap());
+    if timescale == 0 {
+        return Err(anyhow!("MP4 timescale is zero"));
+    }
+    Ok(Some(Duration::from_secs_f64(
+        f64::from(duration) / f64::from(timescale),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::*;
+
+    fn temp_clip(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("picam-mp4-{}-{name}.mp4", process::id()))
+    }
+
+    /// Top-level boxes in `data` as (type, body) pairs.
+    fn boxes(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut found = Vec::new();
+        while data.len() >= 8 {
+            let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+            assert!(size >= 8 && size <= data.len(), "bad box size {size}");
+            found.push((data[4..8].try_into().unwrap(), &data[8..size]));
+            data = &data[size..];
+        }
+        assert!(data.is_empty(), "trailing bytes after the last box");
+        found
+    }
+
+    /// Body of the box reached by following `path` from the top level.
+    fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        let (first, rest) = path.split_first().unwrap();
+        let body = boxes(data)
+            .into_iter()
+            .find(|(kind, _)| kind == *first)
+            .unwrap_or_else(|| panic!("missing {}", String::from_utf8_lossy(*first)))
+            .1;
+        if rest.is_empty() {
+            body
+        } else {
+            find(body, rest)
+        }
+    }
+
+    fn words(body: &[u8]) -> Vec<u32> {
+        body.chunks_exact(4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn written_clip_indexes_every_frame_with_its_capture_timing() {
+        let path = temp_clip("index");
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let frames: [(&[u8], u64); 4] = [
+            (b"\xFF\xD8first\xFF\xD9", 0),
+            (b"\xFF\xD8second frame\xFF\xD9", 100),
+            (b"\xFF\xD8third\xFF\xD9", 200),
+            (b"\xFF\xD8fourth and last\xFF\xD9", 350),
+        ];
+
+        let mut writer = Mp4Writer::create(&path, 640, 480, 10.0).unwrap();
+        for (jpeg, millis) in frames {
+            writer
+                .write_frame(jpeg, start + Duration::from_millis(millis))
+                .unwrap();
+        }
+        assert_eq!(writer.frame_count(), 4);
+        writer.finish().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        let top: Vec<[u8; 4]> = boxes(&data).iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(top, [*b"ftyp", *b"mdat", *b"moov"]);
+        assert_eq!(&find(&data, &[b"ftyp"])[..4], b"isom");
+
+        let stbl = find(&data, &[b"moov", b"trak", b"mdia", b"minf", b"stbl"]);
+        // Full boxes start with version and flags.
+        let stts = words(&find(stbl, &[b"stts"])[4..]);
+        assert_eq!(stts, [3, 2, 100, 1, 150, 1, 100]);
+        let stsz = words(&find(stbl, &[b"stsz"])[4..]);
+        let sizes: Vec<u32> = frames.iter().map(|(jpeg, _)| jpeg.len() as u32).collect();
+        assert_eq!(stsz[..2], [0, 4]);
+        assert_eq!(stsz[2..], sizes[..]);
+        let stco = words(&find(stbl, &[b"stco"])[4..]);
+        assert_eq!(stco[0], 4);
+        for (offset, (jpeg, _)) in stco[1..].iter().zip(frames) {
+            let offset = *offset as usize;
+            assert_eq!(&data[offset..offset + jpeg.len()], jpeg);
+        }
+
+        let mvhd = words(&find(&data, &[b"moov", b"mvhd"])[4..20]);
+        assert_eq!(mvhd[2..], [TIMESCALE, 450]);
+        assert_eq!(
+            read_duration(&path).unwrap(),
+            Some(Duration::from_millis(450))
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn clip_still_recording_has_no_duration() {
+        let path = temp_clip("unfinished");
+        let mut writer = Mp4Writer::create(&path, 320, 240, 15.0).unwrap();
+        writer
+            .write_frame(b"\xFF\xD8frame\xFF\xD9", SystemTime::now())
+            .unwrap();
+        drop(writer);
+
+        assert_eq!(read_duration(&path).unwrap(), None);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tokio::{sync::RwLock, task, time};
+
+use super::library::clip_format;
+use crate::config::Config;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Clips written to this recently may still be recording and are never removed.
+const ACTIVE_CLIP_GRACE: Duration = Duration::from_secs(60);
+
+struct Clip {
+    path: PathBuf,
+    modified: SystemTime,
+    len: u64,
+}
+
+/// Deletes clips in `dir` that are older than `RECORDING_MAX_AGE_HOURS`, then
+/// the oldest ones while the total exceeds `RECORDING_MAX_SIZE_MB`. The
+/// limits are re-read on every pass, so reloading the config applies them.
+pub fn spawn_retention(dir: PathBuf, config: Arc<RwLock<Config>>) {
+    tokio::spawn(async move {
+        let mut interval = time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let (max_age, max_size) = {
+                let config = config.read().await;
+                (config.recording_max_age(), config.recording_max_size())
+            };
+            if max_age.is_none() && max_size.is_none() {
+                continue;
+            }
+
+            let dir = dir.clone();
+            match task::spawn_blocking(move || enforce(&dir, max_age, max_size)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => {
+                    tracing::error!(error = %err, "Applying recording retention failed")
+                }
+                Err(err) => tracing::error!(error = %err, "Recording retention task panicked"),
+            }
+        }
+    });
+}
+
+fn enforce(dir: &Path, max_age: Option<Duration>, max_size: Option<u64>) -> io::Result<()> {
+    let mut clips = list_clips(dir)?;
+    clips.sort_by_key(|clip| clip.modified);
+
+    let now = SystemTime::now();
+    let mut total: u64 = clips.iter().map(|clip| clip.len).sum();
+    for clip in clips {
+        let age = now.duration_since(clip.modified).unwrap_or_default();
+        if age < ACTIVE_CLIP_GRACE {
+            break;
+        }
+
+        let reason = if max_age.is_some_and(|max_age| age > max_age) {
+            "max-age"
+        } else if max_size.is_some_and(|max_size| total > max_size) {
+            "max-size"
+        } else {
+            // Clips are sorted oldest first, so none of the rest qualify either.
+            break;
+        };
+
+        match fs::remove_file(&clip.path) {
+            Ok(()) => {
+                total -= clip.len;
+                tracing::info!(
+                    path = %clip.path.display(),
+                    bytes = clip.len,
+                    age_hours = age.as_secs() / 3600,
+                    reason,
+                    "Deleted recording"
+                );
+            }
+            Err(err) => {
+                tracing::warn!(path = %clip.path.display(), error = %err, "Could not delete recording")
+            }
+        }
+    }
+    Ok(())
+}
+
+fn list_clips(dir: &Path) -> io::Result<Vec<Clip>> {
+    let mut clips = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if clip_format(&path).is_none() || !metadata.is_file() {
+            continue;
+        }
+        clips.push(Clip {
+            path,
+            modified: metadata.modified()?,
+            len: metadata.len(),
+        });
+    }
+    Ok(clips)
+}
@@ -0,0 +1,227 @@
+//! Stream frames re-rendered for viewers: cropped to a region of interest,
+//! smaller when they asked for a `width`, with detection boxes drawn on when
+//! they asked for `boxes`, and at a lower quality while the bandwidth budget
+//! is exceeded.
+//!
+//! Each variant of a frame is rendered once per camera and shared, so a
+//! dozen viewers at 640 pixels cost one downscale per frame, not twelve.
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use image::{imageops, RgbImage};
+use tokio::{sync::Mutex, task};
+
+use crate::{detection::RecentDetections, jpeg, overlay};
+
+pub const MIN_WIDTH: u32 = 16;
+pub const MAX_WIDTH: u32 = 3840;
+/// Variants nobody rendered for this long are dropped.
+const MAX_IDLE: Duration = Duration::from_secs(10);
+/// Crop edges are kept in ten-thousandths of the frame, so equal crops
+/// share a variant.
+const CROP_SCALE: f32 = 10_000.0;
+
+/// How a viewer wants its frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Variant {
+    pub crop: Option<Crop>,
+    /// Frames wider than this, after cropping, are scaled down to it.
+    pub width: Option<u32>,
+    /// Below `JPEG_QUALITY` while the bandwidth budget is exceeded.
+    pub quality: Option<u8>,
+    /// Outline the camera's latest detections.
+    pub boxes: bool,
+}
+
+impl Variant {
+    fn is_original(self) -> bool {
+        self.crop.is_none() && self.width.is_none() && self.quality.is_none() && !self.boxes
+    }
+}
+
+/// A region of interest, as fractions of the frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Crop {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+impl Crop {
+    /// The region cut out of `image`, at least one pixel in size.
+    pub fn apply(self, image: &RgbImage) -> RgbImage {
+        let edge = |fraction: u16, size: u32| {
+            (f64::from(fraction) * f64::from(size) / f64::from(CROP_SCALE)).round() as u32
+        };
+        let x = edge(self.x, image.width()).min(image.width() - 1);
+        let y = edge(self.y, image.height()).min(image.height() - 1);
+        let width = edge(self.width, image.width()).clamp(1, image.width() - x);
+        let height = edge(self.height, image.height()).clamp(1, image.height() - y);
+        imageops::crop_imm(image, x, y, width, height).to_image()
+    }
+}
+
+impl FromStr for Crop {
+    type Err = anyhow::Error;
+
+    /// `x,y,w,h` as 0-1 fractions of the frame, like privacy masks.
+    fn from_str(raw: &str) -> Result<Self> {
+        let values = raw
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .context("expected comma-separated numbers")?;
+        let &[x, y, width, height] = values.as_slice() else {
+            return Err(anyhow!("expected x,y,w,h"));
+        };
+        if !(0.0..1.0).contains(&x)
+            || !(0.0..1.0).contains(&y)
+            || width <= 0.0
+            || height <= 0.0
+            || x + width > 1.0
+            || y + height > 1.0
+        {
+            return Err(anyhow!("region must lie within the frame (0-1)"));
+        }
+        let scaled = |fraction: f32| (fraction * CROP_SCALE).round() as u16;
+        Ok(Self {
+            x: scaled(x),
+            y: scaled(y),
+            width: scaled(width).max(1),
+            height: scaled(height).max(1),
+        })
+    }
+}
+
+/// The last rendered frame of every variant in use, per camera.
+pub struct Scaler {
+    cameras: Vec<std::sync::Mutex<HashMap<Variant, Slot>>>,
+    /// `None` unless `DETECTION_MODEL` is set.
+    detections: Option<Arc<RecentDetections>>,
+    /// `JPEG_QUALITY`, for variants the bandwidth budget leaves alone.
+    quality: AtomicU8,
+}
+
+/// Locked while its variant is rendered.
+type Slot = Arc<Mutex<Option<Rendered>>>;
+
+struct Rendered {
+    /// Kept so its address, which identifies the frame, is not reused.
+    source: Bytes,
+    data: Bytes,
+    rendered_at: Instant,
+}
+
+impl Scaler {
+    pub fn new(cameras: usize, detections: Option<Arc<RecentDetections>>, quality: u8) -> Self {
+        Self {
+            cameras: (0..cameras).map(|_| Default::default()).collect(),
+            detections,
+            quality: AtomicU8::new(quality),
+        }
+    }
+
+    pub fn jpeg_quality(&self) -> u8 {
+        self.quality.load(Ordering::Relaxed)
+    }
+
+    /// Applies a `JPEG_QUALITY` changed by `PUT /config` from the next
+    /// frame on.
+    pub fn set_jpeg_quality(&self, quality: u8) {
+        self.quality.store(quality, Ordering::Relaxed);
+    }
+
+    /// Whether viewers can ask for detection boxes.
+    pub fn draws_boxes(&self) -> bool {
+        self.detections.is_some()
+    }
+
+    /// `frame` as `variant`. The first viewer to ask renders it while the
+    /// others wanting the same variant wait for the result; a frame that
+    /// cannot be rendered is passed on as captured.
+    pub async fn render(&self, camera: usize, frame: Bytes, variant: Variant) -> Bytes {
+        if variant.is_original() {
+            return frame;
+        }
+
+        let slot = {
+            let mut variants = self.cameras[camera].lock().expect("variants poisoned");
+            variants.retain(|_, slot| {
+                slot.try_lock().map_or(true, |rendered| {
+                    rendered
+                        .as_ref()
+                        .is_some_and(|rendered| rendered.rendered_at.elapsed() < MAX_IDLE)
+                })
+            });
+            variants.entry(variant).or_default().clone()
+        };
+
+        let mut rendered = slot.lock().await;
+        // Viewers share the captured buffer, so the same frame has the same
+        // address.
+        if let Some(cached) = rendered.as_ref().filter(|cached| {
+            cached.source.as_ptr() == frame.as_ptr() && cached.source.len() == frame.len()
+        }) {
+            return cached.data.clone();
+        }
+
+        let boxes = match &self.detections {
+            Some(detections) if variant.boxes => detections.latest(camera),
+            _ => Vec::new(),
+        };
+        let source = frame.clone();
+        let quality = variant.quality.unwrap_or_else(|| self.jpeg_quality());
+        let encoded = task::spawn_blocking(move || {
+            let image = match (variant.crop, variant.width) {
+                (Some(crop), width) => {
+                    let mut image = jpeg::decode_rgb(&source)?;
+                    overlay::draw_detections(&mut image, &boxes);
+                    let cropped = crop.apply(&image);
+                    match width {
+                        Some(width) => jpeg::downscale(&cropped, width),
+                        None => cropped,
+                    }
+                }
+                (None, Some(width)) => {
+                    let mut image = jpeg::decode_scaled(&source, width)?;
+                    overlay::draw_detections(&mut image, &boxes);
+                    image
+                }
+                (None, None) => {
+                    let mut image = jpeg::decode_rgb(&source)?;
+                    overlay::draw_detections(&mut image, &boxes);
+                    image
+                }
+            };
+            jpeg::encode_rgb(&image, quality)
+        })
+        .await
+        .expect("spawn_blocking failed");
+        match encoded {
+            Ok(data) => {
+                let data = Bytes::from(data);
+                *rendered = Some(Rendered {
+                    source: frame,
+                    data: data.clone(),
+                    rendered_at: Instant::now(),
+                });
+                data
+            }
+            Err(err) => {
+                tracing::debug!(camera, ?variant, error = %err, "Rendering frame failed; sending it as captured");
+                frame
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Follows `STREAM_SCHEDULE`, so cameras can close their devices and stop
+//! streaming and recording outside the hours they are allowed to.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Local;
+use tokio::{
+    sync::{watch, RwLock},
+    time::interval,
+};
+
+use crate::config::Config;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Whether `config` has cameras off duty right now.
+pub fn off_duty(config: &Config) -> bool {
+    config
+        .stream_schedule
+        .as_ref()
+        .is_some_and(|schedule| !schedule.is_active(Local::now().naive_local()))
+}
+
+/// Checks the schedule every ten seconds and updates `off_duty`. Reads it
+/// from `config` each time, so a reload can change or lift it.
+pub fn spawn(off_duty: watch::Sender<bool>, config: Arc<RwLock<Config>>) {
+    tokio::spawn(async move {
+        let mut ticker = interval(CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let is_off_duty = self::off_duty(&*config.read().await);
+            // Only wakes the cameras when the state actually flips.
+            if off_duty.send_if_modified(|off_duty| {
+                std::mem::replace(off_duty, is_off_duty) != is_off_duty
+            }) {
+                if is_off_duty {
+                    tracing::info!("Outside STREAM_SCHEDULE; cameras off duty");
+                } else {
+                    tracing::info!("Within STREAM_SCHEDULE; cameras back on duty");
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,82 @@
+//! Work that has to be finished before the process exits, such as clips
+//! still being written or notifications not yet delivered.
+//!
+//! Each [`Drain`] is one step of the shutdown: its guards see the request,
+//! wrap up and are dropped, and [`Drain::run`] waits for that, up to a
+//! timeout, before the next step starts.
+
+use std::time::Duration;
+
+use tokio::{
+    sync::{mpsc, watch},
+    time,
+};
+
+pub struct Drain {
+    name: &'static str,
+    requested: watch::Sender<bool>,
+    guard: DrainGuard,
+    finished: mpsc::Receiver<()>,
+}
+
+/// Held by a task the [`Drain`] waits for; the task ends once
+/// [`DrainGuard::requested`] completes, dropping its guards.
+#[derive(Clone)]
+pub struct DrainGuard {
+    requested: watch::Receiver<bool>,
+    // Never sent on; the channel closes once every guard is dropped.
+    _pending: mpsc::Sender<()>,
+}
+
+impl Drain {
+    pub fn new(name: &'static str) -> Self {
+        let (requested, requested_rx) = watch::channel(false);
+        let (pending, finished) = mpsc::channel(1);
+        Self {
+            name,
+            requested,
+            guard: DrainGuard {
+                requested: requested_rx,
+                _pending: pending,
+            },
+            finished,
+        }
+    }
+
+    pub fn guard(&self) -> DrainGuard {
+        self.guard.clone()
+    }
+
+    /// Asks the guards' tasks to finish and waits until they have, giving up
+    /// after `timeout`.
+    pub async fn run(self, timeout: Duration) {
+        let Self {
+            name,
+            requested,
+            guard,
+            mut finished,
+        } = self;
+        drop(guard);
+        let _ = requested.send(true);
+        if time::timeout(timeout, finished.recv()).await.is_err() {
+            tracing::warn!(
+                stage = name,
+                timeout_secs = timeout.as_secs(),
+                "Shutdown did not finish in time; exiting anyway"
+            );
+        }
+    }
+}
+
+impl DrainGuard {
+    /// Completes once shutdown reached this guard's [`Drain`]; returns right
+    /// away if it already has.
+    pub async fn requested(&self) {
+        let mut requested = self.requested.clone();
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+}
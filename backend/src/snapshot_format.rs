@@ -0,0 +1,153 @@
+//! Image formats `/snapshot` can answer in, picked by `?format=` or the
+//! `Accept` header: JPEG as captured (or at `SNAPSHOT_JPEG_QUALITY`),
+//! lossless PNG for calibration, and lossy WebP at `SNAPSHOT_WEBP_QUALITY`
+//! for dashboards on slow links. WebP needs the `webp` feature. Any format
+//! can be cropped to a region of interest first.
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use image::{codecs::png::PngEncoder, ImageEncoder, RgbImage};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::{config::Config, jpeg, scaler::Crop};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl SnapshotFormat {
+    /// Formats this build can encode, JPEG first so it wins ties.
+    const SUPPORTED: &'static [Self] = if cfg!(feature = "webp") {
+        &[Self::Jpeg, Self::Png, Self::Webp]
+    } else {
+        &[Self::Jpeg, Self::Png]
+    };
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpeg",
+            Self::Png => "png",
+            Self::Webp => "webp",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    pub fn is_supported(self) -> bool {
+        Self::SUPPORTED.contains(&self)
+    }
+
+    /// The supported format `accept` prefers most, or `None` when it rules
+    /// them all out.
+    pub fn negotiate(accept: &str) -> Option<Self> {
+        let mut best: Option<(f32, Self)> = None;
+        for &format in Self::SUPPORTED {
+            let weight = format.weight(accept);
+            if weight > 0.0 && best.is_none_or(|(best, _)| weight > best) {
+                best = Some((weight, format));
+            }
+        }
+        best.map(|(_, format)| format)
+    }
+
+    /// The `q` of the most specific media range in `accept` covering this
+    /// format; 0 when none does.
+    fn weight(self, accept: &str) -> f32 {
+        let mut matched: Option<(u8, f32)> = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media = params.next().unwrap_or_default().trim();
+            let specificity = if media.eq_ignore_ascii_case(self.content_type()) {
+                2
+            } else if media.eq_ignore_ascii_case("image/*") {
+                1
+            } else if media == "*/*" {
+                0
+            } else {
+                continue;
+            };
+            let weight = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse().ok())
+                .unwrap_or(1.0);
+            if matched.is_none_or(|(best, _)| specificity > best) {
+                matched = Some((specificity, weight));
+            }
+        }
+        matched.map_or(0.0, |(_, weight)| weight)
+    }
+}
+
+/// Converts a captured JPEG frame; blocks, so run it off the async workers.
+pub fn encode(
+    frame: Bytes,
+    format: SnapshotFormat,
+    crop: Option<Crop>,
+    config: &Config,
+) -> Result<Bytes> {
+    if format == SnapshotFormat::Jpeg && crop.is_none() && config.snapshot_jpeg_quality.is_none() {
+        return Ok(frame);
+    }
+
+    let mut image = jpeg::decode_rgb(&frame)?;
+    if let Some(crop) = crop {
+        image = crop.apply(&image);
+    }
+    match format {
+        SnapshotFormat::Jpeg => {
+            let quality = config.snapshot_jpeg_quality.unwrap_or(config.jpeg_quality);
+            Ok(jpeg::encode_rgb(&image, quality)?.into())
+        }
+        SnapshotFormat::Png => {
+            let mut png = Vec::new();
+            PngEncoder::new(&mut png)
+                .write_image(
+                    image.as_raw(),
+                    image.width(),
+                    image.height(),
+                    image::ColorType::Rgb8,
+                )
+                .context("Failed to encode PNG snapshot")?;
+            Ok(png.into())
+        }
+        SnapshotFormat::Webp => encode_webp(&image, config.snapshot_webp_quality),
+    }
+}
+
+#[cfg(feature = "webp")]
+fn encode_webp(image: &RgbImage, quality: u8) -> Result<Bytes> {
+    use std::io::Cursor;
+
+    use image::codecs::webp::{WebPEncoder, WebPQuality};
+
+    let mut webp = Cursor::new(Vec::new());
+    // Lossy encoding goes through libwebp, which image marks as deprecated
+    // in favour of its own lossless-only encoder.
+    #[allow(deprecated)]
+    WebPEncoder::new_with_quality(&mut webp, WebPQuality::lossy(quality))
+        .write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            image::ColorType::Rgb8,
+        )
+        .context("Failed to encode WebP snapshot")?;
+    Ok(webp.into_inner().into())
+}
+
+#[cfg(not(feature = "webp"))]
+fn encode_webp(_image: &RgbImage, _quality: u8) -> Result<Bytes> {
+    anyhow::bail!("WebP snapshots need a build with the `webp` feature")
+}
@@ -0,0 +1,160 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Timelike};
+use croner::Cron;
+use tokio::{
+    fs,
+    time::{self, Instant},
+};
+
+use crate::{
+    capture::{CameraHandle, FRAME_TIMEOUT},
+    config::SnapshotSchedule,
+    upload::Uploader,
+};
+
+/// Saves a still from every camera on the configured schedule. Frames are
+/// taken from the broadcast, so they match the stream, overlays included.
+pub fn spawn(schedule: &SnapshotSchedule, cameras: &[CameraHandle], uploader: Option<&Uploader>) {
+    for (id, handle) in cameras.iter().enumerate() {
+        tokio::spawn(save_snapshots(
+            schedule.clone(),
+            id,
+            handle.clone(),
+            uploader.cloned(),
+        ));
+    }
+}
+
+async fn save_snapshots(
+    schedule: SnapshotSchedule,
+    camera: usize,
+    handle: CameraHandle,
+    uploader: Option<Uploader>,
+) {
+    // Checked when the configuration was loaded.
+    let cron = schedule.cron().expect("SNAPSHOT_CRON is valid");
+    loop {
+        let now = Local::now();
+        let wait = match &cron {
+            Some(cron) => match until_run(now, cron) {
+                Some(wait) => wait,
+                None => {
+                    tracing::warn!(
+                        camera,
+                        "SNAPSHOT_CRON never matches again; stopping snapshots"
+                    );
+                    return;
+                }
+            },
+            None => until_next(now, schedule.interval()),
+        };
+        time::sleep_until(Instant::now() + wait).await;
+
+        let taken_at = Local::now();
+        let frame = match handle.next_frame(FRAME_TIMEOUT).await {
+            Ok(frame) => frame.data,
+            Err(err) => {
+                tracing::warn!(camera, error = %err, "No frame for scheduled snapshot");
+                continue;
+            }
+        };
+
+        let name = file_name(&schedule.filename, camera, taken_at);
+        let path = schedule.dir.join(&name);
+        match write(&path, &frame).await {
+            Ok(()) => {
+                tracing::debug!(camera, path = %path.display(), "Snapshot saved");
+                if let Some(uploader) = &uploader {
+                    uploader.enqueue(path, format!("snapshots/{name}"));
+                }
+            }
+            Err(err) => {
+                tracing::error!(camera, error = format!("{err:#}"), "Saving snapshot failed")
+            }
+        }
+    }
+}
+
+async fn write(path: &Path, frame: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, frame)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn file_name(template: &str, camera: usize, taken_at: DateTime<Local>) -> String {
+    let template = template.replace("{camera}", &camera.to_string());
+    taken_at.format(&template).to_string()
+}
+
+/// Time left until the next time after `now` that `cron` matches, or `None`
+/// if it matches none within croner's search range.
+fn until_run(now: DateTime<Local>, cron: &Cron) -> Option<Duration> {
+    // From the start of the current second, so a wake-up a little after
+    // the scheduled time does not count the same run again.
+    let from = now.with_nanosecond(0)?;
+    let next = cron.find_next_occurrence(&from, false).ok()?;
+    (next - now).to_std().ok()
+}
+
+/// Time left until the next multiple of `interval` since local midnight.
+fn until_next(now: DateTime<Local>, interval: Duration) -> Duration {
+    let since_midnight = Duration::new(
+        u64::from(now.num_seconds_from_midnight()),
+        now.nanosecond().min(999_999_999),
+    );
+    let interval_nanos = interval.as_nanos().max(1);
+    let elapsed = since_midnight.as_nanos() % interval_nanos;
+    Duration::from_nanos((interval_nanos - elapsed) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(hour: u32, minute: u32, second: u32, millis: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 3, 5, hour, minute, second)
+            .unwrap()
+            .with_nanosecond(millis * 1_000_000)
+            .unwrap()
+    }
+
+    #[test]
+    fn cron_schedule_waits_for_the_next_matching_minute() {
+        let cron = Cron::new("*/10 6-20 * * *").parse().unwrap();
+
+        assert_eq!(
+            until_run(at(9, 59, 30, 250), &cron),
+            Some(Duration::from_millis(29_750))
+        );
+        // Woken just after a run: the next one is ten minutes later.
+        assert_eq!(
+            until_run(at(10, 0, 0, 5), &cron),
+            Some(Duration::from_millis(599_995))
+        );
+        // Outside the hours, the schedule resumes the next morning.
+        assert_eq!(
+            until_run(at(20, 55, 0, 0), &cron),
+            Some(Duration::from_secs((9 * 60 + 5) * 60))
+        );
+    }
+
+    #[test]
+    fn interval_schedule_is_aligned_to_midnight() {
+        let interval = Duration::from_secs(600);
+        assert_eq!(
+            until_next(at(9, 59, 30, 250), interval),
+            Duration::from_millis(29_750)
+        );
+        assert_eq!(until_next(at(10, 0, 0, 0), interval), interval);
+    }
+}
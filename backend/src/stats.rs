@@ -0,0 +1,195 @@
+//! Counters for the live streams, reported by `/stats`.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Connected stream clients and bytes sent, per camera.
+pub struct StreamStats {
+    started_at: Instant,
+    /// Clients across all cameras, checked against `MAX_STREAM_CLIENTS`.
+    clients: AtomicUsize,
+    cameras: Vec<CameraCounters>,
+}
+
+#[derive(Default)]
+struct CameraCounters {
+    clients: AtomicUsize,
+    bytes_sent: AtomicU64,
+    connections: Mutex<Vec<Arc<Connection>>>,
+}
+
+/// What is known about one connected client.
+struct Connection {
+    peer: String,
+    protocol: &'static str,
+    connected_at: DateTime<Local>,
+    started: Instant,
+    frames_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+/// A connected client as listed by `/stats`.
+#[derive(Serialize, ToSchema)]
+pub struct ConnectionStats {
+    pub client: String,
+    /// `mjpeg` or `ws`.
+    pub protocol: &'static str,
+    pub connected_at: String,
+    pub duration_secs: u64,
+    pub frames_sent: u64,
+    pub bytes_sent: u64,
+}
+
+/// Counts one MJPEG or WebSocket client for as long as it is held, and logs
+/// what it was sent once it is dropped.
+pub struct StreamClient {
+    stats: Arc<StreamStats>,
+    camera: usize,
+    connection: Arc<Connection>,
+}
+
+impl StreamStats {
+    pub fn new(cameras: usize) -> Self {
+        Self {
+            started_at: Instant::now(),
+            clients: AtomicUsize::new(0),
+            cameras: (0..cameras).map(|_| CameraCounters::default()).collect(),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Counts a new client of `camera` at `peer`, or `None` when `limit`
+    /// clients are already connected.
+    pub fn connect(
+        self: &Arc<Self>,
+        camera: usize,
+        limit: Option<usize>,
+        peer: String,
+        protocol: &'static str,
+    ) -> Option<StreamClient> {
+        let limit = limit.unwrap_or(usize::MAX);
+        self.clients
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |clients| {
+                (clients < limit).then_some(clients + 1)
+            })
+            .ok()?;
+
+        let counters = &self.cameras[camera];
+        counters.clients.fetch_add(1, Ordering::Relaxed);
+        let connection = Arc::new(Connection {
+            peer,
+            protocol,
+            connected_at: Local::now(),
+            started: Instant::now(),
+            frames_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+        });
+        counters
+            .connections
+            .lock()
+            .expect("connections poisoned")
+            .push(connection.clone());
+        tracing::info!(
+            camera,
+            client = %connection.peer,
+            protocol,
+            "Stream client connected"
+        );
+
+        Some(StreamClient {
+            stats: self.clone(),
+            camera,
+            connection,
+        })
+    }
+
+    pub fn clients(&self, camera: usize) -> usize {
+        self.cameras[camera].clients.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self, camera: usize) -> u64 {
+        self.cameras[camera].bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Clients of `camera`, longest connected first.
+    pub fn connections(&self, camera: usize) -> Vec<ConnectionStats> {
+        self.cameras[camera]
+            .connections
+            .lock()
+            .expect("connections poisoned")
+            .iter()
+            .map(|connection| connection.stats())
+            .collect()
+    }
+}
+
+impl Connection {
+    fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            client: self.peer.clone(),
+            protocol: self.protocol,
+            connected_at: self.connected_at.to_rfc3339(),
+            duration_secs: self.started.elapsed().as_secs(),
+            frames_sent: self.frames_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl StreamClient {
+    pub fn camera(&self) -> usize {
+        self.camera
+    }
+
+    /// Counts a chunk holding a frame.
+    pub fn frame_sent(&self, bytes: usize) {
+        self.connection.frames_sent.fetch_add(1, Ordering::Relaxed);
+        self.sent(bytes);
+    }
+
+    /// Counts any other chunk, such as a camera error notice.
+    pub fn sent(&self, bytes: usize) {
+        self.connection
+            .bytes_sent
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.stats.cameras[self.camera]
+            .bytes_sent
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+impl Drop for StreamClient {
+    fn drop(&mut self) {
+        let counters = &self.stats.cameras[self.camera];
+        self.stats.clients.fetch_sub(1, Ordering::Relaxed);
+        counters.clients.fetch_sub(1, Ordering::Relaxed);
+        counters
+            .connections
+            .lock()
+            .expect("connections poisoned")
+            .retain(|connection| !Arc::ptr_eq(connection, &self.connection));
+
+        let stats = self.connection.stats();
+        tracing::info!(
+            camera = self.camera,
+            client = %stats.client,
+            protocol = stats.protocol,
+            duration_secs = stats.duration_secs,
+            frames = stats.frames_sent,
+            bytes = stats.bytes_sent,
+            "Stream client disconnected"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! How hard the machine is working, read from Linux's `/proc` and `/sys`
+//! and, on a Raspberry Pi, the firmware's throttling flags, with `vcgencmd`
+//! standing in for kernels that do not expose them. Readings the platform
+//! does not offer are `None`.
+
+use std::{
+    fmt::Write,
+    fs,
+    io::ErrorKind,
+    process::{Command, Stdio},
+};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+const CPU_STAT: &str = "/proc/stat";
+const SOC_TEMPERATURE: &str = "/sys/class/thermal/thermal_zone0/temp";
+/// Raspberry Pi kernels expose `vcgencmd get_throttled` here.
+const PI_THROTTLED: &str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+/// The firmware's flags for conditions that have occurred since boot sit
+/// this many bits above the ones for conditions right now.
+const SINCE_BOOT_SHIFT: u32 = 16;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, ToSchema)]
+pub struct SystemLoad {
+    /// CPU usage across all cores since the previous reading, in percent.
+    pub cpu_percent: Option<f32>,
+    pub temperature_celsius: Option<f32>,
+    /// What the Pi firmware does to keep the SoC cool and powered.
+    pub throttling: Option<Throttling>,
+}
+
+impl SystemLoad {
+    /// The Pi firmware is holding the CPU back for heat or power right now.
+    pub fn is_throttled(&self) -> bool {
+        self.throttling
+            .is_some_and(|throttling| throttling.now.any())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, ToSchema)]
+pub struct Throttling {
+    pub now: ThrottleFlags,
+    /// Conditions that occurred at any time since boot, including now.
+    pub since_boot: ThrottleFlags,
+}
+
+/// Conditions reported by `vcgencmd get_throttled`.
+#[derive(Clone, Copy, Debug, Default, Serialize, ToSchema)]
+pub struct ThrottleFlags {
+    pub under_voltage: bool,
+    pub frequency_capped: bool,
+    pub throttled: bool,
+    pub soft_temperature_limit: bool,
+}
+
+impl ThrottleFlags {
+    fn from_bits(bits: u32) -> Self {
+        Self {
+            under_voltage: bits & 0x1 != 0,
+            frequency_capped: bits & 0x2 != 0,
+            throttled: bits & 0x4 != 0,
+            soft_temperature_limit: bits & 0x8 != 0,
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.conditions().iter().any(|(_, set)| *set)
+    }
+
+    /// Each condition by its field name, and whether it is set.
+    pub fn conditions(&self) -> [(&'static str, bool); 4] {
+        [
+            ("under_voltage", self.under_voltage),
+            ("frequency_capped", self.frequency_capped),
+            ("throttled", self.throttled),
+            ("soft_temperature_limit", self.soft_temperature_limit),
+        ]
+    }
+}
+
+/// Reads [`SystemLoad`]; CPU usage is measured between calls to `read`.
+/// Blocks while `vcgencmd` runs, so reads belong off the async runtime.
+#[derive(Default)]
+pub struct Sampler {
+    /// Busy and total CPU time at the previous reading.
+    previous: Option<(u64, u64)>,
+    /// `vcgencmd` was not found, so it is not tried again.
+    no_vcgencmd: bool,
+}
+
+impl Sampler {
+    pub fn read(&mut self) -> SystemLoad {
+        let times = cpu_times();
+        let cpu_percent = match (self.previous, times) {
+            (Some((busy_before, total_before)), Some((busy, total))) if total > total_before => {
+                Some((busy - busy_before) as f32 * 100.0 / (total - total_before) as f32)
+            }
+            _ => None,
+        };
+        self.previous = times;
+
+        let temperature_celsius = temperature().or_else(|| {
+            // temp=47.2'C
+            let output = self.vcgencmd("measure_temp")?;
+            output
+                .trim()
+                .strip_prefix("temp=")?
+                .trim_end_matches("'C")
+                .parse()
+                .ok()
+        });
+        let throttled = fs::read_to_string(PI_THROTTLED)
+            .ok()
+            .or_else(|| {
+                // throttled=0x50000
+                let output = self.vcgencmd("get_throttled")?;
+                Some(output.trim().strip_prefix("throttled=")?.to_string())
+            })
+            .and_then(|raw| u32::from_str_radix(raw.trim().trim_start_matches("0x"), 16).ok());
+
+        SystemLoad {
+            cpu_percent,
+            temperature_celsius,
+            throttling: throttled.map(|bits| Throttling {
+                now: ThrottleFlags::from_bits(bits),
+                since_boot: ThrottleFlags::from_bits(bits | bits >> SINCE_BOOT_SHIFT),
+            }),
+        }
+    }
+
+    /// Output of `vcgencmd command`, or `None` where it is missing or fails.
+    fn vcgencmd(&mut self, command: &str) -> Option<String> {
+        if self.no_vcgencmd {
+            return None;
+        }
+        match Command::new("vcgencmd")
+            .arg(command)
+            .stderr(Stdio::null())
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            Ok(_) => None,
+            Err(err) => {
+                if err.kind() == ErrorKind::NotFound {
+                    self.no_vcgencmd = true;
+                } else {
+                    tracing::debug!(command, error = %err, "Running vcgencmd failed");
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Busy and total jiffies of all cores, from the `cpu` line of
+/// `/proc/stat`. Idle and I/O wait count as not busy.
+fn cpu_times() -> Option<(u64, u64)> {
+    let stat = fs::read_to_string(CPU_STAT).ok()?;
+    let fields: Vec<u64> = stat
+        .lines()
+        .next()?
+        .strip_prefix("cpu ")?
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    // user nice system idle iowait irq softirq steal; guest time is
+    // already included in user.
+    let total: u64 = fields.iter().take(8).sum();
+    let idle = fields.get(3)? + fields.get(4).unwrap_or(&0);
+    Some((total.saturating_sub(idle), total))
+}
+
+fn temperature() -> Option<f32> {
+    let millidegrees: i32 = fs::read_to_string(SOC_TEMPERATURE)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(millidegrees as f32 / 1000.0)
+}
+
+/// The readings in the Prometheus text exposition format; readings the
+/// platform does not offer are left out.
+pub fn prometheus(load: &SystemLoad, frame_rate_share: f32) -> String {
+    let mut out = String::new();
+    if let Some(cpu) = load.cpu_percent {
+        out.push_str("# HELP picam_cpu_usage_ratio CPU usage across all cores.\n");
+        out.push_str("# TYPE picam_cpu_usage_ratio gauge\n");
+        let _ = writeln!(out, "picam_cpu_usage_ratio {}", cpu / 100.0);
+    }
+    if let Some(temperature) = load.temperature_celsius {
+        out.push_str("# HELP picam_soc_temperature_celsius SoC temperature.\n");
+        out.push_str("# TYPE picam_soc_temperature_celsius gauge\n");
+        let _ = writeln!(out, "picam_soc_temperature_celsius {temperature}");
+    }
+    if let Some(throttling) = load.throttling {
+        for (name, help, flags) in [
+            (
+                "picam_soc_throttled",
+                "Throttling conditions the Pi firmware reports now.",
+                throttling.now,
+            ),
+            (
+                "picam_soc_throttled_since_boot",
+                "Throttling conditions the Pi firmware reported since boot.",
+                throttling.since_boot,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            for (condition, set) in flags.conditions() {
+                let _ = writeln!(out, "{name}{{condition=\"{condition}\"}} {}", u8::from(set));
+            }
+        }
+    }
+    out.push_str(
+        "# HELP picam_frame_rate_share Share of FRAME_RATE cameras capture at under ADAPTIVE_FRAME_RATE.\n",
+    );
+    out.push_str("# TYPE picam_frame_rate_share gauge\n");
+    let _ = writeln!(out, "picam_frame_rate_share {frame_rate_share}");
+    out
+}
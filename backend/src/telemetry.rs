@@ -0,0 +1,187 @@
+//! Optional OpenTelemetry export of the `capture_frame`, `overlay` and
+//! `stream_write` spans and of the capture and streaming counters, to an
+//! OTLP/HTTP collector at `OTEL_EXPORTER_OTLP_ENDPOINT`.
+//!
+//! Needs the `otel` feature. The standard `OTEL_*` variables (service name,
+//! resource attributes, headers) are honoured by the exporter itself.
+
+use std::env;
+
+pub use backend::{observe, start, Telemetry};
+
+/// Exporting is on once a collector is configured.
+pub fn enabled() -> bool {
+    env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_ok_and(|endpoint| !endpoint.trim().is_empty())
+}
+
+#[cfg(feature = "otel")]
+mod backend {
+    use std::{env, sync::Arc, time::Duration};
+
+    use anyhow::{Context, Result};
+    use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+    use opentelemetry_otlp::{MetricExporter, SpanExporter};
+    use opentelemetry_sdk::{
+        metrics::{PeriodicReader, SdkMeterProvider},
+        runtime,
+        trace::TracerProvider,
+        Resource,
+    };
+    use tokio::task;
+    use tracing_subscriber::{Layer, Registry};
+
+    use crate::{capture::CameraHandle, stats::StreamStats};
+
+    const SERVICE_NAME: &str = "picam-backend";
+    const METRICS_INTERVAL: Duration = Duration::from_secs(15);
+
+    /// Keeps the exporters running; flush them with [`Telemetry::shutdown`].
+    pub struct Telemetry {
+        tracer: TracerProvider,
+        meter: SdkMeterProvider,
+    }
+
+    /// Starts the exporters and returns the layer feeding spans to them.
+    pub fn start() -> Result<(Telemetry, impl Layer<Registry>)> {
+        let resource = resource();
+        let spans = SpanExporter::builder()
+            .with_http()
+            .build()
+            .context("Failed to create OTLP span exporter")?;
+        let tracer = TracerProvider::builder()
+            .with_batch_exporter(spans, runtime::Tokio)
+            .with_resource(resource.clone())
+            .build();
+
+        let metrics = MetricExporter::builder()
+            .with_http()
+            .build()
+            .context("Failed to create OTLP metric exporter")?;
+        let reader = PeriodicReader::builder(metrics, runtime::Tokio)
+            .with_interval(METRICS_INTERVAL)
+            .build();
+        let meter = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource)
+            .build();
+        global::set_meter_provider(meter.clone());
+
+        let layer = tracing_opentelemetry::layer().with_tracer(tracer.tracer(SERVICE_NAME));
+        Ok((Telemetry { tracer, meter }, layer))
+    }
+
+    /// `OTEL_SERVICE_NAME` and `OTEL_RESOURCE_ATTRIBUTES` win over the default name.
+    fn resource() -> Resource {
+        let name = Resource::new([KeyValue::new("service.name", SERVICE_NAME)]);
+        if env::var_os("OTEL_SERVICE_NAME").is_some() {
+            name.merge(&Resource::default())
+        } else {
+            Resource::default().merge(&name)
+        }
+    }
+
+    /// Reports capture and streaming counters with every metrics export;
+    /// does nothing unless [`start`] ran.
+    pub fn observe(cameras: &[CameraHandle], streams: Arc<StreamStats>) {
+        let meter = global::meter(SERVICE_NAME);
+        let camera = |id: usize| [KeyValue::new("camera", id as i64)];
+
+        let handles = cameras.to_vec();
+        meter
+            .u64_observable_counter("picam.frames_captured")
+            .with_description("Frames captured since startup")
+            .with_callback(move |observer| {
+                for (id, handle) in handles.iter().enumerate() {
+                    observer.observe(handle.capture_stats().frames_captured, &camera(id));
+                }
+            })
+            .build();
+
+        let handles = cameras.to_vec();
+        meter
+            .u64_observable_counter("picam.capture_errors")
+            .with_description("Failed captures since startup")
+            .with_callback(move |observer| {
+                for (id, handle) in handles.iter().enumerate() {
+                    observer.observe(handle.capture_stats().capture_errors, &camera(id));
+                }
+            })
+            .build();
+
+        let handles = cameras.to_vec();
+        meter
+            .f64_observable_gauge("picam.capture_fps")
+            .with_description("Frames per second delivered over the last few seconds")
+            .with_callback(move |observer| {
+                for (id, handle) in handles.iter().enumerate() {
+                    observer.observe(f64::from(handle.capture_stats().fps), &camera(id));
+                }
+            })
+            .build();
+
+        let count = cameras.len();
+        let clients = streams.clone();
+        meter
+            .u64_observable_gauge("picam.stream_clients")
+            .with_description("MJPEG and WebSocket viewers connected")
+            .with_callback(move |observer| {
+                for id in 0..count {
+                    observer.observe(clients.clients(id) as u64, &camera(id));
+                }
+            })
+            .build();
+
+        meter
+            .u64_observable_counter("picam.stream_bytes_sent")
+            .with_unit("By")
+            .with_description("Stream bytes sent to viewers since startup")
+            .with_callback(move |observer| {
+                for id in 0..count {
+                    observer.observe(streams.bytes_sent(id), &camera(id));
+                }
+            })
+            .build();
+    }
+
+    impl Telemetry {
+        /// Sends what is still buffered. The exporters block while flushing,
+        /// so this runs off the async workers.
+        pub async fn shutdown(self) {
+            let (traces, metrics) =
+                task::spawn_blocking(move || (self.tracer.shutdown(), self.meter.shutdown()))
+                    .await
+                    .expect("spawn_blocking failed");
+            if let Err(err) = traces {
+                tracing::warn!(error = %err, "Flushing traces failed");
+            }
+            if let Err(err) = metrics {
+                tracing::warn!(error = %err, "Flushing metrics failed");
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod backend {
+    use std::sync::Arc;
+
+    use anyhow::{bail, Result};
+    use tracing_subscriber::layer::Identity;
+
+    use crate::{capture::CameraHandle, stats::StreamStats};
+
+    /// Never exists, as `start` always fails without the feature.
+    pub enum Telemetry {}
+
+    pub fn start() -> Result<(Telemetry, Identity)> {
+        bail!("OTEL_EXPORTER_OTLP_ENDPOINT needs a build with the `otel` feature")
+    }
+
+    impl Telemetry {
+        pub async fn shutdown(self) {
+            match self {}
+        }
+    }
+
+    pub fn observe(_cameras: &[CameraHandle], _streams: Arc<StreamStats>) {}
+}
@@ -0,0 +1,80 @@
+//! Small stills for dashboards that poll many cameras, cached so the request
+//! rate does not drive decoding and encoding work.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use tokio::{sync::Mutex, task};
+
+use crate::{
+    capture::{CameraHandle, FRAME_TIMEOUT},
+    jpeg,
+};
+
+pub const DEFAULT_WIDTH: u32 = 320;
+pub const MIN_WIDTH: u32 = 16;
+pub const MAX_WIDTH: u32 = 1920;
+/// Requests within this long of the last render get the cached thumbnail.
+const MAX_AGE: Duration = Duration::from_secs(5);
+
+/// Recently rendered thumbnails per camera, keyed by width.
+pub struct Thumbnails {
+    cameras: Vec<Mutex<HashMap<u32, Thumbnail>>>,
+}
+
+struct Thumbnail {
+    data: Bytes,
+    rendered_at: Instant,
+}
+
+impl Thumbnails {
+    pub fn new(cameras: usize) -> Self {
+        Self {
+            cameras: (0..cameras).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    /// A thumbnail of `camera` at most `MAX_AGE` old, rendering a new one
+    /// from the next frame at JPEG `quality` when needed.
+    pub async fn get(
+        &self,
+        camera: usize,
+        handle: &CameraHandle,
+        width: u32,
+        quality: u8,
+    ) -> Result<Bytes> {
+        // Held while rendering, so concurrent requests wait for the one
+        // render instead of each starting their own.
+        let mut cached = self.cameras[camera].lock().await;
+        if let Some(thumbnail) = cached.get(&width) {
+            if thumbnail.rendered_at.elapsed() < MAX_AGE {
+                return Ok(thumbnail.data.clone());
+            }
+        }
+
+        let frame = handle.next_frame(FRAME_TIMEOUT).await?.data;
+        let data = task::spawn_blocking(move || {
+            let image = jpeg::decode_scaled(&frame, width)?;
+            jpeg::encode_rgb(&image, quality)
+        })
+        .await
+        .expect("spawn_blocking failed")
+        .context("Failed to render thumbnail")?;
+        let data = Bytes::from(data);
+
+        // Widths nobody asked for lately are not worth keeping.
+        cached.retain(|_, thumbnail| thumbnail.rendered_at.elapsed() < MAX_AGE);
+        cached.insert(
+            width,
+            Thumbnail {
+                data: data.clone(),
+                rendered_at: Instant::now(),
+            },
+        );
+        Ok(data)
+    }
+}
@@ -0,0 +1,164 @@
+//! How long each stage of the frame pipeline takes, so a frame rate below
+//! `FRAME_RATE` can be traced to its cause. Served as Prometheus histograms
+//! by `/metrics` and summarised in the debug log.
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use tokio::time::interval;
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0,
+];
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+static STAGES: [Histogram; Stage::ALL.len()] = [const { Histogram::new() }; Stage::ALL.len()];
+
+#[derive(Clone, Copy, Debug)]
+pub enum Stage {
+    /// The camera backend delivering a JPEG frame, including the conversion
+    /// and encoding of raw formats.
+    Capture,
+    /// Raw (YUV, RGB565) frames rearranged for the encoder.
+    Convert,
+    /// Raw frames compressed to JPEG.
+    Encode,
+    /// Timestamp, caption, watermark, privacy masks and image adjustments.
+    Overlay,
+    /// A frame handed to an MJPEG or WebSocket viewer's connection.
+    StreamWrite,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Capture,
+        Stage::Convert,
+        Stage::Encode,
+        Stage::Overlay,
+        Stage::StreamWrite,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Capture => "capture",
+            Stage::Convert => "convert",
+            Stage::Encode => "encode",
+            Stage::Overlay => "overlay",
+            Stage::StreamWrite => "stream_write",
+        }
+    }
+}
+
+struct Histogram {
+    /// Observations per bucket, not cumulative; the last one is `+Inf`.
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len() + 1],
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+pub fn record(stage: Stage, elapsed: Duration) {
+    let histogram = &STAGES[stage as usize];
+    let seconds = elapsed.as_secs_f64();
+    let bucket = BUCKETS
+        .iter()
+        .position(|&bound| seconds <= bound)
+        .unwrap_or(BUCKETS.len());
+    histogram.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    histogram
+        .sum_micros
+        .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    histogram.count.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records how long `work` takes as `stage`.
+pub fn time<T>(stage: Stage, work: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = work();
+    record(stage, started.elapsed());
+    result
+}
+
+/// The histograms in the Prometheus text exposition format.
+pub fn prometheus() -> String {
+    let mut out = String::new();
+    out.push_str(
+        "# HELP picam_stage_duration_seconds Time spent per frame in each pipeline stage.\n",
+    );
+    out.push_str("# TYPE picam_stage_duration_seconds histogram\n");
+    for stage in Stage::ALL {
+        let histogram = &STAGES[stage as usize];
+        let name = stage.name();
+        let mut cumulative = 0;
+        for (index, count) in histogram.buckets.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let bound = BUCKETS
+                .get(index)
+                .map_or_else(|| "+Inf".to_string(), f64::to_string);
+            let _ = writeln!(
+                out,
+                "picam_stage_duration_seconds_bucket{{stage=\"{name}\",le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(
+            out,
+            "picam_stage_duration_seconds_sum{{stage=\"{name}\"}} {sum}"
+        );
+        let _ = writeln!(
+            out,
+            "picam_stage_duration_seconds_count{{stage=\"{name}\"}} {cumulative}"
+        );
+    }
+    out
+}
+
+/// Logs the average time per stage every ten seconds, at debug level.
+pub async fn log_periodically() {
+    let mut ticker = interval(LOG_INTERVAL);
+    let mut previous = [(0u64, 0u64); Stage::ALL.len()];
+    loop {
+        ticker.tick().await;
+        if !tracing::enabled!(tracing::Level::DEBUG) {
+            continue;
+        }
+        let mut averages = [0.0; Stage::ALL.len()];
+        for stage in Stage::ALL {
+            let histogram = &STAGES[stage as usize];
+            let count = histogram.count.load(Ordering::Relaxed);
+            let sum = histogram.sum_micros.load(Ordering::Relaxed);
+            let (previous_count, previous_sum) = previous[stage as usize];
+            if count > previous_count {
+                averages[stage as usize] =
+                    (sum - previous_sum) as f64 / (count - previous_count) as f64 / 1000.0;
+            }
+            previous[stage as usize] = (count, sum);
+        }
+        // Nothing ran, e.g. while an on-demand camera is closed.
+        if averages.iter().all(|&average| average == 0.0) {
+            continue;
+        }
+        let [capture, convert, encode, overlay, stream_write] = averages;
+        tracing::debug!(
+            capture_ms = format!("{capture:.2}"),
+            convert_ms = format!("{convert:.2}"),
+            encode_ms = format!("{encode:.2}"),
+            overlay_ms = format!("{overlay:.2}"),
+            stream_write_ms = format!("{stream_write:.2}"),
+            "Average time per frame in each pipeline stage"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Serving over a Unix domain socket, for a reverse proxy on the same host.
+//!
+//! `axum::serve` only takes TCP listeners, so connections are driven with
+//! hyper directly, the same way it does internally.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::Router;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use tokio::{net::UnixListener, sync::watch};
+
+use crate::{config::UnixSocketConfig, wait_for_shutdown};
+
+pub async fn serve(
+    socket: &UnixSocketConfig,
+    app: Router,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let listener = bind(socket)?;
+    tracing::info!(path = %socket.path.display(), "Backend listening on Unix socket");
+
+    // HTTP/1.1, or HTTP/2 for proxies that speak h2c to their upstream.
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    let stopping = wait_for_shutdown(shutdown);
+    tokio::pin!(stopping);
+
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::warn!(error = %err, "Accepting Unix socket connection failed");
+                    continue;
+                }
+            },
+            () = &mut stopping => break,
+        };
+
+        let service = TowerToHyperService::new(app.clone());
+        let connection = builder
+            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                tracing::debug!(error = %err, "Unix socket connection ended with an error");
+            }
+        });
+    }
+
+    drop(listener);
+    if let Err(err) = fs::remove_file(&socket.path) {
+        tracing::warn!(path = %socket.path.display(), error = %err, "Removing Unix socket failed");
+    }
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// Binds the socket, replacing one left behind by an earlier run, and applies
+/// the configured permissions and group.
+fn bind(socket: &UnixSocketConfig) -> Result<UnixListener> {
+    let path = &socket.path;
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?,
+        Ok(_) => {
+            return Err(anyhow!(
+                "LISTEN_SOCKET {} exists and is not a socket",
+                path.display()
+            ))
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to inspect {}", path.display()))
+        }
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind to {}", path.display()))?;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(socket.mode))
+        .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+    if let Some(group) = &socket.group {
+        let gid = group_id(group)?;
+        std::os::unix::fs::chown(path, None, Some(gid))
+            .with_context(|| format!("Failed to hand {} to group {group}", path.display()))?;
+    }
+    Ok(listener)
+}
+
+/// Resolves a group name through `/etc/group`; numeric ids are taken as-is.
+fn group_id(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let groups = fs::read_to_string("/etc/group").context("Failed to read /etc/group")?;
+    groups
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let gid = fields.nth(1)?.parse().ok()?;
+            Some((name, gid))
+        })
+        .find_map(|(name, gid)| (name == group).then_some(gid))
+        .with_context(|| format!("Unknown LISTEN_SOCKET_GROUP {group}"))
+}
@@ -0,0 +1,20 @@
+//! Minimal viewer page embedded in the binary, so the stream can be watched
+//! without deploying the Svelte frontend.
+
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+};
+
+const INDEX_HTML: &str = include_str!("index.html");
+
+pub async fn index_handler() -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        INDEX_HTML,
+    )
+        .into_response()
+}
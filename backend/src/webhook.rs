@@ -0,0 +1,235 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use chrono::Local;
+use reqwest::{header, Client};
+use serde::Serialize;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time,
+};
+
+use crate::{
+    capture::CameraHandle,
+    config::{self, WebhookConfig},
+    motion::MotionEvent,
+    recorder::RecordingEvent,
+    shutdown::DrainGuard,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// How long to wait for a frame to attach to a motion notification.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Body of every webhook `POST`.
+#[derive(Serialize)]
+struct Notification {
+    /// `motion_started`, `motion_stopped`, `recording_finished`,
+    /// `camera_error` or `camera_recovered`.
+    event: &'static str,
+    camera: usize,
+    /// RFC 3339 time the event was observed.
+    timestamp: String,
+    /// Clip id for `recording_finished`, as served at `/recordings/{id}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recording: Option<String>,
+    /// Base64 JPEG attached to motion notifications with `WEBHOOK_SNAPSHOT`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapshot: Option<String>,
+}
+
+impl Notification {
+    fn new(event: &'static str, camera: usize) -> Self {
+        Self {
+            event,
+            camera,
+            timestamp: Local::now().to_rfc3339(),
+            recording: None,
+            snapshot: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Webhooks {
+    client: Client,
+    urls: Arc<Vec<String>>,
+    /// Held by every task, so shutdown waits for deliveries in flight.
+    shutdown: DrainGuard,
+}
+
+/// POSTs a JSON [`Notification`] to every configured URL on motion, finished
+/// recordings and camera outages. Deliveries run independently and are
+/// retried a few times, so a slow endpoint does not hold up the others.
+/// At shutdown, events already received are still delivered.
+pub fn spawn(
+    config: &WebhookConfig,
+    cameras: &[CameraHandle],
+    motion: Option<broadcast::Receiver<MotionEvent>>,
+    recordings: Option<broadcast::Receiver<RecordingEvent>>,
+    shutdown: DrainGuard,
+) -> Result<()> {
+    let webhooks = Webhooks {
+        client: Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to build webhook HTTP client")?,
+        urls: Arc::new(config.urls.clone()),
+        shutdown,
+    };
+
+    for (id, handle) in cameras.iter().enumerate() {
+        tokio::spawn(notify_outages(webhooks.clone(), id, handle.clone()));
+    }
+    if let Some(motion) = motion {
+        let cameras = config.snapshot.then(|| cameras.to_vec());
+        tokio::spawn(notify_motion(webhooks.clone(), motion, cameras));
+    }
+    if let Some(recordings) = recordings {
+        tokio::spawn(notify_recordings(webhooks, recordings));
+    }
+    Ok(())
+}
+
+async fn notify_outages(webhooks: Webhooks, camera: usize, handle: CameraHandle) {
+    let mut reconnecting = handle.watch_reconnecting();
+    loop {
+        let changed = tokio::select! {
+            changed = reconnecting.changed() => changed,
+            () = webhooks.shutdown.requested() => break,
+        };
+        if changed.is_err() {
+            break;
+        }
+        let event = if *reconnecting.borrow_and_update() {
+            "camera_error"
+        } else {
+            "camera_recovered"
+        };
+        webhooks.send(Notification::new(event, camera));
+    }
+}
+
+/// `cameras` is set when snapshots should be attached.
+async fn notify_motion(
+    webhooks: Webhooks,
+    mut motion: broadcast::Receiver<MotionEvent>,
+    cameras: Option<Vec<CameraHandle>>,
+) {
+    loop {
+        // Events already queued win over the shutdown.
+        let event = tokio::select! {
+            biased;
+            event = motion.recv() => event,
+            () = webhooks.shutdown.requested() => break,
+        };
+        let (event, camera) = match event {
+            Ok(MotionEvent::Started { camera, .. }) => ("motion_started", camera),
+            Ok(MotionEvent::Stopped { camera }) => ("motion_stopped", camera),
+            Ok(MotionEvent::Updated { .. }) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+
+        let mut notification = Notification::new(event, camera);
+        if let Some(handle) = cameras.as_ref().and_then(|cameras| cameras.get(camera)) {
+            notification.snapshot = handle
+                .next_frame(SNAPSHOT_TIMEOUT)
+                .await
+                .ok()
+                .map(|frame| STANDARD.encode(frame.data));
+        }
+        webhooks.send(notification);
+    }
+}
+
+async fn notify_recordings(
+    webhooks: Webhooks,
+    mut recordings: broadcast::Receiver<RecordingEvent>,
+) {
+    loop {
+        let event = tokio::select! {
+            biased;
+            event = recordings.recv() => event,
+            () = webhooks.shutdown.requested() => break,
+        };
+        match event {
+            Ok(RecordingEvent::Finished { camera, path }) => {
+                let mut notification = Notification::new("recording_finished", camera);
+                notification.recording = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned());
+                webhooks.send(notification);
+            }
+            Ok(RecordingEvent::Started { .. }) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+impl Webhooks {
+    fn send(&self, notification: Notification) {
+        let body = match serde_json::to_vec(&notification) {
+            Ok(body) => Bytes::from(body),
+            Err(err) => {
+                tracing::error!(error = %err, "Serializing webhook notification failed");
+                return;
+            }
+        };
+
+        for url in self.urls.iter() {
+            let client = self.client.clone();
+            let url = url.clone();
+            let body = body.clone();
+            let event = notification.event;
+            let shutdown = self.shutdown.clone();
+            tokio::spawn(async move {
+                let _shutdown = shutdown;
+                for attempt in 1..=MAX_ATTEMPTS {
+                    match post(&client, &url, body.clone()).await {
+                        Ok(()) => break,
+                        Err(err) if attempt == MAX_ATTEMPTS => {
+                            tracing::warn!(
+                                url = config::url_origin(&url),
+                                event,
+                                attempt,
+                                error = format!("{err:#}"),
+                                "Webhook failed; giving up"
+                            );
+                        }
+                        Err(err) => {
+                            tracing::debug!(
+                                url = config::url_origin(&url),
+                                event,
+                                attempt,
+                                error = format!("{err:#}"),
+                                "Webhook failed; retrying"
+                            );
+                            time::sleep(RETRY_DELAY * attempt).await;
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+async fn post(client: &Client, url: &str, body: Bytes) -> Result<()> {
+    let response = client
+        .post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+        // The path and query may hold a secret token.
+        .map_err(reqwest::Error::without_url)
+        .context("Webhook request failed")?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(anyhow!("Webhook answered {status}"));
+    }
+    Ok(())
+}
@@ -0,0 +1,431 @@
+//! YUV to RGB conversion for the pure-Rust JPEG encoder.
+//!
+//! Every frame of a YUYV or UYVY camera goes through here, so packed 4:2:2
+//! frames are converted 16 or 32 pixels at a time with SSE2 or AVX2 on x86
+//! and NEON on ARM, and one pair at a time elsewhere. Every path does the
+//! same integer math, so they all give the same pixels.
+
+// JFIF (full-range BT.601) YCbCr to RGB coefficients in 2.14 fixed point.
+// Chroma is shifted up by CHROMA_SHIFT and only the high 16 bits of each
+// product kept, which is one instruction on SSE2 and NEON, leaving
+// FRACTION_BITS below the point. Within one level of float math.
+const CHROMA_SHIFT: i32 = 6;
+const FRACTION_BITS: i32 = 4;
+const ROUND: i16 = 1 << (FRACTION_BITS - 1);
+const CR_TO_R: i16 = 22_970; // 1.402
+const CB_TO_G: i16 = 5_638; // 0.344136
+const CR_TO_G: i16 = 11_700; // 0.714136
+const CB_TO_B: i16 = 29_032; // 1.772
+
+/// Converts packed 4:2:2 YUV whose Y0, U, Y1 and V bytes sit at `order`,
+/// with SIMD where the CPU has it.
+pub fn packed_to_rgb(frame: &[u8], order: [usize; 4], rgb: &mut [u8]) {
+    let converted = simd::packed_to_rgb(frame, order, rgb);
+    packed_to_rgb_scalar(&frame[converted * 2..], order, &mut rgb[converted * 3..]);
+}
+
+/// [`packed_to_rgb`] without SIMD.
+pub fn packed_to_rgb_scalar(frame: &[u8], order: [usize; 4], rgb: &mut [u8]) {
+    let [y0, cb, y1, cr] = order;
+    for (chunk, pixels) in frame.chunks_exact(4).zip(rgb.chunks_exact_mut(6)) {
+        let offsets = chroma_offsets(chunk[cb], chunk[cr]);
+        for (pixel, y) in pixels.chunks_exact_mut(3).zip([chunk[y0], chunk[y1]]) {
+            put_pixel(pixel, y, offsets);
+        }
+    }
+}
+
+/// Converts NV12, where each U V pair covers two pixels on two rows.
+pub fn nv12_to_rgb(frame: &[u8], width: usize, rgb: &mut [u8]) {
+    let (luma, chroma) = frame.split_at(rgb.len() / 3);
+    for (row, pixels) in rgb.chunks_exact_mut(width * 3).enumerate() {
+        let luma = &luma[row * width..][..width];
+        let chroma = &chroma[row / 2 * width..][..width];
+        for ((pair, ys), uv) in pixels
+            .chunks_exact_mut(6)
+            .zip(luma.chunks_exact(2))
+            .zip(chroma.chunks_exact(2))
+        {
+            let offsets = chroma_offsets(uv[0], uv[1]);
+            for (pixel, &y) in pair.chunks_exact_mut(3).zip(ys) {
+                put_pixel(pixel, y, offsets);
+            }
+        }
+    }
+}
+
+fn put_pixel(pixel: &mut [u8], y: u8, (r, g, b): (i16, i16, i16)) {
+    let y = i16::from(y);
+    pixel[0] = (y + r).clamp(0, 255) as u8;
+    pixel[1] = (y + g).clamp(0, 255) as u8;
+    pixel[2] = (y + b).clamp(0, 255) as u8;
+}
+
+/// Red, green and blue offsets added to luma for one chroma pair.
+fn chroma_offsets(cb: u8, cr: u8) -> (i16, i16, i16) {
+    let cb = (i16::from(cb) - 128) << CHROMA_SHIFT;
+    let cr = (i16::from(cr) - 128) << CHROMA_SHIFT;
+    (
+        (high_product(cr, CR_TO_R) + ROUND) >> FRACTION_BITS,
+        (ROUND - high_product(cb, CB_TO_G) - high_product(cr, CR_TO_G)) >> FRACTION_BITS,
+        (high_product(cb, CB_TO_B) + ROUND) >> FRACTION_BITS,
+    )
+}
+
+/// The high 16 bits of `a * b`, as `_mm_mulhi_epi16` and `vqdmulhq_s16`
+/// give them.
+fn high_product(a: i16, b: i16) -> i16 {
+    ((i32::from(a) * i32::from(b)) >> 16) as i16
+}
+
+/// Writes `N` pixels from separate red, green and blue bytes.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn interleave<const N: usize>(rgb: &mut [u8], red: &[u8; N], green: &[u8; N], blue: &[u8; N]) {
+    for (index, pixel) in rgb.chunks_exact_mut(3).take(N).enumerate() {
+        pixel[0] = red[index];
+        pixel[1] = green[index];
+        pixel[2] = blue[index];
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod simd {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    use super::{
+        interleave, CB_TO_B, CB_TO_G, CHROMA_SHIFT, CR_TO_G, CR_TO_R, FRACTION_BITS, ROUND,
+    };
+
+    /// Converts as many whole blocks of pixels as the CPU's widest
+    /// extension takes and returns how many pixels that was.
+    pub fn packed_to_rgb(frame: &[u8], order: [usize; 4], rgb: &mut [u8]) -> usize {
+        // Both formats keep U before V; only the luma bytes move.
+        let luma_high = match order {
+            [0, 1, 2, 3] => false,
+            [1, 0, 3, 2] => true,
+            _ => return 0,
+        };
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: the CPU supports AVX2.
+            unsafe { packed_to_rgb_avx2(frame, luma_high, rgb) }
+        } else if is_x86_feature_detected!("sse2") {
+            // SAFETY: the CPU supports SSE2.
+            unsafe { packed_to_rgb_sse2(frame, luma_high, rgb) }
+        } else {
+            0
+        }
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) fn packed_to_rgb_sse2(frame: &[u8], luma_high: bool, rgb: &mut [u8]) -> usize {
+        let mut converted = 0;
+        for (chunk, pixels) in frame.chunks_exact(32).zip(rgb.chunks_exact_mut(48)) {
+            // SAFETY: each load reads 16 of the chunk's 32 bytes.
+            let (first, second) = unsafe {
+                (
+                    _mm_loadu_si128(chunk.as_ptr().cast()),
+                    _mm_loadu_si128(chunk[16..].as_ptr().cast()),
+                )
+            };
+            let [red0, green0, blue0] = convert_sse2(first, luma_high);
+            let [red1, green1, blue1] = convert_sse2(second, luma_high);
+            let (mut red, mut green, mut blue) = ([0; 16], [0; 16], [0; 16]);
+            // SAFETY: each store writes 16 bytes into a 16-byte array.
+            unsafe {
+                _mm_storeu_si128(red.as_mut_ptr().cast(), _mm_packus_epi16(red0, red1));
+                _mm_storeu_si128(green.as_mut_ptr().cast(), _mm_packus_epi16(green0, green1));
+                _mm_storeu_si128(blue.as_mut_ptr().cast(), _mm_packus_epi16(blue0, blue1));
+            }
+            interleave(pixels, &red, &green, &blue);
+            converted += 16;
+        }
+        converted
+    }
+
+    /// Red, green and blue of eight pixels, unclamped in 16-bit lanes.
+    #[target_feature(enable = "sse2")]
+    fn convert_sse2(packed: __m128i, luma_high: bool) -> [__m128i; 3] {
+        let low_bytes = _mm_set1_epi16(0x00ff);
+        let (luma, chroma) = if luma_high {
+            (
+                _mm_srli_epi16::<8>(packed),
+                _mm_and_si128(packed, low_bytes),
+            )
+        } else {
+            (
+                _mm_and_si128(packed, low_bytes),
+                _mm_srli_epi16::<8>(packed),
+            )
+        };
+        // Chroma alternates U and V; each goes to both pixels of its pair.
+        let cb = _mm_and_si128(chroma, _mm_set1_epi32(0xffff));
+        let cb = _mm_or_si128(cb, _mm_slli_epi32::<16>(cb));
+        let cr = _mm_srli_epi32::<16>(chroma);
+        let cr = _mm_or_si128(cr, _mm_slli_epi32::<16>(cr));
+        let centre = _mm_set1_epi16(128);
+        let cb = _mm_slli_epi16::<CHROMA_SHIFT>(_mm_sub_epi16(cb, centre));
+        let cr = _mm_slli_epi16::<CHROMA_SHIFT>(_mm_sub_epi16(cr, centre));
+
+        let round = _mm_set1_epi16(ROUND);
+        let red = _mm_add_epi16(_mm_mulhi_epi16(cr, _mm_set1_epi16(CR_TO_R)), round);
+        let green = _mm_sub_epi16(
+            _mm_sub_epi16(round, _mm_mulhi_epi16(cb, _mm_set1_epi16(CB_TO_G))),
+            _mm_mulhi_epi16(cr, _mm_set1_epi16(CR_TO_G)),
+        );
+        let blue = _mm_add_epi16(_mm_mulhi_epi16(cb, _mm_set1_epi16(CB_TO_B)), round);
+        [red, green, blue]
+            .map(|offset| _mm_add_epi16(luma, _mm_srai_epi16::<FRACTION_BITS>(offset)))
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) fn packed_to_rgb_avx2(frame: &[u8], luma_high: bool, rgb: &mut [u8]) -> usize {
+        let mut converted = 0;
+        for (chunk, pixels) in frame.chunks_exact(64).zip(rgb.chunks_exact_mut(96)) {
+            // SAFETY: each load reads 32 of the chunk's 64 bytes.
+            let (first, second) = unsafe {
+                (
+                    _mm256_loadu_si256(chunk.as_ptr().cast()),
+                    _mm256_loadu_si256(chunk[32..].as_ptr().cast()),
+                )
+            };
+            let [red0, green0, blue0] = convert_avx2(first, luma_high);
+            let [red1, green1, blue1] = convert_avx2(second, luma_high);
+            // Packing works within 128-bit lanes; the permute puts the
+            // pixels back in order.
+            let pack = |a, b| _mm256_permute4x64_epi64::<0b11_01_10_00>(_mm256_packus_epi16(a, b));
+            let (mut red, mut green, mut blue) = ([0; 32], [0; 32], [0; 32]);
+            // SAFETY: each store writes 32 bytes into a 32-byte array.
+            unsafe {
+                _mm256_storeu_si256(red.as_mut_ptr().cast(), pack(red0, red1));
+                _mm256_storeu_si256(green.as_mut_ptr().cast(), pack(green0, green1));
+                _mm256_storeu_si256(blue.as_mut_ptr().cast(), pack(blue0, blue1));
+            }
+            interleave(pixels, &red, &green, &blue);
+            converted += 32;
+        }
+        converted
+    }
+
+    /// [`convert_sse2`] for sixteen pixels.
+    #[target_feature(enable = "avx2")]
+    fn convert_avx2(packed: __m256i, luma_high: bool) -> [__m256i; 3] {
+        let low_bytes = _mm256_set1_epi16(0x00ff);
+        let (luma, chroma) = if luma_high {
+            (
+                _mm256_srli_epi16::<8>(packed),
+                _mm256_and_si256(packed, low_bytes),
+            )
+        } else {
+            (
+                _mm256_and_si256(packed, low_bytes),
+                _mm256_srli_epi16::<8>(packed),
+            )
+        };
+        let cb = _mm256_and_si256(chroma, _mm256_set1_epi32(0xffff));
+        let cb = _mm256_or_si256(cb, _mm256_slli_epi32::<16>(cb));
+        let cr = _mm256_srli_epi32::<16>(chroma);
+        let cr = _mm256_or_si256(cr, _mm256_slli_epi32::<16>(cr));
+        let centre = _mm256_set1_epi16(128);
+        let cb = _mm256_slli_epi16::<CHROMA_SHIFT>(_mm256_sub_epi16(cb, centre));
+        let cr = _mm256_slli_epi16::<CHROMA_SHIFT>(_mm256_sub_epi16(cr, centre));
+
+        let round = _mm256_set1_epi16(ROUND);
+        let red = _mm256_add_epi16(_mm256_mulhi_epi16(cr, _mm256_set1_epi16(CR_TO_R)), round);
+        let green = _mm256_sub_epi16(
+            _mm256_sub_epi16(round, _mm256_mulhi_epi16(cb, _mm256_set1_epi16(CB_TO_G))),
+            _mm256_mulhi_epi16(cr, _mm256_set1_epi16(CR_TO_G)),
+        );
+        let blue = _mm256_add_epi16(_mm256_mulhi_epi16(cb, _mm256_set1_epi16(CB_TO_B)), round);
+        [red, green, blue]
+            .map(|offset| _mm256_add_epi16(luma, _mm256_srai_epi16::<FRACTION_BITS>(offset)))
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod simd {
+    use std::arch::aarch64::*;
+
+    use super::{CB_TO_B, CB_TO_G, CHROMA_SHIFT, CR_TO_G, CR_TO_R, FRACTION_BITS, ROUND};
+
+    /// Converts whole blocks of 32 pixels and returns how many pixels that
+    /// was.
+    pub fn packed_to_rgb(frame: &[u8], order: [usize; 4], rgb: &mut [u8]) -> usize {
+        if !std::arch::is_aarch64_feature_detected!("neon") {
+            return 0;
+        }
+        // SAFETY: the CPU supports NEON.
+        unsafe { packed_to_rgb_neon(frame, order, rgb) }
+    }
+
+    #[target_feature(enable = "neon")]
+    fn packed_to_rgb_neon(frame: &[u8], order: [usize; 4], rgb: &mut [u8]) -> usize {
+        let [y0, cb, y1, cr] = order;
+        let mut converted = 0;
+        for (chunk, pixels) in frame.chunks_exact(64).zip(rgb.chunks_exact_mut(96)) {
+            // SAFETY: reads the chunk's 64 bytes, split by position in
+            // each group of four.
+            let bytes = unsafe { vld4q_u8(chunk.as_ptr()) };
+            let bytes = [bytes.0, bytes.1, bytes.2, bytes.3];
+            let (even, odd) = (bytes[y0], bytes[y1]);
+            let low = convert_neon(vget_low_u8(even), vget_low_u8(odd), {
+                (vget_low_u8(bytes[cb]), vget_low_u8(bytes[cr]))
+            });
+            let high = convert_neon(vget_high_u8(even), vget_high_u8(odd), {
+                (vget_high_u8(bytes[cb]), vget_high_u8(bytes[cr]))
+            });
+            // Even and odd pixels zipped back into order.
+            let [red, green, blue] = [0, 1, 2].map(|channel| {
+                vzipq_u8(
+                    vcombine_u8(low[channel].0, high[channel].0),
+                    vcombine_u8(low[channel].1, high[channel].1),
+                )
+            });
+            // SAFETY: each store writes 48 of the block's 96 bytes.
+            unsafe {
+                vst3q_u8(pixels.as_mut_ptr(), uint8x16x3_t(red.0, green.0, blue.0));
+                vst3q_u8(
+                    pixels[48..].as_mut_ptr(),
+                    uint8x16x3_t(red.1, green.1, blue.1),
+                );
+            }
+            converted += 32;
+        }
+        converted
+    }
+
+    /// Red, green and blue of eight pixel pairs, as even and odd pixels.
+    #[target_feature(enable = "neon")]
+    fn convert_neon(
+        even: uint8x8_t,
+        odd: uint8x8_t,
+        (cb, cr): (uint8x8_t, uint8x8_t),
+    ) -> [(uint8x8_t, uint8x8_t); 3] {
+        // The doubling multiply makes up for one bit less of shift.
+        let centred = |chroma| {
+            vshlq_n_s16::<{ CHROMA_SHIFT - 1 }>(vsubq_s16(
+                vreinterpretq_s16_u16(vmovl_u8(chroma)),
+                vdupq_n_s16(128),
+            ))
+        };
+        let (cb, cr) = (centred(cb), centred(cr));
+        let round = vdupq_n_s16(ROUND);
+        let red = vaddq_s16(vqdmulhq_n_s16(cr, CR_TO_R), round);
+        let green = vsubq_s16(
+            vsubq_s16(round, vqdmulhq_n_s16(cb, CB_TO_G)),
+            vqdmulhq_n_s16(cr, CR_TO_G),
+        );
+        let blue = vaddq_s16(vqdmulhq_n_s16(cb, CB_TO_B), round);
+        let (even, odd) = (
+            vreinterpretq_s16_u16(vmovl_u8(even)),
+            vreinterpretq_s16_u16(vmovl_u8(odd)),
+        );
+        [red, green, blue].map(|offset| {
+            let offset = vshrq_n_s16::<FRACTION_BITS>(offset);
+            (
+                vqmovun_s16(vaddq_s16(even, offset)),
+                vqmovun_s16(vaddq_s16(odd, offset)),
+            )
+        })
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+mod simd {
+    pub fn packed_to_rgb(_frame: &[u8], _order: [usize; 4], _rgb: &mut [u8]) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YUYV: [usize; 4] = [0, 1, 2, 3];
+    const UYVY: [usize; 4] = [1, 0, 3, 2];
+
+    /// Bytes from a fixed xorshift sequence.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Converts `frame` with `simd`, with the pixels it leaves over done by
+    /// the scalar path.
+    fn convert(
+        frame: &[u8],
+        order: [usize; 4],
+        simd: impl Fn(&[u8], &mut [u8]) -> usize,
+    ) -> Vec<u8> {
+        let mut rgb = vec![0; frame.len() / 2 * 3];
+        let converted = simd(frame, &mut rgb);
+        packed_to_rgb_scalar(&frame[converted * 2..], order, &mut rgb[converted * 3..]);
+        rgb
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        // Not a whole number of blocks, so the scalar tail runs too.
+        let frame = noise((1280 + 38) * 2);
+        for order in [YUYV, UYVY] {
+            let scalar = convert(&frame, order, |_, _| 0);
+            let dispatched = convert(&frame, order, |frame, rgb| {
+                simd::packed_to_rgb(frame, order, rgb)
+            });
+            assert!(dispatched == scalar, "SIMD and scalar differ for {order:?}");
+
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            {
+                let luma_high = order == UYVY;
+                if is_x86_feature_detected!("sse2") {
+                    // SAFETY: the CPU supports SSE2.
+                    let sse2 = convert(&frame, order, |frame, rgb| unsafe {
+                        simd::packed_to_rgb_sse2(frame, luma_high, rgb)
+                    });
+                    assert!(sse2 == scalar, "SSE2 and scalar differ for {order:?}");
+                }
+                if is_x86_feature_detected!("avx2") {
+                    // SAFETY: the CPU supports AVX2.
+                    let avx2 = convert(&frame, order, |frame, rgb| unsafe {
+                        simd::packed_to_rgb_avx2(frame, luma_high, rgb)
+                    });
+                    assert!(avx2 == scalar, "AVX2 and scalar differ for {order:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn within_one_level_of_float() {
+        for cb in 0..=255_u8 {
+            for cr in 0..=255_u8 {
+                let offsets = chroma_offsets(cb, cr);
+                let (cb, cr) = (f32::from(cb) - 128.0, f32::from(cr) - 128.0);
+                // Luma only matters where the result is clamped.
+                for y in (0..=255_u8).step_by(5) {
+                    let mut pixel = [0; 3];
+                    put_pixel(&mut pixel, y, offsets);
+                    let y = f32::from(y);
+                    let exact = [
+                        y + 1.402 * cr,
+                        y - 0.344_136 * cb - 0.714_136 * cr,
+                        y + 1.772 * cb,
+                    ];
+                    for (channel, exact) in pixel.into_iter().zip(exact) {
+                        let exact = exact.round().clamp(0.0, 255.0);
+                        assert!((f32::from(channel) - exact).abs() <= 1.0);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -17,7 +17,7 @@ function backendBaseUrl(): string {
 }
 
 export async function fetchConfig(): Promise<BackendConfig> {
-    const response = await fetch(`${backendBaseUrl()}/config`, {
+    const response = await fetch(`${backendBaseUrl()}/api/v1/config`, {
         headers: {
             Accept: 'application/json',
         },
@@ -31,7 +31,7 @@ export async function fetchConfig(): Promise<BackendConfig> {
 }
 
 export async function fetchHealth(): Promise<void> {
-    const response = await fetch(`${backendBaseUrl()}/health`, {
+    const response = await fetch(`${backendBaseUrl()}/api/v1/health`, {
         cache: 'no-store',
     });
 
@@ -41,5 +41,5 @@ export async function fetchHealth(): Promise<void> {
 }
 
 export function streamUrl(): string {
-    return `${backendBaseUrl()}/stream`;
+    return `${backendBaseUrl()}/api/v1/stream`;
 }
@@ -1,8 +1,29 @@
+export type OverlayCorner = 'top-left' | 'top-right' | 'bottom-left' | 'bottom-right';
+
+/** Polygon in fractions (0-1) of the frame size. */
+export interface PrivacyMask {
+    points: [number, number][];
+}
+
 export interface BackendConfig {
-    listen_address: string;
+    listen_addresses: string[];
     port: number;
     frame_rate: number;
     resolution_width: number;
     resolution_height: number;
     camera_device?: string | null;
+    cameras?: string[];
+    on_demand_capture: boolean;
+    motion_detection: boolean;
+    recordings_dir?: string | null;
+    recording_format: 'mp4' | 'avi';
+    overlay_timestamp: boolean;
+    overlay_timestamp_format: string;
+    overlay_corner: OverlayCorner;
+    overlay_caption?: string | null;
+    overlay_watermark_path?: string | null;
+    overlay_watermark: boolean;
+    overlay_watermark_corner: OverlayCorner;
+    privacy_masks?: PrivacyMask[];
+    privacy_mask_style: 'black' | 'pixelate';
 }